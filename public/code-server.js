@@ -33,29 +33,31 @@
                 'RESPONSE_START', 'RESPONSE_END',
                 'CODE_START', 'CODE_END',
                 'OUTPUT_SUCCESS', 'OUTPUT_ERROR', 'OUTPUT_END',
-                'IF', 'ELSE', 'FOR', 'IN', 'BREAK',
-                'RETURN', 'JSON_OUTPUT', 'CONNECTION_CLOSE'
+                'IF', 'THEN', 'ELSE', 'FOR', 'WHILE', 'IN', 'RANGE', 'BREAK', 'CONTINUE', 'RETURN_EARLY', 'FAIL', 'ASSERT', 'DEF', 'CALL',
+                'RETURN', 'JSON_OUTPUT', 'CONNECTION_CLOSE', 'FRAME', 'EXPECT_PACKETS', 'DELAY', 'TIMEOUT', 'NO_RESPONSE', 'COOKIES', 'ENDIAN', 'DEFINE', 'INCLUDE'
             ],
             
             writeCommands: [
-                'WRITE_BYTE', 'WRITE_SHORT', 'WRITE_SHORT_BE',
-                'WRITE_INT', 'WRITE_INT_BE', 'WRITE_VARINT',
-                'WRITE_STRING', 'WRITE_STRING_LEN', 'WRITE_BYTES'
+                'WRITE_BYTE', 'WRITE_SHORT', 'WRITE_SHORT_BE', 'WRITE_SHORT_LE',
+                'WRITE_INT', 'WRITE_INT_BE', 'WRITE_INT_LE', 'WRITE_LONG', 'WRITE_LONG_BE', 'WRITE_LONG_LE', 'WRITE_FLOAT', 'WRITE_FLOAT_BE', 'WRITE_FLOAT_LE', 'WRITE_DOUBLE', 'WRITE_DOUBLE_BE', 'WRITE_DOUBLE_LE', 'WRITE_VARINT',
+                'WRITE_STRING', 'WRITE_STRING_LEN', 'WRITE_STRING_RAW', 'WRITE_BYTES', 'WRITE_BYTES_VAR', 'WRITE_BYTES_REPEAT', 'WRITE_BYTES_REPEAT_TO_LEN', 'WRITE_CRC32', 'COMPRESS', 'WRITE_STRING_VARINT', 'WRITE_STRING_PREFIXED', 'WRITE_STRING_UTF16', 'WRITE_STRING_UTF16_BE', 'WRITE_STRING_UTF16_LE',
+                'WRITE_STRING_BYTE_LEN', 'WRITE_STRING_SHORT_LE_LEN', 'WRITE_STRING_SHORT_BE_LEN', 'WRITE_STRING_VARINT_LEN'
             ],
             
             readCommands: [
-                'READ_BYTE', 'READ_SHORT', 'READ_SHORT_BE',
-                'READ_INT', 'READ_INT_BE', 'READ_VARINT',
-                'READ_STRING', 'READ_STRING_NULL', 'SKIP_BYTES',
+                'READ_BYTE', 'READ_SHORT', 'READ_SHORT_BE', 'READ_SHORT_LE',
+                'READ_INT', 'READ_INT_BE', 'READ_INT_LE', 'READ_LONG', 'READ_LONG_BE', 'READ_LONG_LE', 'READ_SBYTE', 'READ_SSHORT', 'READ_SSHORT_BE', 'READ_SSHORT_LE', 'READ_SINT', 'READ_SINT_BE', 'READ_SINT_LE', 'READ_SHORT_SIGNED', 'READ_SHORT_SIGNED_BE', 'READ_SHORT_SIGNED_LE', 'READ_INT_SIGNED', 'READ_INT_SIGNED_BE', 'READ_INT_SIGNED_LE', 'READ_SLONG', 'READ_SLONG_BE', 'READ_SLONG_LE', 'READ_FLOAT', 'READ_FLOAT_BE', 'READ_FLOAT_LE', 'READ_DOUBLE', 'READ_DOUBLE_BE', 'READ_DOUBLE_LE', 'READ_VARINT',
+                'READ_STRING', 'READ_STRING_NULL', 'SKIP_BYTES', 'DECOMPRESS', 'SEEK_TO_OFFSET', 'SEEK_FROM_END', 'CURRENT_OFFSET', 'READ_STRING_VARINT', 'READ_STRING_PREFIXED', 'READ_STRING_UTF16', 'READ_STRING_UTF16_BE', 'READ_STRING_UTF16_LE', 'READ_BYTES', 'READ_REMAINING',
+                'READ_STRING_BYTE_LEN', 'READ_STRING_SHORT_LE_LEN', 'READ_STRING_SHORT_BE_LEN', 'READ_STRING_VARINT_LEN',
                 'READ_BODY_JSON', 'READ_BODY'
             ],
             
             validationCommands: [
-                'EXPECT_BYTE', 'EXPECT_MAGIC', 'EXPECT_STATUS', 'EXPECT_HEADER'
+                'EXPECT_BYTE', 'EXPECT_MAGIC', 'EXPECT_VARINT', 'EXPECT_INT', 'EXPECT_INT_BE', 'EXPECT_INT_LE', 'EXPECT_VAR', 'EXPECT_STATUS', 'EXPECT_BODY_CONTAINS', 'EXPECT_HEADER', 'READ_HEADER', 'NO_HEADER_VARS'
             ],
             
             httpCommands: [
-                'PARAM', 'HEADER', 'BODY_START', 'DATA', 'BODY_END'
+                'PARAM', 'PARAM_VAR', 'HEADER', 'HEADER_VAR', 'BODY_START', 'DATA', 'DATA_MULTILINE_START', 'DATA_MULTILINE_END', 'BODY_END'
             ],
             
             types: [
@@ -63,7 +65,7 @@
             ],
             
             functions: [
-                'SPLIT', 'REPLACE', 'CONTAINS'
+                'SPLIT', 'REPLACE', 'CONTAINS', 'LEN', 'SUBSTRING', 'UPPER', 'LOWER', 'TRIM', 'CONCAT', 'TO_INT', 'TO_FLOAT', 'TO_STRING', 'HEX', 'CRC32', 'MD5', 'SHA1', 'RANDOM_INT', 'RANDOM_HEX', 'TIMESTAMP', 'TIMESTAMP_MS', 'REGEX_MATCH', 'REGEX_EXTRACT'
             ],
             
             placeholders: [
@@ -89,19 +91,19 @@
                     [/\b(PACKET_START|PACKET_END|HTTP_START|HTTP_END|RESPONSE_START|RESPONSE_END|CODE_START|CODE_END|OUTPUT_SUCCESS|OUTPUT_ERROR|OUTPUT_END)\b/, 'keyword'],
                     
                     // Control flow
-                    [/\b(IF|ELSE|FOR|IN|BREAK)\b/, 'keyword.control'],
+                    [/\b(IF|THEN|ELSE|FOR|WHILE|IN|RANGE|BREAK|CONTINUE|RETURN_EARLY|FAIL|ASSERT)\b/, 'keyword.control'],
                     
                     // Write commands
-                    [/\b(WRITE_BYTE|WRITE_SHORT|WRITE_SHORT_BE|WRITE_INT|WRITE_INT_BE|WRITE_VARINT|WRITE_STRING|WRITE_STRING_LEN|WRITE_BYTES)\b/, 'keyword.write'],
+                    [/\b(WRITE_BYTE|WRITE_SHORT|WRITE_SHORT_BE|WRITE_SHORT_LE|WRITE_INT|WRITE_INT_BE|WRITE_INT_LE|WRITE_LONG|WRITE_LONG_BE|WRITE_LONG_LE|WRITE_FLOAT|WRITE_FLOAT_BE|WRITE_FLOAT_LE|WRITE_DOUBLE|WRITE_DOUBLE_BE|WRITE_DOUBLE_LE|WRITE_VARINT|WRITE_STRING|WRITE_STRING_LEN|WRITE_STRING_RAW|WRITE_STRING_BYTE_LEN|WRITE_STRING_SHORT_LE_LEN|WRITE_STRING_SHORT_BE_LEN|WRITE_STRING_VARINT_LEN|WRITE_STRING_VARINT|WRITE_STRING_PREFIXED|WRITE_STRING_UTF16|WRITE_STRING_UTF16_BE|WRITE_STRING_UTF16_LE|WRITE_BYTES|WRITE_BYTES_VAR|WRITE_BYTES_REPEAT|WRITE_BYTES_REPEAT_TO_LEN|WRITE_CRC32|COMPRESS)\b/, 'keyword.write'],
                     
                     // Read commands
-                    [/\b(READ_BYTE|READ_SHORT|READ_SHORT_BE|READ_INT|READ_INT_BE|READ_VARINT|READ_STRING|READ_STRING_NULL|SKIP_BYTES|READ_BODY_JSON|READ_BODY)\b/, 'keyword.read'],
+                    [/\b(READ_BYTE|READ_SHORT|READ_SHORT_BE|READ_SHORT_LE|READ_INT|READ_INT_BE|READ_INT_LE|READ_LONG|READ_LONG_BE|READ_LONG_LE|READ_SBYTE|READ_SSHORT|READ_SSHORT_BE|READ_SSHORT_LE|READ_SINT|READ_SINT_BE|READ_SINT_LE|READ_SHORT_SIGNED|READ_SHORT_SIGNED_BE|READ_SHORT_SIGNED_LE|READ_INT_SIGNED|READ_INT_SIGNED_BE|READ_INT_SIGNED_LE|READ_SLONG|READ_SLONG_BE|READ_SLONG_LE|READ_FLOAT|READ_FLOAT_BE|READ_FLOAT_LE|READ_DOUBLE|READ_DOUBLE_BE|READ_DOUBLE_LE|READ_VARINT|READ_STRING|READ_STRING_NULL|READ_STRING_BYTE_LEN|READ_STRING_SHORT_LE_LEN|READ_STRING_SHORT_BE_LEN|READ_STRING_VARINT_LEN|READ_STRING_VARINT|READ_STRING_PREFIXED|READ_STRING_UTF16|READ_STRING_UTF16_BE|READ_STRING_UTF16_LE|SKIP_BYTES|DECOMPRESS|SEEK_TO_OFFSET|SEEK_FROM_END|CURRENT_OFFSET|READ_BYTES|READ_REMAINING|READ_BODY_JSON|READ_BODY)\b/, 'keyword.read'],
                     
                     // Validation commands
-                    [/\b(EXPECT_BYTE|EXPECT_MAGIC|EXPECT_STATUS|EXPECT_HEADER)\b/, 'keyword.validation'],
+                    [/\b(EXPECT_BYTE|EXPECT_MAGIC|EXPECT_VARINT|EXPECT_INT_BE|EXPECT_INT_LE|EXPECT_INT|EXPECT_VAR|EXPECT_STATUS|EXPECT_BODY_CONTAINS|EXPECT_HEADER|READ_HEADER|NO_HEADER_VARS)\b/, 'keyword.validation'],
                     
                     // HTTP commands
-                    [/\b(PARAM|HEADER|BODY_START|DATA|BODY_END)\b/, 'keyword.http'],
+                    [/\b(PARAM_VAR|PARAM|HEADER_VAR|HEADER|BODY_START|VERBATIM|DATA_MULTILINE_START|DATA_MULTILINE_END|DATA|BODY_END)\b/, 'keyword.http'],
                     
                     // HTTP methods (for HTTP_START REQUEST)
                     [/\b(GET|POST|PUT|DELETE|PATCH|Custom)\b/, 'keyword.http.method'],
@@ -110,7 +112,7 @@
                     [/\b(STRING|INT|BYTE|FLOAT|ARRAY)\b/, 'type'],
                     
                     // Functions
-                    [/\b(SPLIT|REPLACE|CONTAINS|JSON_OUTPUT)\b/, 'function'],
+                    [/\b(SPLIT|REPLACE|CONTAINS|LEN|SUBSTRING|UPPER|LOWER|TRIM|CONCAT|TO_INT|TO_FLOAT|TO_STRING|HEX|CRC32|MD5|SHA1|RANDOM_INT|RANDOM_HEX|TIMESTAMP|TIMESTAMP_MS|REGEX_MATCH|REGEX_EXTRACT|JSON_OUTPUT)\b/, 'function'],
                     // RETURN gets special styling
                     [/\bRETURN\b/, 'function.return'],
                     
@@ -225,9 +227,13 @@
                     createSuggestion('HTTP_START', monaco.languages.CompletionItemKind.Keyword, 'Marks the beginning of an HTTP request. Example: HTTP_START REQUEST GET /api/status', 'HTTP_START REQUEST ${1:GET|POST|PUT|DELETE} ${2:/path}', true),
                     createSuggestion('HTTP_END', monaco.languages.CompletionItemKind.Keyword, 'Marks the end of an HTTP request', 'HTTP_END', false),
                     createSuggestion('PARAM', monaco.languages.CompletionItemKind.Function, 'Adds a query parameter to the request URL. Example: PARAM key value', 'PARAM ${1:key} ${2:value}', true),
+                    createSuggestion('PARAM_VAR', monaco.languages.CompletionItemKind.Function, 'Adds a query parameter whose value comes from a variable. Example: PARAM_VAR session token', 'PARAM_VAR ${1:key} ${2:var_name}', true),
                     createSuggestion('HEADER', monaco.languages.CompletionItemKind.Function, 'Adds an HTTP header to the request. Example: HEADER Content-Type application/json', 'HEADER ${1:key} ${2:value}', true),
+                    createSuggestion('HEADER_VAR', monaco.languages.CompletionItemKind.Function, 'Adds an HTTP header whose value comes from a variable. Example: HEADER_VAR X-Session-Token token', 'HEADER_VAR ${1:key} ${2:var_name}', true),
                     createSuggestion('BODY_START', monaco.languages.CompletionItemKind.Function, 'Marks the beginning of the request body. Example: BODY_START TYPE RAW', 'BODY_START TYPE ${1:FORM|RAW}', true),
+                    createSuggestion('BODY_START TYPE RAW VERBATIM', monaco.languages.CompletionItemKind.Keyword, 'Request body sent exactly as written, up to BODY_END. Comments and DATA lines inside are part of the body', 'BODY_START TYPE RAW VERBATIM\n${1:content}\nBODY_END', true),
                     createSuggestion('DATA', monaco.languages.CompletionItemKind.Function, 'Adds body content. JSON is automatically stringified. Example: DATA {"key": "value"}', 'DATA ${1:content}', true),
+                    createSuggestion('DATA_MULTILINE_START', monaco.languages.CompletionItemKind.Keyword, 'Adds multi-line body content, joined with newlines, up to DATA_MULTILINE_END', 'DATA_MULTILINE_START\n${1:content}\nDATA_MULTILINE_END', true),
                     createSuggestion('BODY_END', monaco.languages.CompletionItemKind.Keyword, 'Marks the end of the request body', 'BODY_END', false)
                 );
                 
@@ -238,10 +244,25 @@
                     createSuggestion('WRITE_SHORT_BE', monaco.languages.CompletionItemKind.Function, 'Writes a 16-bit integer (big-endian/network byte order). Example: WRITE_SHORT_BE 1234', 'WRITE_SHORT_BE ${1:value}', true),
                     createSuggestion('WRITE_INT', monaco.languages.CompletionItemKind.Function, 'Writes a 32-bit integer (little-endian). Example: WRITE_INT 50000', 'WRITE_INT ${1:value}', true),
                     createSuggestion('WRITE_INT_BE', monaco.languages.CompletionItemKind.Function, 'Writes a 32-bit integer (big-endian/network byte order). Example: WRITE_INT_BE PACKET_LEN', 'WRITE_INT_BE ${1:value}', true),
+                    createSuggestion('WRITE_LONG', monaco.languages.CompletionItemKind.Function, 'Writes a 64-bit integer (little-endian). Example: WRITE_LONG steam_id', 'WRITE_LONG ${1:value}', true),
+                    createSuggestion('WRITE_LONG_BE', monaco.languages.CompletionItemKind.Function, 'Writes a 64-bit integer (big-endian/network byte order). Example: WRITE_LONG_BE timestamp', 'WRITE_LONG_BE ${1:value}', true),
+                    createSuggestion('WRITE_FLOAT', monaco.languages.CompletionItemKind.Function, 'Writes a 32-bit float (little-endian). Example: WRITE_FLOAT 66.5', 'WRITE_FLOAT ${1:value}', true),
+                    createSuggestion('WRITE_FLOAT_BE', monaco.languages.CompletionItemKind.Function, 'Writes a 32-bit float (big-endian/network byte order). Example: WRITE_FLOAT_BE tick_rate', 'WRITE_FLOAT_BE ${1:value}', true),
+                    createSuggestion('WRITE_DOUBLE', monaco.languages.CompletionItemKind.Function, 'Writes a 64-bit float (little-endian). Example: WRITE_DOUBLE 3.14', 'WRITE_DOUBLE ${1:value}', true),
+                    createSuggestion('WRITE_DOUBLE_BE', monaco.languages.CompletionItemKind.Function, 'Writes a 64-bit float (big-endian/network byte order). Example: WRITE_DOUBLE_BE uptime', 'WRITE_DOUBLE_BE ${1:value}', true),
                     createSuggestion('WRITE_VARINT', monaco.languages.CompletionItemKind.Function, 'Writes a variable-length integer (Minecraft-style). Example: WRITE_VARINT 300', 'WRITE_VARINT ${1:value}', true),
                     createSuggestion('WRITE_STRING', monaco.languages.CompletionItemKind.Function, 'Writes a null-terminated string. Example: WRITE_STRING "Hello Server"', 'WRITE_STRING "${1:text}"', true),
                     createSuggestion('WRITE_STRING_LEN', monaco.languages.CompletionItemKind.Function, 'Writes a fixed-length string. Example: WRITE_STRING_LEN "Test" 10', 'WRITE_STRING_LEN "${1:text}" ${2:length}', true),
-                    createSuggestion('WRITE_BYTES', monaco.languages.CompletionItemKind.Function, 'Writes raw hexadecimal bytes. Example: WRITE_BYTES "FF00AA55"', 'WRITE_BYTES "${1:hex}"', true)
+                    createSuggestion('WRITE_STRING_RAW', monaco.languages.CompletionItemKind.Function, 'Writes a string without a null terminator. Example: WRITE_STRING_RAW "GET / HTTP/1.0"', 'WRITE_STRING_RAW "${1:text}"', true),
+                    createSuggestion('WRITE_BYTES', monaco.languages.CompletionItemKind.Function, 'Writes raw hexadecimal bytes. Example: WRITE_BYTES "FF00AA55"', 'WRITE_BYTES "${1:hex}"', true),
+                    createSuggestion('WRITE_STRING_VARINT', monaco.languages.CompletionItemKind.Function, 'Writes a string prefixed with its byte length as a VarInt (Minecraft strings). Example: WRITE_STRING_VARINT "HOST"', 'WRITE_STRING_VARINT "${1:text}"', true),
+                    createSuggestion('WRITE_STRING_PREFIXED', monaco.languages.CompletionItemKind.Function, 'Writes a string with a BYTE, SHORT, SHORT_BE, INT, INT_BE or VARINT length prefix. Example: WRITE_STRING_PREFIXED SHORT_BE "lobby"', 'WRITE_STRING_PREFIXED ${1:SHORT_BE} "${2:text}"', true),
+                    createSuggestion('WRITE_STRING_UTF16_BE', monaco.languages.CompletionItemKind.Function, 'Writes text as big-endian UTF-16 without terminator (WRITE_STRING_UTF16 / _LE for little-endian). Example: WRITE_STRING_UTF16_BE "MC|PingHost"', 'WRITE_STRING_UTF16_BE "${1:text}"', true),
+                    createSuggestion('WRITE_BYTES_VAR', monaco.languages.CompletionItemKind.Function, 'Writes the bytes of a hex string variable (e.g. from READ_BYTES). Example: WRITE_BYTES_VAR token', 'WRITE_BYTES_VAR ${1:var_name}', true)
+                    createSuggestion('WRITE_BYTES_REPEAT', monaco.languages.CompletionItemKind.Function, 'Writes a byte N times. Example: WRITE_BYTES_REPEAT 0x00 16', 'WRITE_BYTES_REPEAT ${1:0x00} ${2:16}', true),
+                    createSuggestion('WRITE_BYTES_REPEAT_TO_LEN', monaco.languages.CompletionItemKind.Function, 'Pads the packet with a byte up to a total length. Example: WRITE_BYTES_REPEAT_TO_LEN 0x00 64', 'WRITE_BYTES_REPEAT_TO_LEN ${1:0x00} ${2:64}', true),
+                    createSuggestion('WRITE_CRC32', monaco.languages.CompletionItemKind.Function, 'Writes the CRC32 of the packet bytes before it (LE by default). Example: WRITE_CRC32 BE', 'WRITE_CRC32 ${1:BE}', true),
+                    createSuggestion('COMPRESS', monaco.languages.CompletionItemKind.Function, 'Compresses everything written after it in the packet. Example: COMPRESS ZLIB', 'COMPRESS ${1|ZLIB,GZIP|}', true),
                 );
                 
                 // Response parsing commands
@@ -253,18 +274,48 @@
                     createSuggestion('READ_SHORT_BE', monaco.languages.CompletionItemKind.Function, 'Reads a 16-bit integer (big-endian). Example: READ_SHORT_BE port_number', 'READ_SHORT_BE ${1:var_name}', true),
                     createSuggestion('READ_INT', monaco.languages.CompletionItemKind.Function, 'Reads a 32-bit integer (little-endian). Example: READ_INT server_version', 'READ_INT ${1:var_name}', true),
                     createSuggestion('READ_INT_BE', monaco.languages.CompletionItemKind.Function, 'Reads a 32-bit integer (big-endian). Example: READ_INT_BE response_length', 'READ_INT_BE ${1:var_name}', true),
+                    createSuggestion('READ_LONG', monaco.languages.CompletionItemKind.Function, 'Reads a 64-bit integer (little-endian). Example: READ_LONG steam_id', 'READ_LONG ${1:var_name}', true),
+                    createSuggestion('READ_LONG_BE', monaco.languages.CompletionItemKind.Function, 'Reads a 64-bit integer (big-endian). Example: READ_LONG_BE timestamp', 'READ_LONG_BE ${1:var_name}', true),
+                    createSuggestion('READ_SBYTE', monaco.languages.CompletionItemKind.Function, 'Reads a signed byte (-128 to 127). Example: READ_SBYTE players_delta', 'READ_SBYTE ${1:var_name}', true),
+                    createSuggestion('READ_SSHORT', monaco.languages.CompletionItemKind.Function, 'Reads a signed 16-bit integer (little-endian). Example: READ_SSHORT temperature', 'READ_SSHORT ${1:var_name}', true),
+                    createSuggestion('READ_SSHORT_BE', monaco.languages.CompletionItemKind.Function, 'Reads a signed 16-bit integer (big-endian). Example: READ_SSHORT_BE temperature', 'READ_SSHORT_BE ${1:var_name}', true),
+                    createSuggestion('READ_SINT', monaco.languages.CompletionItemKind.Function, 'Reads a signed 32-bit integer (little-endian). Example: READ_SINT score', 'READ_SINT ${1:var_name}', true),
+                    createSuggestion('READ_SINT_BE', monaco.languages.CompletionItemKind.Function, 'Reads a signed 32-bit integer (big-endian). Example: READ_SINT_BE score', 'READ_SINT_BE ${1:var_name}', true),
+                    createSuggestion('READ_SHORT_SIGNED', monaco.languages.CompletionItemKind.Function, 'Same as READ_SSHORT (add _BE for big-endian). Example: READ_SHORT_SIGNED temperature', 'READ_SHORT_SIGNED ${1:var_name}', true),
+                    createSuggestion('READ_INT_SIGNED', monaco.languages.CompletionItemKind.Function, 'Same as READ_SINT (add _BE for big-endian). Example: READ_INT_SIGNED score', 'READ_INT_SIGNED ${1:var_name}', true),
+                    createSuggestion('READ_SLONG', monaco.languages.CompletionItemKind.Function, 'Reads a signed 64-bit integer (little-endian). Example: READ_SLONG offset', 'READ_SLONG ${1:var_name}', true),
+                    createSuggestion('READ_SLONG_BE', monaco.languages.CompletionItemKind.Function, 'Reads a signed 64-bit integer (big-endian). Example: READ_SLONG_BE offset', 'READ_SLONG_BE ${1:var_name}', true),
+                    createSuggestion('READ_FLOAT', monaco.languages.CompletionItemKind.Function, 'Reads a 32-bit float (little-endian). Example: READ_FLOAT tick_rate', 'READ_FLOAT ${1:var_name}', true),
+                    createSuggestion('READ_FLOAT_BE', monaco.languages.CompletionItemKind.Function, 'Reads a 32-bit float (big-endian). Example: READ_FLOAT_BE tick_rate', 'READ_FLOAT_BE ${1:var_name}', true),
+                    createSuggestion('READ_DOUBLE', monaco.languages.CompletionItemKind.Function, 'Reads a 64-bit float (little-endian). Example: READ_DOUBLE uptime', 'READ_DOUBLE ${1:var_name}', true),
+                    createSuggestion('READ_DOUBLE_BE', monaco.languages.CompletionItemKind.Function, 'Reads a 64-bit float (big-endian). Example: READ_DOUBLE_BE uptime', 'READ_DOUBLE_BE ${1:var_name}', true),
                     createSuggestion('READ_VARINT', monaco.languages.CompletionItemKind.Function, 'Reads a variable-length integer. Example: READ_VARINT packet_length', 'READ_VARINT ${1:var_name}', true),
                     createSuggestion('READ_STRING', monaco.languages.CompletionItemKind.Function, 'Reads a fixed-length string. Example: READ_STRING server_name 32', 'READ_STRING ${1:var_name} ${2:length}', true),
                     createSuggestion('READ_STRING_NULL', monaco.languages.CompletionItemKind.Function, 'Reads a null-terminated string. Example: READ_STRING_NULL server_name', 'READ_STRING_NULL ${1:var_name}', true),
-                    createSuggestion('SKIP_BYTES', monaco.languages.CompletionItemKind.Function, 'Skips the specified number of bytes. Example: SKIP_BYTES 4', 'SKIP_BYTES ${1:count}', true)
+                    createSuggestion('SKIP_BYTES', monaco.languages.CompletionItemKind.Function, 'Skips the specified number of bytes. Example: SKIP_BYTES 4', 'SKIP_BYTES ${1:count}', true),
+                    createSuggestion('DECOMPRESS', monaco.languages.CompletionItemKind.Function, 'Inflates the unread bytes for the following reads. Example: DECOMPRESS GZIP', 'DECOMPRESS ${1|ZLIB,GZIP|}', true),
+                    createSuggestion('SEEK_TO_OFFSET', monaco.languages.CompletionItemKind.Function, 'Moves the read position to an absolute offset. Example: SEEK_TO_OFFSET 47', 'SEEK_TO_OFFSET ${1:0}', true),
+                    createSuggestion('SEEK_FROM_END', monaco.languages.CompletionItemKind.Function, 'Moves the read position to N bytes before the end of the response. Example: SEEK_FROM_END 4', 'SEEK_FROM_END ${1:4}', true),
+                    createSuggestion('CURRENT_OFFSET', monaco.languages.CompletionItemKind.Function, 'Stores the current read position in a variable. Example: CURRENT_OFFSET pos', 'CURRENT_OFFSET ${1:var_name}', true),
+                    createSuggestion('READ_STRING_VARINT', monaco.languages.CompletionItemKind.Function, 'Reads a string prefixed with a VarInt length (Minecraft strings). Example: READ_STRING_VARINT json', 'READ_STRING_VARINT ${1:var_name}', true),
+                    createSuggestion('READ_STRING_PREFIXED', monaco.languages.CompletionItemKind.Function, 'Reads a string with a BYTE, SHORT, SHORT_BE, INT, INT_BE or VARINT length prefix. Example: READ_STRING_PREFIXED INT_BE map_name', 'READ_STRING_PREFIXED ${1:SHORT_BE} ${2:var_name}', true),
+                    createSuggestion('READ_STRING_UTF16_BE', monaco.languages.CompletionItemKind.Function, 'Reads a big-endian UTF-16 string of a character count or up to 0x0000 with NULL (READ_STRING_UTF16 / _LE for little-endian). Example: READ_STRING_UTF16_BE motd NULL', 'READ_STRING_UTF16_BE ${1:var_name} ${2:NULL}', true),
+                    createSuggestion('READ_BYTES', monaco.languages.CompletionItemKind.Function, 'Reads bytes into a lowercase hex string. Example: READ_BYTES token 16', 'READ_BYTES ${1:var_name} ${2:count}', true),
+                    createSuggestion('READ_REMAINING', monaco.languages.CompletionItemKind.Function, 'Reads the rest of the response into a lowercase hex string. Example: READ_REMAINING payload', 'READ_REMAINING ${1:var_name}', true)
                 );
                 
                 // Validation commands
                 suggestions.push(
                     createSuggestion('EXPECT_BYTE', monaco.languages.CompletionItemKind.Function, 'Validates that the next byte matches the expected value. Example: EXPECT_BYTE 0xFE', 'EXPECT_BYTE ${1:value}', true),
                     createSuggestion('EXPECT_MAGIC', monaco.languages.CompletionItemKind.Function, 'Validates that the next bytes match the expected magic bytes. Example: EXPECT_MAGIC "FEEDFACE"', 'EXPECT_MAGIC "${1:hex_string}"', true),
-                    createSuggestion('EXPECT_STATUS', monaco.languages.CompletionItemKind.Function, 'Validates HTTP response status code. Example: EXPECT_STATUS 200', 'EXPECT_STATUS ${1:200}', true),
-                    createSuggestion('EXPECT_HEADER', monaco.languages.CompletionItemKind.Function, 'Validates HTTP response header. Example: EXPECT_HEADER Content-Type application/json', 'EXPECT_HEADER ${1:key} ${2:value}', true)
+                    createSuggestion('EXPECT_VARINT', monaco.languages.CompletionItemKind.Function, 'Reads a VarInt and validates its value. Example: EXPECT_VARINT 0x00', 'EXPECT_VARINT ${1:value}', true),
+                    createSuggestion('EXPECT_INT', monaco.languages.CompletionItemKind.Function, 'Validates the next 4 bytes as a 32-bit integer (little-endian, EXPECT_INT_BE for big-endian). Example: EXPECT_INT 0xDEADBEEF', 'EXPECT_INT ${1:value}', true),
+                    createSuggestion('EXPECT_VAR', monaco.languages.CompletionItemKind.Function, 'Fails the check with an AssertionError unless the condition holds. Example: EXPECT_VAR echoed == challenge "Wrong echo"', 'EXPECT_VAR ${1:var_name} ${2:==} ${3:value} "${4:message}"', true),
+                    createSuggestion('EXPECT_STATUS', monaco.languages.CompletionItemKind.Function, 'Validates HTTP response status code, class or range. Example: EXPECT_STATUS 200, EXPECT_STATUS 2xx, EXPECT_STATUS 200-299', 'EXPECT_STATUS ${1:200}', true),
+                    createSuggestion('EXPECT_BODY_CONTAINS', monaco.languages.CompletionItemKind.Function, 'Validates that the HTTP response body contains the text. Example: EXPECT_BODY_CONTAINS "online"', 'EXPECT_BODY_CONTAINS "${1:text}"', true),
+                    createSuggestion('EXPECT_HEADER', monaco.languages.CompletionItemKind.Function, 'Validates HTTP response header, exactly or with CONTAINS. Example: EXPECT_HEADER Content-Type CONTAINS application/json', 'EXPECT_HEADER ${1:key} ${2:value}', true),
+                    createSuggestion('READ_HEADER', monaco.languages.CompletionItemKind.Function, 'Stores one HTTP response header in a variable. Example: READ_HEADER X-Players players', 'READ_HEADER ${1:key} ${2:var_name}', true),
+                    createSuggestion('NO_HEADER_VARS', monaco.languages.CompletionItemKind.Keyword, 'Stops storing every response header as a HEADER_<key> variable', 'NO_HEADER_VARS', true)
                 );
                 
                 // HTTP response parsing
@@ -283,7 +334,13 @@
                 suggestions.push(
                     createSuggestion('IF', monaco.languages.CompletionItemKind.Keyword, 'Conditional execution. Example: IF condition == 1:', 'IF ${1:condition}:', true),
                     createSuggestion('ELSE', monaco.languages.CompletionItemKind.Keyword, 'Else clause for IF statements', 'ELSE:', true),
-                    createSuggestion('FOR', monaco.languages.CompletionItemKind.Keyword, 'Loop over an array. Example: FOR item IN array:', 'FOR ${1:var_name} IN ${2:array_name}:', true)
+                    createSuggestion('FOR', monaco.languages.CompletionItemKind.Keyword, 'Loop over an array. Example: FOR item IN array:', 'FOR ${1:var_name} IN ${2:array_name}:', true),
+                    createSuggestion('RANGE', monaco.languages.CompletionItemKind.Keyword, 'Loop over the numbers start..end-1. Example: FOR i IN RANGE(0, count):', 'FOR ${1:i} IN RANGE(${2:0}, ${3:count}):', true),
+                    createSuggestion('WHILE', monaco.languages.CompletionItemKind.Keyword, 'Loop while a condition holds. Example: WHILE state != "done":', 'WHILE ${1:condition}:', true),
+                    createSuggestion('CONTINUE', monaco.languages.CompletionItemKind.Keyword, 'Skips to the next loop iteration', 'CONTINUE', true),
+                    createSuggestion('RETURN_EARLY', monaco.languages.CompletionItemKind.Keyword, 'Stops the current code block without an error', 'RETURN_EARLY', true),
+                    createSuggestion('FAIL', monaco.languages.CompletionItemKind.Keyword, 'Fails the check with an AssertionError. Example: FAIL "Server in maintenance"', 'FAIL "${1:message}"', true),
+                    createSuggestion('ASSERT', monaco.languages.CompletionItemKind.Keyword, 'Marks the server down with HealthCheckFailed unless the condition holds. Example: ASSERT max_players > 0 "No slots"', 'ASSERT ${1:condition} "${2:message}"', true)
                 );
                 
                 // Variable types
@@ -299,13 +356,33 @@
                 suggestions.push(
                     createSuggestion('SPLIT', monaco.languages.CompletionItemKind.Function, 'Splits a string by delimiter. Example: SPLIT(var_name, ",")', 'SPLIT(${1:var_name}, ${2:"delimiter"})', true),
                     createSuggestion('REPLACE', monaco.languages.CompletionItemKind.Function, 'Replaces all occurrences in a string. Example: REPLACE(var_name, "old", "new")', 'REPLACE(${1:var_name}, ${2:"search"}, ${3:"replace"})', true),
+                    createSuggestion('LEN', monaco.languages.CompletionItemKind.Function, 'Length of a string or array. Example: LEN(name)', 'LEN(${1:value})', true),
+                    createSuggestion('SUBSTRING', monaco.languages.CompletionItemKind.Function, 'Part of a string. Example: SUBSTRING(motd, 0, 32)', 'SUBSTRING(${1:value}, ${2:0}, ${3:length})', true),
+                    createSuggestion('UPPER', monaco.languages.CompletionItemKind.Function, 'Upper-case a string. Example: UPPER(map)', 'UPPER(${1:value})', true),
+                    createSuggestion('LOWER', monaco.languages.CompletionItemKind.Function, 'Lower-case a string. Example: LOWER(map)', 'LOWER(${1:value})', true),
+                    createSuggestion('TRIM', monaco.languages.CompletionItemKind.Function, 'Strip surrounding whitespace. Example: TRIM(motd)', 'TRIM(${1:value})', true),
+                    createSuggestion('CONCAT', monaco.languages.CompletionItemKind.Function, 'Join values into a string. Example: CONCAT(map, ":", players)', 'CONCAT(${1:a}, ${2:b})', true),
+                    createSuggestion('TO_INT', monaco.languages.CompletionItemKind.Function, 'Convert to an integer (decimal or 0x strings). Example: TO_INT(protocol)', 'TO_INT(${1:value})', true),
+                    createSuggestion('TO_FLOAT', monaco.languages.CompletionItemKind.Function, 'Convert to a float. Example: TO_FLOAT(tps)', 'TO_FLOAT(${1:value})', true),
+                    createSuggestion('TO_STRING', monaco.languages.CompletionItemKind.Function, 'Convert to a string. Example: TO_STRING(players)', 'TO_STRING(${1:value})', true),
+                    createSuggestion('HEX', monaco.languages.CompletionItemKind.Function, 'Upper-case hex string of an integer. Example: HEX(255) is "FF"', 'HEX(${1:value})', true),
+                    createSuggestion('CRC32', monaco.languages.CompletionItemKind.Function, 'CRC32 checksum as lower-case hex of the text, or of the bytes of a hex string with "HEX". Example: CRC32(token, "HEX")', 'CRC32(${1:value})', true),
+                    createSuggestion('MD5', monaco.languages.CompletionItemKind.Function, 'MD5 digest as lower-case hex of the text, or of the bytes of a hex string with "HEX". Example: MD5(token, "HEX")', 'MD5(${1:value})', true),
+                    createSuggestion('SHA1', monaco.languages.CompletionItemKind.Function, 'SHA-1 digest as lower-case hex of the text, or of the bytes of a hex string with "HEX". Example: SHA1(token, "HEX")', 'SHA1(${1:value})', true),
+                    createSuggestion('RANDOM_INT', monaco.languages.CompletionItemKind.Function, 'Random integer between min and max, inclusive. Example: RANDOM_INT(1, 1000)', 'RANDOM_INT(${1:min}, ${2:max})', true),
+                    createSuggestion('RANDOM_HEX', monaco.languages.CompletionItemKind.Function, 'Random bytes as lower-case hex. Example: RANDOM_HEX(4)', 'RANDOM_HEX(${1:4})', true),
+                    createSuggestion('TIMESTAMP', monaco.languages.CompletionItemKind.Function, 'Current Unix time in seconds. Example: TIMESTAMP()', 'TIMESTAMP()', true),
+                    createSuggestion('TIMESTAMP_MS', monaco.languages.CompletionItemKind.Function, 'Current Unix time in milliseconds. Example: TIMESTAMP_MS()', 'TIMESTAMP_MS()', true),
+                    createSuggestion('REGEX_MATCH', monaco.languages.CompletionItemKind.Function, 'True if a regular expression matches. Example: IF REGEX_MATCH(status, "^MOTD"):', 'REGEX_MATCH(${1:text}, "${2:pattern}")', true),
+                    createSuggestion('REGEX_EXTRACT', monaco.languages.CompletionItemKind.Function, 'Text captured by a regex group. Example: REGEX_EXTRACT(status, "(\\d+)/(\\d+)", 1)', 'REGEX_EXTRACT(${1:text}, "${2:pattern}", ${3:1})', true),
                     createSuggestion('JSON_OUTPUT', monaco.languages.CompletionItemKind.Function, 'Parses a string variable as JSON. Example: JSON_OUTPUT JSON_PAYLOAD', 'JSON_OUTPUT ${1:var_name}', true),
-                    createSuggestion('RETURN', monaco.languages.CompletionItemKind.Function, 'Formats the expression into Prometheus metric labels. Example: RETURN "server=HOST, protocol=1"', 'RETURN "${1:expression}"', true)
+                    createSuggestion('RETURN', monaco.languages.CompletionItemKind.Function, 'Formats the expression into Prometheus metric labels. Example: RETURN "server=HOST, protocol=1"', 'RETURN "${1:expression}"', true),
+                    createSuggestion('RETURN IF', monaco.languages.CompletionItemKind.Function, 'Chooses between two RETURN expressions. Example: RETURN IF players > 0 THEN "status=online" ELSE "status=offline"', 'RETURN IF ${1:condition} THEN "${2:expression}" ELSE "${3:expression}"', true)
                 );
                 
                 // Output blocks
                 suggestions.push(
-                    createSuggestion('OUTPUT_SUCCESS', monaco.languages.CompletionItemKind.Keyword, 'Marks output block that executes on success', 'OUTPUT_SUCCESS\n  ${1:// commands}\nOUTPUT_END', true),
+                    createSuggestion('OUTPUT_SUCCESS', monaco.languages.CompletionItemKind.Keyword, 'Marks output block that executes on success. Add a name (OUTPUT_SUCCESS map_a) to export it as a separate series', 'OUTPUT_SUCCESS\n  ${1:// commands}\nOUTPUT_END', true),
                     createSuggestion('OUTPUT_ERROR', monaco.languages.CompletionItemKind.Keyword, 'Marks output block that executes on error', 'OUTPUT_ERROR\n  ${1:// commands}\nOUTPUT_END', true)
                 );
                 
@@ -1,15 +1,67 @@
-use crate::{gameserver_check, models::*, AppState};
+use crate::{gameserver_check, history::EntityKind, models::*, AppState};
 use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
+    extract::{ConnectInfo, Extension, Multipart, Path, Query},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Json},
 };
+use std::net::SocketAddr;
 use std::sync::Arc;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
-pub async fn list_isps(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+/// Query parameters accepted by the list endpoints
+#[derive(Debug, Default, Deserialize)]
+pub struct ListParams {
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+    pub search: Option<String>,
+    pub protocol: Option<String>,
+}
+
+impl ListParams {
+    /// Case-insensitive substring match against any of the given fields (always true without `search`)
+    fn matches_search(&self, fields: &[&str]) -> bool {
+        match self.search.as_deref().map(str::trim) {
+            Some(search) if !search.is_empty() => {
+                let search = search.to_lowercase();
+                fields.iter().any(|field| field.to_lowercase().contains(&search))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Slice an already sorted and filtered list into the requested page.
+/// Without `per_page` the whole list is returned as a single page.
+fn paginate<T: Serialize>(items: Vec<T>, params: &ListParams) -> serde_json::Value {
+    let total = items.len();
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params.per_page.unwrap_or(total).max(1);
+    let data: Vec<T> = items
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+    serde_json::json!({
+        "data": data,
+        "total": total,
+        "page": page,
+        "per_page": per_page,
+    })
+}
+
+pub async fn list_isps(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ListParams>,
+) -> impl IntoResponse {
     match list_isps_internal(&state.store).await {
-        Ok(isps) => (StatusCode::OK, Json(isps)).into_response(),
+        Ok(isps) => {
+            let isps: Vec<Isp> = isps
+                .into_iter()
+                .filter(|isp| params.matches_search(&[&isp.name, &isp.ip]))
+                .collect();
+            (StatusCode::OK, Json(paginate(isps, &params))).into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({"error": e.to_string()})),
@@ -27,6 +79,7 @@ pub async fn list_isps_internal(store: &crate::db::JsonStore) -> Result<Vec<Isp>
 
 pub async fn create_isp(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Json(create_isp): Json<CreateIsp>,
 ) -> impl IntoResponse {
     // Basic validation
@@ -60,8 +113,12 @@ pub async fn create_isp(
             id,
             name: name.clone(),
             ip: ip.clone(),
+            check_method: create_isp.check_method,
+            check_port: create_isp.check_port,
+            is_primary: create_isp.is_primary,
         };
         let isp_clone = isp.clone();
+        db.record_audit(AuditEntry::new("create", "isp", id, None, serde_json::to_value(&isp).ok(), &client));
         db.isps.push(isp);
         Ok(isp_clone)
     }).await;
@@ -86,20 +143,102 @@ pub async fn create_isp(
     }
 }
 
+/// Bulk-add ISPs from an uploaded text file with one `name,ip` or bare `ip` per line.
+/// Blank lines and `#` comments are ignored; addresses already present (or repeated in the file) are skipped.
+pub async fn import_isps(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let content = match read_file_field(&mut multipart).await {
+        Ok(content) => content,
+        Err(response) => return response,
+    };
+    let Ok(text) = std::str::from_utf8(&content) else {
+        return error_response(StatusCode::BAD_REQUEST, "File is not valid UTF-8 text".to_string());
+    };
+
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, ip) = match line.split_once(',') {
+            Some((name, ip)) => (name.trim(), ip.trim()),
+            None => (line, line),
+        };
+        match ip.parse::<std::net::IpAddr>() {
+            Ok(addr) => {
+                let name = if name.is_empty() { addr.to_string() } else { name.to_string() };
+                entries.push((name, addr));
+            }
+            Err(_) => errors.push(format!("line {}: invalid IP '{}'", idx + 1, ip)),
+        }
+    }
+
+    let result = state.store.write(|db| {
+        let mut imported = 0;
+        let mut skipped_duplicates = 0;
+        for (name, addr) in &entries {
+            if db.isps.iter().any(|isp| isp.ip.trim().parse::<std::net::IpAddr>().ok() == Some(*addr)) {
+                skipped_duplicates += 1;
+                continue;
+            }
+            let id = db.get_next_id();
+            let isp = Isp {
+                id,
+                name: name.clone(),
+                ip: addr.to_string(),
+                check_method: IspCheckMethod::default(),
+                check_port: default_isp_check_port(),
+                is_primary: false,
+            };
+            db.record_audit(AuditEntry::new("create", "isp", id, None, serde_json::to_value(&isp).ok(), &client));
+            db.isps.push(isp);
+            imported += 1;
+        }
+        Ok((imported, skipped_duplicates))
+    }).await;
+
+    match result {
+        Ok((imported, skipped_duplicates)) => {
+            crate::out::info(
+                "api",
+                &format!("Imported {} ISPs ({} duplicates, {} invalid)", imported, skipped_duplicates, errors.len()),
+            );
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "imported": imported,
+                    "skipped_duplicates": skipped_duplicates,
+                    "invalid": errors.len(),
+                    "errors": errors,
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
 pub async fn delete_isp(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
     match state.store.write(|db| {
-        let initial_len = db.isps.len();
-        db.isps.retain(|isp| isp.id != id);
-        if db.isps.len() < initial_len {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("ISP not found"))
-        }
+        let Some(index) = db.isps.iter().position(|isp| isp.id == id) else {
+            return Err(anyhow::anyhow!("ISP not found"));
+        };
+        let removed = db.isps.remove(index);
+        db.record_audit(AuditEntry::new("delete", "isp", id, serde_json::to_value(&removed).ok(), None, &client));
+        db.remove_maintenance_windows(EntityKind::Isp, id);
+        Ok(())
     }).await {
         Ok(_) => {
+            state.history.remove(EntityKind::Isp, id);
             (StatusCode::NO_CONTENT, Json(serde_json::json!({"success": true}))).into_response()
         }
         Err(e) => {
@@ -117,9 +256,19 @@ pub async fn delete_isp(
     }
 }
 
-pub async fn list_websites(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+pub async fn list_websites(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ListParams>,
+) -> impl IntoResponse {
     match list_websites_internal(&state.store).await {
-        Ok(websites) => (StatusCode::OK, Json(websites)).into_response(),
+        Ok(websites) => {
+            // Websites have no name, so search only matches the URL
+            let websites: Vec<Website> = websites
+                .into_iter()
+                .filter(|website| params.matches_search(&[&website.url]))
+                .collect();
+            (StatusCode::OK, Json(paginate(websites, &params))).into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({"error": e.to_string()})),
@@ -137,6 +286,7 @@ pub async fn list_websites_internal(store: &crate::db::JsonStore) -> Result<Vec<
 
 pub async fn create_website(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Json(create_website): Json<CreateWebsite>,
 ) -> impl IntoResponse {
     // Basic validation
@@ -151,6 +301,7 @@ pub async fn create_website(
     let url = create_website.url.clone();
     let direct_connect = create_website.direct_connect;
     let direct_connect_url = create_website.direct_connect_url.clone();
+    let metric_label = create_website.metric_label.clone();
 
     let result = state.store.write(|db| {
         // Check for duplicate URL
@@ -164,8 +315,10 @@ pub async fn create_website(
             url: url.clone(),
             direct_connect,
             direct_connect_url: direct_connect_url.clone(),
+            metric_label: metric_label.clone(),
         };
         let website_clone = website.clone();
+        db.record_audit(AuditEntry::new("create", "website", id, None, serde_json::to_value(&website).ok(), &client));
         db.websites.push(website);
         Ok(website_clone)
     }).await;
@@ -192,18 +345,20 @@ pub async fn create_website(
 
 pub async fn delete_website(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
     match state.store.write(|db| {
-        let initial_len = db.websites.len();
-        db.websites.retain(|website| website.id != id);
-        if db.websites.len() < initial_len {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Website not found"))
-        }
+        let Some(index) = db.websites.iter().position(|website| website.id == id) else {
+            return Err(anyhow::anyhow!("Website not found"));
+        };
+        let removed = db.websites.remove(index);
+        db.record_audit(AuditEntry::new("delete", "website", id, serde_json::to_value(&removed).ok(), None, &client));
+        db.remove_maintenance_windows(EntityKind::Website, id);
+        Ok(())
     }).await {
         Ok(_) => {
+            state.history.remove(EntityKind::Website, id);
             (StatusCode::NO_CONTENT, Json(serde_json::json!({"success": true}))).into_response()
         }
         Err(e) => {
@@ -221,9 +376,20 @@ pub async fn delete_website(
     }
 }
 
-pub async fn list_game_servers(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+pub async fn list_game_servers(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ListParams>,
+) -> impl IntoResponse {
     match list_game_servers_internal(&state.store).await {
-        Ok(game_servers) => (StatusCode::OK, Json(game_servers)).into_response(),
+        Ok(game_servers) => {
+            let protocol = params.protocol.as_deref().map(str::trim).filter(|p| !p.is_empty());
+            let game_servers: Vec<GameServer> = game_servers
+                .into_iter()
+                .filter(|server| params.matches_search(&[&server.name, &server.address]))
+                .filter(|server| protocol.is_none_or(|p| server.protocol.as_str().eq_ignore_ascii_case(p)))
+                .collect();
+            (StatusCode::OK, Json(paginate(game_servers, &params))).into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({"error": e.to_string()})),
@@ -239,8 +405,23 @@ pub async fn list_game_servers_internal(store: &crate::db::JsonStore) -> Result<
     Ok(game_servers)
 }
 
+/// Snippet texts by name, for the `INCLUDE`s of the scripts checked by `/metrics`
+pub async fn snippet_map_internal(store: &crate::db::JsonStore) -> Result<crate::packet_parser::Snippets> {
+    Ok(store.read().await?.snippet_map())
+}
+
+/// `?strict=` of `POST /api/gameservers`
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateGameServerParams {
+    /// Reject scripts with error-severity lint diagnostics
+    #[serde(default)]
+    pub strict: bool,
+}
+
 pub async fn create_game_server(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(params): Query<CreateGameServerParams>,
     Json(create_game_server): Json<CreateGameServer>,
 ) -> impl IntoResponse {
     if create_game_server.name.trim().is_empty() {
@@ -273,6 +454,76 @@ pub async fn create_game_server(
     let protocol = create_game_server.protocol.clone();
     let timeout_ms = create_game_server.timeout_ms;
     let pseudo_code = create_game_server.pseudo_code.clone();
+    let insecure_skip_verify = create_game_server.insecure_skip_verify;
+    let sni = create_game_server.sni.clone().filter(|sni| !sni.trim().is_empty());
+
+    // Reject scripts whose packet/HTTP sections don't match the selected protocol
+    let candidate = GameServer {
+        id: 0,
+        name: name.clone(),
+        address: address.clone(),
+        port,
+        protocol: protocol.clone(),
+        timeout_ms,
+        pseudo_code: pseudo_code.clone(),
+        insecure_skip_verify,
+        sni: sni.clone(),
+        danger_accept_invalid_certs: create_game_server.danger_accept_invalid_certs,
+        alpn: create_game_server.alpn.clone(),
+        retries: create_game_server.retries,
+        retry_delay_ms: create_game_server.retry_delay_ms,
+        connect_timeout_ms: create_game_server.connect_timeout_ms,
+        read_timeout_ms: create_game_server.read_timeout_ms,
+        max_response_bytes: create_game_server.max_response_bytes,
+        code_timeout_ms: create_game_server.code_timeout_ms,
+        max_loop_iterations: create_game_server.max_loop_iterations,
+        metric_prefix: create_game_server.metric_prefix.clone(),
+        expose_raw_response: create_game_server.expose_raw_response,
+        shadow_warning_enabled: create_game_server.shadow_warning_enabled,
+        variables: create_game_server.variables.clone(),
+    };
+    let snippets = match state.store.read().await {
+        Ok(db) => db.snippet_map(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    if let Err(e) = gameserver_check::validate_script_protocol(&candidate, &snippets) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response();
+    }
+    if params.strict {
+        let diagnostics = gameserver_check::script_lint_warnings(&candidate, &snippets);
+        if diagnostics.iter().any(|d| d.severity == crate::linter::Severity::Error) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "Script has lint errors", "diagnostics": diagnostics})),
+            )
+                .into_response();
+        }
+    }
+    if let Some(metrics) = gameserver_check::script_metrics(&candidate, &snippets) {
+        if metrics.estimated_complexity == crate::formatter::Complexity::High {
+            crate::out::warning(
+                "api",
+                &format!(
+                    "Game server '{}' has a high-complexity script ({} pairs, loop depth {}, {} commands) and may time out",
+                    name, metrics.pair_count, metrics.max_loop_depth, metrics.total_commands
+                ),
+            );
+        }
+    }
+
+    if create_game_server.expose_raw_response {
+        crate::out::warning(
+            "api",
+            &format!(
+                "Game server '{}' exposes its raw response in metrics; every distinct response becomes a new series",
+                name
+            ),
+        );
+    }
 
     let result = state.store.write(|db| {
         // Check for duplicate name (case-insensitive) and replace if exists
@@ -280,16 +531,17 @@ pub async fn create_game_server(
             server.name.trim().eq_ignore_ascii_case(name.trim())
         });
         
-        let (id, was_replaced) = if let Some(index) = existing_index {
+        let (id, replaced) = if let Some(index) = existing_index {
             // Reuse the existing ID to preserve references
             let existing_id = db.game_servers[index].id;
             // Remove the old game server
-            db.game_servers.remove(index);
-            (existing_id, true)
+            let old = db.game_servers.remove(index);
+            (existing_id, Some(old))
         } else {
             // Create a new ID for a new game server
-            (db.get_next_id(), false)
+            (db.get_next_id(), None)
         };
+        let was_replaced = replaced.is_some();
 
         let game_server = GameServer {
             id,
@@ -299,8 +551,26 @@ pub async fn create_game_server(
             protocol: protocol.clone(),
             timeout_ms,
             pseudo_code: pseudo_code.clone(),
+            insecure_skip_verify,
+            sni: sni.clone(),
+            danger_accept_invalid_certs: create_game_server.danger_accept_invalid_certs,
+            alpn: create_game_server.alpn.clone(),
+            retries: create_game_server.retries,
+            retry_delay_ms: create_game_server.retry_delay_ms,
+            connect_timeout_ms: create_game_server.connect_timeout_ms,
+            read_timeout_ms: create_game_server.read_timeout_ms,
+            max_response_bytes: create_game_server.max_response_bytes,
+            code_timeout_ms: create_game_server.code_timeout_ms,
+            max_loop_iterations: create_game_server.max_loop_iterations,
+            metric_prefix: create_game_server.metric_prefix.clone(),
+            expose_raw_response: create_game_server.expose_raw_response,
+            shadow_warning_enabled: create_game_server.shadow_warning_enabled,
+            variables: create_game_server.variables.clone(),
         };
         let game_server_clone = game_server.clone();
+        let action = if was_replaced { "update" } else { "create" };
+        let before = replaced.and_then(|old| serde_json::to_value(&old).ok());
+        db.record_audit(AuditEntry::new(action, "gameserver", id, before, serde_json::to_value(&game_server).ok(), &client));
         db.game_servers.push(game_server);
         Ok((game_server_clone, was_replaced))
     }).await;
@@ -326,18 +596,20 @@ pub async fn create_game_server(
 
 pub async fn delete_game_server(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
     match state.store.write(|db| {
-        let initial_len = db.game_servers.len();
-        db.game_servers.retain(|server| server.id != id);
-        if db.game_servers.len() < initial_len {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Game server not found"))
-        }
+        let Some(index) = db.game_servers.iter().position(|server| server.id == id) else {
+            return Err(anyhow::anyhow!("Game server not found"));
+        };
+        let removed = db.game_servers.remove(index);
+        db.record_audit(AuditEntry::new("delete", "gameserver", id, serde_json::to_value(&removed).ok(), None, &client));
+        db.remove_maintenance_windows(EntityKind::GameServer, id);
+        Ok(())
     }).await {
         Ok(_) => {
+            state.history.remove(EntityKind::GameServer, id);
             (StatusCode::NO_CONTENT, Json(serde_json::json!({"success": true}))).into_response()
         }
         Err(e) => {
@@ -355,12 +627,109 @@ pub async fn delete_game_server(
     }
 }
 
+/// Optional body of `POST /api/gameservers/:id/clone`
+#[derive(Debug, Default, Deserialize)]
+pub struct CloneGameServer {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// Copy a game server under a new ID. Without a `name` the copy is called "<name> (copy)",
+/// or "<name> (copy 2)" and so on when that is taken.
+pub async fn clone_game_server(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    body: Option<Json<CloneGameServer>>,
+) -> impl IntoResponse {
+    let overrides = body.map(|Json(body)| body).unwrap_or_default();
+    let name = overrides.name.map(|name| name.trim().to_string());
+    let address = overrides.address.map(|address| address.trim().to_string());
+    if name.as_deref() == Some("") || address.as_deref() == Some("") {
+        return error_response(StatusCode::BAD_REQUEST, "Name and address cannot be empty".to_string());
+    }
+
+    let result = state.store.write(|db| {
+        let source = db.game_servers.iter().find(|server| server.id == id).cloned()
+            .ok_or_else(|| anyhow::anyhow!("Game server not found"))?;
+        let name_taken = |candidate: &str| {
+            db.game_servers.iter().any(|server| server.name.trim().eq_ignore_ascii_case(candidate))
+        };
+        let name = match &name {
+            // Saving under an existing name replaces that server, so a clone must not reuse one
+            Some(name) if name_taken(name) => anyhow::bail!("A game server named '{}' already exists", name),
+            Some(name) => name.clone(),
+            None => (1..)
+                .map(|n| if n == 1 { format!("{} (copy)", source.name) } else { format!("{} (copy {})", source.name, n) })
+                .find(|candidate| !name_taken(candidate))
+                .unwrap_or_default(),
+        };
+
+        let copy = GameServer {
+            id: db.get_next_id(),
+            name,
+            address: address.clone().unwrap_or(source.address.clone()),
+            port: overrides.port.unwrap_or(source.port),
+            ..source
+        };
+        db.record_audit(AuditEntry::new("create", "gameserver", copy.id, None, serde_json::to_value(&copy).ok(), &client));
+        db.game_servers.push(copy.clone());
+        Ok(copy)
+    }).await;
+
+    match result {
+        Ok(copy) => (StatusCode::CREATED, Json(copy)).into_response(),
+        Err(e) if e.to_string().contains("not found") => error_response(StatusCode::NOT_FOUND, e.to_string()),
+        Err(e) if e.to_string().contains("already exists") => error_response(StatusCode::CONFLICT, e.to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Optional body of `POST /api/gameservers/:id/test`
+#[derive(Debug, Default, Deserialize)]
+pub struct TestGameServerRequest {
+    /// Merged over the server's `variables` for this test only
+    #[serde(default)]
+    pub variables: std::collections::HashMap<String, String>,
+}
+
+/// `?format=` of `POST /api/gameservers/:id/test`
+#[derive(Debug, Default, Deserialize)]
+pub struct TestFormatParams {
+    #[serde(default)]
+    pub format: TestFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestFormat {
+    /// Only `success`, `response_time_ms` and `warnings`, for CI jobs that want pass/fail
+    Summary,
+    /// The whole `GameServerTestResult`
+    #[default]
+    Full,
+    /// The server's lines of `/metrics`, built from this test's result
+    Prometheus,
+}
+
 pub async fn test_game_server(
     Extension(state): Extension<Arc<AppState>>,
     Path(id): Path<i64>,
+    Query(params): Query<TestFormatParams>,
+    body: axum::body::Bytes,
 ) -> impl IntoResponse {
-    let server = match state.store.read().await {
-        Ok(db) => db.game_servers.into_iter().find(|server| server.id == id),
+    let overrides: TestGameServerRequest = if body.iter().all(u8::is_ascii_whitespace) {
+        TestGameServerRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(overrides) => overrides,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid test overrides: {}", e)),
+        }
+    };
+
+    let (server, snippets) = match state.store.read().await {
+        Ok(db) => (db.game_servers.iter().find(|server| server.id == id).cloned(), db.snippet_map()),
         Err(e) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -370,7 +739,7 @@ pub async fn test_game_server(
         }
     };
 
-    let server = match server {
+    let mut server = match server {
         Some(server) => server,
         None => {
             return (
@@ -380,31 +749,46 @@ pub async fn test_game_server(
                 .into_response();
         }
     };
+    server.variables.extend(overrides.variables);
 
-    let result = gameserver_check::check_game_server(&server).await;
-    (StatusCode::OK, Json(result)).into_response()
+    let result = gameserver_check::check_game_server(&server, &snippets).await;
+    match params.format {
+        TestFormat::Full => (StatusCode::OK, Json(result)).into_response(),
+        TestFormat::Summary => Json(serde_json::json!({
+            "success": result.success,
+            "response_time_ms": result.response_time_ms,
+            "warnings": result.warnings,
+        }))
+        .into_response(),
+        TestFormat::Prometheus => {
+            // A server in a maintenance window gets the same lines as in `/metrics`, which doesn't check it
+            let maintenance = match active_maintenance_internal(&state.store).await {
+                Ok(maintenance) => maintenance,
+                Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            };
+            let mut results = std::collections::HashMap::new();
+            if !maintenance.contains(&(EntityKind::GameServer, server.id)) {
+                results.insert(server.id, (server.name.clone(), server.address.clone(), server.port, result));
+            }
+            let mut metrics = String::new();
+            crate::push_game_server_metrics(&mut metrics, std::slice::from_ref(&server), &results, &maintenance);
+            (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, crate::METRICS_CONTENT_TYPE)], metrics).into_response()
+        }
+    }
 }
 
-pub async fn test_game_server_config(
-    Json(create_game_server): Json<CreateGameServer>,
-) -> impl IntoResponse {
+/// Unsaved game server for the test and build endpoints; the error, answered with a 400,
+/// names what is missing
+fn preview_game_server(create_game_server: &CreateGameServer) -> std::result::Result<GameServer, &'static str> {
     if create_game_server.address.trim().is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Address cannot be empty"})),
-        )
-            .into_response();
+        return Err("Address cannot be empty");
     }
 
     if create_game_server.pseudo_code.trim().is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Pseudo code is required"})),
-        )
-            .into_response();
+        return Err("Pseudo code is required");
     }
 
-    let server = GameServer {
+    Ok(GameServer {
         id: 0,
         name: if create_game_server.name.trim().is_empty() {
             "Preview Server".to_string()
@@ -416,9 +800,1076 @@ pub async fn test_game_server_config(
         protocol: create_game_server.protocol.clone(),
         timeout_ms: create_game_server.timeout_ms,
         pseudo_code: create_game_server.pseudo_code.clone(),
+        insecure_skip_verify: create_game_server.insecure_skip_verify,
+        sni: create_game_server.sni.clone().filter(|sni| !sni.trim().is_empty()),
+        danger_accept_invalid_certs: create_game_server.danger_accept_invalid_certs,
+        alpn: create_game_server.alpn.clone(),
+        retries: create_game_server.retries,
+        retry_delay_ms: create_game_server.retry_delay_ms,
+        connect_timeout_ms: create_game_server.connect_timeout_ms,
+        read_timeout_ms: create_game_server.read_timeout_ms,
+        max_response_bytes: create_game_server.max_response_bytes,
+        code_timeout_ms: create_game_server.code_timeout_ms,
+        max_loop_iterations: create_game_server.max_loop_iterations,
+        metric_prefix: create_game_server.metric_prefix.clone(),
+        expose_raw_response: create_game_server.expose_raw_response,
+        shadow_warning_enabled: create_game_server.shadow_warning_enabled,
+        variables: create_game_server.variables.clone(),
+    })
+}
+
+
+pub async fn test_game_server_config(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(create_game_server): Json<CreateGameServer>,
+) -> impl IntoResponse {
+    let server = match preview_game_server(&create_game_server) {
+        Ok(server) => server,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message.to_string()),
     };
 
-    let result = gameserver_check::check_game_server(&server).await;
+    let snippets = match state.store.read().await {
+        Ok(db) => db.snippet_map(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let result = gameserver_check::check_game_server(&server, &snippets).await;
+    let lint_warnings = gameserver_check::script_lint_warnings(&server, &snippets);
 
-    (StatusCode::OK, Json(result)).into_response()
+    (StatusCode::OK, Json(TestConfigResponse { result, lint_warnings })).into_response()
+}
+
+/// `POST /api/gameservers/build`: the bytes (or HTTP requests) the script would send, without sending them
+pub async fn build_game_server_config(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(create_game_server): Json<CreateGameServer>,
+) -> impl IntoResponse {
+    let server = match preview_game_server(&create_game_server) {
+        Ok(server) => server,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message.to_string()),
+    };
+    let snippets = match state.store.read().await {
+        Ok(db) => db.snippet_map(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let preview = gameserver_check::build_game_server_packets(&server, &snippets).await;
+    (StatusCode::OK, Json(preview)).into_response()
+}
+
+/// Check result of `POST /api/gameservers/test`, with the linter's warnings for the script
+#[derive(Serialize)]
+struct TestConfigResponse {
+    #[serde(flatten)]
+    result: GameServerTestResult,
+    lint_warnings: Vec<crate::linter::LintWarning>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UptimeParams {
+    pub window: Option<String>,
+}
+
+pub async fn isp_uptime(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UptimeParams>,
+) -> impl IntoResponse {
+    let exists = match state.store.read().await {
+        Ok(db) => db.isps.iter().any(|isp| isp.id == id),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    uptime_response(&state, EntityKind::Isp, id, exists, params)
+}
+
+pub async fn website_uptime(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UptimeParams>,
+) -> impl IntoResponse {
+    let exists = match state.store.read().await {
+        Ok(db) => db.websites.iter().any(|website| website.id == id),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    uptime_response(&state, EntityKind::Website, id, exists, params)
+}
+
+pub async fn game_server_uptime(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(params): Query<UptimeParams>,
+) -> impl IntoResponse {
+    let exists = match state.store.read().await {
+        Ok(db) => db.game_servers.iter().any(|server| server.id == id),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    uptime_response(&state, EntityKind::GameServer, id, exists, params)
+}
+
+fn uptime_response(
+    state: &AppState,
+    kind: EntityKind,
+    id: i64,
+    exists: bool,
+    params: UptimeParams,
+) -> axum::response::Response {
+    if !exists {
+        let message = match kind {
+            EntityKind::Isp => "ISP not found",
+            EntityKind::Website => "Website not found",
+            EntityKind::GameServer => "Game server not found",
+        };
+        return error_response(StatusCode::NOT_FOUND, message.to_string());
+    }
+    let window = params.window.unwrap_or_else(|| "1h".to_string());
+    match state.history.uptime(kind, id, &window) {
+        Ok(stats) => (StatusCode::OK, Json(stats)).into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+fn error_response(status: StatusCode, message: String) -> axum::response::Response {
+    (status, Json(serde_json::json!({"error": message}))).into_response()
+}
+
+/// Same response as the request body limit layers in main.rs
+fn body_too_large() -> axum::response::Response {
+    error_response(StatusCode::PAYLOAD_TOO_LARGE, "request body too large".to_string())
+}
+
+/// Query parameters of `GET /api/audit`
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditParams {
+    pub limit: Option<usize>,
+    pub page: Option<usize>,
+    pub entity_type: Option<String>,
+}
+
+/// Audit log entries, newest first, `limit` (default 100) per page
+pub async fn list_audit(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<AuditParams>,
+) -> impl IntoResponse {
+    let db = match state.store.read().await {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let entity_type = params.entity_type.as_deref().map(str::trim).filter(|t| !t.is_empty());
+    let entries: Vec<AuditEntry> = db
+        .audit_log
+        .into_iter()
+        .rev()
+        .filter(|entry| entity_type.is_none_or(|t| entry.entity_type.eq_ignore_ascii_case(t)))
+        .collect();
+    let list_params = ListParams {
+        page: params.page,
+        per_page: Some(params.limit.unwrap_or(100)),
+        ..Default::default()
+    };
+    (StatusCode::OK, Json(paginate(entries, &list_params))).into_response()
+}
+
+/// Query parameters of `GET /api/maintenance`
+#[derive(Debug, Default, Deserialize)]
+pub struct MaintenanceParams {
+    pub entity_type: Option<EntityKind>,
+    /// Only windows in effect right now
+    pub active: Option<bool>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+pub async fn list_maintenance_windows(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<MaintenanceParams>,
+) -> impl IntoResponse {
+    let db = match state.store.read().await {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let now = unix_now();
+    let mut windows: Vec<MaintenanceWindow> = db
+        .maintenance_windows
+        .into_iter()
+        .filter(|window| params.entity_type.is_none_or(|kind| window.entity_type == kind))
+        .filter(|window| params.active.is_none_or(|active| window.is_active_at(now) == active))
+        .collect();
+    windows.sort_by_key(|window| window.id);
+    let list_params = ListParams {
+        page: params.page,
+        per_page: params.per_page,
+        ..Default::default()
+    };
+    (StatusCode::OK, Json(paginate(windows, &list_params))).into_response()
+}
+
+/// Entities in a maintenance window right now; these are not checked by `/metrics`
+pub async fn active_maintenance_internal(
+    store: &crate::db::JsonStore,
+) -> Result<std::collections::HashSet<(EntityKind, i64)>> {
+    Ok(store.read().await?.active_maintenance(unix_now()))
+}
+
+/// Maintenance window from a request body, with its timestamps checked
+fn maintenance_window_from_request(id: i64, request: CreateMaintenanceWindow) -> Result<MaintenanceWindow, String> {
+    let window = MaintenanceWindow {
+        id,
+        entity_type: request.entity_type,
+        entity_id: request.entity_id,
+        start_rfc3339: request.start_rfc3339.trim().to_string(),
+        end_rfc3339: request.end_rfc3339.trim().to_string(),
+        reason: request.reason.trim().to_string(),
+    };
+    let start = parse_rfc3339(&window.start_rfc3339)
+        .ok_or_else(|| format!("Invalid start_rfc3339 '{}'", window.start_rfc3339))?;
+    let end = parse_rfc3339(&window.end_rfc3339)
+        .ok_or_else(|| format!("Invalid end_rfc3339 '{}'", window.end_rfc3339))?;
+    if end <= start {
+        return Err("end_rfc3339 must be after start_rfc3339".to_string());
+    }
+    Ok(window)
+}
+
+/// Reject a window whose entity doesn't exist or that overlaps another window of the same entity
+/// Why a maintenance window can't be created, updated or deleted
+#[derive(Debug)]
+enum MaintenanceError {
+    NotFound,
+    EntityMissing { kind: EntityKind, id: i64 },
+    Overlaps { existing: i64, kind: EntityKind },
+}
+
+impl std::fmt::Display for MaintenanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaintenanceError::NotFound => write!(f, "Maintenance window not found"),
+            MaintenanceError::EntityMissing { kind, id } => write!(f, "{} {} does not exist", kind.as_str(), id),
+            MaintenanceError::Overlaps { existing, kind } => {
+                write!(f, "Window overlaps maintenance window {} of the same {}", existing, kind.as_str())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MaintenanceError {}
+
+fn check_maintenance_window(db: &crate::db::Database, window: &MaintenanceWindow) -> std::result::Result<(), MaintenanceError> {
+    if !db.entity_exists(window.entity_type, window.entity_id) {
+        return Err(MaintenanceError::EntityMissing { kind: window.entity_type, id: window.entity_id });
+    }
+    if let Some(existing) = db
+        .maintenance_windows
+        .iter()
+        .find(|other| other.id != window.id && other.overlaps(window))
+    {
+        return Err(MaintenanceError::Overlaps { existing: existing.id, kind: window.entity_type });
+    }
+    Ok(())
+}
+
+fn maintenance_error_response(e: anyhow::Error) -> axum::response::Response {
+    let status = match e.downcast_ref::<MaintenanceError>() {
+        Some(MaintenanceError::Overlaps { .. }) => StatusCode::CONFLICT,
+        Some(MaintenanceError::EntityMissing { .. }) => StatusCode::BAD_REQUEST,
+        Some(MaintenanceError::NotFound) => StatusCode::NOT_FOUND,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error_response(status, e.to_string())
+}
+
+pub async fn create_maintenance_window(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<CreateMaintenanceWindow>,
+) -> impl IntoResponse {
+    let window = match maintenance_window_from_request(0, request) {
+        Ok(window) => window,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+    };
+
+    let result = state.store.write(|db| {
+        check_maintenance_window(db, &window)?;
+        let mut window = window.clone();
+        window.id = db.get_next_id();
+        db.record_audit(AuditEntry::new("create", "maintenance", window.id, None, serde_json::to_value(&window).ok(), &client));
+        db.maintenance_windows.push(window.clone());
+        Ok(window)
+    }).await;
+
+    match result {
+        Ok(window) => (StatusCode::CREATED, Json(window)).into_response(),
+        Err(e) => maintenance_error_response(e),
+    }
+}
+
+pub async fn update_maintenance_window(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    Json(request): Json<CreateMaintenanceWindow>,
+) -> impl IntoResponse {
+    let window = match maintenance_window_from_request(id, request) {
+        Ok(window) => window,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+    };
+
+    let result = state.store.write(|db| {
+        let Some(index) = db.maintenance_windows.iter().position(|existing| existing.id == id) else {
+            return Err(MaintenanceError::NotFound.into());
+        };
+        check_maintenance_window(db, &window)?;
+        let before = std::mem::replace(&mut db.maintenance_windows[index], window.clone());
+        db.record_audit(AuditEntry::new(
+            "update",
+            "maintenance",
+            id,
+            serde_json::to_value(&before).ok(),
+            serde_json::to_value(&window).ok(),
+            &client,
+        ));
+        Ok(())
+    }).await;
+
+    match result {
+        Ok(()) => (StatusCode::OK, Json(window)).into_response(),
+        Err(e) => maintenance_error_response(e),
+    }
+}
+
+pub async fn delete_maintenance_window(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match state.store.write(|db| {
+        let Some(index) = db.maintenance_windows.iter().position(|window| window.id == id) else {
+            return Err(MaintenanceError::NotFound.into());
+        };
+        let removed = db.maintenance_windows.remove(index);
+        db.record_audit(AuditEntry::new("delete", "maintenance", id, serde_json::to_value(&removed).ok(), None, &client));
+        Ok(())
+    }).await {
+        Ok(()) => (StatusCode::NO_CONTENT, Json(serde_json::json!({"success": true}))).into_response(),
+        Err(e) => maintenance_error_response(e),
+    }
+}
+
+pub async fn list_result_webhooks(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ListParams>,
+) -> impl IntoResponse {
+    match list_result_webhooks_internal(&state.store).await {
+        Ok(webhooks) => {
+            let webhooks: Vec<ResultWebhook> = webhooks
+                .iter()
+                .filter(|webhook| params.matches_search(&[&webhook.url]))
+                .map(ResultWebhook::redacted)
+                .collect();
+            (StatusCode::OK, Json(paginate(webhooks, &params))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+pub async fn list_result_webhooks_internal(store: &crate::db::JsonStore) -> Result<Vec<ResultWebhook>> {
+    let db = store.read().await?;
+    let mut webhooks = db.result_webhooks;
+    webhooks.sort_by_key(|webhook| webhook.id);
+    Ok(webhooks)
+}
+
+pub async fn create_result_webhook(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(create_webhook): Json<CreateResultWebhook>,
+) -> impl IntoResponse {
+    let url = create_webhook.url.trim().to_string();
+    match reqwest::Url::parse(&url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+        _ => return error_response(StatusCode::BAD_REQUEST, format!("Invalid webhook URL '{}'", url)),
+    }
+
+    let result = state.store.write(|db| {
+        let id = db.get_next_id();
+        let webhook = ResultWebhook {
+            id,
+            url: url.clone(),
+            secret: create_webhook.secret.clone().filter(|secret| !secret.is_empty()),
+            include_raw_response: create_webhook.include_raw_response,
+            entity_types: create_webhook.entity_types.clone(),
+        };
+        db.record_audit(AuditEntry::new("create", "webhook", id, None, serde_json::to_value(webhook.redacted()).ok(), &client));
+        db.result_webhooks.push(webhook.clone());
+        Ok(webhook)
+    }).await;
+
+    match result {
+        Ok(webhook) => (StatusCode::CREATED, Json(webhook.redacted())).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+pub async fn delete_result_webhook(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match state.store.write(|db| {
+        let Some(index) = db.result_webhooks.iter().position(|webhook| webhook.id == id) else {
+            return Err(anyhow::anyhow!("Webhook not found"));
+        };
+        let removed = db.result_webhooks.remove(index);
+        db.record_audit(AuditEntry::new("delete", "webhook", id, serde_json::to_value(removed.redacted()).ok(), None, &client));
+        Ok(())
+    }).await {
+        Ok(()) => (StatusCode::NO_CONTENT, Json(serde_json::json!({"success": true}))).into_response(),
+        Err(e) if e.to_string().contains("not found") => error_response(StatusCode::NOT_FOUND, e.to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+pub async fn list_snippets(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ListParams>,
+) -> impl IntoResponse {
+    match state.store.read().await {
+        Ok(db) => {
+            let mut snippets: Vec<Snippet> = db
+                .snippets
+                .into_iter()
+                .filter(|snippet| params.matches_search(&[&snippet.name]))
+                .collect();
+            snippets.sort_by_key(|snippet| snippet.id);
+            (StatusCode::OK, Json(paginate(snippets, &params))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+pub async fn create_snippet(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(request): Json<CreateSnippet>,
+) -> impl IntoResponse {
+    let result = state.store.write(|db| {
+        let snippet = Snippet { id: db.get_next_id(), name: request.name.trim().to_string(), code: request.code.clone() };
+        check_snippet(db, &snippet)?;
+        db.record_audit(AuditEntry::new("create", "snippet", snippet.id, None, serde_json::to_value(&snippet).ok(), &client));
+        db.snippets.push(snippet.clone());
+        Ok(snippet)
+    }).await;
+
+    match result {
+        Ok(snippet) => (StatusCode::CREATED, Json(snippet)).into_response(),
+        Err(e) => snippet_error_response(e),
+    }
+}
+
+pub async fn update_snippet(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    Json(request): Json<CreateSnippet>,
+) -> impl IntoResponse {
+    let snippet = Snippet { id, name: request.name.trim().to_string(), code: request.code };
+    let result = state.store.write(|db| {
+        let Some(index) = db.snippets.iter().position(|existing| existing.id == id) else {
+            return Err(anyhow::anyhow!("Snippet not found"));
+        };
+        // Renaming would break the INCLUDEs of the servers using the old name
+        let old_name = db.snippets[index].name.clone();
+        if old_name != snippet.name {
+            check_no_dependents(db, &old_name, "renamed")?;
+        }
+        check_snippet(db, &snippet)?;
+        let before = std::mem::replace(&mut db.snippets[index], snippet.clone());
+        db.record_audit(AuditEntry::new(
+            "update",
+            "snippet",
+            id,
+            serde_json::to_value(&before).ok(),
+            serde_json::to_value(&snippet).ok(),
+            &client,
+        ));
+        Ok(())
+    }).await;
+
+    match result {
+        Ok(()) => (StatusCode::OK, Json(snippet)).into_response(),
+        Err(e) => snippet_error_response(e),
+    }
+}
+
+pub async fn delete_snippet(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match state.store.write(|db| {
+        let Some(index) = db.snippets.iter().position(|snippet| snippet.id == id) else {
+            return Err(anyhow::anyhow!("Snippet not found"));
+        };
+        check_no_dependents(db, &db.snippets[index].name, "deleted")?;
+        let removed = db.snippets.remove(index);
+        db.record_audit(AuditEntry::new("delete", "snippet", id, serde_json::to_value(&removed).ok(), None, &client));
+        Ok(())
+    }).await {
+        Ok(()) => (StatusCode::NO_CONTENT, Json(serde_json::json!({"success": true}))).into_response(),
+        Err(e) => snippet_error_response(e),
+    }
+}
+
+/// Game servers still including a snippet, which keep it from being deleted or renamed
+#[derive(Debug)]
+struct SnippetInUse {
+    message: String,
+    dependents: Vec<String>,
+}
+
+impl std::fmt::Display for SnippetInUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SnippetInUse {}
+
+fn check_no_dependents(db: &crate::db::Database, name: &str, action: &str) -> Result<()> {
+    let dependents = db.snippet_dependents(name);
+    if !dependents.is_empty() {
+        return Err(SnippetInUse {
+            message: format!("Snippet '{}' is included by {} game server(s) and can't be {}", name, dependents.len(), action),
+            dependents,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Snippet names are single words, unique, and the snippet's own INCLUDEs must resolve without a cycle
+fn check_snippet(db: &crate::db::Database, snippet: &Snippet) -> Result<()> {
+    if snippet.name.is_empty() || snippet.name.contains(char::is_whitespace) || snippet.name.contains('#') {
+        anyhow::bail!("Invalid snippet name '{}': use a single word", snippet.name);
+    }
+    if snippet.code.trim().is_empty() {
+        anyhow::bail!("Invalid snippet: code cannot be empty");
+    }
+    if db.snippets.iter().any(|other| other.id != snippet.id && other.name == snippet.name) {
+        anyhow::bail!("A snippet named '{}' already exists", snippet.name);
+    }
+    let mut snippets = db.snippet_map();
+    if let Some(old) = db.snippets.iter().find(|other| other.id == snippet.id) {
+        snippets.remove(&old.name);
+    }
+    snippets.insert(snippet.name.clone(), snippet.code.clone());
+    crate::packet_parser::check_includes(&format!("INCLUDE {}", snippet.name), &snippets)
+        .map_err(|e| anyhow::anyhow!("Invalid snippet: {}", e))
+}
+
+fn snippet_error_response(e: anyhow::Error) -> axum::response::Response {
+    if let Some(in_use) = e.downcast_ref::<SnippetInUse>() {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({"error": in_use.message, "dependents": in_use.dependents})),
+        )
+            .into_response();
+    }
+    let message = e.to_string();
+    let status = if message.contains("already exists") {
+        StatusCode::CONFLICT
+    } else if message.starts_with("Invalid") {
+        StatusCode::BAD_REQUEST
+    } else if message.contains("not found") {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    error_response(status, message)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrometheusRulesParams {
+    /// Prometheus duration an `_up` metric must stay at 0 before its alert fires
+    pub alert_for: Option<String>,
+}
+
+/// `GET /api/prometheus/rules.yaml`: recording and alerting rules for the configured entities
+pub async fn prometheus_rules(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<PrometheusRulesParams>,
+) -> impl IntoResponse {
+    let alert_for = params.alert_for.as_deref().map(str::trim).unwrap_or(crate::prometheus::DEFAULT_ALERT_FOR);
+    if !crate::prometheus::is_prometheus_duration(alert_for) {
+        return error_response(StatusCode::BAD_REQUEST, format!("Invalid alert_for '{}': use a duration such as 5m or 1h30m", alert_for));
+    }
+    let db = match state.store.read().await {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match crate::prometheus::rules_yaml(&db, alert_for) {
+        Ok(yaml) => export_response(yaml, "application/yaml", "rules.yaml"),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// The raw store file, webhook secrets included, so it requires the API token like `restore`
+pub async fn backup(Extension(state): Extension<Arc<AppState>>, headers: HeaderMap) -> impl IntoResponse {
+    if let Some(response) = api_token_rejection(&state, &headers) {
+        return response;
+    }
+    match state.store.raw().await {
+        Ok(content) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "application/json"),
+                (header::CONTENT_DISPOSITION, "attachment; filename=\"net_sentinel_backup.json\""),
+            ],
+            content,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+/// Query parameters of the `/api/export` endpoints
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportParams {
+    /// `json` (default) or `csv`, which is only offered for ISPs and websites
+    pub format: Option<String>,
+}
+
+/// Download response for an export
+fn export_response(content: String, content_type: &'static str, filename: &str) -> axum::response::Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        content,
+    )
+        .into_response()
+}
+
+/// Quote a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Serialize `items` as pretty JSON, or as CSV with `header` and one row per item from `row`
+fn export_collection<T: Serialize>(
+    items: &[T],
+    params: &ExportParams,
+    name: &str,
+    header: &str,
+    row: impl Fn(&T) -> Vec<String>,
+) -> axum::response::Response {
+    match params.format.as_deref().unwrap_or("json") {
+        "json" => match serde_json::to_string_pretty(items) {
+            Ok(content) => export_response(content, "application/json", &format!("net_sentinel_{}.json", name)),
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        },
+        "csv" => {
+            let mut content = format!("{}\n", header);
+            for item in items {
+                let fields: Vec<String> = row(item).iter().map(|field| csv_field(field)).collect();
+                content.push_str(&fields.join(","));
+                content.push('\n');
+            }
+            export_response(content, "text/csv", &format!("net_sentinel_{}.csv", name))
+        }
+        other => error_response(StatusCode::BAD_REQUEST, format!("Unknown export format '{}', use json or csv", other)),
+    }
+}
+
+/// Whole configuration, serialized the way `JsonStore::save` writes it but with result webhook
+/// secrets masked as in `GET /api/webhooks/results`; `GET /api/backup` keeps them
+pub async fn export_all(Extension(state): Extension<Arc<AppState>>, headers: HeaderMap) -> impl IntoResponse {
+    if let Some(response) = api_token_rejection(&state, &headers) {
+        return response;
+    }
+    let db = match state.store.read().await {
+        Ok(db) => redacted_export(db),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match serde_json::to_string_pretty(&db) {
+        Ok(content) => export_response(content, "application/json", "net_sentinel_export.json"),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+fn redacted_export(mut db: crate::db::Database) -> crate::db::Database {
+    db.result_webhooks = db.result_webhooks.iter().map(ResultWebhook::redacted).collect();
+    db
+}
+
+pub async fn export_isps(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ExportParams>,
+) -> impl IntoResponse {
+    let db = match state.store.read().await {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    export_collection(&db.isps, &params, "isps", "id,name,ip,check_method,check_port,is_primary", |isp| {
+        let check_method = serde_json::to_value(isp.check_method).ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default();
+        vec![
+            isp.id.to_string(),
+            isp.name.clone(),
+            isp.ip.clone(),
+            check_method,
+            isp.check_port.to_string(),
+            isp.is_primary.to_string(),
+        ]
+    })
+}
+
+pub async fn export_websites(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ExportParams>,
+) -> impl IntoResponse {
+    let db = match state.store.read().await {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    export_collection(&db.websites, &params, "websites", "id,url,direct_connect,direct_connect_url,metric_label", |website| {
+        vec![
+            website.id.to_string(),
+            website.url.clone(),
+            website.direct_connect.to_string(),
+            website.direct_connect_url.clone().unwrap_or_default(),
+            website.metric_label.clone().unwrap_or_default(),
+        ]
+    })
+}
+
+/// Game servers carry multi-line scripts, so they are only exported as JSON
+pub async fn export_game_servers(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ExportParams>,
+) -> impl IntoResponse {
+    if params.format.as_deref().is_some_and(|format| format != "json") {
+        return error_response(StatusCode::BAD_REQUEST, "Game servers can only be exported as json".to_string());
+    }
+    let db = match state.store.read().await {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match serde_json::to_string_pretty(&db.game_servers) {
+        Ok(content) => export_response(content, "application/json", "net_sentinel_gameservers.json"),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Contents of the `file` field of a multipart upload, ignoring anything else in the form
+async fn read_file_field(multipart: &mut Multipart) -> std::result::Result<axum::body::Bytes, axum::response::Response> {
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => {
+                return match field.bytes().await {
+                    Ok(bytes) => Ok(bytes),
+                    Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => Err(body_too_large()),
+                    Err(e) => Err(error_response(StatusCode::BAD_REQUEST, format!("Failed to read file: {}", e))),
+                };
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => return Err(error_response(StatusCode::BAD_REQUEST, "Missing 'file' field".to_string())),
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => return Err(body_too_large()),
+            Err(e) => return Err(error_response(StatusCode::BAD_REQUEST, format!("Invalid multipart body: {}", e))),
+        }
+    }
+}
+
+/// Compare bearer tokens in constant time by comparing their HMACs under the expected token,
+/// so neither the length nor a matching prefix shows in the response time
+fn token_matches(provided: &str, expected: &str) -> bool {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    let tag = |message: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(expected.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        mac
+    };
+    tag(provided).verify_slice(&tag(expected).finalize().into_bytes()).is_ok()
+}
+
+/// 401 response unless the request carries `Authorization: Bearer <NET_SENTINEL_API_TOKEN>`;
+/// every request passes when no token is configured
+fn api_token_rejection(state: &AppState, headers: &HeaderMap) -> Option<axum::response::Response> {
+    let token = state.api_token.as_deref()?;
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided.is_some_and(|provided| token_matches(provided, token)) {
+        None
+    } else {
+        Some(error_response(StatusCode::UNAUTHORIZED, "Missing or invalid API token".to_string()))
+    }
+}
+
+pub async fn restore(
+    Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    if let Some(response) = api_token_rejection(&state, &headers) {
+        return response;
+    }
+
+    let content = match read_file_field(&mut multipart).await {
+        Ok(content) => content,
+        Err(response) => return response,
+    };
+
+    let mut db: crate::db::Database = match serde_json::from_slice(&content) {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, format!("Invalid backup file: {}", e)),
+    };
+
+    let limits = [
+        ("ISPs", db.isps.len(), state.max_restore_isps),
+        ("websites", db.websites.len(), state.max_restore_websites),
+        ("game servers", db.game_servers.len(), state.max_restore_gameservers),
+    ];
+    for (name, count, max) in limits {
+        if count > max {
+            return error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Backup contains {} {}, limit is {}", count, name, max),
+            );
+        }
+    }
+
+    let summary = serde_json::json!({
+        "isps": db.isps.len(),
+        "websites": db.websites.len(),
+        "game_servers": db.game_servers.len(),
+    });
+    // The audit log is kept across restores rather than taken from the backup
+    let replaced = state.store.replace(|current| {
+        db.audit_log = current.audit_log;
+        db.record_audit(AuditEntry::new("restore", "database", 0, None, Some(summary), &client));
+        db
+    });
+    let db = match replaced.await {
+        Ok(db) => db,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    // IDs in the restored database may refer to different entities than before
+    state.history.clear();
+    crate::out::info(
+        "api",
+        &format!(
+            "Database restored ({} ISPs, {} websites, {} game servers)",
+            db.isps.len(),
+            db.websites.len(),
+            db.game_servers.len()
+        ),
+    );
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "success": true,
+            "isps": db.isps.len(),
+            "websites": db.websites.len(),
+            "game_servers": db.game_servers.len(),
+        })),
+    )
+        .into_response()
+}
+
+/// Body of the `/api/tools/*` endpoints
+#[derive(Debug, Deserialize)]
+pub struct HexInput {
+    /// Hex digits; whitespace and `0x` prefixes are ignored
+    pub hex: String,
+}
+
+impl HexInput {
+    fn bytes(&self) -> Result<Vec<u8>> {
+        let digits: String = self.hex.split_whitespace()
+            .map(|part| part.trim_start_matches("0x").trim_start_matches("0X"))
+            .collect();
+        hex::decode(&digits).map_err(|e| anyhow::anyhow!("Invalid hex string: {}", e))
+    }
+}
+
+/// `GET /api/tools`: the available debugging tools
+pub async fn list_tools() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "tools": [
+            {
+                "path": "/api/tools/hexdump",
+                "method": "POST",
+                "description": "Formats {\"hex\": \"...\"} as an offset / hex / ASCII dump",
+            },
+            {
+                "path": "/api/tools/parse-varint",
+                "method": "POST",
+                "description": "Decodes {\"hex\": \"...\"} as consecutive VarInts",
+            },
+        ]
+    }))
+}
+
+/// `POST /api/tools/hexdump`
+pub async fn tool_hexdump(Json(input): Json<HexInput>) -> impl IntoResponse {
+    match input.bytes() {
+        Ok(bytes) => Json(serde_json::json!({
+            "length": bytes.len(),
+            "dump": crate::packet_parser::hex_dump(&bytes),
+        })).into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+/// `POST /api/tools/parse-varint`: every VarInt in the bytes, in order
+pub async fn tool_parse_varint(Json(input): Json<HexInput>) -> impl IntoResponse {
+    let bytes = match input.bytes() {
+        Ok(bytes) => bytes,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let mut values = Vec::new();
+    let mut cursor = 0;
+    while cursor < bytes.len() {
+        let start = cursor;
+        match crate::packet_parser::read_varint(&bytes, &mut cursor) {
+            Ok(value) => values.push(value),
+            Err(e) => return error_response(
+                StatusCode::BAD_REQUEST,
+                format!("{} at byte {} (decoded so far: {:?})", e, start, values),
+            ),
+        }
+    }
+    Json(serde_json::json!({ "values": values })).into_response()
+}
+
+/// Body of `POST /api/format-script` and `POST /api/analyze-script`
+#[derive(Debug, Deserialize)]
+pub struct FormatScriptRequest {
+    pub script: String,
+}
+
+/// `POST /api/format-script`: the script with normalized layout, if the result parses and runs the same commands
+pub async fn format_script(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(request): Json<FormatScriptRequest>,
+) -> impl IntoResponse {
+    let snippets = match snippet_map_internal(&state.store).await {
+        Ok(snippets) => snippets,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let formatted = crate::formatter::format_script(&request.script);
+    let original = crate::packet_parser::parse_script(&request.script, &snippets);
+    match (crate::packet_parser::parse_script(&formatted, &snippets), original) {
+        // Keyword case is already fixed in `formatted`, so its error points at the real problem
+        (Err(formatted_error), Err(_)) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                format!("Script does not parse: {} (line numbers refer to the formatted script)", formatted_error),
+            );
+        }
+        (Err(formatted_error), Ok(_)) => {
+            crate::out::error("api", &format!("Formatting broke a valid script: {}", formatted_error));
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Formatting broke the script: {}", formatted_error));
+        }
+        (Ok(after), Ok(before)) if !crate::formatter::same_structure(&before, &after) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "Formatting would change which block or body some commands run in; fix the indentation of the CODE blocks first".to_string(),
+            );
+        }
+        _ => {}
+    }
+    let changed = formatted != request.script;
+    Json(serde_json::json!({ "formatted": formatted, "changed": changed })).into_response()
+}
+
+/// `POST /api/analyze-script`: size and nesting of a script, to spot ones likely to time out
+pub async fn analyze_script(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(request): Json<FormatScriptRequest>,
+) -> impl IntoResponse {
+    let snippets = match snippet_map_internal(&state.store).await {
+        Ok(snippets) => snippets,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match crate::packet_parser::parse_script(&request.script, &snippets) {
+        Ok(script) => Json(crate::formatter::analyze_script(&script)).into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, format!("Script does not parse: {}", e)),
+    }
+}
+
+/// `POST /api/lint-script`: warnings for a script that parses but is probably wrong
+pub async fn lint_script(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(request): Json<FormatScriptRequest>,
+) -> impl IntoResponse {
+    let snippets = match snippet_map_internal(&state.store).await {
+        Ok(snippets) => snippets,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match crate::packet_parser::parse_script(&request.script, &snippets) {
+        Ok(script) => {
+            let mut warnings = crate::linter::lint_script(&script);
+            for warning in &mut warnings {
+                warning.line = warning.line.map(|line| script.sources.script_line(line));
+            }
+            Json(serde_json::json!({ "warnings": warnings })).into_response()
+        }
+        Err(e) => error_response(StatusCode::BAD_REQUEST, format!("Script does not parse: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_matches_only_the_exact_token() {
+        assert!(token_matches("secret-token", "secret-token"));
+        assert!(!token_matches("secret-toke", "secret-token"));
+        assert!(!token_matches("secret-token2", "secret-token"));
+        assert!(!token_matches("", "secret-token"));
+    }
+
+    #[test]
+    fn export_masks_result_webhook_secrets() {
+        let mut db = crate::db::Database::default();
+        db.result_webhooks.push(ResultWebhook {
+            id: 1,
+            url: "http://example.com/hook".to_string(),
+            secret: Some("hunter2".to_string()),
+            include_raw_response: false,
+            entity_types: Vec::new(),
+        });
+        let exported = serde_json::to_string(&redacted_export(db)).unwrap();
+        assert!(!exported.contains("hunter2"));
+        assert!(exported.contains("********"));
+    }
+
+    #[test]
+    fn maintenance_errors_map_to_statuses() {
+        let status = |e: MaintenanceError| maintenance_error_response(e.into()).status();
+        assert_eq!(status(MaintenanceError::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(status(MaintenanceError::EntityMissing { kind: EntityKind::Isp, id: 3 }), StatusCode::BAD_REQUEST);
+        assert_eq!(status(MaintenanceError::Overlaps { existing: 1, kind: EntityKind::Website }), StatusCode::CONFLICT);
+        // Messages mentioning the old keywords no longer decide the status
+        assert_eq!(
+            maintenance_error_response(anyhow::anyhow!("store file not found")).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
 }
@@ -1,7 +1,9 @@
-use crate::models::{Isp, Website, GameServer};
+use crate::models::{AuditEntry, Isp, MaintenanceWindow, ResultWebhook, Snippet, Website, GameServer};
+use crate::history::EntityKind;
 use crate::out;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
 
@@ -10,27 +12,95 @@ pub struct Database {
     pub isps: Vec<Isp>,
     pub websites: Vec<Website>,
     pub game_servers: Vec<GameServer>,
+    /// Configuration changes made through the API, oldest first
+    #[serde(default)]
+    pub audit_log: Vec<AuditEntry>,
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    pub result_webhooks: Vec<ResultWebhook>,
+    /// Script pieces game servers pull in with `INCLUDE`
+    #[serde(default)]
+    pub snippets: Vec<Snippet>,
     #[serde(skip)]
     next_id: i64,
 }
 
+/// Audit entries kept; the oldest are dropped beyond this
+const MAX_AUDIT_ENTRIES: usize = 10_000;
+
 impl Database {
     pub fn get_next_id(&mut self) -> i64 {
         self.next_id += 1;
         self.next_id
     }
 
+    pub fn record_audit(&mut self, entry: AuditEntry) {
+        self.audit_log.push(entry);
+        if self.audit_log.len() > MAX_AUDIT_ENTRIES {
+            let excess = self.audit_log.len() - MAX_AUDIT_ENTRIES;
+            self.audit_log.drain(..excess);
+        }
+    }
+
+    pub fn entity_exists(&self, kind: EntityKind, id: i64) -> bool {
+        match kind {
+            EntityKind::Isp => self.isps.iter().any(|isp| isp.id == id),
+            EntityKind::Website => self.websites.iter().any(|website| website.id == id),
+            EntityKind::GameServer => self.game_servers.iter().any(|server| server.id == id),
+        }
+    }
+
+    /// Entities covered by a maintenance window at `now` (Unix seconds)
+    pub fn active_maintenance(&self, now: i64) -> HashSet<(EntityKind, i64)> {
+        self.maintenance_windows
+            .iter()
+            .filter(|window| window.is_active_at(now))
+            .map(|window| (window.entity_type, window.entity_id))
+            .collect()
+    }
+
+    /// Snippet texts by name, for expanding `INCLUDE`s
+    pub fn snippet_map(&self) -> crate::packet_parser::Snippets {
+        self.snippets.iter().map(|snippet| (snippet.name.clone(), snippet.code.clone())).collect()
+    }
+
+    /// Names of the game servers whose scripts include the snippet, directly or through other snippets
+    pub fn snippet_dependents(&self, name: &str) -> Vec<String> {
+        let snippets = self.snippet_map();
+        self.game_servers
+            .iter()
+            .filter(|server| crate::packet_parser::included_snippets(&server.pseudo_code, &snippets).contains(name))
+            .map(|server| server.name.clone())
+            .collect()
+    }
+
+    /// Drop the maintenance windows of a deleted entity
+    pub fn remove_maintenance_windows(&mut self, kind: EntityKind, id: i64) {
+        self.maintenance_windows.retain(|window| window.entity_type != kind || window.entity_id != id);
+    }
+
     fn update_next_id(&mut self) {
         let max_isp_id = self.isps.iter().map(|isp| isp.id).max().unwrap_or(0);
         let max_website_id = self.websites.iter().map(|website| website.id).max().unwrap_or(0);
         let max_gameserver_id = self.game_servers.iter().map(|gs| gs.id).max().unwrap_or(0);
-        self.next_id = max_isp_id.max(max_website_id).max(max_gameserver_id);
+        let max_window_id = self.maintenance_windows.iter().map(|window| window.id).max().unwrap_or(0);
+        let max_webhook_id = self.result_webhooks.iter().map(|webhook| webhook.id).max().unwrap_or(0);
+        let max_snippet_id = self.snippets.iter().map(|snippet| snippet.id).max().unwrap_or(0);
+        self.next_id = max_isp_id
+            .max(max_website_id)
+            .max(max_gameserver_id)
+            .max(max_window_id)
+            .max(max_webhook_id)
+            .max(max_snippet_id);
     }
 }
 
 #[derive(Clone)]
 pub struct JsonStore {
     path: PathBuf,
+    /// Held from load to save so concurrent writes are not lost
+    lock: std::sync::Arc<tokio::sync::Mutex<()>>,
 }
 
 impl JsonStore {
@@ -47,7 +117,7 @@ impl JsonStore {
             fs::write(&path, content)?;
         }
 
-        Ok(Self { path })
+        Ok(Self { path, lock: Default::default() })
     }
 
     pub async fn load(&self) -> Result<Database> {
@@ -82,6 +152,34 @@ impl JsonStore {
                             }
                         }
                     }
+                    if let Some(windows_array) = partial.get("maintenance_windows").and_then(|v| v.as_array()) {
+                        for window_value in windows_array {
+                            if let Ok(window) = serde_json::from_value::<MaintenanceWindow>(window_value.clone()) {
+                                db.maintenance_windows.push(window);
+                            }
+                        }
+                    }
+                    if let Some(webhooks_array) = partial.get("result_webhooks").and_then(|v| v.as_array()) {
+                        for webhook_value in webhooks_array {
+                            if let Ok(webhook) = serde_json::from_value::<ResultWebhook>(webhook_value.clone()) {
+                                db.result_webhooks.push(webhook);
+                            }
+                        }
+                    }
+                    if let Some(snippets_array) = partial.get("snippets").and_then(|v| v.as_array()) {
+                        for snippet_value in snippets_array {
+                            if let Ok(snippet) = serde_json::from_value::<Snippet>(snippet_value.clone()) {
+                                db.snippets.push(snippet);
+                            }
+                        }
+                    }
+                    if let Some(audit_array) = partial.get("audit_log").and_then(|v| v.as_array()) {
+                        for entry_value in audit_array {
+                            if let Ok(entry) = serde_json::from_value::<AuditEntry>(entry_value.clone()) {
+                                db.audit_log.push(entry);
+                            }
+                        }
+                    }
                 }
                 db
             }
@@ -97,6 +195,29 @@ impl JsonStore {
         Ok(())
     }
 
+    /// Raw contents of the database file, as stored on disk
+    pub async fn raw(&self) -> Result<String> {
+        Ok(tokio::fs::read_to_string(&self.path).await?)
+    }
+
+    /// Replace the whole database with the one `f` builds from the current contents,
+    /// writing a temporary file next to it and renaming it into place
+    pub async fn replace<F>(&self, f: F) -> Result<Database>
+    where
+        F: FnOnce(Database) -> Database,
+    {
+        let _guard = self.lock.lock().await;
+        let db = f(self.load().await?);
+        let content = serde_json::to_string_pretty(&db)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, content).await?;
+        if let Err(e) = tokio::fs::rename(&tmp_path, &self.path).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        Ok(db)
+    }
+
     pub async fn read(&self) -> Result<Database> {
         self.load().await
     }
@@ -105,6 +226,7 @@ impl JsonStore {
     where
         F: FnMut(&mut Database) -> Result<T>,
     {
+        let _guard = self.lock.lock().await;
         let mut db = self.load().await?;
         let result = f(&mut db)?;
         self.save(&db).await?;
@@ -0,0 +1,587 @@
+use crate::packet_parser::{verbatim_body_end, CodeCommand, PacketScript, Sourced};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::mem::{discriminant, Discriminant};
+
+/// Rough cost of a script, from `POST /api/analyze-script`
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptMetrics {
+    pub pair_count: usize,
+    /// Deepest nesting of FOR/WHILE loops in any code block
+    pub max_loop_depth: usize,
+    pub total_response_commands: usize,
+    pub code_block_count: usize,
+    /// Packet, HTTP, response and code commands, counting loop and IF bodies
+    pub total_commands: usize,
+    pub estimated_complexity: Complexity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Complexity {
+    Low,
+    Medium,
+    High,
+}
+
+/// Count what a parsed script does. Complexity is `high` above 10 pairs, a loop depth of 3 or
+/// 100 commands, and `medium` above 5 pairs, a loop depth of 1 or 50 commands.
+pub fn analyze_script(script: &PacketScript) -> ScriptMetrics {
+    let pair_count = script.pairs.len();
+    let total_response_commands = script.pairs.iter().map(|pair| pair.response.len()).sum();
+    let packet_commands: usize = script.pairs.iter()
+        .map(|pair| {
+            let packets: usize = pair.packets.iter().map(Vec::len).sum();
+            let http = pair.http_request.as_ref()
+                .map(|request| 1 + request.params.len() + request.headers.len() + request.body_data.len())
+                .unwrap_or(0);
+            packets + http
+        })
+        .sum();
+    let mut code_commands = 0;
+    let mut max_loop_depth = 0;
+    let bodies = script.code_blocks.iter().map(|block| &block.commands)
+        .chain(script.functions.values().map(|function| &function.body));
+    for commands in bodies {
+        let (count, depth) = measure_code(commands);
+        code_commands += count;
+        max_loop_depth = max_loop_depth.max(depth);
+    }
+    let total_commands = packet_commands + total_response_commands + code_commands;
+
+    let estimated_complexity = if pair_count > 10 || max_loop_depth > 3 || total_commands > 100 {
+        Complexity::High
+    } else if pair_count > 5 || max_loop_depth > 1 || total_commands > 50 {
+        Complexity::Medium
+    } else {
+        Complexity::Low
+    };
+
+    ScriptMetrics {
+        pair_count,
+        max_loop_depth,
+        total_response_commands,
+        code_block_count: script.code_blocks.len(),
+        total_commands,
+        estimated_complexity,
+    }
+}
+
+/// Commands (including nested ones) and loop depth of a code body
+fn measure_code(commands: &[Sourced<CodeCommand>]) -> (usize, usize) {
+    let mut count = 0;
+    let mut depth = 0;
+    for command in commands {
+        count += 1;
+        let (nested_count, nested_depth) = match &command.command {
+            CodeCommand::ForLoop { body, .. } | CodeCommand::WhileLoop { body, .. } | CodeCommand::ForInArray { body, .. } => {
+                let (nested_count, nested_depth) = measure_code(body);
+                (nested_count, nested_depth + 1)
+            }
+            CodeCommand::IfStatement { body, else_if, else_body, .. } => {
+                std::iter::once(body)
+                    .chain(else_if.iter().map(|(_, body)| body))
+                    .chain(else_body.iter())
+                    .map(|body| measure_code(body))
+                    .fold((0, 0), |(count, depth), (body_count, body_depth)| (count + body_count, depth.max(body_depth)))
+            }
+            _ => (0, 0),
+        };
+        count += nested_count;
+        depth = depth.max(nested_depth);
+    }
+    (count, depth)
+}
+
+/// Script sections, each opened and closed by a keyword line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Packet,
+    Http,
+    Response,
+    Code,
+    Output,
+}
+
+impl Block {
+    fn opened_by(keyword: &str) -> Option<Block> {
+        match keyword {
+            "PACKET_START" => Some(Block::Packet),
+            "HTTP_START" => Some(Block::Http),
+            "RESPONSE_START" => Some(Block::Response),
+            "CODE_START" => Some(Block::Code),
+            "OUTPUT_SUCCESS" | "OUTPUT_ERROR" => Some(Block::Output),
+            _ => None,
+        }
+    }
+
+    fn closing_keyword(self) -> &'static str {
+        match self {
+            Block::Packet => "PACKET_END",
+            Block::Http => "HTTP_END",
+            Block::Response => "RESPONSE_END",
+            Block::Code => "CODE_END",
+            Block::Output => "OUTPUT_END",
+        }
+    }
+}
+
+/// Words upper-cased when they start a line (the parser only accepts them in upper case)
+const KEYWORDS: &[&str] = &[
+    "PACKET_START", "PACKET_END", "HTTP_START", "HTTP_END", "RESPONSE_START", "RESPONSE_END",
+    "CODE_START", "CODE_END", "OUTPUT_SUCCESS", "OUTPUT_ERROR", "OUTPUT_END",
+    "CONNECTION_CLOSE", "NO_RESPONSE", "DELAY", "TIMEOUT", "COOKIES", "ENDIAN", "DEFINE", "INCLUDE", "EXPECT_PACKETS", "FRAME",
+    "WRITE_BYTE", "WRITE_SHORT", "WRITE_SHORT_BE", "WRITE_SHORT_LE", "WRITE_INT", "WRITE_INT_BE", "WRITE_INT_LE",
+    "WRITE_LONG", "WRITE_LONG_BE", "WRITE_LONG_LE", "WRITE_FLOAT", "WRITE_FLOAT_BE", "WRITE_FLOAT_LE",
+    "WRITE_DOUBLE", "WRITE_DOUBLE_BE", "WRITE_DOUBLE_LE", "WRITE_VARINT",
+    "WRITE_STRING", "WRITE_STRING_LEN", "WRITE_STRING_RAW", "WRITE_STRING_BYTE_LEN", "WRITE_STRING_SHORT_LE_LEN",
+    "WRITE_STRING_SHORT_BE_LEN", "WRITE_STRING_VARINT_LEN", "WRITE_STRING_VARINT", "WRITE_STRING_PREFIXED",
+    "WRITE_STRING_UTF16", "WRITE_STRING_UTF16_BE", "WRITE_STRING_UTF16_LE",
+    "WRITE_BYTES", "WRITE_BYTES_VAR", "WRITE_BYTES_REPEAT", "WRITE_BYTES_REPEAT_TO_LEN", "WRITE_CRC32", "COMPRESS",
+    "READ_BYTE", "READ_SHORT", "READ_SHORT_BE", "READ_SHORT_LE", "READ_INT", "READ_INT_BE", "READ_INT_LE",
+    "READ_LONG", "READ_LONG_BE", "READ_LONG_LE", "READ_SBYTE", "READ_SSHORT", "READ_SSHORT_BE", "READ_SSHORT_LE",
+    "READ_SINT", "READ_SINT_BE", "READ_SINT_LE", "READ_SHORT_SIGNED", "READ_SHORT_SIGNED_BE", "READ_SHORT_SIGNED_LE",
+    "READ_INT_SIGNED", "READ_INT_SIGNED_BE", "READ_INT_SIGNED_LE", "READ_SLONG", "READ_SLONG_BE", "READ_SLONG_LE",
+    "READ_FLOAT", "READ_FLOAT_BE", "READ_FLOAT_LE", "READ_DOUBLE", "READ_DOUBLE_BE", "READ_DOUBLE_LE", "READ_VARINT",
+    "READ_STRING", "READ_STRING_NULL", "READ_STRING_BYTE_LEN", "READ_STRING_SHORT_LE_LEN", "READ_STRING_SHORT_BE_LEN",
+    "READ_STRING_VARINT_LEN", "READ_STRING_VARINT", "READ_STRING_PREFIXED",
+    "READ_STRING_UTF16", "READ_STRING_UTF16_BE", "READ_STRING_UTF16_LE",
+    "SKIP_BYTES", "DECOMPRESS", "SEEK_TO_OFFSET", "SEEK_FROM_END", "CURRENT_OFFSET", "READ_BYTES", "READ_REMAINING",
+    "EXPECT_BYTE", "EXPECT_MAGIC", "EXPECT_VARINT", "EXPECT_INT", "EXPECT_INT_BE", "EXPECT_INT_LE", "EXPECT_VAR", "EXPECT_STATUS", "EXPECT_BODY_CONTAINS", "EXPECT_HEADER", "READ_HEADER", "NO_HEADER_VARS", "READ_BODY_JSON", "READ_BODY",
+    "PARAM", "PARAM_VAR", "HEADER", "HEADER_VAR", "BODY_START", "BODY_END", "DATA",
+    "DATA_MULTILINE_START", "DATA_MULTILINE_END",
+    "IF", "ELSE", "FOR", "WHILE", "BREAK", "CONTINUE", "RETURN_EARLY", "FAIL", "ASSERT", "DEF", "CALL",
+    "STRING", "INT", "BYTE", "FLOAT", "ARRAY", "SPLIT", "REPLACE",
+    "RETURN", "JSON_OUTPUT",
+];
+
+/// Normalize the layout of a script: keywords in upper case, one space after the leading keyword,
+/// code bodies indented by two spaces per level, no indentation elsewhere, single blank lines,
+/// and a blank line after every block. Comments, `DATA_MULTILINE` bodies and `RAW VERBATIM` bodies
+/// are kept as written, and so are indented top-level code lines, which the parser skips.
+///
+/// This rewrites the script line by line instead of printing it back from the `parse_script` AST.
+/// The AST drops comments, and it stores literals decoded (quoted text, hex bytes, numbers in
+/// their original base), so regenerating from it would delete comments and change how payloads
+/// are spelled. Only the layout is touched; callers check the result with `parse_script` and
+/// `same_structure`.
+pub fn format_script(script: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut block: Option<Block> = None;
+    let mut in_multiline_data = false;
+    // Index of the BODY_END closing a RAW body written as plain lines
+    let mut verbatim_until: Option<usize> = None;
+    // Nesting depth of each line of the open code block, as the parser reads it
+    let mut code_depths: HashMap<usize, usize> = HashMap::new();
+    let mut blank_pending = false;
+    let mut after_block = false;
+
+    let source: Vec<&str> = script.lines().collect();
+    for (idx, raw) in source.iter().enumerate() {
+        if let Some(end) = verbatim_until {
+            if idx == end {
+                lines.push("BODY_END".to_string());
+                verbatim_until = None;
+            } else {
+                lines.push(raw.trim_end().to_string());
+            }
+            continue;
+        }
+        if in_multiline_data {
+            // The body is sent as written
+            if raw.trim().eq_ignore_ascii_case("DATA_MULTILINE_END") {
+                lines.push("DATA_MULTILINE_END".to_string());
+                in_multiline_data = false;
+            } else {
+                lines.push(raw.trim_end().to_string());
+            }
+            continue;
+        }
+
+        let trimmed = raw.trim();
+        if trimmed.is_empty() {
+            blank_pending = true;
+            continue;
+        }
+        let is_comment = trimmed.starts_with('#');
+        let line = if is_comment { trimmed.to_string() } else { normalize_line(trimmed) };
+        let keyword = if is_comment { "" } else { line.split_whitespace().next().unwrap_or("") };
+
+        // NO_RESPONSE belongs to the packet block before it
+        if !lines.is_empty() && (blank_pending || (after_block && keyword != "NO_RESPONSE")) {
+            lines.push(String::new());
+        }
+        blank_pending = false;
+        after_block = false;
+
+        match block {
+            Some(Block::Code) if keyword != "CODE_END" => match code_depths.get(&idx) {
+                Some(&depth) => lines.push(format!("{}{}", "  ".repeat(depth), line)),
+                None => lines.push(raw.trim_end().to_string()),
+            },
+            _ => lines.push(line.clone()),
+        }
+
+        if is_comment {
+            continue;
+        }
+        match block {
+            None if keyword == "NO_RESPONSE" => after_block = true,
+            None => {
+                block = Block::opened_by(keyword);
+                if block == Some(Block::Code) {
+                    code_depths = code_layout(&source, idx + 1);
+                }
+            }
+            Some(current) if keyword == current.closing_keyword() => {
+                block = None;
+                after_block = true;
+            }
+            Some(Block::Http) if keyword == "DATA_MULTILINE_START" => in_multiline_data = true,
+            Some(Block::Http) if line.split_whitespace().map(str::to_ascii_uppercase).eq(["BODY_START", "TYPE", "RAW", "VERBATIM"]) => {
+                verbatim_until = verbatim_body_end(&source, idx + 1);
+            }
+            Some(_) => {}
+        }
+    }
+
+    let mut formatted = lines.join("\n");
+    formatted.push('\n');
+    formatted
+}
+
+/// Depth of each line of the code block starting at `start`, keyed by line index. Code lines follow
+/// the parser: a `DEF` at the top level or an `IF`/`FOR`/`WHILE` at any indentation opens a body of
+/// the lines indented at least two more than where the statement is read, and other indented
+/// top-level lines are skipped and left out. Comments take the level of the line above them, or of
+/// the line below when they are indented less than the line above.
+fn code_layout(source: &[&str], start: usize) -> HashMap<usize, usize> {
+    let end = (start..source.len())
+        .find(|&idx| source[idx].trim().eq_ignore_ascii_case("CODE_END"))
+        .unwrap_or(source.len());
+    let mut layout = CodeLayout {
+        lines: source[start..end].iter()
+            .map(|raw| {
+                let trimmed = raw.trim();
+                let indent = raw.len() - raw.trim_start().len();
+                (indent, if trimmed.starts_with('#') { trimmed.to_string() } else { normalize_line(trimmed) })
+            })
+            .collect(),
+        depths: HashMap::new(),
+    };
+    layout.top_level();
+
+    let mut depths = layout.depths.clone();
+    let code_line = |idx: &usize| !layout.lines[*idx].1.is_empty() && !layout.lines[*idx].1.starts_with('#');
+    for (idx, (indent, text)) in layout.lines.iter().enumerate() {
+        if !text.starts_with('#') {
+            continue;
+        }
+        let depth = match (0..idx).rev().find(code_line) {
+            Some(above) => {
+                let (above_indent, above_text) = &layout.lines[above];
+                match layout.depths.get(&above) {
+                    // Below a skipped line: leave the comment where it is
+                    None => continue,
+                    Some(&depth) if above_text.ends_with(':') && indent > above_indent => Some(depth + 1),
+                    Some(&depth) if indent >= above_indent => Some(depth),
+                    Some(_) => None,
+                }
+            }
+            None => None,
+        };
+        let depth = depth.unwrap_or_else(|| {
+            (idx + 1..layout.lines.len()).find(code_line)
+                .and_then(|below| layout.depths.get(&below).copied())
+                .unwrap_or(0)
+        });
+        depths.insert(idx, depth);
+    }
+    depths.into_iter().map(|(idx, depth)| (start + idx, depth)).collect()
+}
+
+/// Walks a code block the way `parse_script`, `parse_control_flow` and `parse_indented_body` do
+struct CodeLayout {
+    /// Indentation and normalized text of each line; blank lines have no text
+    lines: Vec<(usize, String)>,
+    depths: HashMap<usize, usize>,
+}
+
+impl CodeLayout {
+    fn next_code_line(&self, from: usize) -> Option<usize> {
+        (from..self.lines.len()).find(|&idx| !self.lines[idx].1.is_empty() && !self.lines[idx].1.starts_with('#'))
+    }
+
+    fn top_level(&mut self) {
+        let mut idx = 0;
+        while let Some(line) = self.next_code_line(idx) {
+            let (indent, text) = self.lines[line].clone();
+            idx = if indent == 0 && text.starts_with("DEF ") {
+                self.depths.insert(line, 0);
+                self.body(line + 1, 2, 1)
+            } else if opens_body(&text) {
+                self.control_flow(line, indent, 0)
+            } else {
+                // Indented lines outside a body are skipped by the parser
+                if indent == 0 {
+                    self.depths.insert(line, 0);
+                }
+                line + 1
+            };
+        }
+    }
+
+    /// Lines indented at least `indent`, from `start`; returns the index after the body
+    fn body(&mut self, start: usize, indent: usize, depth: usize) -> usize {
+        let mut idx = start;
+        while let Some(line) = self.next_code_line(idx) {
+            if self.lines[line].0 < indent {
+                return line;
+            }
+            idx = if opens_body(&self.lines[line].1) {
+                self.control_flow(line, indent, depth)
+            } else {
+                self.depths.insert(line, depth);
+                line + 1
+            };
+        }
+        self.lines.len()
+    }
+
+    /// An `IF`/`FOR`/`WHILE` read at `base` indentation and its bodies, including `ELSE` branches
+    fn control_flow(&mut self, line: usize, base: usize, depth: usize) -> usize {
+        self.depths.insert(line, depth);
+        let mut end = self.body(line + 1, base + 2, depth + 1);
+        if !self.lines[line].1.starts_with("IF ") {
+            return end;
+        }
+        while let Some(branch) = self.next_code_line(end) {
+            let (indent, text) = &self.lines[branch];
+            if *indent != base {
+                break;
+            }
+            let last = text == "ELSE:";
+            let else_if = text.starts_with("ELSE IF ") && text.ends_with(':');
+            if !last && !else_if {
+                break;
+            }
+            self.depths.insert(branch, depth);
+            end = self.body(branch + 1, base + 2, depth + 1);
+            if last {
+                break;
+            }
+        }
+        end
+    }
+}
+
+fn opens_body(line: &str) -> bool {
+    line.ends_with(':') && ["IF ", "FOR ", "WHILE "].iter().any(|start| line.starts_with(start))
+}
+
+/// Structure of a parsed script that its layout decides: the pairs, and which block or body each
+/// code command ends up in
+#[derive(Debug, PartialEq)]
+struct Structure {
+    pairs: Vec<(Vec<usize>, bool, usize, bool)>,
+    code_blocks: Vec<(usize, Vec<CodeShape>)>,
+    functions: Vec<(String, Vec<String>, Vec<CodeShape>)>,
+    output_blocks: Vec<usize>,
+    discarded_packets: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct CodeShape {
+    kind: Discriminant<CodeCommand>,
+    bodies: Vec<Vec<CodeShape>>,
+}
+
+/// Whether two parses of a script differ only in line numbers, e.g. before and after formatting
+pub fn same_structure(before: &PacketScript, after: &PacketScript) -> bool {
+    structure(before) == structure(after)
+}
+
+fn structure(script: &PacketScript) -> Structure {
+    Structure {
+        pairs: script.pairs.iter()
+            .map(|pair| (pair.packets.iter().map(Vec::len).collect(), pair.http_request.is_some(), pair.response.len(), pair.no_response))
+            .collect(),
+        code_blocks: script.code_blocks.iter().map(|block| (block.before_pair, code_shape(&block.commands))).collect(),
+        functions: script.functions.iter()
+            .map(|(name, function)| (name.clone(), function.params.clone(), code_shape(&function.body)))
+            .collect(),
+        output_blocks: script.output_blocks.iter().map(|block| block.commands.len()).collect(),
+        discarded_packets: script.discarded_packets.len(),
+    }
+}
+
+fn code_shape(commands: &[Sourced<CodeCommand>]) -> Vec<CodeShape> {
+    commands.iter()
+        .map(|command| {
+            let bodies = match &command.command {
+                CodeCommand::ForLoop { body, .. } | CodeCommand::WhileLoop { body, .. } | CodeCommand::ForInArray { body, .. } => {
+                    vec![code_shape(body)]
+                }
+                CodeCommand::IfStatement { body, else_if, else_body, .. } => std::iter::once(body)
+                    .chain(else_if.iter().map(|(_, body)| body))
+                    .chain(else_body.iter())
+                    .map(|body| code_shape(body))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            CodeShape { kind: discriminant(&command.command), bodies }
+        })
+        .collect()
+}
+
+/// Upper-case the leading keyword (and the fixed words after some of them) and collapse the
+/// whitespace after it. Arguments are left alone, as they may hold text or payload bytes.
+fn normalize_line(line: &str) -> String {
+    let (first, rest) = match line.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim_start()),
+        None => (line, ""),
+    };
+    let upper = first.to_ascii_uppercase();
+    // `len = 3` assigns to a variable that happens to share a keyword's name
+    let is_assignment = rest.starts_with(['=', '+', '-', '*', '/', '[', '.']);
+    if !KEYWORDS.contains(&upper.trim_end_matches(':')) || is_assignment {
+        return if rest.is_empty() { first.to_string() } else { format!("{} {}", first, rest) };
+    }
+
+    let rest = match upper.as_str() {
+        "HTTP_START" => uppercase_leading_word(rest, "REQUEST"),
+        // `TYPE RAW VERBATIM` and the like are all fixed words
+        "BODY_START" => rest.to_ascii_uppercase(),
+        "ELSE" => uppercase_leading_word(rest, "IF"),
+        "FOR" => match find_ascii_case_insensitive(rest, " in ") {
+            Some(pos) => {
+                let iterable = uppercase_leading_word(rest[pos + 4..].trim_start(), "RANGE");
+                format!("{} IN {}", &rest[..pos], iterable)
+            }
+            None => rest.to_string(),
+        },
+        _ => rest.to_string(),
+    };
+    if rest.is_empty() { upper } else { format!("{} {}", upper, rest) }
+}
+
+/// `text` with its first word replaced by `word` when they match ignoring case
+fn uppercase_leading_word(text: &str, word: &str) -> String {
+    match text.get(..word.len()) {
+        Some(head) if head.eq_ignore_ascii_case(word)
+            && !text[word.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            format!("{}{}", word, &text[word.len()..])
+        }
+        _ => text.to_string(),
+    }
+}
+
+fn find_ascii_case_insensitive(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.to_ascii_lowercase().find(needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_parser::{parse_script, Snippets};
+
+    const MESSY: &str = "\
+# status query
+packet_start
+  write_byte   0xFE
+packet_end
+response_start
+    read_byte id
+response_end
+code_start
+string kind = \"small\"
+if id > 3:
+        kind = \"big\"
+        # still inside the IF
+else:
+   kind = \"none\"
+code_end
+
+
+
+output_success
+return \"id=id, kind=kind\"
+output_end
+";
+
+    #[test]
+    fn formats_layout_and_keeps_comments() {
+        assert_eq!(
+            format_script(MESSY),
+            "\
+# status query
+PACKET_START
+WRITE_BYTE 0xFE
+PACKET_END
+
+RESPONSE_START
+READ_BYTE id
+RESPONSE_END
+
+CODE_START
+STRING kind = \"small\"
+IF id > 3:
+  kind = \"big\"
+  # still inside the IF
+ELSE:
+  kind = \"none\"
+CODE_END
+
+OUTPUT_SUCCESS
+RETURN \"id=id, kind=kind\"
+OUTPUT_END
+"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent_and_keeps_the_parsed_structure() {
+        // Already indented the way the parser reads code bodies, so both versions parse the same
+        let untidy = "PACKET_START   \n   WRITE_BYTE    1\nPACKET_END\nRESPONSE_START\n READ_BYTE count\nRESPONSE_END\n\n\n\
+            CODE_START\nSTRING   marks = \"\"\nFOR i IN RANGE(0, count):   \n  IF i == 2:\n    CONTINUE\n  marks = CONCAT(marks, \"x\")\nCODE_END";
+        let formatted = format_script(untidy);
+        assert_eq!(format_script(&formatted), formatted);
+        let before = parse_script(untidy, &Snippets::new()).unwrap();
+        let after = parse_script(&formatted, &Snippets::new()).unwrap();
+        assert!(same_structure(&before, &after));
+    }
+
+    #[test]
+    fn bodies_follow_the_parser_indentation_rule() {
+        // `y = 2` is indented at least two past the IF, so the parser reads it as part of the body
+        let script = "CODE_START\nINT a = 101\nINT y = 0\nIF a > 100:\n    INT x = 1\n  y = 2\nCODE_END\n";
+        let formatted = format_script(script);
+        assert_eq!(formatted, "CODE_START\nINT a = 101\nINT y = 0\nIF a > 100:\n  INT x = 1\n  y = 2\nCODE_END\n");
+        let before = parse_script(script, &Snippets::new()).unwrap();
+        let after = parse_script(&formatted, &Snippets::new()).unwrap();
+        assert!(same_structure(&before, &after));
+    }
+
+    #[test]
+    fn skipped_top_level_lines_stay_indented() {
+        let script = "CODE_START\nINT a = 1\n    a = 2\nCODE_END\n";
+        assert_eq!(format_script(script), script);
+    }
+
+    #[test]
+    fn moved_commands_change_the_structure() {
+        let inside = parse_script("CODE_START\nINT y = 0\nIF 1 > 0:\n  INT x = 1\n  y = 2\nCODE_END\n", &Snippets::new()).unwrap();
+        let outside = parse_script("CODE_START\nINT y = 0\nIF 1 > 0:\n  INT x = 1\ny = 2\nCODE_END\n", &Snippets::new()).unwrap();
+        assert!(!same_structure(&inside, &outside));
+    }
+
+    #[test]
+    fn verbatim_bodies_are_kept_as_written() {
+        let script = "http_start request POST /chat\nbody_start type raw verbatim\n{\n    \"channel\":   \"#general\"\n}\nBODY_END\nHTTP_END\n";
+        assert_eq!(
+            format_script(script),
+            "HTTP_START REQUEST POST /chat\nBODY_START TYPE RAW VERBATIM\n{\n    \"channel\":   \"#general\"\n}\nBODY_END\nHTTP_END\n"
+        );
+    }
+}
@@ -24,6 +24,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                     line: None,
                 }),
                 output_labels_success: Vec::new(),
+                output_label_names_success: Vec::new(),
                 output_labels_error: Vec::new(),
             };
         }
@@ -44,6 +45,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                     line: None,
                 }),
                 output_labels_success: Vec::new(),
+                output_label_names_success: Vec::new(),
                 output_labels_error: Vec::new(),
             };
         }
@@ -64,6 +66,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                     line: None,
                 }),
                 output_labels_success: Vec::new(),
+                output_label_names_success: Vec::new(),
                 output_labels_error: Vec::new(),
             };
         }
@@ -85,6 +88,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                     line: None,
                 }),
                 output_labels_success: Vec::new(),
+                output_label_names_success: Vec::new(),
                 output_labels_error: Vec::new(),
             };
         }
@@ -111,6 +115,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                                 line: Some(line),
                             }),
                             output_labels_success: Vec::new(),
+                            output_label_names_success: Vec::new(),
                             output_labels_error: Vec::new(),
                         };
                     }
@@ -125,6 +130,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                         parsed_values: parsed_json,
                         error: None,
                         output_labels_success: Vec::new(),
+                        output_label_names_success: Vec::new(),
                         output_labels_error: Vec::new(),
                     }
                 }
@@ -140,6 +146,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                             line: None,
                         }),
                         output_labels_success: Vec::new(),
+                        output_label_names_success: Vec::new(),
                         output_labels_error: Vec::new(),
                     }
                 }
@@ -157,6 +164,7 @@ pub async fn test_game_server(server: &GameServer) -> GameServerTestResult {
                     line: None,
                 }),
                 output_labels_success: Vec::new(),
+                output_label_names_success: Vec::new(),
                 output_labels_error: Vec::new(),
             }
         }
@@ -1,17 +1,30 @@
-use crate::models::{GameServer, Protocol, GameServerTestResult, GameServerError};
+use crate::models::{GameServer, Protocol, GameServerTestResult, GameServerError, HttpTimingBreakdown, PairResult, PacketBuildPreview, PairBuild, PacketBuild, PacketBuildSegment, HttpRequestBuild};
 use crate::out;
-use crate::packet_parser::{build_packets_with_vars, parse_response, parse_script, execute_code_blocks, OutputBlock, OutputCommand, OutputStatus, PacketResponsePair, prepare_http_request_with_vars, parse_http_response};
+use crate::packet_parser::{build_packets_with_vars, build_pair_packets, script_command_lines, BuiltPacket, PreparedHttpRequest, expand_placeholders, has_placeholders, resolve_placeholder, parse_response, parse_script, execute_code_blocks, evaluate_condition, LoopLimitExceeded, AssertionFailed, check_response_assertions, error_line, constant_value, OutputBlock, OutputCommand, OutputStatus, CodeBlock, PacketResponsePair, PacketScript, ScriptFunction, SourceMap, Snippets, prepare_http_request_with_vars, parse_http_response, parse_frame_response, FrameType, ExpectPackets};
 use anyhow::{Context, Result};
 use serde_json::Value;
 use indexmap::IndexMap;
+use std::sync::Arc;
 use std::time::Instant;
 
-pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
+/// Default pause between retries when `retry_delay_ms` is not set
+const DEFAULT_RETRY_DELAY_MS: u64 = 500;
+
+/// Upper bound for a whole check including retries, so a flapping server cannot stall a scrape
+const MAX_CHECK_DURATION_MS: u64 = 30_000;
+
+/// Largest possible UDP payload; bigger read buffers would never be filled
+const MAX_UDP_DATAGRAM: usize = 65_536;
+
+/// Run the server's pseudo-code script against it and collect the results.
+///
+/// For `HTTP`/`HTTPS` servers the port is only included in the request URL when it differs from
+/// the scheme's default (80 for `HTTP`, 443 for `HTTPS`); UDP and TCP always connect to `server.port`.
+pub async fn check_game_server(server: &GameServer, snippets: &Snippets) -> GameServerTestResult {
     let start = Instant::now();
 
     // Parse the pseudo-code script
-    let resolved_code = replace_placeholders(&server.pseudo_code, server);
-    let script = match parse_script(&resolved_code) {
+    let script = match parse_server_script(server, snippets) {
         Ok(s) => s,
         Err(e) => {
             out::error("gameserver_check", &format!("Script parsing failed for {}: {}", server.name, e));
@@ -19,22 +32,152 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                 success: false,
                 response_time_ms: 0,
                 raw_response: None,
+                raw_response_truncated: false,
+                pairs: Vec::new(),
                 parsed_values: serde_json::json!({}),
                 variables: serde_json::json!({}),
                 error: Some(GameServerError {
                     error_type: "SyntaxError".to_string(),
                     message: e.to_string(),
-                    line: None,
+                    line: error_line(&e),
                 }),
                 output_labels_success: Vec::new(),
+                output_label_names_success: Vec::new(),
                 output_labels_error: Vec::new(),
+                attempts: 0,
+                wait_time_ms: 0,
+                response_bytes: 0,
+                timing: None,
+                warnings: Vec::new(),
             };
         }
     };
 
-    // Execute pairs sequentially: build packets with current variables, send, receive response, parse response
-    let mut all_responses = Vec::new();
-    let mut all_parsed_vars = IndexMap::new();
+    // Execute the pairs, retrying the whole sequence on network errors
+    let max_attempts = u32::from(server.retries.unwrap_or(0)) + 1;
+    let retry_delay = std::time::Duration::from_millis(server.retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS));
+    let mut attempts = 0;
+    let mut wait_time = std::time::Duration::ZERO;
+    let (pair_records, all_parsed_vars, mut last_error, mut timing, mut code_variables, mut warnings) = loop {
+        attempts += 1;
+        let mut timing = None;
+        let mut code_vars = IndexMap::new();
+        let mut warnings = Vec::new();
+        let (responses, parsed_vars, error) = execute_pairs(server, &script, &mut wait_time, &mut timing, &mut code_vars, &mut warnings).await;
+        let is_network_error = error.as_ref().is_some_and(|e| is_retryable(&e.error_type));
+        // Only start another attempt if it can still finish within the overall check budget
+        let next_attempt_end = start.elapsed() + retry_delay + server.connect_timeout() + server.read_timeout();
+        if !is_network_error || attempts >= max_attempts || next_attempt_end.as_millis() > MAX_CHECK_DURATION_MS as u128 {
+            break (responses, parsed_vars, error.map(|e| relocate_error(e, &script.sources)), timing, code_vars, warnings);
+        }
+        out::warning("gameserver_check", &format!(
+            "{}: attempt {}/{} failed, retrying in {}ms",
+            server.name, attempts, max_attempts, retry_delay.as_millis()
+        ));
+        tokio::time::sleep(retry_delay).await;
+    };
+
+    // DELAY pauses are reported separately and not counted as response time
+    let response_time_ms = start.elapsed().saturating_sub(wait_time).as_millis() as u64;
+    let wait_time_ms = wait_time.as_millis() as u64;
+    if let Some(timing) = timing.as_mut() {
+        timing.total_ms = response_time_ms;
+    }
+    let response_bytes = pair_records.iter()
+        .flat_map(|record| &record.received)
+        .map(|response| response.len() as u64)
+        .sum();
+    // Parsed values are what the responses read, later pairs overriding earlier ones; constants,
+    // placeholders and code block variables share `all_parsed_vars` but didn't come from a response
+    let response_values: IndexMap<String, Value> = pair_records.iter()
+        .flat_map(|record| record.variables.iter().map(|(name, value)| (name.clone(), value.clone())))
+        .collect();
+    let (pairs, raw_response_hex, raw_response_truncated) = encode_pair_records(pair_records, last_error.as_ref());
+
+    // Execute the code blocks after the last pair (variables from CODE_START/CODE_END)
+    // Do this even if there's an error, so variables are available for error output
+    match run_code_blocks(server, script.code_blocks_before(script.pairs.len()), &script.functions, &all_parsed_vars, &code_variables, &mut warnings).await {
+        Ok(Ok(vars)) => code_variables.extend(vars),
+        Ok(Err(e)) => {
+            out::error("gameserver_check", &format!("Code block execution failed: {}", e));
+            // Continue anyway, but log the error
+        }
+        Err(e) => {
+            last_error.get_or_insert(relocate_error(e, &script.sources));
+        }
+    }
+
+    // Merge code variables into parsed vars for output block evaluation
+    // Code variables can override parsed vars if they have the same name
+    let mut all_vars = all_parsed_vars.clone();
+    for (key, value) in code_variables.iter() {
+        all_vars.insert(key.clone(), value.clone());
+    }
+
+    if let Some(err) = last_error {
+        let error_labels = evaluate_output_labels(&script, OutputStatus::Error, &mut all_vars.clone(), server, Some(&err));
+        return GameServerTestResult {
+            success: false,
+            response_time_ms,
+            raw_response: Some(raw_response_hex),
+            raw_response_truncated,
+            pairs,
+            parsed_values: serde_json::json!({}),
+            variables: serde_json::json!({}),
+            error: Some(err),
+            output_labels_success: Vec::new(),
+            output_label_names_success: Vec::new(),
+            output_labels_error: error_labels.into_iter().map(|(_, label)| label).collect(),
+            attempts,
+            wait_time_ms,
+            response_bytes,
+            timing,
+            warnings,
+        };
+    }
+
+    // All pairs succeeded
+    let (output_label_names_success, success_labels) = evaluate_output_labels(&script, OutputStatus::Success, &mut all_vars.clone(), server, None)
+        .into_iter()
+        .unzip();
+    let parsed_values: serde_json::Value = response_values.into_iter().collect();
+    let variables: serde_json::Value = code_variables.into_iter().collect();
+
+    GameServerTestResult {
+        success: true,
+        response_time_ms,
+        raw_response: Some(raw_response_hex),
+        raw_response_truncated,
+        pairs,
+        parsed_values,
+        variables,
+        error: None,
+        output_labels_success: success_labels,
+        output_label_names_success,
+        output_labels_error: Vec::new(),
+        attempts,
+        wait_time_ms,
+        response_bytes,
+        timing,
+        warnings,
+    }
+}
+
+/// Run every pair of the script once against the server.
+/// Returns what each pair sent and received, the parsed variables and the error that stopped execution, if any.
+/// Time spent in `DELAY` pauses is added to `wait_time`, connection phase timings are stored in `timing`.
+/// Code blocks placed between pairs run before the next pair is built; their variables go into
+/// `code_vars` and the parsed variables, so later packets can use them; their warnings go into `warnings`.
+async fn execute_pairs(
+    server: &GameServer,
+    script: &PacketScript,
+    wait_time: &mut std::time::Duration,
+    timing: &mut Option<HttpTimingBreakdown>,
+    code_vars: &mut IndexMap<String, Value>,
+    warnings: &mut Vec<String>,
+) -> (Vec<PairRecord>, IndexMap<String, Value>, Option<GameServerError>) {
+    let mut pair_log = PairLog::default();
+    let mut all_parsed_vars = script_constants(script, server);
     let mut last_error: Option<GameServerError> = None;
 
     // Execute pairs sequentially: build, send, receive, parse immediately
@@ -42,37 +185,49 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
         Protocol::Udp => {
             // Create UDP socket once and reuse for all pairs
             use tokio::net::UdpSocket;
-            let addr = format!("{}:{}", server.address, server.port);
+            let max_response_bytes = server.max_response_bytes();
+            // UDP has no handshake: connect_ms is the time to resolve the address and bind the socket
+            let started = Instant::now();
+            let addr = match resolve_udp_address(server).await {
+                Ok(addr) => addr.to_string(),
+                Err(e) => return (pair_log.finish(), all_parsed_vars, Some(e)),
+            };
             let socket = match UdpSocket::bind("0.0.0.0:0").await {
-                Ok(s) => s,
+                Ok(s) => {
+                    timing.get_or_insert(HttpTimingBreakdown {
+                        connect_ms: started.elapsed().as_millis() as u64,
+                        ..Default::default()
+                    });
+                    s
+                }
                 Err(e) => {
-                    return GameServerTestResult {
-                        success: false,
-                        response_time_ms: start.elapsed().as_millis() as u64,
-                        raw_response: None,
-                        parsed_values: serde_json::json!({}),
-                        variables: serde_json::json!({}),
-                        error: Some(GameServerError {
-                            error_type: "NetworkError".to_string(),
-                            message: format!("Failed to create UDP socket: {}", e),
-                            line: None,
-                        }),
-                        output_labels_success: Vec::new(),
-                        output_labels_error: Vec::new(),
-                    };
+                    last_error = Some(GameServerError {
+                        error_type: "NetworkError".to_string(),
+                        message: format!("Failed to create UDP socket: {}", e),
+                        line: None,
+                    });
+                    return (pair_log.finish(), all_parsed_vars, last_error);
                 }
             };
             
             // Execute all pairs with the same socket, parsing responses immediately
             for (pair_idx, pair) in script.pairs.iter().enumerate() {
+                wait_before_pair(pair, wait_time).await;
+                pair_log.start(pair_idx);
+                if let Err(e) = run_code_blocks_before_pair(server, script, pair_idx, &mut all_parsed_vars, code_vars, warnings).await {
+                    last_error = Some(e);
+                    break;
+                }
+                let read_timeout = pair_read_timeout(server, pair);
+                let read_timeout_ms = read_timeout.as_millis() as u64;
                 // Build packets for this pair with current variables (just before sending)
-                let pair_packets = match build_packets_for_pair(pair, &all_parsed_vars) {
+                let pair_packets = match build_packets_for_pair(pair, &all_parsed_vars, script.big_endian) {
                     Ok(packets) => packets,
                     Err(e) => {
                         last_error = Some(GameServerError {
                             error_type: "BuildError".to_string(),
                             message: format!("Pair {}: {}", pair_idx + 1, e),
-                            line: None,
+                            line: error_line(&e),
                         });
                         break;
                     }
@@ -80,23 +235,48 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                 
                 // For UDP, send only the first packet (each pair has one packet)
                 if let Some(packet) = pair_packets.first() {
-                    match send_packet_udp(&socket, &addr, packet, server.timeout_ms).await {
-                        Ok(response) => {
-                            all_responses.push(response.clone());
+                    pair_log.sent(packet);
+                    // Fire-and-forget pair: send and move on to the next pair immediately
+                    if pair.no_response {
+                        if let Err(e) = send_packet_udp_no_response(&socket, &addr, packet).await {
+                            last_error = Some(network_error(format!("Pair {}: {}", pair_idx + 1, e)));
+                            break;
+                        }
+                        continue;
+                    }
+                    let received = match pair.expect_packets {
+                        Some(expect) => match send_packet_udp_no_response(&socket, &addr, packet).await {
+                            Ok(()) => receive_packets_udp(&socket, read_timeout_ms, expect, max_response_bytes).await,
+                            Err(e) => Err(e),
+                        },
+                        None => send_packet_udp(&socket, &addr, packet, read_timeout_ms, max_response_bytes).await.map(|response| vec![response]),
+                    };
+                    match received {
+                        Ok(datagrams) => {
+                            // Keep each datagram separate in raw_response, parse them concatenated in arrival order
+                            let response = datagrams.concat();
+                            for datagram in datagrams {
+                                pair_log.received(datagram);
+                            }
                             
                             // Parse the response immediately so variables are available for next pair
                             if !pair.response.is_empty() {
-                                match parse_response(&pair.response, &response) {
+                                match parse_response(&pair.response, &response, script.big_endian) {
                                     Ok((vars, _bytes_read)) => {
+                                        pair_log.variables(&vars);
                                         // Merge variables into all_parsed_vars (later pairs can override earlier ones)
                                         all_parsed_vars.extend(vars);
+                                        if let Err(failed) = check_response_assertions(&pair.response, &all_parsed_vars) {
+                                            last_error = Some(response_assertion_error(pair_idx, failed));
+                                            break;
+                                        }
                                     }
                                     Err(e) => {
                                         out::error("gameserver_check", &format!("Pair {} response parsing failed: {}", pair_idx + 1, e));
                                         last_error = Some(GameServerError {
                                             error_type: "ParseError".to_string(),
                                             message: format!("Pair {}: {}", pair_idx + 1, e),
-                                            line: None,
+                                            line: error_line(&e),
                                         });
                                         break;
                                     }
@@ -104,11 +284,7 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                             }
                         },
                         Err(e) => {
-                            last_error = Some(GameServerError {
-                                error_type: "NetworkError".to_string(),
-                                message: format!("Pair {}: {}", pair_idx + 1, e),
-                                line: None,
-                            });
+                            last_error = Some(read_error(pair_idx, e, read_timeout));
                             break;
                         }
                     }
@@ -123,17 +299,18 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
             }
             // UDP parsing is done inline above
         },
-        Protocol::Tcp => {
-            // Create TCP connection and manage it per pair (may be closed/reopened)
-            use tokio::net::TcpStream;
-            use tokio::time::{timeout, Duration};
-            
-            let addr = format!("{}:{}", server.address, server.port);
-            let timeout_duration = Duration::from_millis(server.timeout_ms);
-            
-            let mut stream: Option<TcpStream> = None;
+        Protocol::Tcp | Protocol::Tls => {
+            // Create TCP (or TLS-wrapped TCP) connection and manage it per pair (may be closed/reopened)
+            let mut stream: Option<Box<dyn AsyncStream>> = None;
             
             for (pair_idx, pair) in script.pairs.iter().enumerate() {
+                wait_before_pair(pair, wait_time).await;
+                pair_log.start(pair_idx);
+                if let Err(e) = run_code_blocks_before_pair(server, script, pair_idx, &mut all_parsed_vars, code_vars, warnings).await {
+                    last_error = Some(e);
+                    break;
+                }
+                let read_timeout = pair_read_timeout(server, pair);
                 // Check if we need to close connection before this pair
                 if pair.close_connection_before {
                     if stream.take().is_some() {
@@ -143,37 +320,27 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                 
                 // Check if we need to open a new connection
                 if stream.is_none() {
-                    match timeout(timeout_duration, TcpStream::connect(&addr)).await {
-                        Ok(Ok(s)) => {
+                    let mut breakdown = HttpTimingBreakdown::default();
+                    match connect_stream(server, &mut breakdown).await {
+                        Ok(s) => {
                             stream = Some(s);
+                            timing.get_or_insert(breakdown);
                         },
-                        Ok(Err(e)) => {
-                            last_error = Some(GameServerError {
-                                error_type: "NetworkError".to_string(),
-                                message: format!("Failed to connect to server: {}", e),
-                                line: None,
-                            });
-                            break;
-                        },
-                        Err(_) => {
-                            last_error = Some(GameServerError {
-                                error_type: "NetworkError".to_string(),
-                                message: "Connection timeout".to_string(),
-                                line: None,
-                            });
+                        Err(e) => {
+                            last_error = Some(e);
                             break;
                         }
                     }
                 }
                 
                 // Build packets for this pair with current variables (just before sending)
-                let pair_packets = match build_packets_for_pair(pair, &all_parsed_vars) {
+                let pair_packets = match build_packets_for_pair(pair, &all_parsed_vars, script.big_endian) {
                     Ok(packets) => packets,
                     Err(e) => {
                         last_error = Some(GameServerError {
                             error_type: "BuildError".to_string(),
                             message: format!("Pair {}: {}", pair_idx + 1, e),
-                            line: None,
+                            line: error_line(&e),
                         });
                         break;
                     }
@@ -183,6 +350,7 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                 match stream.as_mut() {
                     Some(s) => {
                         for (packet_in_pair_idx, packet) in pair_packets.iter().enumerate() {
+                            pair_log.sent(packet);
                             match send_packet_tcp_no_response(s, packet).await {
                                 Ok(_) => {},
                                 Err(e) => {
@@ -198,35 +366,36 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                         }
                         
                         // After all packets are sent, wait for response (only if there's a response defined)
-                        if !pair.response.is_empty() {
+                        if !pair.no_response && !pair.response.is_empty() {
                             if let Some(s) = stream.as_mut() {
-                                match receive_packet_tcp(s, timeout_duration).await {
+                                match receive_packet_tcp(s, read_timeout, server.max_response_bytes()).await {
                                     Ok(response) => {
-                                        all_responses.push(response.clone());
+                                        pair_log.received(response.clone());
                                         
                                         // Parse the response immediately so variables are available for next pair
-                                        match parse_response(&pair.response, &response) {
+                                        match parse_response(&pair.response, &response, script.big_endian) {
                                             Ok((vars, _bytes_read)) => {
+                                                pair_log.variables(&vars);
                                                 // Merge variables into all_parsed_vars (later pairs can override earlier ones)
                                                 all_parsed_vars.extend(vars);
+                                                if let Err(failed) = check_response_assertions(&pair.response, &all_parsed_vars) {
+                                                    last_error = Some(response_assertion_error(pair_idx, failed));
+                                                    break;
+                                                }
                                             }
                                             Err(e) => {
                                                 out::error("gameserver_check", &format!("Pair {} response parsing failed: {}", pair_idx + 1, e));
                                                 last_error = Some(GameServerError {
                                                     error_type: "ParseError".to_string(),
                                                     message: format!("Pair {}: {}", pair_idx + 1, e),
-                                                    line: None,
+                                                    line: error_line(&e),
                                                 });
                                                 break;
                                             }
                                         }
                                     },
                                     Err(e) => {
-                                        last_error = Some(GameServerError {
-                                            error_type: "NetworkError".to_string(),
-                                            message: format!("Pair {}: {}", pair_idx + 1, e),
-                                            line: None,
-                                        });
+                                        last_error = Some(read_error(pair_idx, e, read_timeout));
                                         stream = None; // Connection is likely broken
                                         break;
                                     }
@@ -244,24 +413,253 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                     }
                 }
             }
-            // TCP parsing is done inline above
+            // TCP/TLS parsing is done inline above
+        },
+        Protocol::Websocket => {
+            // Open the WebSocket and keep it across pairs (reopened after CONNECTION_CLOSE)
+            let mut socket: Option<WebSocket> = None;
+
+            for (pair_idx, pair) in script.pairs.iter().enumerate() {
+                wait_before_pair(pair, wait_time).await;
+                pair_log.start(pair_idx);
+                if let Err(e) = run_code_blocks_before_pair(server, script, pair_idx, &mut all_parsed_vars, code_vars, warnings).await {
+                    last_error = Some(e);
+                    break;
+                }
+                let read_timeout = pair_read_timeout(server, pair);
+                if pair.close_connection_before {
+                    if let Some(mut ws) = socket.take() {
+                        let _ = ws.close(None).await;
+                    }
+                }
+
+                if socket.is_none() {
+                    match connect_websocket(server).await {
+                        Ok(ws) => {
+                            socket = Some(ws);
+                        },
+                        Err(e) => {
+                            last_error = Some(e);
+                            break;
+                        }
+                    }
+                }
+
+                let pair_packets = match build_packets_for_pair(pair, &all_parsed_vars, script.big_endian) {
+                    Ok(packets) => packets,
+                    Err(e) => {
+                        last_error = Some(GameServerError {
+                            error_type: "BuildError".to_string(),
+                            message: format!("Pair {}: {}", pair_idx + 1, e),
+                            line: error_line(&e),
+                        });
+                        break;
+                    }
+                };
+
+                let ws = match socket.as_mut() {
+                    Some(ws) => ws,
+                    None => break,
+                };
+
+                // Send each packet of the pair as a single frame
+                let mut send_error = None;
+                for (packet_in_pair_idx, packet) in pair_packets.into_iter().enumerate() {
+                    pair_log.sent(&packet);
+                    if let Err(e) = send_websocket_frame(ws, packet, pair.frame_type).await {
+                        send_error = Some(GameServerError {
+                            error_type: "NetworkError".to_string(),
+                            message: format!("Failed to send packet {} of pair {}: {}", packet_in_pair_idx + 1, pair_idx + 1, e),
+                            line: None,
+                        });
+                        break;
+                    }
+                }
+                if send_error.is_some() {
+                    last_error = send_error;
+                    break;
+                }
+
+                if !pair.response.is_empty() {
+                    match receive_websocket_frame(ws, read_timeout).await {
+                        Ok(frame) => {
+                            pair_log.received(frame.clone());
+                            match parse_frame_response(&pair.response, &frame, script.big_endian) {
+                                Ok(vars) => {
+                                    pair_log.variables(&vars);
+                                    all_parsed_vars.extend(vars);
+                                    if let Err(failed) = check_response_assertions(&pair.response, &all_parsed_vars) {
+                                        last_error = Some(response_assertion_error(pair_idx, failed));
+                                        break;
+                                    }
+                                }
+                                Err(e) => {
+                                    out::error("gameserver_check", &format!("Pair {} response parsing failed: {}", pair_idx + 1, e));
+                                    last_error = Some(GameServerError {
+                                        error_type: "ParseError".to_string(),
+                                        message: format!("Pair {}: {}", pair_idx + 1, e),
+                                        line: error_line(&e),
+                                    });
+                                    break;
+                                }
+                            }
+                        },
+                        Err(e) => {
+                            last_error = Some(read_error(pair_idx, e, read_timeout));
+                            break;
+                        }
+                    }
+                }
+            }
+        },
+        Protocol::Quic => {
+            // Keep one QUIC connection across pairs (reopened after CONNECTION_CLOSE) and open a
+            // bidirectional stream for each pair
+            let mut connection: Option<quinn::Connection> = None;
+
+            for (pair_idx, pair) in script.pairs.iter().enumerate() {
+                wait_before_pair(pair, wait_time).await;
+                pair_log.start(pair_idx);
+                if let Err(e) = run_code_blocks_before_pair(server, script, pair_idx, &mut all_parsed_vars, code_vars, warnings).await {
+                    last_error = Some(e);
+                    break;
+                }
+                let read_timeout = pair_read_timeout(server, pair);
+                if pair.close_connection_before {
+                    if let Some(conn) = connection.take() {
+                        conn.close(0u32.into(), b"");
+                    }
+                }
+
+                if connection.is_none() {
+                    let mut breakdown = HttpTimingBreakdown::default();
+                    match connect_quic(server, &mut breakdown).await {
+                        Ok(conn) => {
+                            connection = Some(conn);
+                            timing.get_or_insert(breakdown);
+                        },
+                        Err(e) => {
+                            last_error = Some(e);
+                            break;
+                        }
+                    }
+                }
+
+                let pair_packets = match build_packets_for_pair(pair, &all_parsed_vars, script.big_endian) {
+                    Ok(packets) => packets,
+                    Err(e) => {
+                        last_error = Some(GameServerError {
+                            error_type: "BuildError".to_string(),
+                            message: format!("Pair {}: {}", pair_idx + 1, e),
+                            line: error_line(&e),
+                        });
+                        break;
+                    }
+                };
+
+                let conn = match connection.as_ref() {
+                    Some(conn) => conn,
+                    None => break,
+                };
+                let (mut send, mut recv) = match conn.open_bi().await {
+                    Ok(streams) => streams,
+                    Err(e) => {
+                        last_error = Some(network_error(format!("Pair {}: Failed to open QUIC stream: {}", pair_idx + 1, e)));
+                        connection = None; // Connection is likely broken
+                        break;
+                    }
+                };
+
+                // Send all packets of the pair on the stream, then finish our side of it
+                let mut send_error = None;
+                for (packet_in_pair_idx, packet) in pair_packets.iter().enumerate() {
+                    pair_log.sent(packet);
+                    if let Err(e) = send.write_all(packet).await {
+                        send_error = Some(network_error(format!(
+                            "Failed to send packet {} of pair {}: {}",
+                            packet_in_pair_idx + 1,
+                            pair_idx + 1,
+                            e
+                        )));
+                        break;
+                    }
+                }
+                if send_error.is_none() {
+                    if let Err(e) = send.finish() {
+                        send_error = Some(network_error(format!("Pair {}: Failed to finish QUIC stream: {}", pair_idx + 1, e)));
+                    }
+                }
+                if send_error.is_some() {
+                    last_error = send_error;
+                    connection = None; // Connection is likely broken
+                    break;
+                }
+
+                if !pair.no_response && !pair.response.is_empty() {
+                    match receive_quic_stream(&mut recv, read_timeout, server.max_response_bytes()).await {
+                        Ok(response) => {
+                            pair_log.received(response.clone());
+
+                            // Parse the response immediately so variables are available for next pair
+                            match parse_response(&pair.response, &response, script.big_endian) {
+                                Ok((vars, _bytes_read)) => {
+                                    pair_log.variables(&vars);
+                                    all_parsed_vars.extend(vars);
+                                    if let Err(failed) = check_response_assertions(&pair.response, &all_parsed_vars) {
+                                        last_error = Some(response_assertion_error(pair_idx, failed));
+                                        break;
+                                    }
+                                }
+                                Err(e) => {
+                                    out::error("gameserver_check", &format!("Pair {} response parsing failed: {}", pair_idx + 1, e));
+                                    last_error = Some(GameServerError {
+                                        error_type: "ParseError".to_string(),
+                                        message: format!("Pair {}: {}", pair_idx + 1, e),
+                                        line: error_line(&e),
+                                    });
+                                    break;
+                                }
+                            }
+                        },
+                        Err(e) => {
+                            last_error = Some(read_error(pair_idx, e, read_timeout));
+                            break;
+                        }
+                    }
+                }
+            }
+            if let Some(conn) = connection {
+                conn.close(0u32.into(), b"");
+            }
         },
         Protocol::Http | Protocol::Https => {
             let is_https = server.protocol == Protocol::Https;
-            let scheme = if is_https { "https" } else { "http" };
-            let default_port = if is_https { 443 } else { 80 };
+            let base_url = http_base_url(server);
             
-            // Build base URL - use IP only, add port only if non-default
-            let base_url = if server.port == default_port {
-                format!("{}://{}", scheme, server.address)
-            } else {
-                format!("{}://{}:{}", scheme, server.address, server.port)
-            };
-            
-            let client = match reqwest::Client::builder()
-                .timeout(std::time::Duration::from_millis(server.timeout_ms))
+            // reqwest doesn't expose its connection phases, so time DNS, connect and TLS on a
+            // separate connection first, at the cost of one extra connection per check, and let the
+            // client reuse the resolved address (IP literals need no lookup).
+            // These are estimates of the client's own connection, and a failed probe only loses them.
+            let mut breakdown = HttpTimingBreakdown { estimated: true, ..Default::default() };
+            let mut client_builder = reqwest::Client::builder()
+                .connect_timeout(server.connect_timeout())
+                .timeout(server.read_timeout())
                 .danger_accept_invalid_certs(true) // Accept self-signed certs for HTTPS
-                .build() {
+                // COOKIES ON: later requests of this check send the cookies set by earlier responses
+                .cookie_store(script.cookies);
+            if script.pairs.iter().any(|pair| pair.http_request.is_some()) {
+                match probe_http_timing(server, is_https, &mut breakdown).await {
+                    Ok(addr) if server.address.parse::<std::net::IpAddr>().is_err() => {
+                        client_builder = client_builder.resolve(&server.address, addr);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        out::warning("gameserver_check", &format!("Timing probe for {} failed: {}", server.name, e.message));
+                        breakdown = HttpTimingBreakdown { estimated: true, ..Default::default() };
+                    }
+                }
+            }
+            let client = match client_builder.build() {
                 Ok(c) => c,
                 Err(e) => {
                     last_error = Some(GameServerError {
@@ -269,21 +667,17 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                         message: format!("Failed to create HTTP client: {}", e),
                         line: None,
                     });
-                    // Break out of match, will return error result
-                    return GameServerTestResult {
-                        success: false,
-                        response_time_ms: start.elapsed().as_millis() as u64,
-                        raw_response: None,
-                        parsed_values: serde_json::json!({}),
-                        variables: serde_json::json!({}),
-                        error: last_error,
-                        output_labels_success: Vec::new(),
-                        output_labels_error: Vec::new(),
-                    };
+                    return (pair_log.finish(), all_parsed_vars, last_error);
                 }
             };
             
             for (pair_idx, pair) in script.pairs.iter().enumerate() {
+                wait_before_pair(pair, wait_time).await;
+                pair_log.start(pair_idx);
+                if let Err(e) = run_code_blocks_before_pair(server, script, pair_idx, &mut all_parsed_vars, code_vars, warnings).await {
+                    last_error = Some(e);
+                    break;
+                }
                 // Check if this is an HTTP request or binary packets
                 if let Some(http_req) = &pair.http_request {
                     // Build HTTP request with current variables
@@ -293,15 +687,15 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                             last_error = Some(GameServerError {
                                 error_type: "BuildError".to_string(),
                                 message: format!("Pair {}: {}", pair_idx + 1, e),
-                                line: None,
+                                line: error_line(&e),
                             });
                             break;
                         }
                     };
                     
                     // Build full URL with path and query parameters
-                    let mut url = match reqwest::Url::parse(&format!("{}{}", base_url, prepared_req.path)) {
-                        Ok(u) => u,
+                    let url = match request_url(&base_url, &prepared_req) {
+                        Ok(url) => url,
                         Err(e) => {
                             last_error = Some(GameServerError {
                                 error_type: "BuildError".to_string(),
@@ -312,16 +706,6 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                         }
                     };
                     
-                    // Add query parameters
-                    if !prepared_req.params.is_empty() {
-                        let mut query_pairs = url.query_pairs_mut();
-                        for (k, v) in &prepared_req.params {
-                            query_pairs.append_pair(k, v);
-                        }
-                        drop(query_pairs); // Explicitly drop to apply changes
-                    }
-                    let url = url.to_string();
-                    
                     // Build request
                     let request_builder = match prepared_req.method.as_str() {
                         "GET" => client.get(&url),
@@ -366,6 +750,7 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                     
                     // Add body if present
                     let request_builder = if let Some((content_type, body_bytes)) = &prepared_req.body {
+                        pair_log.sent(body_bytes);
                         request_builder
                             .header("Content-Type", content_type)
                             .body(body_bytes.clone())
@@ -373,47 +758,78 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                         request_builder
                     };
                     
+                    // Per-pair TIMEOUT overrides the client's read timeout
+                    let request_builder = match pair.read_timeout_ms {
+                        Some(_) => request_builder.timeout(pair_read_timeout(server, pair)),
+                        None => request_builder,
+                    };
+                    
                     // Send request
+                    let send_start = Instant::now();
                     let response = match request_builder.send().await {
                         Ok(resp) => resp,
                         Err(e) => {
-                            last_error = Some(GameServerError {
-                                error_type: "NetworkError".to_string(),
-                                message: format!("Pair {}: HTTP request failed: {}", pair_idx + 1, e),
-                                line: None,
-                            });
+                            last_error = Some(http_error(pair_idx, "HTTP request failed", &e, server, pair));
                             break;
                         }
                     };
+                    if timing.is_none() {
+                        // Time to the response headers of the first request, minus the connection setup
+                        let setup_ms = breakdown.connect_ms + breakdown.tls_ms;
+                        breakdown.ttfb_ms = (send_start.elapsed().as_millis() as u64).saturating_sub(setup_ms);
+                        *timing = Some(breakdown.clone());
+                    }
                     
                     let status_code = response.status().as_u16();
                     let headers = response.headers().clone();
-                    let body_bytes = match response.bytes().await {
-                        Ok(bytes) => bytes.to_vec(),
-                        Err(e) => {
-                            last_error = Some(GameServerError {
-                                error_type: "NetworkError".to_string(),
-                                message: format!("Pair {}: Failed to read response body: {}", pair_idx + 1, e),
-                                line: None,
-                            });
-                            break;
+                    // Read the body in chunks so an oversized response is cut off instead of buffered whole
+                    let max_body_bytes = server.max_http_response_bytes();
+                    let mut response = response;
+                    let mut body_bytes = Vec::new();
+                    let mut read_error = None;
+                    loop {
+                        match response.chunk().await {
+                            Ok(Some(chunk)) if body_bytes.len() + chunk.len() > max_body_bytes => {
+                                body_bytes.extend_from_slice(&chunk[..max_body_bytes - body_bytes.len()]);
+                                read_error = Some(GameServerError {
+                                    error_type: "ParseError".to_string(),
+                                    message: format!("Pair {}: Response body is larger than {} bytes", pair_idx + 1, max_body_bytes),
+                                    line: None,
+                                });
+                                break;
+                            }
+                            Ok(Some(chunk)) => body_bytes.extend_from_slice(&chunk),
+                            Ok(None) => break,
+                            Err(e) => {
+                                read_error = Some(http_error(pair_idx, "Failed to read response body", &e, server, pair));
+                                break;
+                            }
                         }
-                    };
+                    }
                     
-                    all_responses.push(body_bytes.clone());
+                    pair_log.received(body_bytes.clone());
+                    if let Some(e) = read_error {
+                        last_error = Some(e);
+                        break;
+                    }
                     
                     // Parse HTTP response
                     if !pair.response.is_empty() {
                         match parse_http_response(&pair.response, status_code, &headers, &body_bytes) {
                             Ok(vars) => {
+                                pair_log.variables(&vars);
                                 all_parsed_vars.extend(vars);
+                                if let Err(failed) = check_response_assertions(&pair.response, &all_parsed_vars) {
+                                    last_error = Some(response_assertion_error(pair_idx, failed));
+                                    break;
+                                }
                             }
                             Err(e) => {
                                 out::error("gameserver_check", &format!("Pair {} response parsing failed: {}", pair_idx + 1, e));
                                 last_error = Some(GameServerError {
                                     error_type: "ParseError".to_string(),
                                     message: format!("Pair {}: {}", pair_idx + 1, e),
-                                    line: None,
+                                    line: error_line(&e),
                                 });
                                 break;
                             }
@@ -430,134 +846,719 @@ pub async fn check_game_server(server: &GameServer) -> GameServerTestResult {
                 }
             }
         }
+    }
+
+    (pair_log.finish(), all_parsed_vars, last_error)
+}
+
+/// Parse the server's script with its placeholders substituted, in the script and in the snippets it includes
+fn parse_server_script(server: &GameServer, snippets: &Snippets) -> Result<PacketScript> {
+    let snippets: Snippets = snippets
+        .iter()
+        .map(|(name, code)| (name.clone(), replace_placeholders(code, server)))
+        .collect();
+    parse_script(&replace_placeholders(&server.pseudo_code, server), &snippets)
+}
+
+/// Point an error at the server's script: messages name the snippet line, `line` the `INCLUDE`
+fn relocate_error(error: GameServerError, sources: &SourceMap) -> GameServerError {
+    GameServerError {
+        message: sources.relocate_message(&error.message),
+        line: error.line.map(|line| sources.script_line(line)),
+        ..error
+    }
+}
+
+/// Complexity of the server's script, or `None` if it doesn't parse
+pub fn script_metrics(server: &GameServer, snippets: &Snippets) -> Option<crate::formatter::ScriptMetrics> {
+    let script = parse_server_script(server, snippets).ok()?;
+    Some(crate::formatter::analyze_script(&script))
+}
+
+/// Linter warnings for the server's script; none if it doesn't parse, as the check reports that
+pub fn script_lint_warnings(server: &GameServer, snippets: &Snippets) -> Vec<crate::linter::LintWarning> {
+    let Ok(script) = parse_server_script(server, snippets) else {
+        return Vec::new();
     };
+    let mut warnings = crate::linter::lint_script_with_variables(&script, &server.variables);
+    for warning in &mut warnings {
+        warning.line = warning.line.map(|line| script.sources.script_line(line));
+    }
+    warnings
+}
+
+/// Check that the script's pairs match the server protocol: HTTP/HTTPS servers may only use
+/// HTTP_START/HTTP_END requests, all other protocols may only use PACKET_START/PACKET_END packets.
+/// Scripts that fail to parse are not rejected here; the syntax error is reported when the check runs.
+pub fn validate_script_protocol(server: &GameServer, snippets: &Snippets) -> Result<()> {
+    let script = match parse_server_script(server, snippets) {
+        Ok(s) => s,
+        Err(_) => return Ok(()),
+    };
+
+    let is_http = matches!(server.protocol, Protocol::Http | Protocol::Https);
+    for (pair_idx, pair) in script.pairs.iter().enumerate() {
+        if is_http && !pair.packets.is_empty() {
+            anyhow::bail!(
+                "Pair {}: Binary packets (PACKET_START/PACKET_END) are not supported for {} protocol",
+                pair_idx + 1,
+                server.protocol.as_str()
+            );
+        }
+        if !is_http && pair.http_request.is_some() {
+            anyhow::bail!(
+                "Pair {}: HTTP requests (HTTP_START/HTTP_END) are not supported for {} protocol",
+                pair_idx + 1,
+                server.protocol.as_str()
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn send_single_udp_packet(
+    address: &str,
+    port: u16,
+    packet: &[u8],
+    timeout_ms: u64,
+) -> Result<Vec<u8>> {
+    use tokio::net::UdpSocket;
+    use tokio::time::{timeout, Duration};
+
+    let addr = format!("{}:{}", address, port);
+    let socket = UdpSocket::bind("0.0.0.0:0").await
+        .context("Failed to create UDP socket")?;
+
+    socket
+        .send_to(packet, &addr)
+        .await
+        .context("Failed to send UDP packet")?;
+
+    let mut buf = vec![0u8; 16384];
+    let timeout_duration = Duration::from_millis(timeout_ms);
+
+    match timeout(timeout_duration, socket.recv_from(&mut buf)).await {
+        Ok(Ok((size, _))) => Ok(buf[..size].to_vec()),
+        Ok(Err(e)) => Err(anyhow::anyhow!("Failed to receive UDP response: {}", e)),
+        // Kept as `Elapsed` so `read_error` reports it as a `ReadTimeout`
+        Err(elapsed) => Err(elapsed.into()),
+    }
+}
+
+async fn send_packet_udp_no_response(
+    socket: &tokio::net::UdpSocket,
+    addr: &str,
+    packet: &[u8],
+) -> Result<()> {
+    socket
+        .send_to(packet, addr)
+        .await
+        .context("Failed to send UDP packet")?;
+    Ok(())
+}
+
+async fn receive_packet_udp(
+    socket: &tokio::net::UdpSocket,
+    timeout_ms: u64,
+    max_bytes: usize,
+) -> Result<Vec<u8>> {
+    use tokio::time::{timeout, Duration};
+
+    let mut buf = vec![0u8; max_bytes.min(MAX_UDP_DATAGRAM)];
+    let timeout_duration = Duration::from_millis(timeout_ms);
+
+    match timeout(timeout_duration, socket.recv_from(&mut buf)).await {
+        Ok(Ok((size, _))) => Ok(buf[..size].to_vec()),
+        Ok(Err(e)) => Err(anyhow::anyhow!("Failed to receive UDP response: {}", e)),
+        Err(elapsed) => Err(elapsed.into()),
+    }
+}
+
+/// Receive up to `expect.max` datagrams. The first one waits for the full `timeout_ms`,
+/// later ones stop the read as soon as `expect.idle_ms` passes without a new datagram.
+async fn receive_packets_udp(
+    socket: &tokio::net::UdpSocket,
+    timeout_ms: u64,
+    expect: ExpectPackets,
+    max_bytes: usize,
+) -> Result<Vec<Vec<u8>>> {
+    use tokio::time::{timeout, Duration};
+
+    let mut datagrams = vec![receive_packet_udp(socket, timeout_ms, max_bytes).await?];
+    let mut buf = vec![0u8; max_bytes.min(MAX_UDP_DATAGRAM)];
+    while datagrams.len() < expect.max {
+        match timeout(Duration::from_millis(expect.idle_ms), socket.recv_from(&mut buf)).await {
+            Ok(Ok((size, _))) => datagrams.push(buf[..size].to_vec()),
+            Ok(Err(e)) => return Err(anyhow::anyhow!("Failed to receive UDP response: {}", e)),
+            Err(_) => break,
+        }
+    }
+    Ok(datagrams)
+}
+
+async fn send_packet_udp(
+    socket: &tokio::net::UdpSocket,
+    addr: &str,
+    packet: &[u8],
+    timeout_ms: u64,
+    max_bytes: usize,
+) -> Result<Vec<u8>> {
+    send_packet_udp_no_response(socket, addr, packet).await?;
+    receive_packet_udp(socket, timeout_ms, max_bytes).await
+}
+
+/// What one pair sent and received, recorded while the pairs execute
+struct PairRecord {
+    index: usize,
+    started: Instant,
+    duration: std::time::Duration,
+    sent: Vec<Vec<u8>>,
+    received: Vec<Vec<u8>>,
+    variables: IndexMap<String, Value>,
+}
+
+/// Records of the pairs executed so far; the last one is the pair currently running
+#[derive(Default)]
+struct PairLog {
+    records: Vec<PairRecord>,
+}
+
+impl PairLog {
+    fn start(&mut self, pair_idx: usize) {
+        self.stop_current();
+        self.records.push(PairRecord {
+            index: pair_idx + 1,
+            started: Instant::now(),
+            duration: std::time::Duration::ZERO,
+            sent: Vec::new(),
+            received: Vec::new(),
+            variables: IndexMap::new(),
+        });
+    }
+
+    fn sent(&mut self, packet: &[u8]) {
+        if let Some(record) = self.records.last_mut() {
+            record.sent.push(packet.to_vec());
+        }
+    }
+
+    fn received(&mut self, response: Vec<u8>) {
+        if let Some(record) = self.records.last_mut() {
+            record.received.push(response);
+        }
+    }
+
+    fn variables(&mut self, vars: &IndexMap<String, Value>) {
+        if let Some(record) = self.records.last_mut() {
+            record.variables.extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+    }
+
+    fn stop_current(&mut self) {
+        if let Some(record) = self.records.last_mut() {
+            record.duration = record.started.elapsed();
+        }
+    }
+
+    fn finish(mut self) -> Vec<PairRecord> {
+        self.stop_current();
+        self.records
+    }
+}
+
+/// Turn the pair records into per-pair results and the legacy `raw_response` (every response's hex,
+/// space-separated). Execution stops at the first error, so it belongs to the last pair.
+/// Each packet/response keeps at most `max_raw_response_bytes()`; also returns whether any was cut short.
+fn encode_pair_records(records: Vec<PairRecord>, error: Option<&GameServerError>) -> (Vec<PairResult>, String, bool) {
+    let max = max_raw_response_bytes();
+    let mut truncated = false;
+    let mut encode = |bytes: &Vec<u8>| {
+        truncated |= bytes.len() > max;
+        hex::encode(&bytes[..bytes.len().min(max)])
+    };
+
+    let count = records.len();
+    let pairs: Vec<PairResult> = records
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| PairResult {
+            index: record.index,
+            sent_hex: record.sent.iter().map(&mut encode).collect(),
+            received_hex: record.received.iter().map(&mut encode).collect(),
+            duration_ms: record.duration.as_millis() as u64,
+            variables: record.variables.into_iter().collect(),
+            error: if i + 1 == count { error.cloned() } else { None },
+        })
+        .collect();
+    let raw_response = pairs
+        .iter()
+        .flat_map(|pair| pair.received_hex.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    (pairs, raw_response, truncated)
+}
+
+/// Bytes of each response kept in `raw_response`, from `NET_SENTINEL_MAX_RAW_RESPONSE_BYTES` (default 64 KiB)
+fn max_raw_response_bytes() -> usize {
+    static MAX: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+    *MAX.get_or_init(|| {
+        std::env::var("NET_SENTINEL_MAX_RAW_RESPONSE_BYTES")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .filter(|&bytes| bytes > 0)
+            .unwrap_or(64 * 1024)
+    })
+}
+
+/// Run the code blocks placed right before pair `pair_idx`. Failures are logged like for
+/// trailing code blocks and the pair then runs without the block's variables; only a timeout is an error.
+async fn run_code_blocks_before_pair(
+    server: &GameServer,
+    script: &PacketScript,
+    pair_idx: usize,
+    parsed_vars: &mut IndexMap<String, Value>,
+    code_vars: &mut IndexMap<String, Value>,
+    warnings: &mut Vec<String>,
+) -> std::result::Result<(), GameServerError> {
+    let blocks = script.code_blocks_before(pair_idx);
+    if blocks.is_empty() {
+        return Ok(());
+    }
+    match run_code_blocks(server, blocks, &script.functions, parsed_vars, code_vars, warnings).await? {
+        Ok(vars) => {
+            parsed_vars.extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+            code_vars.extend(vars);
+        }
+        Err(e) => out::error("gameserver_check", &format!("Code block before pair {} failed: {}", pair_idx + 1, e)),
+    }
+    Ok(())
+}
+
+/// Execute code blocks on the blocking thread pool so a long-running block can't stall the runtime.
+/// Gives up after the server's `code_timeout_ms`; the outer error is the timeout, a loop over its iteration limit or a failed assertion,
+/// the inner one a failed block.
+/// A block that times out is not interrupted, its result is just discarded.
+/// Unless the server turned them off, a shadowing warning is added to `warnings` for each declared
+/// variable that came from a response; `code_vars` from earlier blocks don't count as parsed.
+async fn run_code_blocks(
+    server: &GameServer,
+    blocks: &[CodeBlock],
+    functions: &IndexMap<String, ScriptFunction>,
+    parsed_vars: &IndexMap<String, Value>,
+    code_vars: &IndexMap<String, Value>,
+    warnings: &mut Vec<String>,
+) -> std::result::Result<Result<IndexMap<String, Value>>, GameServerError> {
+    if blocks.is_empty() {
+        return Ok(Ok(IndexMap::new()));
+    }
+    let blocks = blocks.to_vec();
+    let functions = functions.clone();
+    let mut parsed_vars = parsed_vars.clone();
+    let max_loop_iterations = server.max_loop_iterations();
+    let task = tokio::task::spawn_blocking(move || {
+        let mut shadowed = Vec::new();
+        let result = execute_code_blocks(&blocks, &functions, &mut parsed_vars, max_loop_iterations, &mut shadowed);
+        (result, shadowed)
+    });
+    let task = async {
+        let (result, shadowed) = task.await?;
+        if server.shadow_warning_enabled {
+            for name in shadowed.iter().filter(|name| !code_vars.contains_key(*name)) {
+                let warning = format!("Variable '{}' in CODE block shadows parsed response variable", name);
+                out::warning("exec", &warning);
+                warnings.push(warning);
+            }
+        }
+        Ok::<_, tokio::task::JoinError>(result)
+    };
+    match tokio::time::timeout(server.code_timeout(), task).await {
+        // A runaway loop fails the check instead of only being logged like other block errors
+        Ok(Ok(Err(e))) if e.downcast_ref::<LoopLimitExceeded>().is_some() => Err(GameServerError {
+            error_type: "ScriptError".to_string(),
+            line: e.downcast_ref::<LoopLimitExceeded>().map(|limit| limit.line),
+            message: e.to_string(),
+        }),
+        // So do EXPECT_VAR, ASSERT and FAIL, with the script's own message
+        Ok(Ok(Err(e))) => match e.downcast::<AssertionFailed>() {
+            Ok(failed) => Err(assertion_error(failed)),
+            Err(e) => Ok(Err(e)),
+        },
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(e)) => Ok(Err(anyhow::anyhow!("Code block execution panicked: {}", e))),
+        Err(_) => Err(GameServerError {
+            error_type: "Timeout".to_string(),
+            message: "Code block execution timed out".to_string(),
+            line: None,
+        }),
+    }
+}
+
+fn assertion_error(failed: AssertionFailed) -> GameServerError {
+    GameServerError {
+        error_type: failed.kind.error_type().to_string(),
+        message: failed.message,
+        line: Some(failed.line),
+    }
+}
+
+/// Failed `EXPECT_VAR` in a pair's response
+fn response_assertion_error(pair_idx: usize, failed: AssertionFailed) -> GameServerError {
+    out::warning("gameserver_check", &format!("Pair {} assertion failed at line {}: {}", pair_idx + 1, failed.line, failed.message));
+    assertion_error(failed)
+}
+
+/// Receive timeout for a pair: its `TIMEOUT` directive if set, otherwise the server's read timeout
+fn pair_read_timeout(server: &GameServer, pair: &PacketResponsePair) -> std::time::Duration {
+    match pair.read_timeout_ms {
+        Some(ms) => std::time::Duration::from_millis(ms),
+        None => server.read_timeout(),
+    }
+}
+
+/// Sleep for the pair's `DELAY` time, if any
+async fn wait_before_pair(pair: &PacketResponsePair, wait_time: &mut std::time::Duration) {
+    if pair.delay_before_ms > 0 {
+        let delay = std::time::Duration::from_millis(pair.delay_before_ms);
+        tokio::time::sleep(delay).await;
+        *wait_time += delay;
+    }
+}
+
+/// Error types that are worth retrying: anything that went wrong on the network side
+fn is_retryable(error_type: &str) -> bool {
+    matches!(error_type, "NetworkError" | "ConnectTimeout" | "ReadTimeout")
+}
+
+fn network_error(message: String) -> GameServerError {
+    GameServerError {
+        error_type: "NetworkError".to_string(),
+        message,
+        line: None,
+    }
+}
+
+fn connect_timeout_error(what: &str, timeout_duration: tokio::time::Duration) -> GameServerError {
+    GameServerError {
+        error_type: "ConnectTimeout".to_string(),
+        message: format!("{} timed out after {}ms", what, timeout_duration.as_millis()),
+        line: None,
+    }
+}
 
-    let response_time_ms = start.elapsed().as_millis() as u64;
-    let raw_response_hex = if all_responses.len() == 1 {
-        hex::encode(&all_responses[0])
+/// Error for a failed response read, reported as `ReadTimeout` when the read timed out
+fn read_error(pair_idx: usize, e: anyhow::Error, timeout_duration: tokio::time::Duration) -> GameServerError {
+    if e.downcast_ref::<tokio::time::error::Elapsed>().is_some() {
+        GameServerError {
+            error_type: "ReadTimeout".to_string(),
+            message: format!("Pair {}: No response within {}ms", pair_idx + 1, timeout_duration.as_millis()),
+            line: None,
+        }
     } else {
-        // Multiple responses - concatenate hex strings
-        all_responses.iter().map(|r| hex::encode(r)).collect::<Vec<_>>().join(" ")
-    };
+        network_error(format!("Pair {}: {}", pair_idx + 1, e))
+    }
+}
 
-    // Execute code blocks (variables from CODE_START/CODE_END)
-    // Do this even if there's an error, so variables are available for error output
-    let code_variables = match execute_code_blocks(&script.code_blocks, &mut all_parsed_vars) {
-        Ok(vars) => vars,
-        Err(e) => {
-            out::error("gameserver_check", &format!("Code block execution failed: {}", e));
-            // Continue anyway, but log the error
-            IndexMap::new()
+/// Map a reqwest error to `ConnectTimeout`, `ReadTimeout` or `NetworkError`
+fn http_error(pair_idx: usize, what: &str, e: &reqwest::Error, server: &GameServer, pair: &PacketResponsePair) -> GameServerError {
+    if e.is_timeout() && e.is_connect() {
+        GameServerError {
+            error_type: "ConnectTimeout".to_string(),
+            message: format!("Pair {}: Connection timed out after {}ms", pair_idx + 1, server.connect_timeout().as_millis()),
+            line: None,
         }
+    } else if e.is_timeout() {
+        GameServerError {
+            error_type: "ReadTimeout".to_string(),
+            message: format!("Pair {}: No response within {}ms", pair_idx + 1, pair_read_timeout(server, pair).as_millis()),
+            line: None,
+        }
+    } else {
+        network_error(format!("Pair {}: {}: {}", pair_idx + 1, what, e))
+    }
+}
+
+/// A connected byte stream: plain TCP or TLS over TCP
+trait AsyncStream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+/// Open a TCP connection to the server, performing a TLS handshake on top of it for `Protocol::Tls`.
+/// The connect timeout applies to the TCP connect and the TLS handshake separately.
+/// The time each step took is stored in `breakdown`.
+async fn connect_stream(
+    server: &GameServer,
+    breakdown: &mut HttpTimingBreakdown,
+) -> std::result::Result<Box<dyn AsyncStream>, GameServerError> {
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+
+    let timeout_duration = server.connect_timeout();
+    let addr = format!("{}:{}", server.address, server.port);
+    let started = Instant::now();
+    let tcp = match timeout(timeout_duration, TcpStream::connect(&addr)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => return Err(network_error(format!("Failed to connect to server: {}", e))),
+        Err(_) => return Err(connect_timeout_error("Connection", timeout_duration)),
     };
+    breakdown.connect_ms = started.elapsed().as_millis() as u64;
 
-    // Merge code variables into parsed vars for output block evaluation
-    // Code variables can override parsed vars if they have the same name
-    let mut all_vars = all_parsed_vars.clone();
-    for (key, value) in code_variables.iter() {
-        all_vars.insert(key.clone(), value.clone());
+    if server.protocol != Protocol::Tls {
+        return Ok(Box::new(tcp));
     }
 
-    if let Some(err) = last_error {
-        let error_labels = evaluate_output_labels(&script, OutputStatus::Error, &mut all_vars.clone(), server, Some(&err));
-        return GameServerTestResult {
-            success: false,
-            response_time_ms,
-            raw_response: Some(raw_response_hex),
-            parsed_values: serde_json::json!({}),
-            variables: serde_json::json!({}),
-            error: Some(err),
-            output_labels_success: Vec::new(),
-            output_labels_error: error_labels,
-        };
+    let connector = tokio_native_tls::native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(server.insecure_skip_verify)
+        .danger_accept_invalid_hostnames(server.insecure_skip_verify)
+        .build()
+        .map_err(|e| network_error(format!("Failed to create TLS connector: {}", e)))?;
+    let connector = tokio_native_tls::TlsConnector::from(connector);
+    let domain = server.sni.as_deref().unwrap_or(&server.address);
+
+    let started = Instant::now();
+    let tls = match timeout(timeout_duration, connector.connect(domain, tcp)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => return Err(network_error(format!("TLS handshake failed: {}", e))),
+        Err(_) => return Err(connect_timeout_error("TLS handshake", timeout_duration)),
+    };
+    breakdown.tls_ms = started.elapsed().as_millis() as u64;
+    Ok(Box::new(tls))
+}
+
+/// First address of the server, looked up within the connect timeout
+async fn resolve_udp_address(server: &GameServer) -> std::result::Result<std::net::SocketAddr, GameServerError> {
+    let timeout_duration = server.connect_timeout();
+    match tokio::time::timeout(timeout_duration, tokio::net::lookup_host((server.address.as_str(), server.port))).await {
+        Ok(Ok(mut addrs)) => addrs
+            .next()
+            .ok_or_else(|| network_error(format!("No addresses found for {}", server.address))),
+        Ok(Err(e)) => Err(network_error(format!("Failed to resolve {}: {}", server.address, e))),
+        Err(_) => Err(connect_timeout_error("DNS lookup", timeout_duration)),
     }
+}
 
-    // All pairs succeeded
-    let success_labels = evaluate_output_labels(&script, OutputStatus::Success, &mut all_vars.clone(), server, None);
-    strip_placeholder_vars(&mut all_parsed_vars);
-    let parsed_values: serde_json::Value = all_parsed_vars.clone().into_iter().collect();
-    let variables: serde_json::Value = code_variables.into_iter().collect();
+/// Resolve, connect and (for HTTPS) complete a TLS handshake with the server, storing the time
+/// each step took in `breakdown`. The connection is dropped; the resolved address is returned.
+async fn probe_http_timing(
+    server: &GameServer,
+    is_https: bool,
+    breakdown: &mut HttpTimingBreakdown,
+) -> std::result::Result<std::net::SocketAddr, GameServerError> {
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
 
-    GameServerTestResult {
-        success: true,
-        response_time_ms,
-        raw_response: Some(raw_response_hex),
-        parsed_values,
-        variables,
-        error: None,
-        output_labels_success: success_labels,
-        output_labels_error: Vec::new(),
+    let timeout_duration = server.connect_timeout();
+    let started = Instant::now();
+    let addr = match timeout(timeout_duration, tokio::net::lookup_host((server.address.as_str(), server.port))).await {
+        Ok(Ok(mut addrs)) => addrs
+            .next()
+            .ok_or_else(|| network_error(format!("No addresses found for {}", server.address)))?,
+        Ok(Err(e)) => return Err(network_error(format!("Failed to resolve {}: {}", server.address, e))),
+        Err(_) => return Err(connect_timeout_error("DNS lookup", timeout_duration)),
+    };
+    breakdown.dns_ms = started.elapsed().as_millis() as u64;
+
+    let started = Instant::now();
+    let tcp = match timeout(timeout_duration, TcpStream::connect(addr)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => return Err(network_error(format!("Failed to connect to server: {}", e))),
+        Err(_) => return Err(connect_timeout_error("Connection", timeout_duration)),
+    };
+    breakdown.connect_ms = started.elapsed().as_millis() as u64;
+
+    if is_https {
+        // Same certificate handling as the HTTP client
+        let connector = tokio_native_tls::native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .map_err(|e| network_error(format!("Failed to create TLS connector: {}", e)))?;
+        let connector = tokio_native_tls::TlsConnector::from(connector);
+        let started = Instant::now();
+        match timeout(timeout_duration, connector.connect(&server.address, tcp)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(network_error(format!("TLS handshake failed: {}", e))),
+            Err(_) => return Err(connect_timeout_error("TLS handshake", timeout_duration)),
+        }
+        breakdown.tls_ms = started.elapsed().as_millis() as u64;
     }
+    Ok(addr)
 }
 
-async fn send_single_udp_packet(
-    address: &str,
-    port: u16,
-    packet: &[u8],
-    timeout_ms: u64,
-) -> Result<Vec<u8>> {
-    use tokio::net::UdpSocket;
-    use tokio::time::{timeout, Duration};
+/// Connect to the server over QUIC within the connect timeout, storing the handshake time in
+/// `breakdown`. Certificates are checked against the platform's trust store unless the server
+/// sets `danger_accept_invalid_certs`, and the server's `alpn` protocols are offered.
+async fn connect_quic(
+    server: &GameServer,
+    breakdown: &mut HttpTimingBreakdown,
+) -> std::result::Result<quinn::Connection, GameServerError> {
+    use tokio::time::timeout;
 
-    let addr = format!("{}:{}", address, port);
-    let socket = UdpSocket::bind("0.0.0.0:0").await
-        .context("Failed to create UDP socket")?;
+    let addr = resolve_udp_address(server).await?;
+    let client_config = quic_client_config(server)
+        .map_err(|e| network_error(format!("Failed to create QUIC client: {}", e)))?;
+    let bind_addr: std::net::SocketAddr = if addr.is_ipv6() {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let mut endpoint = quinn::Endpoint::client(bind_addr)
+        .map_err(|e| network_error(format!("Failed to create QUIC endpoint: {}", e)))?;
+    endpoint.set_default_client_config(client_config);
 
-    socket
-        .send_to(packet, &addr)
-        .await
-        .context("Failed to send UDP packet")?;
+    let server_name = server.sni.as_deref().unwrap_or(&server.address);
+    let connecting = endpoint
+        .connect(addr, server_name)
+        .map_err(|e| network_error(format!("Failed to connect to server: {}", e)))?;
 
-    let mut buf = vec![0u8; 16384];
-    let timeout_duration = Duration::from_millis(timeout_ms);
+    let timeout_duration = server.connect_timeout();
+    let started = Instant::now();
+    let connection = match timeout(timeout_duration, connecting).await {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => return Err(network_error(format!("QUIC handshake failed: {}", e))),
+        Err(_) => return Err(connect_timeout_error("QUIC handshake", timeout_duration)),
+    };
+    breakdown.quic_handshake_ms = started.elapsed().as_millis() as u64;
+    Ok(connection)
+}
 
-    match timeout(timeout_duration, socket.recv_from(&mut buf)).await {
-        Ok(Ok((size, _))) => Ok(buf[..size].to_vec()),
-        Ok(Err(e)) => Err(anyhow::anyhow!("Failed to receive UDP response: {}", e)),
-        Err(_) => Err(anyhow::anyhow!("UDP request timed out after {}ms", timeout_ms)),
+fn quic_client_config(server: &GameServer) -> Result<quinn::ClientConfig> {
+    use quinn::rustls;
+    use rustls_platform_verifier::BuilderVerifierExt;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?;
+    let mut crypto = if server.danger_accept_invalid_certs {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert(provider)))
+            .with_no_client_auth()
+    } else {
+        builder.with_platform_verifier()?.with_no_client_auth()
+    };
+    // Most QUIC servers refuse a handshake that doesn't offer a protocol they speak
+    crypto.alpn_protocols = server.alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?;
+    Ok(quinn::ClientConfig::new(Arc::new(crypto)))
+}
+
+/// Accepts any server certificate and name; handshake signatures are still verified
+#[derive(Debug)]
+struct AcceptAnyServerCert(Arc<quinn::rustls::crypto::CryptoProvider>);
+
+impl quinn::rustls::client::danger::ServerCertVerifier for AcceptAnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &quinn::rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[quinn::rustls::pki_types::CertificateDer<'_>],
+        _server_name: &quinn::rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: quinn::rustls::pki_types::UnixTime,
+    ) -> std::result::Result<quinn::rustls::client::danger::ServerCertVerified, quinn::rustls::Error> {
+        Ok(quinn::rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &quinn::rustls::pki_types::CertificateDer<'_>,
+        dss: &quinn::rustls::DigitallySignedStruct,
+    ) -> std::result::Result<quinn::rustls::client::danger::HandshakeSignatureValid, quinn::rustls::Error> {
+        quinn::rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &quinn::rustls::pki_types::CertificateDer<'_>,
+        dss: &quinn::rustls::DigitallySignedStruct,
+    ) -> std::result::Result<quinn::rustls::client::danger::HandshakeSignatureValid, quinn::rustls::Error> {
+        quinn::rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<quinn::rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
     }
 }
 
-async fn send_packet_udp_no_response(
-    socket: &tokio::net::UdpSocket,
-    addr: &str,
-    packet: &[u8],
-) -> Result<()> {
-    socket
-        .send_to(packet, addr)
+/// Read whatever the server has sent on the stream so far, like `receive_packet_tcp`.
+/// A stream the server finished without data gives an empty response.
+async fn receive_quic_stream(
+    recv: &mut quinn::RecvStream,
+    timeout_duration: tokio::time::Duration,
+    max_bytes: usize,
+) -> Result<Vec<u8>> {
+    use tokio::time::timeout;
+
+    let mut buf = vec![0u8; max_bytes];
+    let size = timeout(timeout_duration, recv.read(&mut buf))
         .await
-        .context("Failed to send UDP packet")?;
-    Ok(())
+        .context("Read timeout")?
+        .context("Failed to read response")?
+        .unwrap_or(0);
+    Ok(buf[..size].to_vec())
 }
 
-async fn receive_packet_udp(
-    socket: &tokio::net::UdpSocket,
-    timeout_ms: u64,
-) -> Result<Vec<u8>> {
-    use tokio::time::{timeout, Duration};
+type WebSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
 
-    let mut buf = vec![0u8; 16384];
-    let timeout_duration = Duration::from_millis(timeout_ms);
+/// Connect to the server's WebSocket endpoint. The address may be a full `ws://` or `wss://` URL,
+/// otherwise `ws://address:port/` is used.
+async fn connect_websocket(server: &GameServer) -> std::result::Result<WebSocket, GameServerError> {
+    use tokio::time::timeout;
 
-    match timeout(timeout_duration, socket.recv_from(&mut buf)).await {
-        Ok(Ok((size, _))) => Ok(buf[..size].to_vec()),
-        Ok(Err(e)) => Err(anyhow::anyhow!("Failed to receive UDP response: {}", e)),
-        Err(_) => Err(anyhow::anyhow!("UDP request timed out after {}ms", timeout_ms)),
+    let timeout_duration = server.connect_timeout();
+    let url = if server.address.starts_with("ws://") || server.address.starts_with("wss://") {
+        server.address.clone()
+    } else {
+        format!("ws://{}:{}/", server.address, server.port)
+    };
+
+    match timeout(timeout_duration, tokio_tungstenite::connect_async(url.as_str())).await {
+        Ok(Ok((ws, _response))) => Ok(ws),
+        Ok(Err(e)) => Err(network_error(format!("WebSocket handshake failed: {}", e))),
+        Err(_) => Err(connect_timeout_error("WebSocket handshake", timeout_duration)),
     }
 }
 
-async fn send_packet_udp(
-    socket: &tokio::net::UdpSocket,
-    addr: &str,
-    packet: &[u8],
-    timeout_ms: u64,
+async fn send_websocket_frame(ws: &mut WebSocket, packet: Vec<u8>, frame_type: FrameType) -> Result<()> {
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let message = match frame_type {
+        FrameType::Text => Message::Text(
+            String::from_utf8(packet).context("Text frames must contain valid UTF-8")?,
+        ),
+        FrameType::Binary => Message::Binary(packet),
+    };
+    ws.send(message).await.context("Failed to send WebSocket frame")?;
+    Ok(())
+}
+
+/// Wait for the next text or binary frame, skipping control frames
+async fn receive_websocket_frame(
+    ws: &mut WebSocket,
+    timeout_duration: tokio::time::Duration,
 ) -> Result<Vec<u8>> {
-    send_packet_udp_no_response(socket, addr, packet).await?;
-    receive_packet_udp(socket, timeout_ms).await
+    use futures::StreamExt;
+    use tokio::time::timeout;
+    use tokio_tungstenite::tungstenite::Message;
+
+    loop {
+        let message = timeout(timeout_duration, ws.next())
+            .await
+            .context("Read timeout")?
+            .ok_or_else(|| anyhow::anyhow!("WebSocket closed by server"))?
+            .context("Failed to read WebSocket frame")?;
+        match message {
+            Message::Text(text) => return Ok(text.into_bytes()),
+            Message::Binary(data) => return Ok(data),
+            Message::Close(_) => anyhow::bail!("WebSocket closed by server"),
+            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+        }
+    }
 }
 
-async fn send_packet_tcp_no_response(
-    stream: &mut tokio::net::TcpStream,
+async fn send_packet_tcp_no_response<S: tokio::io::AsyncWrite + Unpin + ?Sized>(
+    stream: &mut S,
     packet: &[u8],
 ) -> Result<()> {
     use tokio::io::AsyncWriteExt;
@@ -565,17 +1566,22 @@ async fn send_packet_tcp_no_response(
     stream.write_all(packet)
         .await
         .context("Failed to write packet")?;
+    // TLS streams may buffer records until flushed
+    stream.flush()
+        .await
+        .context("Failed to flush packet")?;
     Ok(())
 }
 
-async fn receive_packet_tcp(
-    stream: &mut tokio::net::TcpStream,
+async fn receive_packet_tcp<S: tokio::io::AsyncRead + Unpin + ?Sized>(
+    stream: &mut S,
     timeout_duration: tokio::time::Duration,
+    max_bytes: usize,
 ) -> Result<Vec<u8>> {
     use tokio::io::AsyncReadExt;
     use tokio::time::timeout;
 
-    let mut buf = vec![0u8; 16384];
+    let mut buf = vec![0u8; max_bytes];
     let size = timeout(timeout_duration, stream.read(&mut buf))
         .await
         .context("Read timeout")?
@@ -587,9 +1593,10 @@ async fn send_packet_tcp(
     stream: &mut tokio::net::TcpStream,
     packet: &[u8],
     timeout_duration: tokio::time::Duration,
+    max_bytes: usize,
 ) -> Result<Vec<u8>> {
     send_packet_tcp_no_response(stream, packet).await?;
-    receive_packet_tcp(stream, timeout_duration).await
+    receive_packet_tcp(stream, timeout_duration, max_bytes).await
 }
 
 async fn send_udp_packets(
@@ -619,7 +1626,7 @@ async fn send_udp_packets(
     match timeout(timeout_duration, socket.recv_from(&mut buf)).await {
         Ok(Ok((size, _))) => Ok(buf[..size].to_vec()),
         Ok(Err(e)) => Err(anyhow::anyhow!("Failed to receive UDP response: {}", e)),
-        Err(_) => Err(anyhow::anyhow!("UDP request timed out after {}ms", timeout_ms)),
+        Err(elapsed) => Err(elapsed.into()),
     }
 }
 
@@ -656,13 +1663,14 @@ async fn send_single_tcp_packet(
     Ok(buf[..size].to_vec())
 }
 
+/// Evaluate the output blocks for `status`, returning each label with the name of the block it came from
 fn evaluate_output_labels(
     script: &crate::packet_parser::PacketScript,
     status: OutputStatus,
     vars: &mut IndexMap<String, Value>,
     server: &GameServer,
     error: Option<&GameServerError>,
-) -> Vec<String> {
+) -> Vec<(Option<String>, String)> {
     insert_server_placeholders(vars, server);
     match process_output_blocks(&script.output_blocks, status, vars, server, error) {
         Ok(lines) => lines,
@@ -679,10 +1687,11 @@ fn process_output_blocks(
     vars: &mut IndexMap<String, Value>,
     server: &GameServer,
     error: Option<&GameServerError>,
-) -> Result<Vec<String>> {
+) -> Result<Vec<(Option<String>, String)>> {
     let mut labels = Vec::new();
     for block in blocks.iter().filter(|block| block.status == status) {
-        labels.extend(evaluate_output_block(block, vars, server, error)?);
+        let lines = evaluate_output_block(block, vars, server, error)?;
+        labels.extend(lines.into_iter().map(|line| (block.output_label_name.clone(), line)));
     }
     Ok(labels)
 }
@@ -704,6 +1713,14 @@ fn evaluate_output_block(
                 let result = format_return(template, vars, server, error);
                 results.push(result);
             }
+            OutputCommand::ConditionalReturn { condition, then_template, else_template } => {
+                let template = if evaluate_condition(condition, vars, &IndexMap::new())? {
+                    then_template
+                } else {
+                    else_template
+                };
+                results.push(format_return(template, vars, server, error));
+            }
         }
     }
     Ok(results)
@@ -748,6 +1765,16 @@ fn format_return(
         was_quoted = true;
     }
 
+    // Explicit {var} placeholders; unknown ones are left as written. Templates without
+    // placeholders fall back to the deprecated bare-token substitution below.
+    if has_placeholders(template_str) {
+        let expanded = expand_placeholders(template_str, |path| {
+            Ok(resolve_placeholder(path, vars).unwrap_or_else(|_| format!("{{{}}}", path)))
+        })
+        .unwrap_or_else(|_| template_str.to_string());
+        return if was_quoted { format!("\"{}\"", expanded) } else { expanded };
+    }
+
     // Check if the entire template (after removing quotes) is just a variable name
     if is_valid_var_name(template_str) {
         // Entire template is a variable name, output as "varname=value"
@@ -887,38 +1914,456 @@ fn insert_server_placeholders(vars: &mut IndexMap<String, Value>, server: &GameS
         .or_insert_with(|| Value::Number(server.port.into()));
 }
 
-fn strip_placeholder_vars(vars: &mut IndexMap<String, Value>) {
-    for key in &["HOST", "IP", "HOST_LEN", "IP_LEN", "IP_LEN_HEX", "PORT"] {
-        vars.shift_remove(*key);
+/// The script's `DEFINE` constants, overridden and extended by the server's `variables`
+fn script_constants(script: &PacketScript, server: &GameServer) -> IndexMap<String, Value> {
+    let mut constants = script.defines.clone();
+    let mut overrides: Vec<_> = server.variables.iter().collect();
+    overrides.sort();
+    for (name, value) in overrides {
+        constants.insert(name.clone(), constant_value(value));
     }
+    constants
 }
 
 fn is_token_char(ch: char) -> bool {
     ch.is_ascii_alphabetic() || ch.is_ascii_digit() || ch == '_' || ch == '.'
 }
 
+/// Substitute the server placeholders (`HOST`, `IP`, `PORT`, `HOST_LEN`, `IP_LEN`, `IP_LEN_HEX`) in a script.
+/// Only whole words are replaced, so `SUPPORT` or `ip_whitelist` stay intact; `{HOST}` is replaced braces and all.
 fn replace_placeholders(code: &str, server: &GameServer) -> String {
-    let host = server.address.clone();
-    let host_len = host.len();
-    let ip_len_hex = format!("{:X}", host_len);
-    let mut replaced = code.replace("IP_LEN_HEX", &ip_len_hex);
-    replaced = replaced.replace("HOST_LEN", &host_len.to_string());
-    replaced = replaced.replace("IP_LEN", &host_len.to_string());
-    replaced = replaced.replace("PORT", &server.port.to_string());
-    replaced = replaced.replace("IP", &host);
-    replaced = replaced.replace("HOST", &host);
-    replaced
-}
-
-/// Build packets for a single pair using the provided variables
-fn build_packets_for_pair(pair: &PacketResponsePair, vars: &IndexMap<String, Value>) -> Result<Vec<Vec<u8>>> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    let placeholder_value = |word: &str| match word {
+        "HOST" | "IP" => Some(server.address.clone()),
+        "PORT" => Some(server.port.to_string()),
+        "HOST_LEN" | "IP_LEN" => Some(server.address.len().to_string()),
+        "IP_LEN_HEX" => Some(format!("{:X}", server.address.len())),
+        _ => None,
+    };
+
+    let mut result = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(start) = rest.find(is_word_char) {
+        result.push_str(&rest[..start]);
+        let word_end = rest[start..].find(|c: char| !is_word_char(c)).map_or(rest.len(), |len| start + len);
+        let word = &rest[start..word_end];
+        rest = &rest[word_end..];
+        match placeholder_value(word) {
+            Some(value) => {
+                // A single `{` before and `}` after make it a `{HOST}` placeholder; `{{` is an escaped brace
+                if result.ends_with('{') && !result.ends_with("{{") && rest.starts_with('}') {
+                    result.pop();
+                    rest = &rest[1..];
+                }
+                result.push_str(&value);
+            }
+            None => result.push_str(word),
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Build packets for a single pair using the provided variables and the script's `ENDIAN` default
+fn build_packets_for_pair(pair: &PacketResponsePair, vars: &IndexMap<String, Value>, big_endian: bool) -> Result<Vec<Vec<u8>>> {
     // Create a temporary script with just this pair
     use crate::packet_parser::PacketScript;
     let temp_script = PacketScript {
         pairs: vec![pair.clone()],
         output_blocks: Vec::new(),
         code_blocks: Vec::new(),
+        cookies: false,
+        defines: IndexMap::new(),
+        sources: SourceMap::default(),
+        functions: IndexMap::new(),
+        discarded_packets: Vec::new(),
+        big_endian,
     };
     build_packets_with_vars(&temp_script, vars)
 }
 
+/// `scheme://address`, with the port only when it isn't the scheme's default
+fn http_base_url(server: &GameServer) -> String {
+    let is_https = server.protocol == Protocol::Https;
+    let scheme = if is_https { "https" } else { "http" };
+    let default_port = if is_https { 443 } else { 80 };
+    if server.port == default_port {
+        format!("{}://{}", scheme, server.address)
+    } else {
+        format!("{}://{}:{}", scheme, server.address, server.port)
+    }
+}
+
+/// Full URL of a prepared request: its path on `base_url` plus the query parameters
+fn request_url(base_url: &str, prepared: &PreparedHttpRequest) -> Result<String> {
+    let mut url = reqwest::Url::parse(&format!("{}{}", base_url, prepared.path))?;
+    if !prepared.params.is_empty() {
+        url.query_pairs_mut().extend_pairs(&prepared.params);
+    }
+    Ok(url.to_string())
+}
+
+/// Build what each pair of the server's script would send, without opening a connection.
+/// Code blocks run as in a check, but nothing is received, so pairs that use response variables fail to build.
+pub async fn build_game_server_packets(server: &GameServer, snippets: &Snippets) -> PacketBuildPreview {
+    let script = match parse_server_script(server, snippets) {
+        Ok(script) => script,
+        Err(e) => {
+            return PacketBuildPreview {
+                pairs: Vec::new(),
+                error: Some(GameServerError {
+                    error_type: "SyntaxError".to_string(),
+                    message: e.to_string(),
+                    line: error_line(&e),
+                }),
+            };
+        }
+    };
+    let command_lines = script_command_lines(&replace_placeholders(&server.pseudo_code, server), snippets);
+    let mut vars = script_constants(&script, server);
+    let mut code_vars = IndexMap::new();
+    let mut pairs = Vec::new();
+    for (pair_idx, pair) in script.pairs.iter().enumerate() {
+        if let Err(e) = run_code_blocks_before_pair(server, &script, pair_idx, &mut vars, &mut code_vars, &mut Vec::new()).await {
+            return PacketBuildPreview { pairs, error: Some(relocate_error(e, &script.sources)) };
+        }
+        let mut built = PairBuild { index: pair_idx + 1, packets: Vec::new(), http_request: None, error: None };
+        let outcome = match &pair.http_request {
+            Some(http_req) => prepare_http_request_with_vars(http_req, &vars).and_then(|prepared| {
+                built.http_request = Some(http_request_build(server, prepared)?);
+                Ok(())
+            }),
+            None => build_pair_packets(pair, &vars, script.big_endian).map(|packets| {
+                built.packets = packets.into_iter()
+                    .map(|packet| packet_build(packet, &command_lines, &script.sources))
+                    .collect();
+            }),
+        };
+        if let Err(e) = outcome {
+            let error = GameServerError {
+                error_type: "BuildError".to_string(),
+                message: format!("Pair {}: {}", pair_idx + 1, e),
+                line: error_line(&e),
+            };
+            built.error = Some(relocate_error(error, &script.sources));
+        }
+        pairs.push(built);
+    }
+    PacketBuildPreview { pairs, error: None }
+}
+
+fn packet_build(packet: BuiltPacket, command_lines: &[String], sources: &SourceMap) -> PacketBuild {
+    let breakdown = packet.segments.iter()
+        .map(|segment| PacketBuildSegment {
+            line: sources.script_line(segment.line),
+            command: command_lines.get(segment.line - 1).cloned().unwrap_or_default(),
+            offset: segment.offset,
+            hex: hex::encode(&packet.bytes[segment.offset..segment.offset + segment.len]),
+        })
+        .collect();
+    PacketBuild { hex: hex::encode(&packet.bytes), len: packet.bytes.len(), breakdown }
+}
+
+/// The request as the check would send it; the `User-Agent` default is added, cookies are not
+fn http_request_build(server: &GameServer, prepared: PreparedHttpRequest) -> Result<HttpRequestBuild> {
+    let url = request_url(&http_base_url(server), &prepared)?;
+    let mut headers = prepared.headers;
+    if !headers.iter().any(|(key, _)| key.eq_ignore_ascii_case("User-Agent")) {
+        headers.push(("User-Agent".to_string(), "NetSentinel/1.0".to_string()));
+    }
+    let (content_type, body) = match prepared.body {
+        Some((content_type, body)) => (Some(content_type), Some(String::from_utf8_lossy(&body).into_owned())),
+        None => (None, None),
+    };
+    Ok(HttpRequestBuild { method: prepared.method, url, headers, content_type, body })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A game server with only the fields the API requires, like one posted to `POST /api/gameservers`
+    fn test_server(protocol: &str, port: u16, script: &str) -> GameServer {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "test",
+            "address": "127.0.0.1",
+            "port": port,
+            "protocol": protocol,
+            "timeout_ms": 2000,
+            "pseudo_code": script,
+            "insecure_skip_verify": true,
+        }))
+        .expect("valid game server")
+    }
+
+    #[tokio::test]
+    async fn https_read_body_json_reaches_parsed_values() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let tls = axum_server::tls_rustls::RustlsConfig::from_pem(
+            cert.cert.pem().into_bytes(),
+            cert.key_pair.serialize_pem().into_bytes(),
+        )
+        .await
+        .unwrap();
+        let app = axum::Router::new().route(
+            "/status",
+            axum::routing::get(|| async { axum::Json(serde_json::json!({"players": 7, "map": "dust2"})) }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(axum_server::from_tcp_rustls(listener, tls).serve(app.into_make_service()));
+
+        let script = "HTTP_START REQUEST GET /status\nHTTP_END\nRESPONSE_START\nEXPECT_STATUS 200\nREAD_BODY_JSON status\nRESPONSE_END\n";
+        let server = test_server("HTTPS", port, script);
+        validate_script_protocol(&server, &Snippets::new()).unwrap();
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(result.success, "check failed: {:?}", result.error);
+        assert_eq!(result.parsed_values["status"]["players"], 7);
+        assert_eq!(result.parsed_values["status"]["map"], "dust2");
+    }
+
+    #[tokio::test]
+    async fn cookies_on_sends_back_the_cookies_that_apply() {
+        let app = axum::Router::new()
+            .route("/login", axum::routing::post(|| async {
+                let mut headers = axum::http::HeaderMap::new();
+                headers.append("Set-Cookie", "session=\"abc\"; Path=/".parse().unwrap());
+                headers.append("Set-Cookie", "admin=1; Path=/admin".parse().unwrap());
+                headers.append("Set-Cookie", "expired=1; Max-Age=0".parse().unwrap());
+                (headers, "ok")
+            }))
+            .route("/status", axum::routing::get(|headers: axum::http::HeaderMap| async move {
+                headers.get("Cookie").and_then(|cookie| cookie.to_str().ok()).unwrap_or("").to_string()
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let script = "COOKIES ON\nHTTP_START REQUEST POST /login\nHTTP_END\nRESPONSE_START\nEXPECT_STATUS 200\nRESPONSE_END\n\
+            HTTP_START REQUEST GET /status\nHTTP_END\nRESPONSE_START\nREAD_BODY sent\nRESPONSE_END\n";
+        let server = test_server("HTTP", port, script);
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(result.success, "check failed: {:?}", result.error);
+        assert_eq!(result.parsed_values["sent"], "session=\"abc\"");
+        assert_eq!(result.parsed_values["COOKIE_admin"], "1");
+    }
+
+    #[tokio::test]
+    async fn udp_read_timeout_is_reported_as_read_timeout() {
+        // Bound but never answers
+        let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = silent.local_addr().unwrap().port();
+        let script = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n";
+        let mut server = test_server("UDP", port, script);
+        server.timeout_ms = 200;
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(!result.success);
+        assert_eq!(result.error.expect("timeout error").error_type, "ReadTimeout");
+    }
+
+    #[tokio::test]
+    async fn udp_checks_report_connect_ms() {
+        let echo = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = echo.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let (size, peer) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..size], peer).await.unwrap();
+        });
+        let script = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n";
+        let server = test_server("UDP", port, script);
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(result.success, "check failed: {:?}", result.error);
+        let timing = result.timing.expect("UDP timing");
+        assert!(!timing.estimated);
+        assert_eq!(timing.phases(&Protocol::Udp).iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["connect_ms"]);
+    }
+
+    /// QUIC server with a self-signed certificate that echoes each bidirectional stream back
+    /// QUIC server answering each stream with what it received, speaking the `alpn` protocols if any
+    fn quic_echo_server(alpn: &[&str]) -> u16 {
+        use quinn::rustls;
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.cert.der().clone()],
+                rustls::pki_types::PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into()),
+            )
+            .unwrap();
+        crypto.alpn_protocols = alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+        let config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint = quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = endpoint.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                tokio::spawn(async move {
+                    let Ok(connection) = incoming.await else { return };
+                    while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                        let data = recv.read_to_end(64).await.unwrap();
+                        send.write_all(&data).await.unwrap();
+                        send.finish().unwrap();
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn trailing_code_blocks_keep_parsed_values_of_the_same_name() {
+        let echo = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = echo.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let (size, peer) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..size], peer).await.unwrap();
+        });
+        let script = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\nCODE_START\nINT b = 9\nCODE_END\n";
+        let server = test_server("UDP", port, script);
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(result.success, "check failed: {:?}", result.error);
+        assert_eq!(result.parsed_values["b"], 1);
+        assert_eq!(result.variables["b"], 9);
+    }
+
+    #[tokio::test]
+    async fn parsed_values_equal_to_a_define_are_kept() {
+        let echo = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = echo.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let (size, peer) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..size], peer).await.unwrap();
+        });
+        let script = "DEFINE ONE 1\nPACKET_START\nWRITE_BYTE ONE\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n";
+        let server = test_server("UDP", port, script);
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(result.success, "check failed: {:?}", result.error);
+        assert_eq!(result.parsed_values, serde_json::json!({"b": 1}));
+    }
+
+    #[tokio::test]
+    async fn quic_pairs_run_over_bidirectional_streams() {
+        let port = quic_echo_server(&[]);
+        let script = "PACKET_START\nWRITE_BYTE 7\nWRITE_SHORT 513\nPACKET_END\nRESPONSE_START\nREAD_BYTE kind\nREAD_SHORT value\nRESPONSE_END\n";
+        let mut server = test_server("QUIC", port, script);
+        server.danger_accept_invalid_certs = true;
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(result.success, "check failed: {:?}", result.error);
+        assert_eq!(result.parsed_values["kind"], 7);
+        assert_eq!(result.parsed_values["value"], 513);
+        let timing = result.timing.expect("QUIC timing");
+        assert_eq!(timing.phases(&Protocol::Quic).iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["quic_handshake_ms"]);
+    }
+
+    #[tokio::test]
+    async fn quic_offers_the_configured_alpn_protocols() {
+        let port = quic_echo_server(&["ns-test"]);
+        let script = "PACKET_START\nWRITE_BYTE 7\nPACKET_END\nRESPONSE_START\nREAD_BYTE kind\nRESPONSE_END\n";
+        let mut server = test_server("QUIC", port, script);
+        server.danger_accept_invalid_certs = true;
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(!result.success, "a handshake without ALPN should be refused");
+
+        server.alpn = vec!["h3".to_string(), "ns-test".to_string()];
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(result.success, "check failed: {:?}", result.error);
+        assert_eq!(result.parsed_values["kind"], 7);
+    }
+
+    #[tokio::test]
+    async fn quic_rejects_self_signed_certificates_by_default() {
+        let port = quic_echo_server(&[]);
+        let script = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n";
+        let server = test_server("QUIC", port, script);
+
+        let result = check_game_server(&server, &Snippets::new()).await;
+        assert!(!result.success);
+        let error = result.error.expect("handshake error");
+        assert_eq!(error.error_type, "NetworkError");
+        assert!(error.message.starts_with("QUIC handshake failed"), "{}", error.message);
+    }
+
+    #[test]
+    fn quic_rejects_http_scripts() {
+        let server = test_server("QUIC", 4433, "HTTP_START REQUEST GET /\nHTTP_END\n");
+        assert!(validate_script_protocol(&server, &Snippets::new()).is_err());
+    }
+
+    #[test]
+    fn placeholders_leave_words_containing_them_alone() {
+        let mut server = test_server("HTTP", 8080, "");
+        server.address = "10.0.0.5".to_string();
+        let script = [
+            "# SUPPORT ticket: REPORT the shipment PORT",
+            "HTTP_START REQUEST POST /api",
+            "HEADER X-Api-Support yes",
+            "HEADER Host {HOST}:{PORT}",
+            "BODY_START TYPE RAW",
+            r#"DATA {"port": PORT, "ip_whitelist": ["IP"], "transport": "shipment", "description": "IP_LEN"}"#,
+            "BODY_END",
+            "HTTP_END",
+        ]
+        .join("\n");
+        let expected = [
+            "# SUPPORT ticket: REPORT the shipment 8080",
+            "HTTP_START REQUEST POST /api",
+            "HEADER X-Api-Support yes",
+            "HEADER Host 10.0.0.5:8080",
+            "BODY_START TYPE RAW",
+            r#"DATA {"port": 8080, "ip_whitelist": ["10.0.0.5"], "transport": "shipment", "description": "8"}"#,
+            "BODY_END",
+            "HTTP_END",
+        ]
+        .join("\n");
+        assert_eq!(replace_placeholders(&script, &server), expected);
+    }
+
+    #[test]
+    fn placeholder_scripts_with_lookalike_words_still_parse() {
+        let script = [
+            "HTTP_START REQUEST POST /support",
+            "HEADER X-Api-Support SUPPORT",
+            "BODY_START TYPE RAW",
+            r#"DATA {"port": PORT, "shipment": "HOST", "support": "SUPPORT"}"#,
+            "BODY_END",
+            "HTTP_END",
+            "RESPONSE_START",
+            "EXPECT_STATUS 200",
+            "RESPONSE_END",
+        ]
+        .join("\n");
+        let server = test_server("HTTP", 8080, &script);
+        let parsed = parse_server_script(&server, &Snippets::new()).unwrap();
+        let request = parsed.pairs[0].http_request.as_ref().expect("HTTP request");
+        let prepared = prepare_http_request_with_vars(request, &IndexMap::new()).unwrap();
+        assert!(prepared.headers.iter().any(|(name, value)| name == "X-Api-Support" && value == "SUPPORT"));
+        let (_, body) = prepared.body.expect("request body");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, serde_json::json!({"port": 8080, "shipment": "127.0.0.1", "support": "SUPPORT"}));
+    }
+
+    #[test]
+    fn http_protocols_reject_binary_scripts() {
+        let binary = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n";
+        let http = "HTTP_START REQUEST GET /\nHTTP_END\nRESPONSE_START\nEXPECT_STATUS 200\nRESPONSE_END\n";
+        assert!(validate_script_protocol(&test_server("HTTPS", 443, binary), &Snippets::new()).is_err());
+        assert!(validate_script_protocol(&test_server("HTTP", 80, binary), &Snippets::new()).is_err());
+        assert!(validate_script_protocol(&test_server("TCP", 25565, http), &Snippets::new()).is_err());
+        assert!(validate_script_protocol(&test_server("HTTP", 80, http), &Snippets::new()).is_ok());
+    }
+}
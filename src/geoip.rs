@@ -0,0 +1,56 @@
+use crate::out;
+use maxminddb::{geoip2, Reader};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+pub type GeoIpReader = Arc<Reader<Vec<u8>>>;
+
+/// Load the MaxMind database pointed to by `NET_SENTINEL_GEOIP_DB_PATH`, if set
+pub fn load_from_env() -> Option<GeoIpReader> {
+    let path = std::env::var("NET_SENTINEL_GEOIP_DB_PATH").ok()?;
+    let path = path.trim();
+    if path.is_empty() {
+        return None;
+    }
+    match Reader::open_readfile(path) {
+        Ok(reader) => {
+            out::info("geoip", &format!("Loaded GeoIP database from {} ({})", path, reader.metadata.database_type));
+            Some(Arc::new(reader))
+        }
+        Err(e) => {
+            out::warning("geoip", &format!("Failed to load GeoIP database from {}: {}. ISP metrics will not have geo labels", path, e));
+            None
+        }
+    }
+}
+
+/// Extra Prometheus labels (`,country="US",asn="AS15169"`) for an ISP address.
+/// Labels the database has no data for are left out; hostnames are not resolved.
+pub fn isp_labels(reader: &Reader<Vec<u8>>, address: &str) -> String {
+    let Some(ip) = parse_ip(address) else {
+        return String::new();
+    };
+
+    let mut labels = String::new();
+    if let Ok(country) = reader.lookup::<geoip2::Country>(ip) {
+        if let Some(iso_code) = country.country.and_then(|c| c.iso_code) {
+            labels.push_str(&format!(",country=\"{}\"", crate::escape_prometheus_label(iso_code)));
+        }
+    }
+    // Only present in databases that carry ASN data (e.g. GeoLite2-ASN or a merged database)
+    if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) {
+        if let Some(number) = asn.autonomous_system_number {
+            labels.push_str(&format!(",asn=\"AS{}\"", number));
+        }
+    }
+    labels
+}
+
+/// Accept a bare IP or an `ip:port` / `[ipv6]:port` address
+fn parse_ip(address: &str) -> Option<IpAddr> {
+    let address = address.trim();
+    address
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| address.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
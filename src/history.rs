@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long check outcomes are kept, the longest window in `METRIC_WINDOWS`
+const RETENTION: Duration = Duration::from_secs(7 * 86400);
+
+/// Checks within one bucket are counted together, so memory depends on `RETENTION` rather than
+/// on how often `/metrics` is scraped
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// Windows exported as `net_sentinel_uptime_pct_<window>` metrics
+pub const METRIC_WINDOWS: [&str; 3] = ["1h", "24h", "7d"];
+
+/// Check outcomes of a single ISP, website or game server, counted per minute
+#[derive(Debug, Default)]
+pub struct CheckHistory {
+    /// (bucket start, checks, successful checks), oldest first
+    buckets: VecDeque<(Instant, usize, usize)>,
+    /// Wall-clock time of the most recent successful check, kept even after its bucket is evicted
+    last_success: Option<SystemTime>,
+}
+
+impl CheckHistory {
+    pub fn record(&mut self, success: bool) {
+        self.record_at(Instant::now(), success);
+        if success {
+            self.last_success = Some(SystemTime::now());
+        }
+    }
+
+    fn record_at(&mut self, now: Instant, success: bool) {
+        while self.buckets.front().is_some_and(|(start, _, _)| now.duration_since(*start) > RETENTION) {
+            self.buckets.pop_front();
+        }
+        match self.buckets.back_mut() {
+            Some((start, total, ok)) if now.duration_since(*start) < BUCKET => {
+                *total += 1;
+                *ok += usize::from(success);
+            }
+            _ => self.buckets.push_back((now, 1, usize::from(success))),
+        }
+    }
+
+    /// Count (total, successful) checks recorded within `window` of now, to the minute
+    pub fn counts(&self, window: Duration) -> (usize, usize) {
+        self.counts_at(Instant::now(), window)
+    }
+
+    fn counts_at(&self, now: Instant, window: Duration) -> (usize, usize) {
+        self.buckets
+            .iter()
+            .rev()
+            .take_while(|(start, _, _)| now.duration_since(*start) <= window)
+            .fold((0, 0), |(total, ok), (_, checks, successes)| (total + checks, ok + successes))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityKind {
+    Isp,
+    Website,
+    GameServer,
+}
+
+impl EntityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntityKind::Isp => "isp",
+            EntityKind::Website => "website",
+            EntityKind::GameServer => "gameserver",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UptimeStats {
+    pub uptime_pct: Option<f64>,
+    pub total_checks: usize,
+    pub successful_checks: usize,
+    pub window: String,
+}
+
+/// In-memory check history for every monitored entity, shared between requests
+#[derive(Debug, Clone, Default)]
+pub struct CheckResultCache {
+    inner: Arc<Mutex<HashMap<(EntityKind, i64), CheckHistory>>>,
+}
+
+impl CheckResultCache {
+    pub fn record(&self, kind: EntityKind, id: i64, success: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entry((kind, id)).or_default().record(success);
+    }
+
+    pub fn remove(&self, kind: EntityKind, id: i64) {
+        self.inner.lock().unwrap().remove(&(kind, id));
+    }
+
+    pub fn last_success(&self, kind: EntityKind, id: i64) -> Option<SystemTime> {
+        let inner = self.inner.lock().unwrap();
+        inner.get(&(kind, id)).and_then(|history| history.last_success)
+    }
+
+    /// Drop all recorded history, e.g. after the database has been restored
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    pub fn uptime(&self, kind: EntityKind, id: i64, window: &str) -> anyhow::Result<UptimeStats> {
+        let duration = parse_window(window)?;
+        let inner = self.inner.lock().unwrap();
+        let (total, successful) = inner
+            .get(&(kind, id))
+            .map(|history| history.counts(duration))
+            .unwrap_or((0, 0));
+        let uptime_pct = if total > 0 {
+            Some(successful as f64 * 100.0 / total as f64)
+        } else {
+            None
+        };
+        Ok(UptimeStats {
+            uptime_pct,
+            total_checks: total,
+            successful_checks: successful,
+            window: window.to_string(),
+        })
+    }
+}
+
+/// Parse a window like `30m`, `1h`, `24h` or `7d` into a duration
+pub fn parse_window(window: &str) -> anyhow::Result<Duration> {
+    let window = window.trim();
+    let split = window
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow::anyhow!("Window '{}' is missing a unit (s, m, h or d)", window))?;
+    let (amount, unit) = window.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid window '{}'", window))?;
+    let seconds = match unit {
+        "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 3600,
+        "d" => amount * 86400,
+        _ => anyhow::bail!("Invalid window unit '{}'. Must be s, m, h or d", unit),
+    };
+    if seconds == 0 {
+        anyhow::bail!("Window must be greater than zero");
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequent_checks_share_buckets_and_keep_seven_days() {
+        let start = Instant::now();
+        let mut history = CheckHistory::default();
+        // One check per second for eight days would be 691200 samples
+        for second in 0..8 * 86400u64 {
+            history.record_at(start + Duration::from_secs(second), second % 10 != 0);
+        }
+        let now = start + Duration::from_secs(8 * 86400 - 1);
+        assert!(history.buckets.len() <= (RETENTION.as_secs() / BUCKET.as_secs()) as usize + 1);
+        let (total, ok) = history.counts_at(now, Duration::from_secs(3600));
+        assert_eq!((total, ok), (3600, 3240));
+        let (total, _) = history.counts_at(now, RETENTION);
+        assert!((7 * 86400 - 60..=7 * 86400 + 60).contains(&total), "{total}");
+    }
+}
@@ -0,0 +1,549 @@
+use crate::packet_parser::{
+    expand_placeholders, Condition, CodeCommand, Expression, HttpValue, OutputCommand, OutputStatus, PacketCommand,
+    PacketScript, PathSegment, ResponseCommand, Sourced,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Variables every output block can use without setting them
+const BUILTIN_VARS: &[&str] = &["HOST", "IP", "PORT", "HOST_LEN", "IP_LEN", "IP_LEN_HEX", "ERROR"];
+
+/// Something in a script that parses but is probably a mistake, from `POST /api/lint-script`
+#[derive(Debug, Clone, Serialize)]
+pub struct LintWarning {
+    pub rule: LintRule,
+    pub severity: Severity,
+    pub message: String,
+    /// Script line the warning points at, when the construct has one
+    pub line: Option<usize>,
+}
+
+impl LintWarning {
+    fn new(rule: LintRule, message: String, line: Option<usize>) -> Self {
+        LintWarning { rule, severity: rule.severity(), message, line }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LintRule {
+    /// A packet is sent but nothing reads the reply, and the pair isn't marked NO_RESPONSE
+    UnpairedPacket,
+    /// A packet, condition or output block uses a variable no response, code block or DEFINE sets
+    UndefinedVariable,
+    /// An OUTPUT_ERROR block in a script without EXPECT/ASSERT/FAIL commands, or pairs after a FAIL that always runs
+    DeadCode,
+    /// A code block declares a variable nothing reads
+    UnusedVariable,
+    /// A CODE_START between a packet and its RESPONSE_START, which drops the packet
+    DiscardedPacket,
+}
+
+/// Errors fail at runtime; warnings only point at something that is probably unintended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl LintRule {
+    pub fn severity(self) -> Severity {
+        match self {
+            LintRule::UndefinedVariable | LintRule::DiscardedPacket => Severity::Error,
+            LintRule::UnpairedPacket | LintRule::DeadCode | LintRule::UnusedVariable => Severity::Warning,
+        }
+    }
+}
+
+/// Check a parsed script for likely mistakes
+pub fn lint_script(script: &PacketScript) -> Vec<LintWarning> {
+    lint_script_with_variables(script, &HashMap::new())
+}
+
+/// Like [`lint_script`], also treating the server's variables as set
+pub fn lint_script_with_variables(script: &PacketScript, variables: &HashMap<String, String>) -> Vec<LintWarning> {
+    let mut usage = Usage::default();
+    for pair in &script.pairs {
+        for command in pair.packets.iter().flatten() {
+            usage.line = Some(command.source_line);
+            usage.packet_command(&command.command);
+        }
+        usage.line = None;
+        if let Some(request) = &pair.http_request {
+            usage.template(&request.path);
+            for (_, value) in request.params.iter().chain(&request.headers) {
+                match value {
+                    HttpValue::Literal(text) => usage.template(text),
+                    HttpValue::Var(name) => usage.read(name),
+                }
+            }
+            for line in &request.body_data {
+                usage.template(line);
+            }
+        }
+        for command in &pair.response {
+            usage.line = Some(command.source_line);
+            usage.response_command(&command.command);
+        }
+    }
+    for block in &script.code_blocks {
+        usage.code_commands(&block.commands);
+    }
+    for function in script.functions.values() {
+        usage.set.extend(function.params.iter().cloned());
+        usage.code_commands(&function.body);
+    }
+    usage.line = None;
+    for block in &script.output_blocks {
+        for command in &block.commands {
+            usage.output_command(command);
+        }
+    }
+
+    let mut warnings = Vec::new();
+
+    for line in &script.discarded_packets {
+        warnings.push(LintWarning::new(
+            LintRule::DiscardedPacket,
+            "CODE_START before the RESPONSE_START of the packets above drops them; move the CODE block after RESPONSE_END".to_string(),
+            Some(*line),
+        ));
+    }
+
+    for (idx, pair) in script.pairs.iter().enumerate() {
+        if pair.packets.is_empty() || pair.no_response || !pair.response.is_empty() {
+            continue;
+        }
+        warnings.push(LintWarning::new(
+            LintRule::UnpairedPacket,
+            format!("Pair {} sends a packet but has no RESPONSE block; add one or mark it NO_RESPONSE", idx + 1),
+            pair.packets.iter().flatten().next().map(|command| command.source_line),
+        ));
+    }
+
+    // A FAIL at the top level of a code block always runs unless a RETURN_EARLY before it can stop
+    // the block first, so the pairs after it never do
+    let always_fails = script.code_blocks.iter().find_map(|block| {
+        let position = block.commands.iter().position(|command| matches!(command.command, CodeCommand::Fail { .. }))?;
+        let reached = !may_return_early(&block.commands[..position]);
+        (reached && block.before_pair < script.pairs.len()).then_some((block.before_pair, block.commands[position].source_line))
+    });
+    if let Some((first, line)) = always_fails {
+        let message = if first + 1 == script.pairs.len() {
+            format!("Pair {} never runs: the FAIL before it always fails the check", first + 1)
+        } else {
+            format!("Pairs {}-{} never run: the FAIL before them always fails the check", first + 1, script.pairs.len())
+        };
+        warnings.push(LintWarning::new(LintRule::DeadCode, message, Some(line)));
+    }
+
+    let uses_http = script.pairs.iter().any(|pair| pair.http_request.is_some());
+    let is_set = |name: &str| {
+        usage.set.contains(name)
+            || script.defines.contains_key(name)
+            || variables.contains_key(name)
+            || BUILTIN_VARS.contains(&name)
+            || (uses_http && (name == "STATUS_CODE" || name.starts_with("HEADER_") || name.starts_with("COOKIE_")))
+    };
+    let mut reported = HashSet::new();
+    for (name, line) in &usage.used {
+        if !is_set(name) && reported.insert(name.as_str()) {
+            warnings.push(LintWarning::new(
+                LintRule::UndefinedVariable,
+                format!("Variable '{}' is used but no RESPONSE, CODE block or DEFINE sets it", name),
+                *line,
+            ));
+        }
+    }
+    for name in &usage.output_reads {
+        if !is_set(name) && reported.insert(name.as_str()) {
+            warnings.push(LintWarning::new(
+                LintRule::UndefinedVariable,
+                format!("Output uses variable '{}' which no RESPONSE, CODE block or DEFINE sets", name),
+                None,
+            ));
+        }
+    }
+
+    if !usage.has_expectations && script.output_blocks.iter().any(|block| block.status == OutputStatus::Error) {
+        warnings.push(LintWarning::new(
+            LintRule::DeadCode,
+            "OUTPUT_ERROR only runs on network or parse errors: the script has no EXPECT, ASSERT or FAIL command".to_string(),
+            None,
+        ));
+    }
+
+    for (name, line) in &usage.declared {
+        if !usage.read.contains(name) {
+            warnings.push(LintWarning::new(
+                LintRule::UnusedVariable,
+                format!("Variable '{}' is declared but never used", name),
+                Some(*line),
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Variables a script sets and reads, gathered in one walk
+#[derive(Default)]
+struct Usage {
+    set: HashSet<String>,
+    read: HashSet<String>,
+    /// Variables read by output blocks, in script order
+    output_reads: Vec<String>,
+    /// `VAR` declarations in code blocks, with their line
+    declared: Vec<(String, usize)>,
+    /// Variables read by packets, responses and code blocks, with the line of the first read
+    used: Vec<(String, Option<usize>)>,
+    /// Line of the command being walked; `None` in output blocks and HTTP requests
+    line: Option<usize>,
+    in_output: bool,
+    has_expectations: bool,
+}
+
+impl Usage {
+    fn read(&mut self, name: &str) {
+        if self.read.insert(name.to_string()) && !self.in_output {
+            self.used.push((name.to_string(), self.line));
+        }
+    }
+
+    /// Roots of the `{var.path}` placeholders in a string
+    fn template(&mut self, text: &str) {
+        let _ = expand_placeholders(text, |path| {
+            self.read(placeholder_root(path));
+            Ok(String::new())
+        });
+    }
+
+    fn packet_command(&mut self, command: &PacketCommand) {
+        match command {
+            PacketCommand::WriteIntVar(name, _)
+            | PacketCommand::WriteLongVar(name, _)
+            | PacketCommand::WriteFloatVar(name, _)
+            | PacketCommand::WriteDoubleVar(name, _)
+            | PacketCommand::WriteShortVar(name, _)
+            | PacketCommand::WriteByteVar(name)
+            | PacketCommand::WriteVarIntVar(name)
+            | PacketCommand::WriteStringVar(name, _)
+            | PacketCommand::WriteStringRawVar(name)
+            | PacketCommand::WriteStringLenPrefixedVar(name, _)
+            | PacketCommand::WriteBytesVar(name) => self.read(name),
+            _ => {}
+        }
+    }
+
+    fn response_command(&mut self, command: &ResponseCommand) {
+        if let Some(name) = command.target() {
+            self.set.insert(name.to_string());
+        }
+        match command {
+            ResponseCommand::ReadStringVarLen(_, len_var) | ResponseCommand::ReadBytesVarLen(_, len_var) => self.read(len_var),
+            ResponseCommand::SkipBytesVar(len_var) => self.read(len_var),
+            ResponseCommand::ExpectVar(assertion) => {
+                self.has_expectations = true;
+                self.condition(&assertion.condition);
+            }
+            ResponseCommand::ExpectByte(_)
+            | ResponseCommand::ExpectMagic(_)
+            | ResponseCommand::ExpectVarInt(_)
+            | ResponseCommand::ExpectInt(..)
+            | ResponseCommand::ExpectStatus(..)
+            | ResponseCommand::ExpectBodyContains(_)
+            | ResponseCommand::ExpectHeader { .. } => self.has_expectations = true,
+            _ => {}
+        }
+    }
+
+    fn code_commands(&mut self, commands: &[Sourced<CodeCommand>]) {
+        for sourced in commands {
+            self.line = Some(sourced.source_line);
+            match &sourced.command {
+                CodeCommand::DeclareVar { name, value, .. } => {
+                    self.set.insert(name.clone());
+                    self.declared.push((name.clone(), sourced.source_line));
+                    self.expression(value);
+                }
+                CodeCommand::AssignVar { name, value } => {
+                    self.set.insert(name.clone());
+                    self.expression(value);
+                }
+                CodeCommand::ForLoop { var_name, range_start, range_end, body, .. } => {
+                    self.set.insert(var_name.clone());
+                    self.expression(range_start);
+                    self.expression(range_end);
+                    self.code_commands(body);
+                }
+                CodeCommand::WhileLoop { condition, body, .. } => {
+                    self.condition(condition);
+                    self.code_commands(body);
+                }
+                CodeCommand::ForInArray { var_name, array_name, body } => {
+                    self.set.insert(var_name.clone());
+                    self.read(array_name);
+                    self.code_commands(body);
+                }
+                CodeCommand::IfStatement { condition, body, else_if, else_body } => {
+                    self.condition(condition);
+                    self.code_commands(body);
+                    for (condition, branch) in else_if {
+                        self.condition(condition);
+                        self.code_commands(branch);
+                    }
+                    if let Some(branch) = else_body {
+                        self.code_commands(branch);
+                    }
+                }
+                CodeCommand::Split { var_name, source_expr, .. } | CodeCommand::Replace { var_name, source_expr, .. } => {
+                    self.set.insert(var_name.clone());
+                    self.expression(source_expr);
+                }
+                CodeCommand::ExpectVar(assertion) | CodeCommand::Assert(assertion) => {
+                    self.has_expectations = true;
+                    self.condition(&assertion.condition);
+                }
+                CodeCommand::Fail { message, .. } => {
+                    self.has_expectations = true;
+                    self.template(message);
+                }
+                CodeCommand::Call { args, result, declared, .. } => {
+                    for arg in args {
+                        self.expression(arg);
+                    }
+                    if let Some(var_name) = result {
+                        self.set.insert(var_name.clone());
+                        if *declared {
+                            self.declared.push((var_name.clone(), sourced.source_line));
+                        }
+                    }
+                }
+                CodeCommand::Return(value) => {
+                    if let Some(value) = value {
+                        self.expression(value);
+                    }
+                }
+                CodeCommand::ExecutePacketCommand(command) => self.packet_command(command),
+                CodeCommand::ExecuteResponseCommand(command) => self.response_command(command),
+                CodeCommand::Break | CodeCommand::Continue | CodeCommand::ReturnEarly => {}
+            }
+        }
+    }
+
+    fn output_command(&mut self, command: &OutputCommand) {
+        self.in_output = true;
+        let before = self.read.clone();
+        let mut bare_tokens = Vec::new();
+        match command {
+            OutputCommand::JsonOutput(name) => self.read(name),
+            OutputCommand::Return(template) => bare_tokens.extend(self.output_template(template)),
+            OutputCommand::ConditionalReturn { condition, then_template, else_template } => {
+                self.condition(condition);
+                bare_tokens.extend(self.output_template(then_template));
+                bare_tokens.extend(self.output_template(else_template));
+            }
+        }
+        let mut reads = self.read.difference(&before).cloned().collect::<Vec<_>>();
+        reads.sort();
+        for name in reads {
+            if !self.output_reads.contains(&name) {
+                self.output_reads.push(name);
+            }
+        }
+        self.read.extend(bare_tokens);
+        self.in_output = false;
+    }
+
+    /// Reads the `{var}` placeholders of a RETURN template. Templates without placeholders use the
+    /// deprecated bare variable tokens; any word there could be plain text, so those are returned to
+    /// count as uses but are never reported as undefined.
+    fn output_template(&mut self, template: &str) -> Vec<String> {
+        let text = template.trim().trim_matches(|c| c == '"' || c == '\'');
+        if text.contains('{') {
+            self.template(text);
+            return Vec::new();
+        }
+        text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .map(placeholder_root)
+            .filter(|root| !root.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn condition(&mut self, condition: &Condition) {
+        match condition {
+            Condition::Equals(left, right)
+            | Condition::NotEquals(left, right)
+            | Condition::GreaterThan(left, right)
+            | Condition::LessThan(left, right)
+            | Condition::GreaterOrEqual(left, right)
+            | Condition::LessOrEqual(left, right)
+            | Condition::Contains(left, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Condition::IsTrue(expr) => self.expression(expr),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Literal(_) => {}
+            Expression::Variable(name) => self.read(name),
+            Expression::ArrayIndex { array_name, index } => {
+                self.read(array_name);
+                self.expression(index);
+            }
+            Expression::FunctionCall { args, .. } | Expression::RegexCall { args, .. } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::Template(text) => self.template(text),
+            Expression::Path { var_name, segments } => {
+                self.read(var_name);
+                for segment in segments {
+                    if let PathSegment::Index(index) = segment {
+                        self.expression(index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether a `RETURN_EARLY` in the commands, at any depth, can stop their code block
+fn may_return_early(commands: &[Sourced<CodeCommand>]) -> bool {
+    commands.iter().any(|sourced| match &sourced.command {
+        CodeCommand::ReturnEarly => true,
+        CodeCommand::ForLoop { body, .. } | CodeCommand::WhileLoop { body, .. } | CodeCommand::ForInArray { body, .. } => {
+            may_return_early(body)
+        }
+        CodeCommand::IfStatement { body, else_if, else_body, .. } => {
+            may_return_early(body)
+                || else_if.iter().any(|(_, branch)| may_return_early(branch))
+                || else_body.as_deref().is_some_and(may_return_early)
+        }
+        _ => false,
+    })
+}
+
+/// `status` for `status.players[0]`
+fn placeholder_root(path: &str) -> &str {
+    path.split(['.', '[']).next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_parser::{parse_script, Snippets};
+
+    fn lint(script: &str) -> Vec<LintWarning> {
+        lint_script(&parse_script(script, &Snippets::new()).expect("script parses"))
+    }
+
+    fn rules(script: &str) -> Vec<(LintRule, Option<usize>)> {
+        lint(script).into_iter().map(|warning| (warning.rule, warning.line)).collect()
+    }
+
+    const PAIR: &str = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n";
+
+    #[test]
+    fn clean_scripts_have_no_warnings() {
+        assert!(rules(&format!("{PAIR}OUTPUT_SUCCESS\nRETURN \"b={{b}}\"\nOUTPUT_END\n")).is_empty());
+    }
+
+    #[test]
+    fn packets_without_a_response_are_unpaired() {
+        assert_eq!(rules("PACKET_START\nWRITE_BYTE 1\nPACKET_END\n"), [(LintRule::UnpairedPacket, Some(2))]);
+        assert!(rules("PACKET_START\nWRITE_BYTE 1\nPACKET_END\nNO_RESPONSE\n").is_empty());
+    }
+
+    #[test]
+    fn variables_nothing_sets_are_undefined_errors() {
+        let script = "PACKET_START\nWRITE_BYTE count\nPACKET_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n\
+            CODE_START\nIF b > limit:\n  FAIL \"too big\"\nCODE_END\nOUTPUT_SUCCESS\nRETURN \"{b} {missing}\"\nOUTPUT_END\n";
+        let warnings = lint(script);
+        let undefined: Vec<_> = warnings.iter()
+            .filter(|warning| warning.rule == LintRule::UndefinedVariable)
+            .map(|warning| (warning.message.split('\'').nth(1).unwrap(), warning.line, warning.severity))
+            .collect();
+        assert_eq!(undefined, [
+            ("count", Some(2), Severity::Error),
+            ("limit", Some(8), Severity::Error),
+            ("missing", None, Severity::Error),
+        ]);
+    }
+
+    #[test]
+    fn builtin_and_provided_variables_are_not_undefined() {
+        // Placeholders, DEFINEs, every READ command and function parameters
+        let script = "DEFINE MAGIC 5\nPACKET_START\nWRITE_BYTE MAGIC\nWRITE_STRING HOST\nPACKET_END\nRESPONSE_START\n\
+            READ_STRING_UTF16 name NULL\nREAD_STRING_NULL motd\nRESPONSE_END\nCODE_START\nDEF label(text):\n  RETURN CONCAT(text, \"!\")\n\
+            STRING shown = CALL label(name)\nCODE_END\nOUTPUT_SUCCESS\nRETURN \"{shown} {motd} {PORT}\"\nOUTPUT_END\n\
+            OUTPUT_ERROR\nRETURN \"{ERROR}\"\nOUTPUT_END\n";
+        assert!(rules(script).iter().all(|(rule, _)| *rule != LintRule::UndefinedVariable), "{:?}", rules(script));
+
+        // Variables every HTTP response sets
+        let http = "HTTP_START REQUEST GET /\nHTTP_END\nRESPONSE_START\nEXPECT_STATUS 200\nRESPONSE_END\n\
+            OUTPUT_SUCCESS\nRETURN \"{STATUS_CODE} {HEADER_server} {COOKIE_session}\"\nOUTPUT_END\n";
+        assert!(rules(http).is_empty(), "{:?}", rules(http));
+        // ...but not binary ones
+        let binary = format!("{PAIR}OUTPUT_SUCCESS\nRETURN \"{{b}} {{STATUS_CODE}}\"\nOUTPUT_END\n");
+        assert_eq!(rules(&binary), [(LintRule::UndefinedVariable, None)]);
+
+        // Per-server variables
+        let script = format!("{PAIR}OUTPUT_SUCCESS\nRETURN \"{{b}} {{REGION}}\"\nOUTPUT_END\n");
+        let parsed = parse_script(&script, &Snippets::new()).unwrap();
+        assert_eq!(lint_script(&parsed).len(), 1);
+        let variables = HashMap::from([("REGION".to_string(), "eu".to_string())]);
+        assert!(lint_script_with_variables(&parsed, &variables).is_empty());
+    }
+
+    #[test]
+    fn error_outputs_without_expectations_are_dead_code() {
+        let script = format!("{PAIR}OUTPUT_ERROR\nRETURN \"down\"\nOUTPUT_END\n");
+        assert_eq!(rules(&script), [(LintRule::DeadCode, None)]);
+        let expecting = script.replace("READ_BYTE b", "EXPECT_BYTE 1");
+        assert!(rules(&expecting).is_empty());
+    }
+
+    #[test]
+    fn pairs_after_a_fail_are_dead_code() {
+        let script = format!("CODE_START\nFAIL \"disabled\"\nCODE_END\n{PAIR}{PAIR}");
+        let warnings = lint(&script);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].rule, warnings[0].line), (LintRule::DeadCode, Some(2)));
+        assert_eq!(warnings[0].message, "Pairs 1-2 never run: the FAIL before them always fails the check");
+        // A FAIL inside an IF may not run
+        assert!(rules(&format!("CODE_START\nIF 1 > 2:\n  FAIL \"never\"\nCODE_END\n{PAIR}")).is_empty());
+    }
+
+    #[test]
+    fn fails_after_a_return_early_guard_may_not_run() {
+        let guarded = format!("DEFINE ENABLED 1\nCODE_START\nIF ENABLED == 1:\n  RETURN_EARLY\nFAIL \"disabled\"\nCODE_END\n{PAIR}");
+        assert!(rules(&guarded).is_empty(), "{:?}", rules(&guarded));
+        // A RETURN_EARLY after the FAIL comes too late
+        let late = format!("CODE_START\nFAIL \"disabled\"\nRETURN_EARLY\nCODE_END\n{PAIR}");
+        assert_eq!(rules(&late), [(LintRule::DeadCode, Some(2))]);
+    }
+
+    #[test]
+    fn declared_variables_nothing_reads_are_unused() {
+        let script = format!("{PAIR}CODE_START\nINT doubled = b\nINT used = b\nCODE_END\nOUTPUT_SUCCESS\nRETURN \"{{used}}\"\nOUTPUT_END\n");
+        let warnings = lint(&script);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].rule, warnings[0].line, warnings[0].severity), (LintRule::UnusedVariable, Some(8), Severity::Warning));
+        assert!(warnings[0].message.contains("'doubled'"));
+    }
+
+    #[test]
+    fn code_blocks_before_a_response_discard_the_packet() {
+        let script = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nCODE_START\nINT x = 1\nCODE_END\nRESPONSE_START\nREAD_BYTE b\nRESPONSE_END\n";
+        let warnings = lint(script);
+        let discarded: Vec<_> = warnings.iter().filter(|warning| warning.rule == LintRule::DiscardedPacket).collect();
+        assert_eq!(discarded.len(), 1);
+        assert_eq!((discarded[0].line, discarded[0].severity), (Some(4), Severity::Error));
+    }
+}
@@ -1,28 +1,57 @@
 mod api;
 mod code_server;
 mod db;
+mod formatter;
+mod geoip;
+mod history;
+mod linter;
 mod models;
 mod out;
+mod probes;
+mod prometheus;
+mod webhooks;
 mod packet_parser;
 mod gameserver_check;
 
 use axum::{
-    extract::Extension,
-    http::StatusCode,
+    extract::{DefaultBodyLimit, Extension},
+    http::{header, StatusCode},
     response::{Html, IntoResponse, Response},
-    routing::{get, post, delete},
+    routing::{get, post, put, delete},
     Router,
 };
 use std::sync::Arc;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Largest response exported by `net_sentinel_gameserver_raw_response`
+const MAX_RAW_RESPONSE_METRIC_BYTES: u64 = 100;
+
+/// Content type of the Prometheus text exposition format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Initialize JSON database
     let store = db::init_db().await?;
 
-    let app_state = Arc::new(AppState { store });
+    let app_state = Arc::new(AppState {
+        store,
+        max_concurrent_isps: env_usize("NET_SENTINEL_MAX_CONCURRENT_ISPS", 100),
+        max_concurrent_websites: env_usize("NET_SENTINEL_MAX_CONCURRENT_WEBSITES", 100),
+        max_concurrent_gameservers: env_usize("NET_SENTINEL_MAX_CONCURRENT_GAMESERVERS", 50),
+        history: history::CheckResultCache::default(),
+        api_token: env_token("NET_SENTINEL_API_TOKEN"),
+        max_restore_isps: env_usize("NET_SENTINEL_MAX_RESTORE_ISPS", 10000),
+        max_restore_websites: env_usize("NET_SENTINEL_MAX_RESTORE_WEBSITES", 10000),
+        max_restore_gameservers: env_usize("NET_SENTINEL_MAX_RESTORE_GAMESERVERS", 10000),
+        geoip: geoip::load_from_env(),
+    });
+
+    let max_body_bytes = env_usize("NET_SENTINEL_MAX_REQUEST_BODY_BYTES", 1024 * 1024);
+    let max_restore_body_bytes = env_usize("NET_SENTINEL_MAX_RESTORE_BODY_BYTES", 10 * 1024 * 1024);
 
     // Build our application with routes
     let app = Router::new()
@@ -30,6 +59,7 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/code-server.js", get(code_server::language_server_handler))
         .route("/api/isps", get(api::list_isps))
         .route("/api/isps", post(api::create_isp))
+        .route("/api/isps/import", post(api::import_isps))
         .route("/api/isps/:id", delete(api::delete_isp))
         .route("/api/websites", get(api::list_websites))
         .route("/api/websites", post(api::create_website))
@@ -37,15 +67,69 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/gameservers", get(api::list_game_servers))
         .route("/api/gameservers", post(api::create_game_server))
         .route("/api/gameservers/test", post(api::test_game_server_config))
+        .route("/api/gameservers/build", post(api::build_game_server_config))
+        .route("/api/format-script", post(api::format_script))
+        .route("/api/analyze-script", post(api::analyze_script))
+        .route("/api/lint-script", post(api::lint_script))
         .route("/api/gameservers/:id", delete(api::delete_game_server))
         .route("/api/gameservers/:id/test", post(api::test_game_server))
+        .route("/api/gameservers/:id/clone", post(api::clone_game_server))
+        .route("/api/stats/isps/:id/uptime", get(api::isp_uptime))
+        .route("/api/stats/websites/:id/uptime", get(api::website_uptime))
+        .route("/api/stats/gameservers/:id/uptime", get(api::game_server_uptime))
+        .route("/api/backup", get(api::backup))
+        .route("/api/export", get(api::export_all))
+        .route("/api/export/isps", get(api::export_isps))
+        .route("/api/export/websites", get(api::export_websites))
+        .route("/api/export/gameservers", get(api::export_game_servers))
+        .route("/api/audit", get(api::list_audit))
+        .route("/api/maintenance", get(api::list_maintenance_windows))
+        .route("/api/maintenance", post(api::create_maintenance_window))
+        .route("/api/maintenance/:id", put(api::update_maintenance_window))
+        .route("/api/maintenance/:id", delete(api::delete_maintenance_window))
+        .route("/api/webhooks", get(api::list_result_webhooks))
+        .route("/api/webhooks", post(api::create_result_webhook))
+        .route("/api/webhooks/:id", delete(api::delete_result_webhook))
+        .route("/api/snippets", get(api::list_snippets))
+        .route("/api/snippets", post(api::create_snippet))
+        .route("/api/snippets/:id", put(api::update_snippet))
+        .route("/api/snippets/:id", delete(api::delete_snippet))
+        .route("/api/tools", get(api::list_tools))
+        .route("/api/tools/hexdump", post(api::tool_hexdump))
+        .route("/api/tools/parse-varint", post(api::tool_parse_varint))
+        .route("/api/prometheus/rules.yaml", get(api::prometheus_rules))
         .route("/metrics", get(metrics_handler))
+        .layer(RequestBodyLimitLayer::new(max_body_bytes))
+        // Restore uploads a whole backup, so it gets its own (larger) limit
+        .merge(Router::new().route(
+            "/api/restore",
+            post(api::restore).layer(RequestBodyLimitLayer::new(max_restore_body_bytes)),
+        ))
+        // The limit layers above replace axum's built-in 2MB extractor limit
+        .layer(DefaultBodyLimit::disable())
+        .layer(axum::middleware::map_response(body_too_large_as_json))
         .layer(Extension(app_state));
+    // Outermost, so preflight requests are answered before any other layer sees them
+    let app = match cors_layer_from_env() {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
 
     // Run it
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3100").await?;
-    out::info("main", &format!("Net Sentinel running on http://localhost:3100"));
-    axum::serve(listener, app).await?;
+    // Connection info gives handlers the client address recorded in the audit log
+    let service = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    match tls_config_from_env().await? {
+        Some(tls) => {
+            let addr: std::net::SocketAddr = "0.0.0.0:3143".parse()?;
+            out::info("main", "Net Sentinel running on https://localhost:3143");
+            axum_server::bind_rustls(addr, tls).serve(service).await?;
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind("0.0.0.0:3100").await?;
+            out::info("main", "Net Sentinel running on http://localhost:3100");
+            axum::serve(listener, service).await?;
+        }
+    }
 
     Ok(())
 }
@@ -53,6 +137,110 @@ async fn main() -> anyhow::Result<()> {
 #[derive(Clone)]
 struct AppState {
     store: db::JsonStore,
+    max_concurrent_isps: usize,
+    max_concurrent_websites: usize,
+    max_concurrent_gameservers: usize,
+    history: history::CheckResultCache,
+    api_token: Option<String>,
+    max_restore_isps: usize,
+    max_restore_websites: usize,
+    max_restore_gameservers: usize,
+    geoip: Option<geoip::GeoIpReader>,
+}
+
+/// Read a positive `usize` from the environment, falling back to `default` when unset or invalid
+fn env_usize(name: &str, default: usize) -> usize {
+    match std::env::var(name) {
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(n) if n > 0 => {
+                out::info("main", &format!("{} set to {}", name, n));
+                n
+            }
+            _ => {
+                out::warning("main", &format!("Invalid value for {}: '{}'. Using default {}", name, value, default));
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Read an optional secret from the environment, treating an empty value as unset
+fn env_token(name: &str) -> Option<String> {
+    let token = std::env::var(name).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+    if token.is_some() {
+        out::info("main", &format!("{} set, protected endpoints require a bearer token", name));
+    }
+    token
+}
+
+/// TLS config from the PEM files in `NET_SENTINEL_TLS_CERT` and `NET_SENTINEL_TLS_KEY`.
+/// Returns `None` (plain HTTP) when neither is set, and an error if only one is set or the files can't be loaded.
+async fn tls_config_from_env() -> anyhow::Result<Option<axum_server::tls_rustls::RustlsConfig>> {
+    use anyhow::Context;
+
+    let path = |name: &str| std::env::var(name).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+    let cert = path("NET_SENTINEL_TLS_CERT");
+    let key = path("NET_SENTINEL_TLS_KEY");
+    let (cert, key) = match (cert, key) {
+        (None, None) => return Ok(None),
+        (Some(cert), Some(key)) => (cert, key),
+        _ => anyhow::bail!("NET_SENTINEL_TLS_CERT and NET_SENTINEL_TLS_KEY must be set together"),
+    };
+
+    let config = axum_server::tls_rustls::RustlsConfig::from_pem_file(&cert, &key)
+        .await
+        .with_context(|| format!("Failed to load TLS certificate '{}' and key '{}'", cert, key))?;
+    Ok(Some(config))
+}
+
+/// CORS layer for the origins in `NET_SENTINEL_CORS_ORIGINS` (comma-separated, or `*` for any).
+/// Cross-origin requests are not allowed when it is unset.
+fn cors_layer_from_env() -> Option<CorsLayer> {
+    use axum::http::{HeaderValue, Method};
+
+    let value = std::env::var("NET_SENTINEL_CORS_ORIGINS").ok()?;
+    let allow_origin = if value.trim() == "*" {
+        AllowOrigin::any()
+    } else {
+        let mut origins = Vec::new();
+        for origin in value.split(',').map(str::trim).filter(|origin| !origin.is_empty()) {
+            match HeaderValue::from_str(origin) {
+                Ok(origin) => origins.push(origin),
+                Err(_) => out::warning("main", &format!("Ignoring invalid CORS origin '{}'", origin)),
+            }
+        }
+        if origins.is_empty() {
+            return None;
+        }
+        AllowOrigin::list(origins)
+    };
+    out::info("main", &format!("CORS enabled for NET_SENTINEL_CORS_ORIGINS={}", value.trim()));
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE, Method::OPTIONS])
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+            .max_age(std::time::Duration::from_secs(24 * 60 * 60)),
+    )
+}
+
+/// Give 413 responses from the body limit layers the same JSON error body as the API handlers
+async fn body_too_large_as_json(response: Response) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_json {
+        return response;
+    }
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        axum::Json(serde_json::json!({"error": "request body too large"})),
+    )
+        .into_response()
 }
 
 async fn index_handler() -> impl IntoResponse {
@@ -96,6 +284,82 @@ async fn check_internet_connectivity(ip: &str) -> (bool, u64) {
     (false, elapsed_ms)
 }
 
+/// Name looked up by DNS ISP checks
+const ISP_DNS_CHECK_NAME: &str = "google.com.";
+
+/// Check an ISP by resolving a well-known name with the ISP's IP as the DNS server.
+/// `ip` may carry a port (`192.168.1.1:5353`); otherwise port 53 is used.
+async fn check_isp_dns(ip: &str) -> (bool, u64) {
+    use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+    use hickory_resolver::TokioAsyncResolver;
+    use tokio::time::{timeout, Duration, Instant};
+    let start = Instant::now();
+    
+    let server = match ip.parse::<std::net::SocketAddr>() {
+        Ok(addr) => addr,
+        Err(_) => match ip.parse::<std::net::IpAddr>() {
+            Ok(addr) => std::net::SocketAddr::new(addr, 53),
+            Err(_) => {
+                out::warning("isp_check", &format!("DNS check needs an IP address, got {}", ip));
+                return (false, 0);
+            }
+        },
+    };
+    
+    let name_servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true);
+    let mut opts = ResolverOpts::default();
+    opts.timeout = Duration::from_secs(2);
+    opts.attempts = 1;
+    opts.cache_size = 0;
+    let resolver = TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], name_servers), opts);
+    
+    let resolved = matches!(
+        timeout(Duration::from_secs(2), resolver.lookup_ip(ISP_DNS_CHECK_NAME)).await,
+        Ok(Ok(_))
+    );
+    (resolved, start.elapsed().as_millis() as u64)
+}
+
+/// Connect timeout for TCP ISP checks, matching the 2 second HTTP check timeout
+const ISP_TCP_CHECK_TIMEOUT_MS: u64 = 2000;
+
+/// Reply timeout for ICMP ISP checks, matching the 2 second HTTP check timeout
+const ISP_ICMP_CHECK_TIMEOUT_MS: u64 = 2000;
+
+/// Check an ISP with an ICMP echo request, for ISPs that only answer pings.
+/// The timing is the round-trip time of the reply.
+async fn check_isp_icmp(ip: &str) -> (bool, u64) {
+    use tokio::time::{Duration, Instant};
+    let start = Instant::now();
+
+    let addr = match ip.parse::<std::net::IpAddr>() {
+        Ok(addr) => addr,
+        Err(_) => {
+            out::warning("isp_check", &format!("ICMP check needs an IP address, got {}", ip));
+            return (false, 0);
+        }
+    };
+    match probes::icmp::icmp_ping(addr, Duration::from_millis(ISP_ICMP_CHECK_TIMEOUT_MS)).await {
+        Ok(rtt) => (true, rtt.as_millis() as u64),
+        Err(e) => {
+            out::warning("isp_check", &format!("ICMP check of {} failed: {}", ip, e));
+            (false, start.elapsed().as_millis() as u64)
+        }
+    }
+}
+
+/// Check an ISP by opening a TCP connection to `ip:port`; no data is sent
+async fn check_isp_tcp(ip: &str, port: u16, timeout_ms: u64) -> (bool, u64) {
+    use tokio::time::{timeout, Duration, Instant};
+    let start = Instant::now();
+    
+    let connected = matches!(
+        timeout(Duration::from_millis(timeout_ms), tokio::net::TcpStream::connect((ip, port))).await,
+        Ok(Ok(_))
+    );
+    (connected, start.elapsed().as_millis() as u64)
+}
+
 async fn check_website_external(url: &str) -> (bool, u64) {
     use tokio::time::{timeout, Duration, Instant};
     let start = Instant::now();
@@ -268,40 +532,69 @@ async fn metrics_handler(Extension(state): Extension<Arc<AppState>>) -> Response
         }
     };
 
+    let snippets = match api::snippet_map_internal(&state.store).await {
+        Ok(snippets) => snippets,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "# HELP net_sentinel_error Error fetching snippets\n# TYPE net_sentinel_error counter\nnet_sentinel_error 1\n",
+            )
+                .into_response();
+        }
+    };
+
+    // Entities in an active maintenance window are reported as up without being checked
+    let maintenance = match api::active_maintenance_internal(&state.store).await {
+        Ok(maintenance) => maintenance,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "# HELP net_sentinel_error Error fetching maintenance windows\n# TYPE net_sentinel_error counter\nnet_sentinel_error 1\n",
+            )
+                .into_response();
+        }
+    };
+    let in_maintenance = |kind: history::EntityKind, id: i64| maintenance.contains(&(kind, id));
+
     // Run all checks concurrently: ISPs, websites, and game servers all at the same time
-    let ((internet_up, isp_timing_results), website_results, game_server_results) = tokio::join!(
-        // Check internet connectivity - check all ISPs concurrently (max 100 at a time)
+    let (isp_results, website_results, game_server_results) = tokio::join!(
+        // Check internet connectivity - check all ISPs concurrently (limited by max_concurrent_isps)
         async {
             if !isps.is_empty() {
                 use futures::stream::{self, StreamExt};
                 use std::collections::HashMap;
                 
-                // Create a stream of futures with concurrency limit of 100
-                let ip_addresses: Vec<String> = isps.iter().map(|isp| isp.ip.clone()).collect();
-                let results = stream::iter(ip_addresses.iter().cloned())
-                    .map(|ip| async move {
-                        let (success, timing_ms) = check_internet_connectivity(&ip).await;
-                        (ip, success, timing_ms)
+                let active: Vec<&crate::models::Isp> = isps
+                    .iter()
+                    .filter(|isp| !in_maintenance(history::EntityKind::Isp, isp.id))
+                    .collect();
+                let targets = unique_isp_checks(&active);
+                
+                // Create a stream of futures with the configured concurrency limit
+                let results = stream::iter(targets)
+                    .map(|(ip, check_method, check_port)| async move {
+                        let (success, timing_ms) = match check_method {
+                            crate::models::IspCheckMethod::Http => check_internet_connectivity(&ip).await,
+                            crate::models::IspCheckMethod::Dns => check_isp_dns(&ip).await,
+                            crate::models::IspCheckMethod::Tcp => check_isp_tcp(&ip, check_port, ISP_TCP_CHECK_TIMEOUT_MS).await,
+                            crate::models::IspCheckMethod::Icmp => check_isp_icmp(&ip).await,
+                        };
+                        ((ip, check_method, check_port), success, timing_ms)
                     })
-                    .buffer_unordered(100);
+                    .buffer_unordered(state.max_concurrent_isps);
                 
-                // Check results as they come in - return true on first success
+                // Collect success and timing for every ISP, failed ones included
                 let mut stream = results;
-                let mut internet_up_result = false;
-                let mut timing_map: HashMap<String, u64> = HashMap::new();
-                while let Some((ip, success, timing_ms)) = stream.next().await {
-                    timing_map.insert(ip.clone(), timing_ms);
-                    if success && !internet_up_result {
-                        // Found a reachable ISP, internet is up
-                        internet_up_result = true;
-                    }
+                let mut result_map: HashMap<crate::models::IspCheckKey, (bool, u64)> = HashMap::new();
+                while let Some((key, success, timing_ms)) = stream.next().await {
+                    result_map.insert(key, (success, timing_ms));
                 }
-                (internet_up_result, timing_map)
+                result_map
             } else {
-                (false, std::collections::HashMap::new())
+                std::collections::HashMap::new()
             }
         },
-        // Check all websites concurrently (max 100 at a time)
+        // Check all websites concurrently (limited by max_concurrent_websites)
         async {
             if !websites.is_empty() {
                 use std::collections::HashMap;
@@ -309,7 +602,7 @@ async fn metrics_handler(Extension(state): Extension<Arc<AppState>>) -> Response
                 
                 // Build a list of all check operations (external and direct) to perform with cloned data
                 let mut check_operations = Vec::new();
-                for website in &websites {
+                for website in websites.iter().filter(|website| !in_maintenance(history::EntityKind::Website, website.id)) {
                     let url = website.url.clone();
                     let url_for_check = website.url.clone();
                     check_operations.push(("external".to_string(), url.clone(), url_for_check.clone(), None));
@@ -335,7 +628,7 @@ async fn metrics_handler(Extension(state): Extension<Arc<AppState>>) -> Response
                         };
                         ((url, check_type), (result, timing_ms))
                     })
-                    .buffer_unordered(100);
+                    .buffer_unordered(state.max_concurrent_websites);
                 
                 let mut results = HashMap::new();
                 let mut stream = results_stream;
@@ -348,19 +641,25 @@ async fn metrics_handler(Extension(state): Extension<Arc<AppState>>) -> Response
                 std::collections::HashMap::new()
             }
         },
-        // Check game servers concurrently
+        // Check game servers concurrently (limited by max_concurrent_gameservers)
         async {
             if !game_servers.is_empty() {
                 use std::collections::HashMap;
                 use futures::stream::{self, StreamExt};
                 
-                let servers_clone: Vec<_> = game_servers.iter().cloned().collect();
+                let servers_clone: Vec<_> = game_servers.iter()
+                    .filter(|server| !in_maintenance(history::EntityKind::GameServer, server.id))
+                    .cloned()
+                    .collect();
                 let results_stream = stream::iter(servers_clone)
-                    .map(|server| async move {
-                        let result = crate::gameserver_check::check_game_server(&server).await;
-                        (server.id, server.name.clone(), server.address.clone(), server.port, result)
+                    .map(|server| {
+                        let snippets = &snippets;
+                        async move {
+                            let result = crate::gameserver_check::check_game_server(&server, snippets).await;
+                            (server.id, server.name.clone(), server.address.clone(), server.port, result)
+                        }
                     })
-                    .buffer_unordered(100);
+                    .buffer_unordered(state.max_concurrent_gameservers);
                 
                 let mut results = HashMap::new();
                 let mut stream = results_stream;
@@ -374,19 +673,70 @@ async fn metrics_handler(Extension(state): Extension<Arc<AppState>>) -> Response
         }
     );
 
-    let response = build_metrics_response(&isps, internet_up, &isp_timing_results, &websites, &website_results, &game_servers, &game_server_results);
+    record_check_history(&state.history, &isps, &isp_results, &websites, &website_results, &game_server_results);
+
+    match api::list_result_webhooks_internal(&state.store).await {
+        Ok(result_webhooks) if !result_webhooks.is_empty() => {
+            let events = webhooks::events_from_results(&isps, &isp_results, &websites, &website_results, &game_servers, &game_server_results);
+            webhooks::dispatch(&result_webhooks, &events);
+        }
+        Ok(_) => {}
+        Err(e) => out::warning("webhooks", &format!("Failed to load result webhooks: {}", e)),
+    }
+
+    let checks = CheckResults {
+        isps: &isps,
+        isp_results: &isp_results,
+        websites: &websites,
+        website_results: &website_results,
+        game_servers: &game_servers,
+        game_server_results: &game_server_results,
+        maintenance: &maintenance,
+    };
+    let mut metrics = build_metrics_response(checks, state.geoip.as_deref());
+    metrics.push_str(&build_uptime_metrics(&state.history, &isps, &websites, &game_servers));
+    let response = (StatusCode::OK, [(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)], metrics).into_response();
     
     // Log timing information for fastest and slowest checks
-    log_timing_info(&isps, &isp_timing_results, &websites, &website_results, &game_servers, &game_server_results);
+    log_timing_info(&isps, &isp_results, &websites, &website_results, &game_servers, &game_server_results);
     
     let elapsed = start.elapsed();
     out::info("metrics", &format!("Processed /metrics endpoint in {:.2}ms", elapsed.as_secs_f64() * 1000.0));
     response
 }
 
+/// Record the outcome of every check in the uptime history.
+/// Websites count as up when their external check succeeds.
+fn record_check_history(
+    history: &history::CheckResultCache,
+    isps: &[crate::models::Isp],
+    isp_results: &std::collections::HashMap<crate::models::IspCheckKey, (bool, u64)>,
+    websites: &[crate::models::Website],
+    website_results: &std::collections::HashMap<(String, String), (bool, u64)>,
+    game_server_results: &std::collections::HashMap<i64, (String, String, u16, crate::models::GameServerTestResult)>,
+) {
+    use history::EntityKind;
+
+    for isp in isps {
+        if let Some(&(success, _)) = isp_results.get(&isp.check_key()) {
+            history.record(EntityKind::Isp, isp.id, success);
+        }
+    }
+
+    for website in websites {
+        if let Some(&(success, _)) = website_results.get(&(website.url.clone(), "external".to_string())) {
+            history.record(EntityKind::Website, website.id, success);
+        }
+    }
+
+    for (id, (_, _, _, result)) in game_server_results {
+        history.record(EntityKind::GameServer, *id, result.success);
+    }
+}
+
 fn log_timing_info(
     isps: &[crate::models::Isp],
-    isp_timing_results: &std::collections::HashMap<String, u64>,
+    isp_results: &std::collections::HashMap<crate::models::IspCheckKey, (bool, u64)>,
     websites: &[crate::models::Website],
     website_results: &std::collections::HashMap<(String, String), (bool, u64)>,
     game_servers: &[crate::models::GameServer],
@@ -399,7 +749,7 @@ fn log_timing_info(
     
     // ISP timings
     for isp in isps {
-        if let Some(&timing_ms) = isp_timing_results.get(&isp.ip) {
+        if let Some(&(_, timing_ms)) = isp_results.get(&isp.check_key()) {
             all_timings.push((format!("ISP: {} ({})", isp.name, isp.ip), timing_ms));
         }
     }
@@ -481,6 +831,71 @@ fn escape_prometheus_label(value: &str) -> String {
         .replace('\n', "\\n")
 }
 
+/// Metric name -> TYPE for every `# TYPE` line already in `metrics`
+fn declared_metric_types(metrics: &str) -> std::collections::HashMap<String, String> {
+    metrics
+        .lines()
+        .filter_map(|line| line.strip_prefix("# TYPE "))
+        .filter_map(|rest| rest.split_once(' '))
+        .map(|(name, metric_type)| (name.to_string(), metric_type.trim().to_string()))
+        .collect()
+}
+
+/// Write the HELP and TYPE lines for a metric unless it was already declared.
+/// A metric declared again with a different TYPE keeps its first declaration and logs a warning.
+fn declare_metric(
+    metrics: &mut String,
+    declared: &mut std::collections::HashMap<String, String>,
+    name: &str,
+    help: &str,
+    metric_type: &str,
+) {
+    match declared.get(name) {
+        None => {
+            metrics.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, metric_type));
+            declared.insert(name.to_string(), metric_type.to_string());
+        }
+        Some(existing) if existing != metric_type => out::warning(
+            "metrics",
+            &format!(
+                "Metric {} is already declared as {}, skipping TYPE {}",
+                name, existing, metric_type
+            ),
+        ),
+        Some(_) => {}
+    }
+}
+
+/// `site` label of a website's metrics: its `metric_label`, or the host name from its URL
+fn website_site_label(website: &crate::models::Website) -> String {
+    if let Some(label) = &website.metric_label {
+        return label.clone();
+    }
+    // Extract site name from URL (remove protocol, path, etc.)
+    website.url
+        .replace("https://", "")
+        .replace("http://", "")
+        .split('/')
+        .next()
+        .unwrap_or(&website.url)
+        .split(':')
+        .next()
+        .unwrap_or(&website.url)
+        .to_string()
+}
+
+const DEFAULT_GAMESERVER_METRIC_PREFIX: &str = "net_sentinel_gameserver";
+
+/// Metric name prefix for a game server: its sanitized `metric_prefix`, or `net_sentinel_gameserver`
+fn game_server_metric_prefix(server: &crate::models::GameServer) -> String {
+    server.metric_prefix
+        .as_deref()
+        .map(str::trim)
+        .filter(|prefix| !prefix.is_empty())
+        .map(sanitize_metric_name)
+        .unwrap_or_else(|| DEFAULT_GAMESERVER_METRIC_PREFIX.to_string())
+}
+
 fn sanitize_metric_name(name: &str) -> String {
     // Prometheus metric names must match [a-zA-Z_:][a-zA-Z0-9_:]*
     // Replace invalid characters with underscores
@@ -510,34 +925,107 @@ fn sanitize_metric_name(name: &str) -> String {
     sanitized
 }
 
-fn build_metrics_response(
-    isps: &[crate::models::Isp],
-    internet_up: bool,
-    isp_timing_results: &std::collections::HashMap<String, u64>,
-    websites: &[crate::models::Website],
-    website_results: &std::collections::HashMap<(String, String), (bool, u64)>,
-    game_servers: &[crate::models::GameServer],
-    game_server_results: &std::collections::HashMap<i64, (String, String, u16, crate::models::GameServerTestResult)>,
-) -> Response {
+/// The checks to run for the ISPs. Results are keyed by IP, check method and port, so ISPs that
+/// would run the same check are checked once and the result is reported under each of their names.
+fn unique_isp_checks(isps: &[&crate::models::Isp]) -> Vec<crate::models::IspCheckKey> {
+    let mut seen_checks = std::collections::HashSet::new();
+    let mut targets = Vec::new();
+    for isp in isps {
+        if seen_checks.insert(isp.check_key()) {
+            targets.push(isp.check_key());
+        } else {
+            out::warning("metrics", &format!("ISP '{}' has the same IP and check as another ISP, checking it once", isp.name));
+        }
+    }
+    targets
+}
+
+/// Everything checked for one `/metrics` request, with the results and the entities in maintenance
+#[derive(Clone, Copy)]
+struct CheckResults<'a> {
+    isps: &'a [crate::models::Isp],
+    isp_results: &'a std::collections::HashMap<crate::models::IspCheckKey, (bool, u64)>,
+    websites: &'a [crate::models::Website],
+    website_results: &'a std::collections::HashMap<(String, String), (bool, u64)>,
+    game_servers: &'a [crate::models::GameServer],
+    game_server_results: &'a std::collections::HashMap<i64, (String, String, u16, crate::models::GameServerTestResult)>,
+    maintenance: &'a std::collections::HashSet<(history::EntityKind, i64)>,
+}
+
+fn build_metrics_response(checks: CheckResults, geoip: Option<&maxminddb::Reader<Vec<u8>>>) -> String {
+    let CheckResults { isps, isp_results, websites, website_results, game_servers, game_server_results, maintenance } = checks;
+    let in_maintenance = |kind: history::EntityKind, id: i64| maintenance.contains(&(kind, id));
     let mut metrics = format!(
         "# HELP net_sentinel_version Version information\n# TYPE net_sentinel_version gauge\nnet_sentinel_version{{version=\"{}\"}} 1\n",
         VERSION
     );
 
+    // Internet is up as long as any ISP is reachable, or counts as up in maintenance when every ISP is
+    let internet_up = isp_results.values().any(|&(success, _)| success);
+    let all_isps_in_maintenance = !isps.is_empty() && isps.iter().all(|isp| in_maintenance(history::EntityKind::Isp, isp.id));
     metrics.push_str("# HELP net_sentinel_internet_up Internet connectivity status (1 = up, 0 = down)\n# TYPE net_sentinel_internet_up gauge\n");
-    metrics.push_str(&format!("net_sentinel_internet_up {}\n", if internet_up { 1 } else { 0 }));
+    if !internet_up && all_isps_in_maintenance {
+        metrics.push_str("net_sentinel_internet_up{maintenance=\"true\"} 1\n");
+    } else {
+        metrics.push_str(&format!("net_sentinel_internet_up {}\n", if internet_up { 1 } else { 0 }));
+    }
 
-    // Add ISP timing metrics
-    metrics.push_str("# HELP net_sentinel_isp_response_time ISP response time in milliseconds\n# TYPE net_sentinel_isp_response_time gauge\n");
-    for isp in isps {
-        if let Some(&timing_ms) = isp_timing_results.get(&isp.ip) {
-            metrics.push_str(&format!(
-                "net_sentinel_isp_response_time{{name=\"{}\",ip=\"{}\"}} {}\n",
+    // Add per-ISP metrics for every configured ISP, whether or not it responded.
+    // Failed checks report the time spent before giving up (0 if the ISP was never checked).
+    // With a GeoIP database loaded, country/asn labels are added when the IP is found.
+    let isp_labels: Vec<String> = isps
+        .iter()
+        .map(|isp| {
+            let geo_labels = geoip.map(|reader| geoip::isp_labels(reader, &isp.ip)).unwrap_or_default();
+            format!(
+                "name=\"{}\",ip=\"{}\"{}",
                 escape_prometheus_label(&isp.name),
                 escape_prometheus_label(&isp.ip),
-                timing_ms
-            ));
+                geo_labels
+            )
+        })
+        .collect();
+
+    // Primary ISPs: up if any primary ISP is reachable, plus the response time of the fastest one.
+    // Only exported when at least one ISP is marked primary.
+    let primary_isps: Vec<&crate::models::Isp> = isps.iter().filter(|isp| isp.is_primary).collect();
+    if !primary_isps.is_empty() {
+        let fastest_primary_ms = primary_isps
+            .iter()
+            .filter_map(|isp| isp_results.get(&isp.check_key()))
+            .filter(|&&(success, _)| success)
+            .map(|&(_, timing_ms)| timing_ms)
+            .min();
+        let all_primaries_in_maintenance = primary_isps.iter().all(|isp| in_maintenance(history::EntityKind::Isp, isp.id));
+        metrics.push_str("# HELP net_sentinel_primary_isp_up Primary ISP connectivity status (1 = a primary ISP is up, 0 = all down)\n# TYPE net_sentinel_primary_isp_up gauge\n");
+        if fastest_primary_ms.is_none() && all_primaries_in_maintenance {
+            metrics.push_str("net_sentinel_primary_isp_up{maintenance=\"true\"} 1\n");
+        } else {
+            metrics.push_str(&format!("net_sentinel_primary_isp_up {}\n", if fastest_primary_ms.is_some() { 1 } else { 0 }));
+        }
+        if let Some(timing_ms) = fastest_primary_ms {
+            metrics.push_str("# HELP net_sentinel_primary_isp_response_time_ms Response time of the fastest reachable primary ISP in milliseconds\n# TYPE net_sentinel_primary_isp_response_time_ms gauge\n");
+            metrics.push_str(&format!("net_sentinel_primary_isp_response_time_ms {}\n", timing_ms));
+        }
+    }
+
+    metrics.push_str("# HELP net_sentinel_isp_up ISP reachability status (1 = up, 0 = down)\n# TYPE net_sentinel_isp_up gauge\n");
+    for (isp, labels) in isps.iter().zip(&isp_labels) {
+        if in_maintenance(history::EntityKind::Isp, isp.id) {
+            metrics.push_str(&format!("net_sentinel_isp_up{{{},maintenance=\"true\"}} 1\n", labels));
+            continue;
         }
+        let up = isp_results.get(&isp.check_key()).map(|&(success, _)| success).unwrap_or(false);
+        metrics.push_str(&format!("net_sentinel_isp_up{{{}}} {}\n", labels, if up { 1 } else { 0 }));
+    }
+
+    metrics.push_str("# HELP net_sentinel_isp_response_time ISP response time in milliseconds\n# TYPE net_sentinel_isp_response_time gauge\n");
+    for (isp, labels) in isps.iter().zip(&isp_labels) {
+        if in_maintenance(history::EntityKind::Isp, isp.id) {
+            continue;
+        }
+        let timing_ms = isp_results.get(&isp.check_key()).map(|&(_, timing_ms)| timing_ms).unwrap_or(0);
+        metrics.push_str(&format!("net_sentinel_isp_response_time{{{}}} {}\n", labels, timing_ms));
     }
 
     // Add website metrics
@@ -547,17 +1035,15 @@ fn build_metrics_response(
     metrics.push_str("# HELP net_sentinel_website_direct_response_time Direct website response time in milliseconds\n# TYPE net_sentinel_website_direct_response_time gauge\n");
     
     for website in websites {
-        // Extract site name from URL (remove protocol, path, etc.)
-        let site = website.url
-            .replace("https://", "")
-            .replace("http://", "")
-            .split('/')
-            .next()
-            .unwrap_or(&website.url)
-            .split(':')
-            .next()
-            .unwrap_or(&website.url)
-            .to_string();
+        let site = escape_prometheus_label(&website_site_label(website));
+
+        if in_maintenance(history::EntityKind::Website, website.id) {
+            metrics.push_str(&format!("net_sentinel_website_external_up{{site=\"{}\",maintenance=\"true\"}} 1\n", site));
+            if website.direct_connect {
+                metrics.push_str(&format!("net_sentinel_website_direct_up{{site=\"{}\",maintenance=\"true\"}} 1\n", site));
+            }
+            continue;
+        }
         
         // External check result
         if let Some(&(external_result, timing_ms)) = website_results.get(&(website.url.clone(), "external".to_string())) {
@@ -590,20 +1076,48 @@ fn build_metrics_response(
         }
     }
 
-    // Add game server metrics
-    metrics.push_str("# HELP net_sentinel_gameserver_up Game server connectivity status (1 = up, 0 = down)\n# TYPE net_sentinel_gameserver_up gauge\n");
-    metrics.push_str("# HELP net_sentinel_gameserver_response_time Game server response time in milliseconds\n# TYPE net_sentinel_gameserver_response_time gauge\n");
+    push_game_server_metrics(&mut metrics, game_servers, game_server_results, maintenance);
+    metrics
+}
+
+/// Game server metrics for `/metrics`, appended to `metrics` without repeating its HELP/TYPE lines.
+/// Also used by `POST /api/gameservers/:id/test?format=prometheus` for a single server.
+fn push_game_server_metrics(
+    metrics: &mut String,
+    game_servers: &[crate::models::GameServer],
+    game_server_results: &std::collections::HashMap<i64, (String, String, u16, crate::models::GameServerTestResult)>,
+    maintenance: &std::collections::HashSet<(history::EntityKind, i64)>,
+) {
+    let in_maintenance = |kind: history::EntityKind, id: i64| maintenance.contains(&(kind, id));
+
+    // Track the TYPE of every metric declared so far, so game server metrics (whose names come
+    // from prefixes and output keys) never repeat or contradict a HELP/TYPE declaration
+    let mut declared_metrics = declared_metric_types(metrics);
     
-    // Track which output metrics we've documented to avoid duplicate HELP/TYPE lines
-    let mut documented_metrics = std::collections::HashSet::new();
+    // Add game server metrics
+    declare_metric(metrics, &mut declared_metrics, "net_sentinel_gameserver_up", "Game server connectivity status (1 = up, 0 = down)", "gauge");
+    declare_metric(metrics, &mut declared_metrics, "net_sentinel_gameserver_response_time", "Game server response time in milliseconds", "gauge");
     
     for server in game_servers {
-        if let Some((name, address, port, result)) = game_server_results.get(&server.id) {
+        let prefix = game_server_metric_prefix(server);
+        declare_metric(metrics, &mut declared_metrics, &format!("{}_up", prefix), "Game server connectivity status (1 = up, 0 = down)", "gauge");
+        declare_metric(metrics, &mut declared_metrics, &format!("{}_response_time", prefix), "Game server response time in milliseconds", "gauge");
+        
+        if in_maintenance(history::EntityKind::GameServer, server.id) {
+            metrics.push_str(&format!(
+                "{}_up{{name=\"{}\",address=\"{}\",port=\"{}\",maintenance=\"true\"}} 1\n",
+                prefix,
+                escape_prometheus_label(&server.name),
+                escape_prometheus_label(&server.address),
+                server.port
+            ));
+        } else if let Some((name, address, port, result)) = game_server_results.get(&server.id) {
             let is_up = result.success;
             let response_time = result.response_time_ms;
             
             metrics.push_str(&format!(
-                "net_sentinel_gameserver_up{{name=\"{}\",address=\"{}\",port=\"{}\"}} {}\n",
+                "{}_up{{name=\"{}\",address=\"{}\",port=\"{}\"}} {}\n",
+                prefix,
                 escape_prometheus_label(name),
                 escape_prometheus_label(address),
                 port,
@@ -611,7 +1125,8 @@ fn build_metrics_response(
             ));
             
             metrics.push_str(&format!(
-                "net_sentinel_gameserver_response_time{{name=\"{}\",address=\"{}\",port=\"{}\"}} {}\n",
+                "{}_response_time{{name=\"{}\",address=\"{}\",port=\"{}\"}} {}\n",
+                prefix,
                 escape_prometheus_label(name),
                 escape_prometheus_label(address),
                 port,
@@ -627,34 +1142,35 @@ fn build_metrics_response(
             );
             
             // Add output metrics for success case
-            for label in &result.output_labels_success {
+            for (idx, label) in result.output_labels_success.iter().enumerate() {
                 // Parse the RETURN output string (e.g., "protocol=773, player_max=500, version=1.20.1")
                 let parsed_labels = parse_return_output(label);
                 
+                // Labels from a named OUTPUT_SUCCESS block are a separate series per block
+                let series_labels = match result.output_label_names_success.get(idx).and_then(|name| name.as_deref()) {
+                    Some(output_name) => format!("{},output=\"{}\"", common_labels, escape_prometheus_label(output_name)),
+                    None => common_labels.clone(),
+                };
+                
                 // Create a separate metric for each key-value pair
                 for (key, value) in &parsed_labels {
                     // Sanitize key for metric name (Prometheus metric names must match [a-zA-Z_:][a-zA-Z0-9_:]*)
                     let sanitized_key = sanitize_metric_name(key);
-                    let metric_name = format!("net_sentinel_gameserver_output_{}", sanitized_key);
-                    
-                    // Add HELP and TYPE lines once per metric type
-                    if documented_metrics.insert(metric_name.clone()) {
-                        metrics.push_str(&format!(
-                            "# HELP {} Game server output metric for {}\n# TYPE {} gauge\n",
-                            metric_name, key, metric_name
-                        ));
-                    }
+                    let metric_name = format!("{}_output_{}", prefix, sanitized_key);
                     
                     // Try to parse value as a number, otherwise use 1 and add value as a label
-                    let (metric_value, labels_str) = if let Ok(num) = value.parse::<f64>() {
+                    let (metric_type, metric_value, labels_str) = if let Ok(num) = value.parse::<f64>() {
                         // Numeric value - use it directly
-                        (num, common_labels.clone())
+                        ("gauge", num, series_labels.clone())
                     } else {
                         // String value - use 1 as value and add original value as a label
-                        let labels_with_value = format!("{},value=\"{}\"", common_labels, escape_prometheus_label(value));
-                        (1.0, labels_with_value)
+                        let labels_with_value = format!("{},value=\"{}\"", series_labels, escape_prometheus_label(value));
+                        ("gauge", 1.0, labels_with_value)
                     };
                     
+                    // Add HELP and TYPE lines once per metric name
+                    declare_metric(metrics, &mut declared_metrics, &metric_name, &format!("Game server output metric for {}", key), metric_type);
+                    
                     metrics.push_str(&format!(
                         "{}{{{}}} {}\n",
                         metric_name,
@@ -670,23 +1186,18 @@ fn build_metrics_response(
                 
                 for (key, value) in &parsed_labels {
                     let sanitized_key = sanitize_metric_name(key);
-                    let metric_name = format!("net_sentinel_gameserver_output_{}", sanitized_key);
-                    
-                    if documented_metrics.insert(metric_name.clone()) {
-                        metrics.push_str(&format!(
-                            "# HELP {} Game server output metric for {}\n# TYPE {} gauge\n",
-                            metric_name, key, metric_name
-                        ));
-                    }
+                    let metric_name = format!("{}_output_{}", prefix, sanitized_key);
                     
                     // For error cases, might want to handle differently, but using same logic for now
-                    let (metric_value, labels_str) = if let Ok(num) = value.parse::<f64>() {
-                        (num, common_labels.clone())
+                    let (metric_type, metric_value, labels_str) = if let Ok(num) = value.parse::<f64>() {
+                        ("gauge", num, common_labels.clone())
                     } else {
                         let labels_with_value = format!("{},value=\"{}\"", common_labels, escape_prometheus_label(value));
-                        (1.0, labels_with_value)
+                        ("gauge", 1.0, labels_with_value)
                     };
                     
+                    declare_metric(metrics, &mut declared_metrics, &metric_name, &format!("Game server output metric for {}", key), metric_type);
+                    
                     metrics.push_str(&format!(
                         "{}{{{}}} {}\n",
                         metric_name,
@@ -698,7 +1209,8 @@ fn build_metrics_response(
         } else {
             // Server not checked (shouldn't happen, but handle gracefully)
             metrics.push_str(&format!(
-                "net_sentinel_gameserver_up{{name=\"{}\",address=\"{}\",port=\"{}\"}} 0\n",
+                "{}_up{{name=\"{}\",address=\"{}\",port=\"{}\"}} 0\n",
+                prefix,
                 server.name.replace('"', "\\\""),
                 server.address.replace('"', "\\\""),
                 server.port
@@ -706,5 +1218,285 @@ fn build_metrics_response(
         }
     }
 
-    (StatusCode::OK, metrics).into_response()
+    // Retries used by the last check of each game server (0 when the first attempt settled it)
+    declare_metric(metrics, &mut declared_metrics, "net_sentinel_gameserver_retries", "Retries used by the last game server check", "gauge");
+    for server in game_servers {
+        if let Some((name, address, port, result)) = game_server_results.get(&server.id) {
+            metrics.push_str(&format!(
+                "net_sentinel_gameserver_retries{{name=\"{}\",address=\"{}\",port=\"{}\"}} {}\n",
+                escape_prometheus_label(name),
+                escape_prometheus_label(address),
+                port,
+                result.attempts.saturating_sub(1)
+            ));
+        }
+    }
+
+    // Bytes received by the last check of each game server
+    declare_metric(metrics, &mut declared_metrics, "net_sentinel_gameserver_response_bytes", "Bytes received by the last game server check", "gauge");
+    for server in game_servers {
+        if let Some((name, address, port, result)) = game_server_results.get(&server.id) {
+            metrics.push_str(&format!(
+                "net_sentinel_gameserver_response_bytes{{name=\"{}\",address=\"{}\",port=\"{}\"}} {}\n",
+                escape_prometheus_label(name),
+                escape_prometheus_label(address),
+                port,
+                result.response_bytes
+            ));
+        }
+    }
+
+    // Raw response of the last check for servers that opted in, if it was small. Every distinct
+    // response is a new series, so this is meant for debugging only
+    declare_metric(metrics, &mut declared_metrics, "net_sentinel_gameserver_raw_response", "Raw response of the last game server check as a hex label (opt-in, responses up to 100 bytes)", "gauge");
+    for server in game_servers.iter().filter(|server| server.expose_raw_response) {
+        let Some((name, address, port, result)) = game_server_results.get(&server.id) else {
+            continue;
+        };
+        let Some(raw) = result.raw_response.as_deref() else {
+            continue;
+        };
+        if raw.is_empty() || result.raw_response_truncated || result.response_bytes > MAX_RAW_RESPONSE_METRIC_BYTES {
+            continue;
+        }
+        metrics.push_str(&format!(
+            "net_sentinel_gameserver_raw_response{{name=\"{}\",address=\"{}\",port=\"{}\",hex=\"{}\"}} 1\n",
+            escape_prometheus_label(name),
+            escape_prometheus_label(address),
+            port,
+            escape_prometheus_label(&raw.to_uppercase())
+        ));
+    }
+
+    // Connection phase timings of the last check, only for the protocols each phase applies to
+    let timing_metrics = [
+        ("dns_ms", "DNS resolution time in milliseconds, estimated on a probe connection"),
+        ("connect_ms", "Connect time in milliseconds, estimated on a probe connection for HTTP/HTTPS"),
+        ("tls_ms", "TLS handshake time in milliseconds, estimated on a probe connection for HTTPS"),
+        ("ttfb_ms", "Estimated time to the first response headers in milliseconds"),
+        ("quic_handshake_ms", "QUIC handshake time in milliseconds"),
+    ];
+    for (phase, help) in timing_metrics {
+        declare_metric(
+            metrics,
+            &mut declared_metrics,
+            &format!("net_sentinel_gameserver_{phase}"),
+            &format!("{help} (last game server check)"),
+            "gauge",
+        );
+        for server in game_servers {
+            let Some((name, address, port, result)) = game_server_results.get(&server.id) else {
+                continue;
+            };
+            let phase_ms = result.timing.as_ref().and_then(|timing| {
+                timing.phases(&server.protocol).into_iter().find(|(name, _)| *name == phase)
+            });
+            if let Some((_, ms)) = phase_ms {
+                metrics.push_str(&format!(
+                    "net_sentinel_gameserver_{}{{name=\"{}\",address=\"{}\",port=\"{}\"}} {}\n",
+                    phase,
+                    escape_prometheus_label(name),
+                    escape_prometheus_label(address),
+                    port,
+                    ms
+                ));
+            }
+        }
+    }
+
+}
+
+fn build_uptime_metrics(
+    history: &history::CheckResultCache,
+    isps: &[crate::models::Isp],
+    websites: &[crate::models::Website],
+    game_servers: &[crate::models::GameServer],
+) -> String {
+    let mut metrics = String::new();
+    let entities: Vec<(history::EntityKind, i64, &str)> = isps
+        .iter()
+        .map(|isp| (history::EntityKind::Isp, isp.id, isp.name.as_str()))
+        .chain(websites.iter().map(|website| (history::EntityKind::Website, website.id, website.url.as_str())))
+        .chain(game_servers.iter().map(|server| (history::EntityKind::GameServer, server.id, server.name.as_str())))
+        .collect();
+    for window in history::METRIC_WINDOWS {
+        let metric_name = format!("net_sentinel_uptime_pct_{}", window);
+        metrics.push_str(&format!(
+            "# HELP {} Percentage of successful checks over the last {}\n# TYPE {} gauge\n",
+            metric_name, window, metric_name
+        ));
+        for (kind, id, name) in &entities {
+            if let Ok(stats) = history.uptime(*kind, *id, window) {
+                if let Some(uptime_pct) = stats.uptime_pct {
+                    metrics.push_str(&format!(
+                        "{}{{type=\"{}\",id=\"{}\",name=\"{}\"}} {}\n",
+                        metric_name,
+                        kind.as_str(),
+                        id,
+                        escape_prometheus_label(name),
+                        uptime_pct
+                    ));
+                }
+            }
+        }
+    }
+
+    // Only entities that have passed at least one check since startup are exported
+    metrics.push_str("# HELP net_sentinel_last_success_timestamp_seconds Unix timestamp of the last successful check\n# TYPE net_sentinel_last_success_timestamp_seconds gauge\n");
+    for (kind, id, name) in &entities {
+        let Some(last_success) = history.last_success(*kind, *id) else {
+            continue;
+        };
+        if let Ok(since_epoch) = last_success.duration_since(std::time::UNIX_EPOCH) {
+            metrics.push_str(&format!(
+                "net_sentinel_last_success_timestamp_seconds{{type=\"{}\",id=\"{}\",name=\"{}\"}} {}\n",
+                kind.as_str(),
+                id,
+                escape_prometheus_label(name),
+                since_epoch.as_secs()
+            ));
+        }
+    }
+
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{GameServer, GameServerTestResult};
+    use std::collections::{HashMap, HashSet};
+
+    fn game_server(id: i64, name: &str) -> GameServer {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": name,
+            "address": "127.0.0.1",
+            "port": 27015,
+            "protocol": "UDP",
+            "timeout_ms": 1000,
+            "pseudo_code": "",
+        }))
+        .expect("valid game server")
+    }
+
+    fn passed_with_output(output: &str) -> GameServerTestResult {
+        GameServerTestResult {
+            success: true,
+            response_time_ms: 5,
+            raw_response: None,
+            raw_response_truncated: false,
+            pairs: Vec::new(),
+            parsed_values: serde_json::json!({}),
+            variables: serde_json::json!({}),
+            error: None,
+            output_labels_success: vec![output.to_string()],
+            output_label_names_success: vec![None],
+            output_labels_error: Vec::new(),
+            attempts: 1,
+            wait_time_ms: 0,
+            response_bytes: 4,
+            timing: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// `/metrics` body for game servers returning the given outputs
+    fn game_server_metrics(outputs: &[&str]) -> String {
+        let servers: Vec<GameServer> = (0..outputs.len()).map(|idx| game_server(idx as i64 + 1, &format!("server {idx}"))).collect();
+        let results: HashMap<i64, (String, String, u16, GameServerTestResult)> = servers
+            .iter()
+            .zip(outputs)
+            .map(|(server, output)| {
+                (server.id, (server.name.clone(), server.address.clone(), server.port, passed_with_output(output)))
+            })
+            .collect();
+        let checks = CheckResults {
+            isps: &[],
+            isp_results: &HashMap::new(),
+            websites: &[],
+            website_results: &HashMap::new(),
+            game_servers: &servers,
+            game_server_results: &results,
+            maintenance: &HashSet::new(),
+        };
+        build_metrics_response(checks, None)
+    }
+
+    /// Parse the body with `prometheus-parse`, then check the rules it doesn't enforce that a
+    /// duplicate output key could break: one HELP and one TYPE per name, declared before the
+    /// first sample, and no repeated series
+    fn assert_valid_exposition(metrics: &str) {
+        let lines = metrics.lines().map(|line| Ok(line.to_string()));
+        let scrape = prometheus_parse::Scrape::parse(lines).expect("metrics parse");
+        assert_eq!(scrape.samples.len(), metrics.lines().filter(|line| !line.is_empty() && !line.starts_with('#')).count());
+
+        let mut helps = HashSet::new();
+        let mut types = HashSet::new();
+        let mut series = HashSet::new();
+        for line in metrics.lines() {
+            if let Some(rest) = line.strip_prefix("# HELP ") {
+                let name = rest.split(' ').next().unwrap();
+                assert!(helps.insert(name.to_string()), "second HELP for {name}");
+            } else if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let (name, metric_type) = rest.split_once(' ').unwrap();
+                assert!(["counter", "gauge", "histogram", "summary", "untyped"].contains(&metric_type), "bad TYPE {line}");
+                assert!(types.insert(name.to_string()), "second TYPE for {name}");
+            } else if !line.is_empty() {
+                let (series_name, value) = line.rsplit_once(' ').unwrap();
+                value.parse::<f64>().unwrap_or_else(|_| panic!("bad value in {line}"));
+                let name = series_name.split('{').next().unwrap();
+                assert!(types.contains(name), "sample before TYPE for {name}");
+                assert!(series.insert(series_name.to_string()), "duplicate series {series_name}");
+            }
+        }
+    }
+
+    #[test]
+    fn identical_output_keys_are_declared_once() {
+        let metrics = game_server_metrics(&["players=12, map=dust2", "players=3, map=dust2"]);
+        assert_valid_exposition(&metrics);
+        assert_eq!(metrics.matches("# TYPE net_sentinel_gameserver_output_players gauge\n").count(), 1);
+        assert_eq!(metrics.matches("# TYPE net_sentinel_gameserver_output_map gauge\n").count(), 1);
+        assert!(metrics.contains("net_sentinel_gameserver_output_players{name=\"server 0\",address=\"127.0.0.1\",port=\"27015\"} 12\n"));
+        assert!(metrics.contains("net_sentinel_gameserver_output_players{name=\"server 1\",address=\"127.0.0.1\",port=\"27015\"} 3\n"));
+    }
+
+    #[test]
+    fn text_and_numeric_outputs_share_one_declaration() {
+        let metrics = game_server_metrics(&["version=1.20.1", "version=7"]);
+        assert_valid_exposition(&metrics);
+        assert_eq!(metrics.matches("# TYPE net_sentinel_gameserver_output_version ").count(), 1);
+        assert!(metrics.contains("# TYPE net_sentinel_gameserver_output_version gauge\n"));
+        assert!(metrics.contains("net_sentinel_gameserver_output_version{name=\"server 0\",address=\"127.0.0.1\",port=\"27015\",value=\"1.20.1\"} 1\n"));
+        assert!(metrics.contains("net_sentinel_gameserver_output_version{name=\"server 1\",address=\"127.0.0.1\",port=\"27015\"} 7\n"));
+    }
+
+    #[test]
+    fn declare_metric_skips_a_conflicting_type() {
+        let mut metrics = String::new();
+        let mut declared = HashMap::new();
+        declare_metric(&mut metrics, &mut declared, "example", "Example", "gauge");
+        declare_metric(&mut metrics, &mut declared, "example", "Example", "counter");
+        declare_metric(&mut metrics, &mut declared, "example", "Example", "gauge");
+        assert_eq!(metrics, "# HELP example Example\n# TYPE example gauge\n");
+        assert_eq!(declared["example"], "gauge");
+    }
+
+    #[test]
+    fn isps_sharing_an_ip_are_deduplicated_per_check() {
+        let isp = |id: i64, method: &str, port: u16| -> crate::models::Isp {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "name": format!("isp {id}"),
+                "ip": "1.1.1.1",
+                "check_method": method,
+                "check_port": port,
+            }))
+            .unwrap()
+        };
+        let isps = [isp(1, "tcp", 53), isp(2, "tcp", 53), isp(3, "tcp", 443), isp(4, "dns", 53), isp(5, "icmp", 443)];
+        let targets = unique_isp_checks(&isps.iter().collect::<Vec<_>>());
+        assert_eq!(targets, vec![isps[0].check_key(), isps[2].check_key(), isps[3].check_key(), isps[4].check_key()]);
+    }
 }
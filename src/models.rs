@@ -1,16 +1,58 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Isp {
     pub id: i64,
     pub name: String,
     pub ip: String,
+    #[serde(default)]
+    pub check_method: IspCheckMethod,
+    /// Port connected to by the `tcp` check method
+    #[serde(default = "default_isp_check_port")]
+    pub check_port: u16,
+    /// Primary ISPs make up `net_sentinel_primary_isp_up`
+    #[serde(default)]
+    pub is_primary: bool,
+}
+
+/// What an ISP check actually probes. ISPs with the same key are checked once and share the result.
+pub type IspCheckKey = (String, IspCheckMethod, u16);
+
+impl Isp {
+    pub fn check_key(&self) -> IspCheckKey {
+        (self.ip.clone(), self.check_method, self.check_port)
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateIsp {
     pub name: String,
     pub ip: String,
+    #[serde(default)]
+    pub check_method: IspCheckMethod,
+    /// Port connected to by the `tcp` check method
+    #[serde(default = "default_isp_check_port")]
+    pub check_port: u16,
+    /// Primary ISPs make up `net_sentinel_primary_isp_up`
+    #[serde(default)]
+    pub is_primary: bool,
+}
+
+/// How an ISP is checked: an HTTP(S) request to its IP, a DNS query with the IP as the resolver,
+/// or a plain TCP connect to `check_port`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum IspCheckMethod {
+    #[default]
+    Http,
+    Dns,
+    Tcp,
+    Icmp,
+}
+
+pub fn default_isp_check_port() -> u16 {
+    443
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +61,9 @@ pub struct Website {
     pub url: String,
     pub direct_connect: bool,
     pub direct_connect_url: Option<String>,
+    /// Used verbatim as the `site` label in metrics instead of the host parsed from `url`
+    #[serde(default)]
+    pub metric_label: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,6 +71,9 @@ pub struct CreateWebsite {
     pub url: String,
     pub direct_connect: bool,
     pub direct_connect_url: Option<String>,
+    /// Used verbatim as the `site` label in metrics instead of the host parsed from `url`
+    #[serde(default)]
+    pub metric_label: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -35,6 +83,23 @@ pub enum Protocol {
     Tcp,
     Http,
     Https,
+    Tls,
+    Websocket,
+    Quic,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Udp => "UDP",
+            Protocol::Tcp => "TCP",
+            Protocol::Http => "HTTP",
+            Protocol::Https => "HTTPS",
+            Protocol::Tls => "TLS",
+            Protocol::Websocket => "WEBSOCKET",
+            Protocol::Quic => "QUIC",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +111,90 @@ pub struct GameServer {
     pub protocol: Protocol,
     pub timeout_ms: u64,
     pub pseudo_code: String,
+    /// TLS only: accept invalid certificates and hostnames
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    /// TLS/QUIC: server name sent in the handshake (defaults to `address`)
+    #[serde(default)]
+    pub sni: Option<String>,
+    /// QUIC only: accept invalid certificates and hostnames
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// QUIC only: ALPN protocols offered in the handshake, e.g. `h3`
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    /// Extra attempts after a network error (none by default)
+    #[serde(default)]
+    pub retries: Option<u8>,
+    /// Pause between attempts in milliseconds (default 500)
+    #[serde(default)]
+    pub retry_delay_ms: Option<u64>,
+    /// Time allowed to establish a connection/handshake (defaults to `timeout_ms`)
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    /// Time allowed to wait for each response (defaults to `timeout_ms`)
+    #[serde(default)]
+    pub read_timeout_ms: Option<u64>,
+    /// Largest response read per pair, in bytes (defaults to 16384 for TCP/UDP and 1 MiB for HTTP bodies)
+    #[serde(default)]
+    pub max_response_bytes: Option<usize>,
+    /// Time allowed for each run of the script's code blocks (defaults to 5000)
+    #[serde(default)]
+    pub code_timeout_ms: Option<u64>,
+    /// Most iterations one `FOR ... IN RANGE(...)` or `WHILE` loop may run (defaults to `NET_SENTINEL_MAX_LOOP_ITERATIONS`)
+    #[serde(default)]
+    pub max_loop_iterations: Option<u64>,
+    /// Replaces `net_sentinel_gameserver` in this server's up, response time and output metric names
+    #[serde(default)]
+    pub metric_prefix: Option<String>,
+    /// Export responses of up to 100 bytes as a `hex` label of `net_sentinel_gameserver_raw_response`
+    #[serde(default)]
+    pub expose_raw_response: bool,
+    /// Warn when a CODE block declares a variable that was already parsed from a response
+    #[serde(default = "default_shadow_warning_enabled")]
+    pub shadow_warning_enabled: bool,
+    /// Variables set before the script runs, overriding the script's `DEFINE` defaults
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024;
+const DEFAULT_MAX_HTTP_RESPONSE_BYTES: usize = 1024 * 1024;
+const MAX_RESPONSE_BYTES_LIMIT: usize = 16 * 1024 * 1024;
+const DEFAULT_CODE_TIMEOUT_MS: u64 = 5000;
+
+impl GameServer {
+    pub fn connect_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.connect_timeout_ms.unwrap_or(self.timeout_ms))
+    }
+
+    pub fn read_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.read_timeout_ms.unwrap_or(self.timeout_ms))
+    }
+
+    pub fn code_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.code_timeout_ms.unwrap_or(DEFAULT_CODE_TIMEOUT_MS))
+    }
+
+    pub fn max_loop_iterations(&self) -> u64 {
+        self.max_loop_iterations
+            .filter(|&n| n > 0)
+            .unwrap_or_else(crate::packet_parser::default_max_loop_iterations)
+    }
+
+    /// Size of the read buffer, clamped to 1 byte..16 MiB
+    pub fn max_response_bytes(&self) -> usize {
+        self.max_response_bytes
+            .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)
+            .clamp(1, MAX_RESPONSE_BYTES_LIMIT)
+    }
+
+    /// Largest HTTP response body read, clamped to 1 byte..16 MiB
+    pub fn max_http_response_bytes(&self) -> usize {
+        self.max_response_bytes
+            .unwrap_or(DEFAULT_MAX_HTTP_RESPONSE_BYTES)
+            .clamp(1, MAX_RESPONSE_BYTES_LIMIT)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -56,6 +205,40 @@ pub struct CreateGameServer {
     pub protocol: Protocol,
     pub timeout_ms: u64,
     pub pseudo_code: String,
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    #[serde(default)]
+    pub sni: Option<String>,
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    #[serde(default)]
+    pub retries: Option<u8>,
+    #[serde(default)]
+    pub retry_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub read_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub max_response_bytes: Option<usize>,
+    #[serde(default)]
+    pub code_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub max_loop_iterations: Option<u64>,
+    #[serde(default)]
+    pub metric_prefix: Option<String>,
+    #[serde(default)]
+    pub expose_raw_response: bool,
+    #[serde(default = "default_shadow_warning_enabled")]
+    pub shadow_warning_enabled: bool,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+pub fn default_shadow_warning_enabled() -> bool {
+    true
 }
 
 #[derive(Debug, Serialize)]
@@ -63,17 +246,368 @@ pub struct GameServerTestResult {
     pub success: bool,
     pub response_time_ms: u64,
     pub raw_response: Option<String>,
+    /// True when at least one packet or response was cut short in `raw_response`/`pairs`
+    #[serde(default)]
+    pub raw_response_truncated: bool,
+    /// What each executed pair sent and received
+    #[serde(default)]
+    pub pairs: Vec<PairResult>,
     pub parsed_values: serde_json::Value,
     #[serde(default)]
     pub variables: serde_json::Value,
     pub error: Option<GameServerError>,
     #[serde(default)]
     pub output_labels_success: Vec<String>,
+    /// `OUTPUT_SUCCESS <name>` of the block each success label came from (same order as `output_labels_success`)
+    #[serde(default)]
+    pub output_label_names_success: Vec<Option<String>>,
     #[serde(default)]
     pub output_labels_error: Vec<String>,
+    /// Number of times the pairs were executed (1 + retries used; 0 if the script failed to parse)
+    #[serde(default)]
+    pub attempts: u32,
+    /// Time spent in `DELAY` pauses, not included in `response_time_ms`
+    #[serde(default)]
+    pub wait_time_ms: u64,
+    /// Bytes received from the server over all pairs of the last attempt
+    #[serde(default)]
+    pub response_bytes: u64,
+    /// Connection phase timings (TCP, TLS, HTTP and HTTPS only)
+    #[serde(default)]
+    pub timing: Option<HttpTimingBreakdown>,
+    /// Problems in the script that did not stop the check, like a CODE variable shadowing a parsed one
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Result of a single packet/response pair. HTTP pairs record the request body as sent data.
+#[derive(Debug, Serialize)]
+pub struct PairResult {
+    /// 1-based, as in "Pair N" error messages
+    pub index: usize,
+    pub sent_hex: Vec<String>,
+    pub received_hex: Vec<String>,
+    pub duration_ms: u64,
+    /// Variables read from this pair's response
+    pub variables: serde_json::Value,
+    pub error: Option<GameServerError>,
+}
+
+/// Result of `POST /api/gameservers/build`: what each pair would send, built without opening a connection
+#[derive(Debug, Serialize)]
+pub struct PacketBuildPreview {
+    pub pairs: Vec<PairBuild>,
+    /// Set when the script doesn't parse or a code block fails, like the check's error
+    pub error: Option<GameServerError>,
+}
+
+/// The packets of a binary pair, or the prepared request of an HTTP pair
+#[derive(Debug, Serialize)]
+pub struct PairBuild {
+    /// 1-based, as in "Pair N" error messages
+    pub index: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub packets: Vec<PacketBuild>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_request: Option<HttpRequestBuild>,
+    /// Pairs using variables read from an earlier response can't be built without sending
+    pub error: Option<GameServerError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PacketBuild {
+    pub hex: String,
+    pub len: usize,
+    /// The bytes each command wrote, in packet order
+    pub breakdown: Vec<PacketBuildSegment>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PacketBuildSegment {
+    pub line: usize,
+    pub command: String,
+    pub offset: usize,
+    pub hex: String,
 }
 
 #[derive(Debug, Serialize)]
+pub struct HttpRequestBuild {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub content_type: Option<String>,
+    /// The body as text, with invalid UTF-8 replaced
+    pub body: Option<String>,
+}
+
+/// Time spent in each phase of the first connection of a check, in milliseconds.
+/// Phases that don't apply to the protocol are 0: TCP and UDP only have `connect_ms`,
+/// TLS adds `tls_ms`, `dns_ms`/`ttfb_ms` are only measured for HTTP/HTTPS and QUIC only
+/// has `quic_handshake_ms`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HttpTimingBreakdown {
+    pub dns_ms: u64,
+    pub connect_ms: u64,
+    pub tls_ms: u64,
+    pub ttfb_ms: u64,
+    pub quic_handshake_ms: u64,
+    pub total_ms: u64,
+    /// True for HTTP/HTTPS, where the phases are timed on a separate probe connection
+    /// rather than the one the requests use
+    pub estimated: bool,
+}
+
+impl HttpTimingBreakdown {
+    /// The phases measured for a protocol, as (name, milliseconds)
+    pub fn phases(&self, protocol: &Protocol) -> Vec<(&'static str, u64)> {
+        let is_http = matches!(protocol, Protocol::Http | Protocol::Https);
+        let mut phases = Vec::new();
+        if is_http {
+            phases.push(("dns_ms", self.dns_ms));
+        }
+        if is_http || matches!(protocol, Protocol::Tcp | Protocol::Tls | Protocol::Udp) {
+            phases.push(("connect_ms", self.connect_ms));
+        }
+        if matches!(protocol, Protocol::Tls | Protocol::Https) {
+            phases.push(("tls_ms", self.tls_ms));
+        }
+        if is_http {
+            phases.push(("ttfb_ms", self.ttfb_ms));
+        }
+        if *protocol == Protocol::Quic {
+            phases.push(("quic_handshake_ms", self.quic_handshake_ms));
+        }
+        phases
+    }
+}
+
+/// One create, update, delete or restore made through the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// UTC time of the change, RFC 3339 (e.g. `2024-05-01T12:00:00Z`)
+    pub timestamp: String,
+    /// `create`, `update`, `delete` or `restore`
+    pub action: String,
+    /// `isp`, `website`, `gameserver`, `maintenance`, `webhook` or `database`
+    pub entity_type: String,
+    /// ID of the changed entity (0 for `database`)
+    pub entity_id: i64,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    /// Address the request came from
+    pub client_ip: String,
+}
+
+impl AuditEntry {
+    pub fn new(
+        action: &str,
+        entity_type: &str,
+        entity_id: i64,
+        before: Option<serde_json::Value>,
+        after: Option<serde_json::Value>,
+        client_ip: &std::net::SocketAddr,
+    ) -> Self {
+        Self {
+            timestamp: utc_timestamp(std::time::SystemTime::now()),
+            action: action.to_string(),
+            entity_type: entity_type.to_string(),
+            entity_id,
+            before,
+            after,
+            client_ip: client_ip.ip().to_string(),
+        }
+    }
+}
+
+/// A scheduled period during which an entity is not checked and reported as up in maintenance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub id: i64,
+    pub entity_type: crate::history::EntityKind,
+    pub entity_id: i64,
+    /// Start of the window, RFC 3339 (e.g. `2024-05-01T02:00:00Z`)
+    pub start_rfc3339: String,
+    /// End of the window (exclusive), RFC 3339
+    pub end_rfc3339: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateMaintenanceWindow {
+    pub entity_type: crate::history::EntityKind,
+    pub entity_id: i64,
+    pub start_rfc3339: String,
+    pub end_rfc3339: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl MaintenanceWindow {
+    /// Start and end as Unix seconds, `None` if either timestamp can't be parsed
+    pub fn bounds(&self) -> Option<(i64, i64)> {
+        Some((parse_rfc3339(&self.start_rfc3339)?, parse_rfc3339(&self.end_rfc3339)?))
+    }
+
+    pub fn is_active_at(&self, now: i64) -> bool {
+        self.bounds().is_some_and(|(start, end)| start <= now && now < end)
+    }
+
+    /// Whether both windows cover the same entity at some common point in time
+    pub fn overlaps(&self, other: &MaintenanceWindow) -> bool {
+        if self.entity_type != other.entity_type || self.entity_id != other.entity_id {
+            return false;
+        }
+        match (self.bounds(), other.bounds()) {
+            (Some((start, end)), Some((other_start, other_end))) => start < other_end && other_start < end,
+            _ => false,
+        }
+    }
+}
+
+/// External endpoint that receives every check result as a JSON POST
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultWebhook {
+    pub id: i64,
+    pub url: String,
+    /// Key for the `X-Net-Sentinel-Signature` HMAC-SHA256 header; unsigned when unset
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Keep `raw_response` and the hex dumps of each pair in game server results
+    #[serde(default)]
+    pub include_raw_response: bool,
+    /// Entity types to send results for; all when empty
+    #[serde(default)]
+    pub entity_types: Vec<crate::history::EntityKind>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateResultWebhook {
+    pub url: String,
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub include_raw_response: bool,
+    #[serde(default)]
+    pub entity_types: Vec<crate::history::EntityKind>,
+}
+
+impl ResultWebhook {
+    pub fn accepts(&self, kind: crate::history::EntityKind) -> bool {
+        self.entity_types.is_empty() || self.entity_types.contains(&kind)
+    }
+
+    /// Copy with the secret masked, for API responses and the audit log
+    pub fn redacted(&self) -> Self {
+        Self {
+            secret: self.secret.as_ref().map(|_| "********".to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+/// Reusable piece of script, expanded into game server scripts by `INCLUDE <name>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snippet {
+    pub id: i64,
+    pub name: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSnippet {
+    pub name: String,
+    pub code: String,
+}
+
+/// Current time as Unix seconds
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Parse an RFC 3339 timestamp (`2024-05-01T12:00:00Z`, `2024-05-01T14:00:00.5+02:00`) into Unix seconds.
+/// Fractional seconds are dropped.
+pub fn parse_rfc3339(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let bytes = value.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':' || bytes[16] != b':'
+    {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = value.get(range)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &value[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first() {
+                Some(b'+') => 1,
+                Some(b'-') => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            let offset_hours: i64 = rest[1..3].parse().ok()?;
+            let offset_minutes: i64 = rest[4..6].parse().ok()?;
+            sign * (offset_hours * 3600 + offset_minutes * 60)
+        }
+    };
+
+    // Days since 1970-01-01 from a civil date (inverse of the algorithm in `utc_timestamp`)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Format a time as an RFC 3339 UTC timestamp with second precision
+pub fn utc_timestamp(time: std::time::SystemTime) -> String {
+    let secs = time.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs_of_day / 3600, (secs_of_day / 60) % 60, secs_of_day % 60
+    )
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct GameServerError {
     #[serde(rename = "type")]
     pub error_type: String,
@@ -1,42 +1,267 @@
 use anyhow::{Context, Result};
 use indexmap::IndexMap;
 use serde_json::Value as JsonValue;
+use regex::{Regex, RegexBuilder};
+use crate::out;
+use sha1::Digest;
 
+/// A packet command. The `Option<bool>` big_endian of numeric commands is `None` without a
+/// `_BE`/`_LE` suffix, leaving the byte order to the script's `ENDIAN` directive
 #[derive(Debug, Clone)]
 pub enum PacketCommand {
     WriteByte(u8),
-    WriteShort(u16, bool), // value, big_endian
-    WriteInt(u32, bool),   // value, big_endian
-    WriteIntVar(String, bool), // variable name, big_endian - resolved at build time
-    WriteShortVar(String, bool), // variable name, big_endian - resolved at build time
+    WriteShort(u16, Option<bool>), // value, big_endian
+    WriteInt(u32, Option<bool>),   // value, big_endian
+    WriteIntVar(String, Option<bool>), // variable name, big_endian - resolved at build time
+    WriteLong(u64, Option<bool>),  // value, big_endian
+    WriteLongVar(String, Option<bool>), // variable name, big_endian - resolved at build time
+    WriteFloat(f32, Option<bool>), // value, big_endian
+    WriteFloatVar(String, Option<bool>), // variable name, big_endian - resolved at build time
+    WriteDouble(f64, Option<bool>), // value, big_endian
+    WriteDoubleVar(String, Option<bool>), // variable name, big_endian - resolved at build time
+    WriteShortVar(String, Option<bool>), // variable name, big_endian - resolved at build time
     WriteByteVar(String), // variable name - resolved at build time
     WriteVarIntVar(String), // variable name - resolved at build time
     WriteString(String, Option<usize>), // value, optional fixed length
     WriteStringVar(String, Option<usize>), // variable name, optional fixed length - resolved at build time
+    WriteStringRaw(String), // value, written without null terminator
+    WriteStringRawVar(String), // variable name, written without null terminator - resolved at build time
+    WriteStringLenPrefixed(String, LenPrefix), // value, length prefix written before the bytes
+    WriteStringLenPrefixedVar(String, LenPrefix), // variable name, length prefix - resolved at build time
+    WriteStringUtf16(String, bool), // value, big_endian - UTF-16 code units, no terminator
+    WriteStringUtf16Var(String, bool), // variable name, big_endian - resolved at build time
     WriteBytes(Vec<u8>),
+    WriteBytesVar(String), // variable holding a hex string (as stored by READ_BYTES) - decoded at build time
+    WriteBytesRepeat(u8, usize), // byte, count
+    WriteBytesRepeatToLen(u8, usize), // byte, total packet length to pad to
     WriteVarInt(u64),
     WriteVarIntLen,
-    WriteIntLen(bool), // big_endian flag for length placeholder
+    WriteIntLen(Option<bool>), // big_endian flag for length placeholder
+    WriteCrc32(bool), // big_endian - CRC32 of the bytes before it, filled in after the length placeholders
+    Compress(Compression), // everything written after it in the packet is compressed
+}
+
+/// Format of `COMPRESS` / `DECOMPRESS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zlib,
+    Gzip,
+}
+
+/// Upper bound on inflated data, so a small compressed response can't exhaust memory
+const MAX_DECOMPRESSED_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Largest count or length of `WRITE_BYTES_REPEAT`/`WRITE_BYTES_REPEAT_TO_LEN`, so one line
+/// can't make a packet allocate without bound
+pub const MAX_REPEAT_BYTES: usize = 1024 * 1024;
+
+impl Compression {
+    fn from_name(name: &str) -> Option<Compression> {
+        match name.to_ascii_uppercase().as_str() {
+            "ZLIB" => Some(Compression::Zlib),
+            "GZIP" => Some(Compression::Gzip),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Zlib => "ZLIB",
+            Compression::Gzip => "GZIP",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        use std::io::Write;
+        let level = flate2::Compression::default();
+        Ok(match self {
+            Compression::Zlib => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+        })
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        use std::io::Read;
+        let reader: Box<dyn Read + '_> = match self {
+            Compression::Zlib => Box::new(flate2::read::ZlibDecoder::new(data)),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+        };
+        let mut inflated = Vec::new();
+        reader.take(MAX_DECOMPRESSED_BYTES + 1).read_to_end(&mut inflated)
+            .map_err(|e| anyhow::anyhow!("{} decompression failed: {}", self.name(), e))?;
+        if inflated.len() as u64 > MAX_DECOMPRESSED_BYTES {
+            anyhow::bail!("{} data inflates to more than {} bytes", self.name(), MAX_DECOMPRESSED_BYTES);
+        }
+        Ok(inflated)
+    }
+}
+
+/// Length prefix of a Pascal-style string (`*_BYTE_LEN`, `*_SHORT_LE_LEN`, `*_SHORT_BE_LEN`, `*_VARINT_LEN` or `*_VARINT`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LenPrefix {
+    Byte,
+    Short,
+    ShortBe,
+    Int,
+    IntBe,
+    VarInt,
+}
+
+impl LenPrefix {
+    /// Map e.g. `READ_STRING_SHORT_BE_LEN` (with `prefix` = `READ_STRING_`) to its length prefix
+    fn from_keyword(keyword: &str, prefix: &str) -> Option<Self> {
+        match keyword.strip_prefix(prefix)? {
+            "BYTE_LEN" => Some(LenPrefix::Byte),
+            "SHORT_LE_LEN" => Some(LenPrefix::Short),
+            "SHORT_BE_LEN" => Some(LenPrefix::ShortBe),
+            "VARINT_LEN" | "VARINT" => Some(LenPrefix::VarInt),
+            _ => None,
+        }
+    }
+
+    /// Prefix named by the first operand of `WRITE_STRING_PREFIXED` / `READ_STRING_PREFIXED`
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "BYTE" => Some(LenPrefix::Byte),
+            "SHORT" => Some(LenPrefix::Short),
+            "SHORT_BE" => Some(LenPrefix::ShortBe),
+            "INT" => Some(LenPrefix::Int),
+            "INT_BE" => Some(LenPrefix::IntBe),
+            "VARINT" => Some(LenPrefix::VarInt),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, len: usize) -> Result<Vec<u8>> {
+        match self {
+            LenPrefix::Byte => {
+                let len = u8::try_from(len).map_err(|_| anyhow::anyhow!("String of {} bytes is too long for a byte length prefix", len))?;
+                Ok(vec![len])
+            }
+            LenPrefix::Short | LenPrefix::ShortBe => {
+                let len = u16::try_from(len).map_err(|_| anyhow::anyhow!("String of {} bytes is too long for a short length prefix", len))?;
+                Ok(if *self == LenPrefix::ShortBe { len.to_be_bytes() } else { len.to_le_bytes() }.to_vec())
+            }
+            LenPrefix::Int | LenPrefix::IntBe => {
+                let len = u32::try_from(len).map_err(|_| anyhow::anyhow!("String of {} bytes is too long for an int length prefix", len))?;
+                Ok(if *self == LenPrefix::IntBe { len.to_be_bytes() } else { len.to_le_bytes() }.to_vec())
+            }
+            LenPrefix::VarInt => Ok(encode_varint(len as u64)),
+        }
+    }
+
+    fn decode(&self, response: &[u8], cursor: &mut usize) -> Result<usize> {
+        match self {
+            LenPrefix::Byte => {
+                if *cursor >= response.len() {
+                    anyhow::bail!("Insufficient data: need 1 byte for string length, have 0");
+                }
+                *cursor += 1;
+                Ok(response[*cursor - 1] as usize)
+            }
+            LenPrefix::Short | LenPrefix::ShortBe => {
+                if *cursor + 2 > response.len() {
+                    anyhow::bail!("Insufficient data: need 2 bytes for string length, have {}", response.len() - *cursor);
+                }
+                let bytes = [response[*cursor], response[*cursor + 1]];
+                *cursor += 2;
+                Ok(if *self == LenPrefix::ShortBe { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) } as usize)
+            }
+            LenPrefix::Int | LenPrefix::IntBe => {
+                if *cursor + 4 > response.len() {
+                    anyhow::bail!("Insufficient data: need 4 bytes for string length, have {}", response.len() - *cursor);
+                }
+                let bytes: [u8; 4] = response[*cursor..*cursor + 4].try_into()?;
+                *cursor += 4;
+                Ok(if *self == LenPrefix::IntBe { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) } as usize)
+            }
+            LenPrefix::VarInt => Ok(read_varint(response, cursor)? as usize),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum ResponseCommand {
     ReadByte(String),
-    ReadShort(String, bool), // var_name, big_endian
-    ReadInt(String, bool),   // var_name, big_endian
+    ReadShort(String, Option<bool>), // var_name, big_endian
+    ReadInt(String, Option<bool>),   // var_name, big_endian
+    ReadLong(String, Option<bool>),  // var_name, big_endian
+    ReadSByte(String),
+    ReadSShort(String, Option<bool>), // var_name, big_endian
+    ReadSInt(String, Option<bool>),   // var_name, big_endian
+    ReadSLong(String, Option<bool>),  // var_name, big_endian
+    ReadFloat(String, Option<bool>), // var_name, big_endian
+    ReadDouble(String, Option<bool>), // var_name, big_endian
     ReadString(String, Option<usize>), // var_name, optional fixed length
+    ReadStringVarLen(String, String), // var_name, variable holding the length
     ReadStringNull(String),
     SkipBytes(usize),
+    SkipBytesVar(String), // variable holding the count
+    SeekToOffset(usize), // absolute offset from the start of the response
+    SeekFromEnd(usize),  // offset counted back from the end of the response
+    ReadCurrentOffset(String), // var_name - stores the cursor position
+    ReadBytes(String, usize), // var_name, count - stored as a lowercase hex string
+    ReadBytesVarLen(String, String), // var_name, variable holding the count
+    ReadRemaining(String),    // var_name - rest of the response as a lowercase hex string
+    Decompress(Compression),  // inflate the unread bytes; later reads see the decompressed data
     ExpectByte(u8),
     ExpectMagic(Vec<u8>),
+    ExpectVarInt(u64),
+    ExpectInt(u32, Option<bool>), // value, big_endian
     ReadVarInt(String),
+    ReadStringLenPrefixed(String, LenPrefix), // var_name, length prefix read before the bytes
+    ReadStringUtf16(String, Option<usize>, bool), // var_name, code unit count (None = 0x0000 terminated), big_endian
+    ExpectVar(Assertion), // checked once the response is read, against every variable parsed so far
     // HTTP-specific response commands
-    ExpectStatus(u16),
-    ExpectHeader { key: String, value: String },
+    ExpectStatus(u16, u16), // lowest and highest accepted status code
+    ExpectBodyContains(String),
+    ExpectHeader { key: String, value: String, contains: bool }, // contains: substring match instead of equality
+    ReadHeader { key: String, var_name: String },
+    NoHeaderVars, // don't store every header as a HEADER_<Key> variable
     ReadBodyJson(String),
     ReadBody(String),
 }
 
+impl ResponseCommand {
+    /// Variable the command reads a value into
+    pub fn target(&self) -> Option<&str> {
+        match self {
+            ResponseCommand::ReadByte(name)
+            | ResponseCommand::ReadShort(name, _)
+            | ResponseCommand::ReadInt(name, _)
+            | ResponseCommand::ReadLong(name, _)
+            | ResponseCommand::ReadSByte(name)
+            | ResponseCommand::ReadSShort(name, _)
+            | ResponseCommand::ReadSInt(name, _)
+            | ResponseCommand::ReadSLong(name, _)
+            | ResponseCommand::ReadFloat(name, _)
+            | ResponseCommand::ReadDouble(name, _)
+            | ResponseCommand::ReadString(name, _)
+            | ResponseCommand::ReadStringVarLen(name, _)
+            | ResponseCommand::ReadStringNull(name)
+            | ResponseCommand::ReadCurrentOffset(name)
+            | ResponseCommand::ReadBytes(name, _)
+            | ResponseCommand::ReadBytesVarLen(name, _)
+            | ResponseCommand::ReadRemaining(name)
+            | ResponseCommand::ReadVarInt(name)
+            | ResponseCommand::ReadStringLenPrefixed(name, _)
+            | ResponseCommand::ReadStringUtf16(name, ..)
+            | ResponseCommand::ReadHeader { var_name: name, .. }
+            | ResponseCommand::ReadBodyJson(name)
+            | ResponseCommand::ReadBody(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OutputStatus {
     Success,
@@ -47,11 +272,19 @@ pub enum OutputStatus {
 pub enum OutputCommand {
     JsonOutput(String),
     Return(String),
+    /// `RETURN IF <condition> THEN "<template>" ELSE "<template>"`
+    ConditionalReturn {
+        condition: Box<Condition>,
+        then_template: String,
+        else_template: String,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub struct OutputBlock {
     pub status: OutputStatus,
+    /// Name from `OUTPUT_SUCCESS <name>`, exported as the `output` label of the block's metrics
+    pub output_label_name: Option<String>,
     pub commands: Vec<OutputCommand>,
 }
 
@@ -82,18 +315,24 @@ pub enum CodeCommand {
         var_name: String,
         range_start: Expression,
         range_end: Expression,
-        body: Vec<CodeCommand>,
+        body: Vec<Sourced<CodeCommand>>,
+        line: usize,
+    },
+    WhileLoop {
+        condition: Condition,
+        body: Vec<Sourced<CodeCommand>>,
+        line: usize,
     },
     ForInArray {
         var_name: String,
         array_name: String,
-        body: Vec<CodeCommand>,
+        body: Vec<Sourced<CodeCommand>>,
     },
     IfStatement {
         condition: Condition,
-        body: Vec<CodeCommand>,
-        else_if: Vec<(Condition, Vec<CodeCommand>)>,
-        else_body: Option<Vec<CodeCommand>>,
+        body: Vec<Sourced<CodeCommand>>,
+        else_if: Vec<(Condition, Vec<Sourced<CodeCommand>>)>,
+        else_body: Option<Vec<Sourced<CodeCommand>>>,
     },
     // String functions
     Split {
@@ -109,6 +348,24 @@ pub enum CodeCommand {
     },
     // Control flow
     Break,
+    Continue,
+    ReturnEarly,
+    // User-defined functions: `CALL name(args)`, optionally storing what the function returns
+    Call {
+        name: String,
+        args: Vec<Expression>,
+        /// Variable that receives the `RETURN` value; `declared` when written as `TYPE var = CALL ...`
+        result: Option<String>,
+        declared: bool,
+        line: usize,
+    },
+    /// `RETURN [expr]` inside a `DEF`
+    Return(Option<Expression>),
+    // Assertions: fail the check with an AssertionError
+    ExpectVar(Assertion),
+    Fail { message: String, line: usize },
+    // Health checks: fail the check with HealthCheckFailed
+    Assert(Assertion),
     // Execute packet/response commands (nested)
     ExecutePacketCommand(PacketCommand),
     ExecuteResponseCommand(ResponseCommand),
@@ -126,6 +383,26 @@ pub enum Expression {
         name: String,
         args: Vec<Expression>,
     },
+    /// String literal containing `{var}` placeholders, expanded when evaluated
+    Template(String),
+    /// `REGEX_MATCH` / `REGEX_EXTRACT`; `regex` is compiled at parse time when the pattern is a literal
+    RegexCall {
+        name: String,
+        args: Vec<Expression>,
+        regex: Option<Regex>,
+        line: usize,
+    },
+    /// Field/index lookup into a JSON variable, e.g. `payload.sample[0].name`
+    Path {
+        var_name: String,
+        segments: Vec<PathSegment>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum PathSegment {
+    Field(String),
+    Index(Box<Expression>),
 }
 
 #[derive(Debug, Clone)]
@@ -137,6 +414,36 @@ pub enum Condition {
     GreaterOrEqual(Expression, Expression),
     LessOrEqual(Expression, Expression),
     Contains(Expression, Expression), // string contains substring
+    IsTrue(Expression), // bare boolean expression, e.g. REGEX_MATCH(s, "^ok")
+}
+
+/// `EXPECT_VAR <var> <op> <value-or-var> ["message"]` or `ASSERT <condition> ["message"]`
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub kind: AssertionKind,
+    pub condition: Condition,
+    /// The command as written, used in the default failure message
+    pub text: String,
+    pub message: Option<String>,
+    pub line: usize,
+}
+
+/// How a failed assertion is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertionKind {
+    /// `EXPECT_VAR` and `FAIL`: the response isn't what the script expects
+    Expectation,
+    /// `ASSERT`: the server answered but doesn't count as healthy
+    HealthCheck,
+}
+
+impl AssertionKind {
+    pub fn error_type(self) -> &'static str {
+        match self {
+            AssertionKind::Expectation => "AssertionError",
+            AssertionKind::HealthCheck => "HealthCheckFailed",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -158,33 +465,114 @@ pub enum HttpBodyType {
 pub enum HttpCommand {
     HttpStart { method: HttpMethod, path: String },
     Param { key: String, value: String },
+    ParamVar { key: String, var_name: String },
     Header { key: String, value: String },
-    BodyStart { body_type: HttpBodyType },
+    HeaderVar { key: String, var_name: String },
+    /// `verbatim`: `BODY_START TYPE RAW VERBATIM`, the lines up to `BODY_END` are the body as written
+    BodyStart { body_type: HttpBodyType, verbatim: bool },
     Data { content: String },
+    DataMultiline { lines: Vec<String> },
     BodyEnd,
 }
 
+/// Value of a query parameter or header: a literal, or a variable resolved when the request is sent
+#[derive(Debug, Clone)]
+pub enum HttpValue {
+    Literal(String),
+    Var(String),
+}
+
 #[derive(Debug, Clone)]
 pub struct HttpRequest {
     pub method: HttpMethod,
     pub path: String,
-    pub params: Vec<(String, String)>,
-    pub headers: Vec<(String, String)>,
+    pub params: Vec<(String, HttpValue)>,
+    pub headers: Vec<(String, HttpValue)>,
     pub body_type: Option<HttpBodyType>,
     pub body_data: Vec<String>,
 }
 
+/// A parsed command and the script line it came from, so execution errors can point at it
+#[derive(Debug, Clone)]
+pub struct Sourced<T> {
+    pub command: T,
+    pub source_line: usize,
+}
+
+impl<T> Sourced<T> {
+    pub fn new(command: T, source_line: usize) -> Self {
+        Sourced { command, source_line }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CodeBlock {
-    pub commands: Vec<CodeCommand>,
+    pub commands: Vec<Sourced<CodeCommand>>,
+    /// Index of the pair that follows the block (`pairs.len()` for trailing blocks)
+    pub before_pair: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct PacketResponsePair {
-    pub packets: Vec<Vec<PacketCommand>>, // Binary packets (empty if HTTP request is used)
+    pub packets: Vec<Vec<Sourced<PacketCommand>>>, // Binary packets (empty if HTTP request is used)
     pub http_request: Option<HttpRequest>, // HTTP request (None if binary packets are used)
-    pub response: Vec<ResponseCommand>,
+    pub response: Vec<Sourced<ResponseCommand>>,
     pub close_connection_before: bool, // If true, close connection before this pair
+    pub frame_type: FrameType, // WebSocket frame type used to send this pair's packets
+    pub expect_packets: Option<ExpectPackets>, // UDP: receive several datagrams for this pair's response
+    pub delay_before_ms: u64, // Pause before sending this pair (DELAY directives since the previous pair)
+    pub read_timeout_ms: Option<u64>, // Receive timeout override for this pair (TIMEOUT directive)
+    pub no_response: bool, // Fire-and-forget: send the packets and move on without waiting (NO_RESPONSE)
+}
+
+/// Upper bound for a single `DELAY`, so a typo cannot stall checks
+pub const MAX_DELAY_MS: u64 = 5000;
+
+/// Upper bound for all `DELAY`s of a script together, so many of them cannot stall checks either
+pub const MAX_TOTAL_DELAY_MS: u64 = 10_000;
+
+/// Largest value accepted by the `TIMEOUT` directive, from `NET_SENTINEL_MAX_PAIR_TIMEOUT_MS` (default 60000)
+fn max_pair_timeout_ms() -> u64 {
+    static MAX: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+    *MAX.get_or_init(|| {
+        std::env::var("NET_SENTINEL_MAX_PAIR_TIMEOUT_MS")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .filter(|&ms| ms > 0)
+            .unwrap_or(60_000)
+    })
+}
+
+/// Default for the most iterations one `FOR ... IN RANGE(...)` or `WHILE` loop may run,
+/// from `NET_SENTINEL_MAX_LOOP_ITERATIONS` (default 10000)
+pub fn default_max_loop_iterations() -> u64 {
+    static MAX: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+    *MAX.get_or_init(|| {
+        std::env::var("NET_SENTINEL_MAX_LOOP_ITERATIONS")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(10_000)
+    })
+}
+
+/// Multi-datagram UDP response set with `EXPECT_PACKETS <max> [idle_ms]`.
+/// Datagrams are concatenated in arrival order (no reordering or reassembly) before parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectPackets {
+    pub max: usize,     // Stop once this many datagrams have arrived
+    pub idle_ms: u64,   // Stop when no further datagram arrives within this time
+}
+
+/// Idle timeout used when `EXPECT_PACKETS` is given without one
+pub const DEFAULT_EXPECT_PACKETS_IDLE_MS: u64 = 500;
+
+/// WebSocket frame type selected with the `FRAME TEXT|BINARY` directive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameType {
+    Text,
+    #[default]
+    Binary,
 }
 
 #[derive(Debug)]
@@ -192,9 +580,200 @@ pub struct PacketScript {
     pub pairs: Vec<PacketResponsePair>,
     pub output_blocks: Vec<OutputBlock>,
     pub code_blocks: Vec<CodeBlock>,
+    /// `COOKIES ON`: HTTP requests send back the cookies set by earlier responses of the check
+    pub cookies: bool,
+    /// `DEFINE NAME value` constants, available as variables from the start of the check
+    pub defines: IndexMap<String, JsonValue>,
+    /// Where the lines of a script with `INCLUDE`s came from; line numbers above count expanded lines
+    pub sources: SourceMap,
+    /// `DEF name(params):` functions from all CODE blocks, callable from any of them
+    pub functions: IndexMap<String, ScriptFunction>,
+    /// Lines of `CODE_START`s that came between packets and their `RESPONSE_START`, dropping the packets
+    pub discarded_packets: Vec<usize>,
+    /// `ENDIAN BIG`: SHORT/INT/LONG/FLOAT/DOUBLE commands without a `_BE`/`_LE` suffix are big-endian
+    pub big_endian: bool,
+}
+
+/// A function defined with `DEF`
+#[derive(Debug, Clone)]
+pub struct ScriptFunction {
+    pub params: Vec<String>,
+    pub body: Vec<Sourced<CodeCommand>>,
+}
+
+/// Deepest chain of `CALL`s, so runaway recursion fails instead of overflowing the stack
+pub const MAX_CALL_DEPTH: usize = 32;
+
+/// Snippet name to script text, for `INCLUDE <name>`
+pub type Snippets = std::collections::HashMap<String, String>;
+
+/// Deepest chain of snippets including snippets
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Origin of each line of a script after its `INCLUDE`s were expanded. Empty when nothing was included.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    origins: Vec<LineOrigin>,
+}
+
+#[derive(Debug, Clone)]
+struct LineOrigin {
+    /// Snippet the line is from; `None` for the script itself
+    snippet: Option<String>,
+    /// 1-based line within that snippet or script
+    line: usize,
+    /// Line of the script itself: the line, or the top-level `INCLUDE` it came through
+    script_line: usize,
+}
+
+impl SourceMap {
+    fn origin(&self, line: usize) -> Option<&LineOrigin> {
+        self.origins.get(line.checked_sub(1)?)
+    }
+
+    /// Line of the server's own script for expanded line `line`; snippet lines map to their `INCLUDE`
+    pub fn script_line(&self, line: usize) -> usize {
+        self.origin(line).map_or(line, |origin| origin.script_line)
+    }
+
+    /// Rewrite the `line N` references of an error message to the original script or snippet line
+    pub fn relocate_message(&self, message: &str) -> String {
+        if self.origins.is_empty() {
+            return message.to_string();
+        }
+        line_ref_regex()
+            .replace_all(message, |caps: &regex::Captures| {
+                let origin = caps[1].parse().ok().and_then(|line| self.origin(line));
+                match origin {
+                    Some(LineOrigin { snippet: Some(name), line, .. }) => format!("line {} of snippet '{}'", line, name),
+                    Some(origin) => format!("line {}", origin.line),
+                    None => caps[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+}
+
+/// Matches the `line N` references parse errors put in their messages
+fn line_ref_regex() -> &'static Regex {
+    static LINE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    LINE.get_or_init(|| Regex::new(r"\bline (\d+)").expect("valid regex"))
+}
+
+/// Tag a parse error of the expanded script with the script line its message names first
+fn parse_error(e: anyhow::Error, sources: &SourceMap) -> anyhow::Error {
+    let message = e.to_string();
+    let line = line_ref_regex()
+        .captures(&message)
+        .and_then(|caps| caps[1].parse().ok())
+        .map(|line| sources.script_line(line));
+    ScriptParseError { line, message: sources.relocate_message(&message) }.into()
+}
+
+/// Names of the snippets a script includes, directly or through other snippets. Unknown names are skipped.
+pub fn included_snippets(script: &str, snippets: &Snippets) -> std::collections::BTreeSet<String> {
+    let mut found = std::collections::BTreeSet::new();
+    let mut pending: Vec<&str> = script.lines().filter_map(include_target).collect();
+    while let Some(name) = pending.pop() {
+        if let Some(text) = snippets.get(name) {
+            if found.insert(name.to_string()) {
+                pending.extend(text.lines().filter_map(include_target));
+            }
+        }
+    }
+    found
+}
+
+/// Check that a script's `INCLUDE`s name existing snippets, without cycles or nesting beyond [`MAX_INCLUDE_DEPTH`]
+pub fn check_includes(script: &str, snippets: &Snippets) -> Result<()> {
+    expand_includes(script, snippets, None, ("", None), &mut Vec::new(), &mut Vec::new(), &mut Vec::new())
+}
+
+/// Snippet name of an `INCLUDE <name>` line
+fn include_target(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("INCLUDE ")?;
+    Some(strip_inline_comment(rest).trim())
+}
+
+/// Replace `INCLUDE <name>` lines with the snippet's lines, indented like the `INCLUDE`
+fn expand_includes(
+    text: &str,
+    snippets: &Snippets,
+    snippet: Option<&str>,
+    context: (&str, Option<usize>), // indentation, top-level INCLUDE line
+    stack: &mut Vec<String>,
+    expanded: &mut Vec<String>,
+    origins: &mut Vec<LineOrigin>,
+) -> Result<()> {
+    let (indent, include_line) = context;
+    for (idx, line) in text.lines().enumerate() {
+        let line_num = idx + 1;
+        let script_line = include_line.unwrap_or(line_num);
+        let Some(name) = include_target(line) else {
+            expanded.push(format!("{}{}", indent, line));
+            origins.push(LineOrigin { snippet: snippet.map(str::to_string), line: line_num, script_line });
+            continue;
+        };
+        let location = match snippet {
+            Some(parent) => format!("line {} of snippet '{}'", line_num, parent),
+            None => format!("line {}", line_num),
+        };
+        let failed = |message: String| ScriptParseError { line: Some(script_line), message };
+        if stack.iter().any(|open| open == name) {
+            return Err(failed(format!("INCLUDE cycle: {} -> {} at {}", stack.join(" -> "), name, location)).into());
+        }
+        if stack.len() >= MAX_INCLUDE_DEPTH {
+            return Err(failed(format!("INCLUDE nested deeper than {} snippets at {}", MAX_INCLUDE_DEPTH, location)).into());
+        }
+        let body = snippets
+            .get(name)
+            .ok_or_else(|| failed(format!("Unknown snippet '{}' in INCLUDE at {}", name, location)))?;
+        let nested_indent = format!("{}{}", indent, &line[..line.len() - line.trim_start().len()]);
+        stack.push(name.to_string());
+        expand_includes(body, snippets, Some(name), (&nested_indent, Some(script_line)), stack, expanded, origins)?;
+        stack.pop();
+    }
+    Ok(())
+}
+
+impl PacketScript {
+    /// The code blocks placed between the previous pair and pair `pair_idx`, in script order
+    pub fn code_blocks_before(&self, pair_idx: usize) -> &[CodeBlock] {
+        let start = self.code_blocks.partition_point(|block| block.before_pair < pair_idx);
+        let end = self.code_blocks.partition_point(|block| block.before_pair <= pair_idx);
+        &self.code_blocks[start..end]
+    }
+}
+
+/// Parse a script, first replacing its `INCLUDE <name>` lines with the snippets' text.
+/// Parse errors name the script or snippet line they come from; [`error_line`] gives the script line.
+pub fn parse_script(script: &str, snippets: &Snippets) -> Result<PacketScript> {
+    if !script.lines().any(|line| include_target(line).is_some()) {
+        return parse_expanded_script(script).map_err(|e| parse_error(e, &SourceMap::default()));
+    }
+    let mut expanded = Vec::new();
+    let mut origins = Vec::new();
+    expand_includes(script, snippets, None, ("", None), &mut Vec::new(), &mut expanded, &mut origins)?;
+    let sources = SourceMap { origins };
+    let mut parsed = parse_expanded_script(&expanded.join("\n"))
+        .map_err(|e| parse_error(e, &sources))?;
+    parsed.sources = sources;
+    Ok(parsed)
+}
+
+/// Command text of each line as the parser sees it (after `INCLUDE` expansion, without comments),
+/// indexed by the line numbers commands are tagged with
+pub fn script_command_lines(script: &str, snippets: &Snippets) -> Vec<String> {
+    let mut expanded = Vec::new();
+    if script.lines().any(|line| include_target(line).is_some())
+        && expand_includes(script, snippets, None, ("", None), &mut Vec::new(), &mut expanded, &mut Vec::new()).is_ok()
+    {
+        return expanded.iter().map(|line| strip_inline_comment(line.trim()).to_string()).collect();
+    }
+    script.lines().map(|line| strip_inline_comment(line.trim()).to_string()).collect()
 }
 
-pub fn parse_script(script: &str) -> Result<PacketScript> {
+fn parse_expanded_script(script: &str) -> Result<PacketScript> {
     let lines: Vec<&str> = script.lines().collect();
     let mut pairs = Vec::new();
     let mut current_packets = Vec::new(); // Accumulate multiple packets
@@ -211,6 +790,16 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
     let mut in_response = false;
     let mut in_code = false;
     let mut close_connection_before_next = false; // Track if CONNECTION_CLOSE was seen
+    let mut cookies = false;
+    let mut defines = IndexMap::new();
+    let mut functions: IndexMap<String, ScriptFunction> = IndexMap::new();
+    let mut discarded_packets = Vec::new();
+    let mut big_endian: Option<bool> = None; // ENDIAN directive
+    let mut frame_type = FrameType::default(); // Current WebSocket frame type (FRAME directive)
+    let mut expect_packets_next: Option<ExpectPackets> = None; // Pending EXPECT_PACKETS for the next pair
+    let mut delay_before_next: u64 = 0; // Pending DELAY time for the next pair
+    let mut total_delay: u64 = 0; // All DELAY time of the script so far
+    let mut read_timeout_next: Option<u64> = None; // Pending TIMEOUT override for the next pair
 
     let mut line_num = 0;
     let mut processed_lines = std::collections::HashSet::new();
@@ -228,6 +817,8 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             line_num += 1;
             continue;
         }
+        // Drop inline comments; CODE lines handle their own
+        let line = if in_code { line } else { strip_inline_comment(line) };
 
         // Connection close command
         if line == "CONNECTION_CLOSE" {
@@ -236,6 +827,124 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             continue;
         }
 
+        // Cookie jar for the whole check (top level only)
+        if let Some(rest) = line.strip_prefix("COOKIES ") {
+            if in_packet || in_response || in_http || in_code || current_output.is_some() {
+                anyhow::bail!("COOKIES is only allowed between blocks, not inside one (line {})", line_num + 1);
+            }
+            cookies = match rest.trim() {
+                "ON" => true,
+                "OFF" => false,
+                other => anyhow::bail!("COOKIES must be ON or OFF, got '{}' at line {}", other, line_num + 1),
+            };
+            line_num += 1;
+            continue;
+        }
+
+        // Default byte order, before the first block
+        if let Some(rest) = line.strip_prefix("ENDIAN ") {
+            if in_packet || in_response || in_http || in_code || current_output.is_some() {
+                anyhow::bail!("ENDIAN is only allowed between blocks, not inside one (line {})", line_num + 1);
+            }
+            if !pairs.is_empty() || !current_packets.is_empty() || !code_blocks.is_empty() || big_endian.is_some() {
+                anyhow::bail!("ENDIAN must come once, before the first PACKET, HTTP or CODE block (line {})", line_num + 1);
+            }
+            big_endian = Some(match rest.trim() {
+                "BIG" => true,
+                "LITTLE" => false,
+                other => anyhow::bail!("ENDIAN must be BIG or LITTLE, got '{}' at line {}", other, line_num + 1),
+            });
+            line_num += 1;
+            continue;
+        }
+
+        // Script constant (top level only)
+        if let Some(rest) = line.strip_prefix("DEFINE ") {
+            if in_packet || in_response || in_http || in_code || current_output.is_some() {
+                anyhow::bail!("DEFINE is only allowed between blocks, not inside one (line {})", line_num + 1);
+            }
+            let (name, value) = rest.trim().split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow::anyhow!("DEFINE requires a name and a value at line {}", line_num + 1))?;
+            if !is_variable_name(name) {
+                anyhow::bail!("Invalid DEFINE name '{}' at line {}", name, line_num + 1);
+            }
+            if defines.insert(name.to_string(), constant_value(value)).is_some() {
+                anyhow::bail!("{} is defined twice (line {})", name, line_num + 1);
+            }
+            line_num += 1;
+            continue;
+        }
+
+        // Pause between pairs (top level only)
+        if let Some(rest) = line.strip_prefix("DELAY ") {
+            if in_packet || in_response || in_http || in_code || current_output.is_some() {
+                anyhow::bail!("DELAY is only allowed between pairs, not inside a block (line {})", line_num + 1);
+            }
+            let ms: u64 = rest.trim().parse()
+                .map_err(|_| anyhow::anyhow!("Invalid DELAY value '{}' at line {}", rest.trim(), line_num + 1))?;
+            if ms > MAX_DELAY_MS {
+                anyhow::bail!("DELAY {} exceeds the maximum of {}ms at line {}", ms, MAX_DELAY_MS, line_num + 1);
+            }
+            total_delay += ms;
+            if total_delay > MAX_TOTAL_DELAY_MS {
+                anyhow::bail!(
+                    "DELAYs add up to {}ms, more than the maximum of {}ms per script, at line {}",
+                    total_delay, MAX_TOTAL_DELAY_MS, line_num + 1
+                );
+            }
+            delay_before_next += ms;
+            line_num += 1;
+            continue;
+        }
+
+        // Receive timeout override for the next pair (top level only)
+        if let Some(rest) = line.strip_prefix("TIMEOUT ") {
+            if in_packet || in_response || in_http || in_code || current_output.is_some() {
+                anyhow::bail!("TIMEOUT must be placed before PACKET_START/HTTP_START, not inside a block (line {})", line_num + 1);
+            }
+            let ms: u64 = rest.trim().parse()
+                .map_err(|_| anyhow::anyhow!("Invalid TIMEOUT value '{}' at line {}", rest.trim(), line_num + 1))?;
+            let max = max_pair_timeout_ms();
+            if ms == 0 || ms > max {
+                anyhow::bail!("TIMEOUT {} must be between 1 and {}ms at line {}", ms, max, line_num + 1);
+            }
+            read_timeout_next = Some(ms);
+            line_num += 1;
+            continue;
+        }
+
+        // Multi-datagram UDP response for the current/next pair
+        if let Some(rest) = line.strip_prefix("EXPECT_PACKETS ") {
+            let parts: Vec<&str> = rest.split_whitespace().collect();
+            if parts.is_empty() || parts.len() > 2 {
+                anyhow::bail!("EXPECT_PACKETS requires <max> [idle_ms] at line {}", line_num + 1);
+            }
+            let max: usize = parts[0].parse()
+                .map_err(|_| anyhow::anyhow!("Invalid EXPECT_PACKETS count '{}' at line {}", parts[0], line_num + 1))?;
+            if max == 0 {
+                anyhow::bail!("EXPECT_PACKETS count must be at least 1 at line {}", line_num + 1);
+            }
+            let idle_ms = match parts.get(1) {
+                Some(value) => value.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid EXPECT_PACKETS idle timeout '{}' at line {}", value, line_num + 1))?,
+                None => DEFAULT_EXPECT_PACKETS_IDLE_MS,
+            };
+            expect_packets_next = Some(ExpectPackets { max, idle_ms });
+            line_num += 1;
+            continue;
+        }
+
+        // WebSocket frame type for the following packets
+        if let Some(rest) = line.strip_prefix("FRAME ") {
+            frame_type = match rest.trim().to_uppercase().as_str() {
+                "TEXT" => FrameType::Text,
+                "BINARY" => FrameType::Binary,
+                _ => anyhow::bail!("FRAME must be TEXT or BINARY at line {}", line_num + 1),
+            };
+            line_num += 1;
+            continue;
+        }
+
         // HTTP section
         if line.starts_with("HTTP_START REQUEST ") {
             // Parse HTTP_START REQUEST <METHOD> <PATH>
@@ -266,6 +975,7 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
                 parts[1].to_string()
             };
             
+            check_placeholders(&path, line_num + 1)?;
             current_http_request = Some(HttpRequest {
                 method,
                 path,
@@ -301,8 +1011,7 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
                 current_packets.push(current_packet.clone());
                 current_packet.clear();
             }
-            // Mark this new pair to close connection before it if CONNECTION_CLOSE was seen
-            close_connection_before_next = false; // Reset flag
+            // CONNECTION_CLOSE stays pending until the pair is completed at RESPONSE_END
             in_packet = true;
             in_http = false;
             in_response = false;
@@ -328,10 +1037,41 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             line_num += 1;
             continue;
         }
+        // Fire-and-forget pair: completes the pending packets without a response block
+        if line == "NO_RESPONSE" {
+            if in_packet || in_response || in_http || in_code || current_output.is_some() {
+                anyhow::bail!("NO_RESPONSE must follow PACKET_END, not be inside a block (line {})", line_num + 1);
+            }
+            if current_http_request.is_some() {
+                anyhow::bail!("NO_RESPONSE is not supported for HTTP requests (line {})", line_num + 1);
+            }
+            if current_packets.is_empty() {
+                anyhow::bail!("NO_RESPONSE without a preceding PACKET_START/PACKET_END block (line {})", line_num + 1);
+            }
+            pairs.push(PacketResponsePair {
+                packets: std::mem::take(&mut current_packets),
+                http_request: None,
+                response: Vec::new(),
+                close_connection_before: std::mem::take(&mut close_connection_before_next),
+                frame_type,
+                expect_packets: None,
+                delay_before_ms: std::mem::take(&mut delay_before_next),
+                read_timeout_ms: None,
+                no_response: true,
+            });
+            expect_packets_next = None;
+            read_timeout_next = None;
+            line_num += 1;
+            continue;
+        }
+
         if line == "RESPONSE_END" {
             // When response ends, save all accumulated packets or HTTP request with the response
             let should_close = close_connection_before_next;
             close_connection_before_next = false; // Reset flag
+            let expect_packets = expect_packets_next.take();
+            let delay_before_ms = std::mem::take(&mut delay_before_next);
+            let read_timeout_ms = read_timeout_next.take();
             
             if !current_packets.is_empty() {
                 pairs.push(PacketResponsePair {
@@ -339,6 +1079,11 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
                     http_request: None,
                     response: current_response.clone(),
                     close_connection_before: should_close,
+                    frame_type,
+                    expect_packets,
+                    delay_before_ms,
+                    read_timeout_ms,
+                    no_response: false,
                 });
                 current_packets.clear();
             } else if current_http_request.is_some() {
@@ -349,6 +1094,11 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
                     http_request: Some(http_req),
                     response: current_response.clone(),
                     close_connection_before: should_close,
+                    frame_type,
+                    expect_packets,
+                    delay_before_ms,
+                    read_timeout_ms,
+                    no_response: false,
                 });
                 // Commands were already cleared at HTTP_END, but clear again just in case
                 current_http_commands.clear();
@@ -365,6 +1115,7 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             // This can happen if CODE_START appears after PACKET_END but before RESPONSE_START
             if !current_packets.is_empty() && current_response.is_empty() {
                 current_packets.clear();
+                discarded_packets.push(line_num + 1);
             }
             in_code = true;
             in_packet = false;
@@ -377,6 +1128,7 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             if !current_code.is_empty() {
                 code_blocks.push(CodeBlock {
                     commands: current_code.clone(),
+                    before_pair: pairs.len(),
                 });
                 current_code.clear();
             }
@@ -385,23 +1137,67 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             continue;
         }
 
-        if in_http {
+        if in_http && line == "DATA_MULTILINE_START" {
+            // Collect the raw lines up to DATA_MULTILINE_END as a single DATA entry
+            let start_line = line_num + 1;
+            let mut data_lines = Vec::new();
+            line_num += 1;
+            loop {
+                let Some(raw) = lines.get(line_num) else {
+                    anyhow::bail!("DATA_MULTILINE_START at line {} has no matching DATA_MULTILINE_END", start_line);
+                };
+                line_num += 1;
+                if raw.trim() == "DATA_MULTILINE_END" {
+                    break;
+                }
+                check_placeholders(raw, line_num)?;
+                data_lines.push(raw.trim_end().to_string());
+            }
+            current_http_commands.push(HttpCommand::DataMultiline { lines: data_lines });
+        } else if in_http {
+            check_placeholders(line, line_num + 1)?;
             let cmd = parse_http_command(line, line_num + 1)?;
+            let verbatim = matches!(cmd, HttpCommand::BodyStart { verbatim: true, .. });
             current_http_commands.push(cmd);
             line_num += 1;
+            // A VERBATIM body is opaque: every line up to BODY_END is sent as written
+            if verbatim {
+                let start_line = line_num;
+                let end = verbatim_body_end(&lines, line_num).ok_or_else(|| {
+                    anyhow::anyhow!("BODY_START TYPE RAW VERBATIM at line {} has no matching BODY_END", start_line)
+                })?;
+                for (offset, raw) in lines[line_num..end].iter().enumerate() {
+                    check_placeholders(raw, line_num + offset + 1)?;
+                }
+                let body_lines = lines[line_num..end].iter().map(|raw| raw.trim_end().to_string()).collect();
+                current_http_commands.push(HttpCommand::DataMultiline { lines: body_lines });
+                current_http_commands.push(HttpCommand::BodyEnd);
+                line_num = end + 1;
+            }
         } else if in_packet {
-            current_packet.push(parse_packet_command(line, line_num + 1)?);
+            check_placeholders(line, line_num + 1)?;
+            current_packet.push(Sourced::new(parse_packet_command(line, line_num + 1)?, line_num + 1));
             line_num += 1;
         } else if in_response {
-            current_response.push(parse_response_command(line, line_num + 1)?);
+            current_response.push(Sourced::new(parse_response_command(line, line_num + 1)?, line_num + 1));
             line_num += 1;
         } else if in_code {
             let indent_level = lines[line_num].len() - lines[line_num].trim_start().len();
             
-            if line.ends_with(':') && (line.starts_with("FOR ") || line.starts_with("IF ")) {
+            if indent_level == 0 && line.starts_with("DEF ") {
+                let (name, function, lines_consumed) = parse_function(&lines, line_num)?;
+                if functions.contains_key(&name) {
+                    anyhow::bail!("Function '{}' is already defined at line {}", name, line_num + 1);
+                }
+                functions.insert(name, function);
+                for i in 0..lines_consumed {
+                    processed_lines.insert(line_num + i);
+                }
+                line_num += lines_consumed;
+            } else if line.ends_with(':') && (line.starts_with("FOR ") || line.starts_with("WHILE ") || line.starts_with("IF ")) {
                 // Parse multi-line control flow statement
                 let (cmd, lines_consumed) = parse_control_flow(&lines, line_num, indent_level)?;
-                current_code.push(cmd);
+                current_code.push(Sourced::new(cmd, line_num + 1));
                 // Mark all consumed lines as processed
                 for i in 0..lines_consumed {
                     processed_lines.insert(line_num + i);
@@ -411,7 +1207,7 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
                 // This is an indented line, skip it (it's part of a control flow body we already parsed)
                 line_num += 1;
             } else {
-                current_code.push(parse_code_command(line, line_num + 1)?);
+                current_code.push(Sourced::new(parse_code_command(line, line_num + 1)?, line_num + 1));
                 line_num += 1;
             }
         } else {
@@ -427,6 +1223,11 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             http_request: None,
             response: current_response,
             close_connection_before: close_connection_before_next,
+            frame_type,
+            expect_packets: expect_packets_next,
+            delay_before_ms: delay_before_next,
+            read_timeout_ms: read_timeout_next,
+            no_response: false,
         });
     } else if current_http_request.is_some() {
         // HTTP request was already built at HTTP_END, just use it
@@ -436,6 +1237,11 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
             http_request: Some(http_req),
             response: current_response,
             close_connection_before: close_connection_before_next,
+            frame_type,
+            expect_packets: expect_packets_next,
+            delay_before_ms: delay_before_next,
+            read_timeout_ms: read_timeout_next,
+            no_response: false,
         });
     }
 
@@ -447,104 +1253,269 @@ pub fn parse_script(script: &str) -> Result<PacketScript> {
     if !current_code.is_empty() {
         code_blocks.push(CodeBlock {
             commands: current_code,
+            before_pair: pairs.len(),
         });
     }
 
+    for command in pairs.iter().flat_map(|pair| &pair.response) {
+        if let Some(name) = command.command.target().filter(|name| defines.contains_key(*name)) {
+            anyhow::bail!("{} is a DEFINE constant and can't be read into at line {}", name, command.source_line);
+        }
+    }
+    for block in &code_blocks {
+        check_constant_assignments(&block.commands, &defines)?;
+        check_function_use(&block.commands, &functions, false)?;
+    }
+    for function in functions.values() {
+        check_constant_assignments(&function.body, &defines)?;
+        check_function_use(&function.body, &functions, true)?;
+    }
+
     Ok(PacketScript {
         pairs,
         output_blocks,
         code_blocks,
+        cookies,
+        defines,
+        sources: SourceMap::default(),
+        functions,
+        discarded_packets,
+        big_endian: big_endian.unwrap_or(false),
     })
 }
 
-fn parse_packet_command(line: &str, line_num: usize) -> Result<PacketCommand> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.is_empty() {
-        anyhow::bail!("Empty command at line {}", line_num);
+/// Value of a `DEFINE` or a server variable: a number if the text is one (decimal, `0x` hex or float),
+/// otherwise the text without surrounding quotes. Quote a number to keep it a string.
+pub fn constant_value(text: &str) -> JsonValue {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        return JsonValue::String(strip_quotes(text));
+    }
+    if let Ok(num) = text.parse::<i64>() {
+        return JsonValue::from(num);
+    }
+    if let Some(num) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).and_then(|hex| u64::from_str_radix(hex, 16).ok()) {
+        return JsonValue::from(num);
+    }
+    if let Some(num) = text.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+        return JsonValue::Number(num);
     }
+    JsonValue::String(text.to_string())
+}
 
-    match parts[0] {
-        "WRITE_BYTE" => {
-            let token = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("WRITE_BYTE requires value at line {}", line_num))?;
-            if is_variable_name(token) {
-                Ok(PacketCommand::WriteByteVar(token.to_string()))
-            } else {
-                let value = parse_byte_value(Some(token))?;
-                Ok(PacketCommand::WriteByte(value))
-            }
+/// `DEFINE` constants are read-only: code blocks may not declare or assign them
+fn check_constant_assignments(commands: &[Sourced<CodeCommand>], defines: &IndexMap<String, JsonValue>) -> Result<()> {
+    for sourced in commands {
+        let target = match &sourced.command {
+            CodeCommand::DeclareVar { name, .. }
+            | CodeCommand::AssignVar { name, .. }
+            | CodeCommand::Split { var_name: name, .. }
+            | CodeCommand::Replace { var_name: name, .. }
+            | CodeCommand::Call { result: Some(name), .. } => Some(name.as_str()),
+            CodeCommand::ExecuteResponseCommand(command) => command.target(),
+            _ => None,
+        };
+        if let Some(name) = target.filter(|name| defines.contains_key(*name)) {
+            anyhow::bail!("{} is a DEFINE constant and can't be assigned at line {}", name, sourced.source_line);
         }
-        "WRITE_SHORT" => {
-            let token = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("WRITE_SHORT requires value at line {}", line_num))?;
-            if is_variable_name(token) {
-                Ok(PacketCommand::WriteShortVar(token.to_string(), false))
-            } else {
-                let value = parse_short_value(Some(token))?;
-                Ok(PacketCommand::WriteShort(value, false))
+        match &sourced.command {
+            CodeCommand::ForLoop { body, .. } | CodeCommand::WhileLoop { body, .. } | CodeCommand::ForInArray { body, .. } => {
+                check_constant_assignments(body, defines)?;
             }
-        }
-        "WRITE_SHORT_BE" => {
-            let token = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("WRITE_SHORT_BE requires value at line {}", line_num))?;
-            if is_variable_name(token) {
-                Ok(PacketCommand::WriteShortVar(token.to_string(), true))
-            } else {
-                let value = parse_short_value(Some(token))?;
-                Ok(PacketCommand::WriteShort(value, true))
+            CodeCommand::IfStatement { body, else_if, else_body, .. } => {
+                check_constant_assignments(body, defines)?;
+                for (_, branch) in else_if {
+                    check_constant_assignments(branch, defines)?;
+                }
+                if let Some(branch) = else_body {
+                    check_constant_assignments(branch, defines)?;
+                }
             }
+            _ => {}
         }
-        "WRITE_INT" => {
-            let token = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("WRITE_INT requires value at line {}", line_num))?;
-            if token.eq_ignore_ascii_case("PACKET_LEN") {
-                Ok(PacketCommand::WriteIntLen(false)) // little-endian by default
-            } else if is_variable_name(token) {
-                Ok(PacketCommand::WriteIntVar(token.to_string(), false))
-            } else {
-                let value = parse_int_value(Some(token))?;
-                Ok(PacketCommand::WriteInt(value, false))
+    }
+    Ok(())
+}
+
+/// Every `CALL` must name a defined function with the right number of arguments,
+/// and `RETURN` is only allowed inside a function
+fn check_function_use(
+    commands: &[Sourced<CodeCommand>],
+    functions: &IndexMap<String, ScriptFunction>,
+    inside_function: bool,
+) -> Result<()> {
+    for sourced in commands {
+        match &sourced.command {
+            CodeCommand::Call { name, args, line, .. } => {
+                let function = functions.get(name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown function '{}' at line {}", name, line))?;
+                if function.params.len() != args.len() {
+                    anyhow::bail!(
+                        "Function '{}' takes {} argument(s) but {} were given at line {}",
+                        name, function.params.len(), args.len(), line
+                    );
+                }
+            }
+            CodeCommand::Return(_) if !inside_function => {
+                anyhow::bail!("RETURN outside of a function at line {}", sourced.source_line);
+            }
+            CodeCommand::ForLoop { body, .. } | CodeCommand::WhileLoop { body, .. } | CodeCommand::ForInArray { body, .. } => {
+                check_function_use(body, functions, inside_function)?;
+            }
+            CodeCommand::IfStatement { body, else_if, else_body, .. } => {
+                check_function_use(body, functions, inside_function)?;
+                for (_, branch) in else_if {
+                    check_function_use(branch, functions, inside_function)?;
+                }
+                if let Some(branch) = else_body {
+                    check_function_use(branch, functions, inside_function)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Byte order a command's `_BE`/`_LE` suffix asks for; `None` leaves it to the script's `ENDIAN`
+fn explicit_endian(keyword: &str) -> Option<bool> {
+    if keyword.ends_with("_BE") {
+        Some(true)
+    } else if keyword.ends_with("_LE") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// `keyword` without its `_BE`/`_LE` suffix
+fn without_endian(keyword: &str) -> &str {
+    keyword.strip_suffix("_BE").or_else(|| keyword.strip_suffix("_LE")).unwrap_or(keyword)
+}
+
+fn parse_packet_command(line: &str, line_num: usize) -> Result<PacketCommand> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.is_empty() {
+        anyhow::bail!("Empty command at line {}", line_num);
+    }
+
+    match parts[0] {
+        "WRITE_BYTE" => {
+            let token = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("WRITE_BYTE requires value at line {}", line_num))?;
+            if is_variable_name(token) {
+                Ok(PacketCommand::WriteByteVar(token.to_string()))
+            } else {
+                let value = parse_byte_value(Some(token))?;
+                Ok(PacketCommand::WriteByte(value))
+            }
+        }
+        "WRITE_SHORT" | "WRITE_SHORT_BE" | "WRITE_SHORT_LE" => {
+            let big_endian = explicit_endian(parts[0]);
+            let token = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("{} requires value at line {}", parts[0], line_num))?;
+            if is_variable_name(token) {
+                Ok(PacketCommand::WriteShortVar(token.to_string(), big_endian))
+            } else {
+                let value = parse_short_value(Some(token))?;
+                Ok(PacketCommand::WriteShort(value, big_endian))
             }
         }
-        "WRITE_INT_BE" => {
+        "WRITE_INT" | "WRITE_INT_BE" | "WRITE_INT_LE" => {
+            let big_endian = explicit_endian(parts[0]);
             let token = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("WRITE_INT_BE requires value at line {}", line_num))?;
+                .ok_or_else(|| anyhow::anyhow!("{} requires value at line {}", parts[0], line_num))?;
             if token.eq_ignore_ascii_case("PACKET_LEN") {
-                Ok(PacketCommand::WriteIntLen(true)) // big-endian
+                Ok(PacketCommand::WriteIntLen(big_endian))
             } else if is_variable_name(token) {
-                Ok(PacketCommand::WriteIntVar(token.to_string(), true))
+                Ok(PacketCommand::WriteIntVar(token.to_string(), big_endian))
             } else {
                 let value = parse_int_value(Some(token))?;
-                Ok(PacketCommand::WriteInt(value, true))
+                Ok(PacketCommand::WriteInt(value, big_endian))
             }
         }
-        "WRITE_STRING" => {
-            // Handle quoted strings with spaces by finding the closing quote
-            if let Some(rest) = line.strip_prefix("WRITE_STRING ") {
-                if let Some(quote_start) = rest.find('"') {
-                    // Find the closing quote after the opening one
-                    if let Some(quote_end) = rest[quote_start + 1..].find('"') {
-                        let text = rest[quote_start + 1..quote_start + 1 + quote_end].to_string();
-                        Ok(PacketCommand::WriteString(text, None))
-                    } else {
-                        // Fallback: treat rest as unquoted string
-                        let text = parse_string_value(Some(rest))?;
-                        Ok(PacketCommand::WriteString(text, None))
-                    }
+        "WRITE_LONG" | "WRITE_LONG_BE" | "WRITE_LONG_LE" => {
+            let big_endian = explicit_endian(parts[0]);
+            let token = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("{} requires value at line {}", parts[0], line_num))?;
+            if is_variable_name(token) {
+                Ok(PacketCommand::WriteLongVar(token.to_string(), big_endian))
+            } else {
+                // Negative literals are written as their two's complement
+                let value = parse_literal_value(token)
+                    .or_else(|e| token.parse::<i64>().map(|n| n as u64).map_err(|_| e))
+                    .with_context(|| format!("Invalid long value at line {}", line_num))?;
+                Ok(PacketCommand::WriteLong(value, big_endian))
+            }
+        }
+        "WRITE_FLOAT" | "WRITE_FLOAT_BE" | "WRITE_FLOAT_LE" | "WRITE_DOUBLE" | "WRITE_DOUBLE_BE" | "WRITE_DOUBLE_LE" => {
+            let big_endian = explicit_endian(parts[0]);
+            let double = parts[0].starts_with("WRITE_DOUBLE");
+            let token = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("{} requires value at line {}", parts[0], line_num))?;
+            if is_variable_name(token) {
+                Ok(if double {
+                    PacketCommand::WriteDoubleVar(token.to_string(), big_endian)
                 } else {
-                    // No quotes, check if it's a variable name
-                    let token = rest.trim();
-                    if is_variable_name(token) {
-                        Ok(PacketCommand::WriteStringVar(token.to_string(), None))
-                    } else {
-                        let text = parse_string_value(Some(token))?;
-                        Ok(PacketCommand::WriteString(text, None))
-                    }
-                }
+                    PacketCommand::WriteFloatVar(token.to_string(), big_endian)
+                })
             } else {
-                anyhow::bail!("WRITE_STRING requires text at line {}", line_num);
+                let value = token.parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("Invalid float value '{}' at line {}", token, line_num))?;
+                Ok(if double {
+                    PacketCommand::WriteDouble(value, big_endian)
+                } else {
+                    PacketCommand::WriteFloat(value as f32, big_endian)
+                })
             }
         }
+        "WRITE_STRING" | "WRITE_STRING_RAW" => {
+            // WRITE_STRING appends a null terminator, WRITE_STRING_RAW writes the bytes only
+            let keyword = parts[0];
+            let raw = keyword == "WRITE_STRING_RAW";
+            let rest = line.strip_prefix(keyword).and_then(|rest| rest.strip_prefix(' '))
+                .ok_or_else(|| anyhow::anyhow!("{} requires text at line {}", keyword, line_num))?;
+            Ok(match (parse_string_operand(rest)?, raw) {
+                (StringOperand::Literal(text), false) => PacketCommand::WriteString(text, None),
+                (StringOperand::Var(var), false) => PacketCommand::WriteStringVar(var, None),
+                (StringOperand::Literal(text), true) => PacketCommand::WriteStringRaw(text),
+                (StringOperand::Var(var), true) => PacketCommand::WriteStringRawVar(var),
+            })
+        }
+        "WRITE_STRING_BYTE_LEN" | "WRITE_STRING_SHORT_LE_LEN" | "WRITE_STRING_SHORT_BE_LEN" | "WRITE_STRING_VARINT_LEN"
+        | "WRITE_STRING_VARINT" => {
+            let keyword = parts[0];
+            let prefix = LenPrefix::from_keyword(keyword, "WRITE_STRING_").unwrap();
+            let rest = line.strip_prefix(keyword).and_then(|rest| rest.strip_prefix(' '))
+                .ok_or_else(|| anyhow::anyhow!("{} requires text at line {}", keyword, line_num))?;
+            Ok(match parse_string_operand(rest)? {
+                StringOperand::Literal(text) => PacketCommand::WriteStringLenPrefixed(text, prefix),
+                StringOperand::Var(var) => PacketCommand::WriteStringLenPrefixedVar(var, prefix),
+            })
+        }
+        "WRITE_STRING_PREFIXED" => {
+            let usage = || anyhow::anyhow!(
+                "WRITE_STRING_PREFIXED requires a prefix (BYTE, SHORT, SHORT_BE, INT, INT_BE or VARINT) and text at line {}",
+                line_num
+            );
+            let rest = line.strip_prefix("WRITE_STRING_PREFIXED").ok_or_else(usage)?.trim_start();
+            let (prefix_name, operand) = rest.split_once(' ').ok_or_else(usage)?;
+            let prefix = LenPrefix::from_name(prefix_name).ok_or_else(usage)?;
+            Ok(match parse_string_operand(operand.trim_start())? {
+                StringOperand::Literal(text) => PacketCommand::WriteStringLenPrefixed(text, prefix),
+                StringOperand::Var(var) => PacketCommand::WriteStringLenPrefixedVar(var, prefix),
+            })
+        }
+        "WRITE_STRING_UTF16" | "WRITE_STRING_UTF16_BE" | "WRITE_STRING_UTF16_LE" => {
+            let keyword = parts[0];
+            let big_endian = keyword == "WRITE_STRING_UTF16_BE";
+            let rest = line.strip_prefix(keyword).and_then(|rest| rest.strip_prefix(' '))
+                .ok_or_else(|| anyhow::anyhow!("{} requires text at line {}", keyword, line_num))?;
+            Ok(match parse_string_operand(rest)? {
+                StringOperand::Literal(text) => PacketCommand::WriteStringUtf16(text, big_endian),
+                StringOperand::Var(var) => PacketCommand::WriteStringUtf16Var(var, big_endian),
+            })
+        }
         "WRITE_STRING_LEN" => {
             // Handle quoted strings with spaces by finding the closing quote
             if let Some(rest) = line.strip_prefix("WRITE_STRING_LEN ") {
@@ -591,11 +1562,48 @@ fn parse_packet_command(line: &str, line_num: usize) -> Result<PacketCommand> {
             }
         }
         "WRITE_BYTES" => {
-            let hex = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("WRITE_BYTES requires hex string at line {}", line_num))?;
-            let bytes = hex::decode(hex.replace("0x", "").replace("0X", ""))
-                .with_context(|| format!("Invalid hex string at line {}", line_num))?;
-            Ok(PacketCommand::WriteBytes(bytes))
+            if parts.len() < 2 {
+                anyhow::bail!("WRITE_BYTES requires hex string at line {}", line_num);
+            }
+            Ok(PacketCommand::WriteBytes(parse_hex_literal(&parts[1..].join(" "), line_num)?))
+        }
+        "WRITE_BYTES_VAR" => {
+            let var = parts.get(1)
+                .filter(|token| is_variable_name(token))
+                .ok_or_else(|| anyhow::anyhow!("WRITE_BYTES_VAR requires variable name at line {}", line_num))?;
+            Ok(PacketCommand::WriteBytesVar(var.to_string()))
+        }
+        "WRITE_CRC32" => {
+            let big_endian = match parts.get(1).map(|order| order.to_ascii_uppercase()) {
+                None => false,
+                Some(order) if order == "LE" => false,
+                Some(order) if order == "BE" => true,
+                Some(order) => anyhow::bail!("WRITE_CRC32 byte order must be BE or LE, got {} at line {}", order, line_num),
+            };
+            Ok(PacketCommand::WriteCrc32(big_endian))
+        }
+        "COMPRESS" => {
+            let compression = parts.get(1).and_then(|name| Compression::from_name(name))
+                .ok_or_else(|| anyhow::anyhow!("COMPRESS requires ZLIB or GZIP at line {}", line_num))?;
+            Ok(PacketCommand::Compress(compression))
+        }
+        "WRITE_BYTES_REPEAT" | "WRITE_BYTES_REPEAT_TO_LEN" => {
+            if parts.len() < 3 {
+                let operand = if parts[0] == "WRITE_BYTES_REPEAT" { "count" } else { "length" };
+                anyhow::bail!("{} requires byte value and {} at line {}", parts[0], operand, line_num);
+            }
+            let value = parse_byte_value(Some(parts[1]))
+                .with_context(|| format!("Invalid byte value at line {}", line_num))?;
+            let count: usize = parts[2].parse()
+                .with_context(|| format!("Invalid count at line {}", line_num))?;
+            if count > MAX_REPEAT_BYTES {
+                anyhow::bail!("{} {} is larger than the maximum of {} bytes at line {}", parts[0], count, MAX_REPEAT_BYTES, line_num);
+            }
+            Ok(if parts[0] == "WRITE_BYTES_REPEAT" {
+                PacketCommand::WriteBytesRepeat(value, count)
+            } else {
+                PacketCommand::WriteBytesRepeatToLen(value, count)
+            })
         }
         _ => anyhow::bail!("Unknown packet command: {} at line {}", parts[0], line_num),
     }
@@ -613,31 +1621,55 @@ fn parse_response_command(line: &str, line_num: usize) -> Result<ResponseCommand
                 .ok_or_else(|| anyhow::anyhow!("READ_BYTE requires variable name at line {}", line_num))?;
             Ok(ResponseCommand::ReadByte(var.to_string()))
         }
-        "READ_SHORT" => {
+        "READ_SHORT" | "READ_SHORT_BE" | "READ_SHORT_LE" | "READ_INT" | "READ_INT_BE" | "READ_INT_LE"
+        | "READ_LONG" | "READ_LONG_BE" | "READ_LONG_LE" => {
+            let var = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("{} requires variable name at line {}", parts[0], line_num))?;
+            let var = var.to_string();
+            let big_endian = explicit_endian(parts[0]);
+            Ok(match without_endian(parts[0]) {
+                "READ_SHORT" => ResponseCommand::ReadShort(var, big_endian),
+                "READ_INT" => ResponseCommand::ReadInt(var, big_endian),
+                _ => ResponseCommand::ReadLong(var, big_endian),
+            })
+        }
+        "READ_SBYTE" => {
             let var = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("READ_SHORT requires variable name at line {}", line_num))?;
-            Ok(ResponseCommand::ReadShort(var.to_string(), false))
+                .ok_or_else(|| anyhow::anyhow!("READ_SBYTE requires variable name at line {}", line_num))?;
+            Ok(ResponseCommand::ReadSByte(var.to_string()))
         }
-        "READ_SHORT_BE" => {
+        "READ_SSHORT" | "READ_SSHORT_BE" | "READ_SSHORT_LE" | "READ_SINT" | "READ_SINT_BE" | "READ_SINT_LE"
+        | "READ_SLONG" | "READ_SLONG_BE" | "READ_SLONG_LE" | "READ_SHORT_SIGNED" | "READ_SHORT_SIGNED_BE"
+        | "READ_SHORT_SIGNED_LE" | "READ_INT_SIGNED" | "READ_INT_SIGNED_BE" | "READ_INT_SIGNED_LE" => {
             let var = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("READ_SHORT_BE requires variable name at line {}", line_num))?;
-            Ok(ResponseCommand::ReadShort(var.to_string(), true))
+                .ok_or_else(|| anyhow::anyhow!("{} requires variable name at line {}", parts[0], line_num))?;
+            let var = var.to_string();
+            let big_endian = explicit_endian(parts[0]);
+            // READ_SHORT_SIGNED and READ_INT_SIGNED are longer spellings of READ_SSHORT and READ_SINT
+            Ok(match without_endian(parts[0]) {
+                "READ_SSHORT" | "READ_SHORT_SIGNED" => ResponseCommand::ReadSShort(var, big_endian),
+                "READ_SINT" | "READ_INT_SIGNED" => ResponseCommand::ReadSInt(var, big_endian),
+                _ => ResponseCommand::ReadSLong(var, big_endian),
+            })
         }
-        "READ_INT" => {
+        "READ_FLOAT" | "READ_FLOAT_BE" | "READ_FLOAT_LE" => {
             let var = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("READ_INT requires variable name at line {}", line_num))?;
-            Ok(ResponseCommand::ReadInt(var.to_string(), false))
+                .ok_or_else(|| anyhow::anyhow!("{} requires variable name at line {}", parts[0], line_num))?;
+            Ok(ResponseCommand::ReadFloat(var.to_string(), explicit_endian(parts[0])))
         }
-        "READ_INT_BE" => {
+        "READ_DOUBLE" | "READ_DOUBLE_BE" | "READ_DOUBLE_LE" => {
             let var = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("READ_INT_BE requires variable name at line {}", line_num))?;
-            Ok(ResponseCommand::ReadInt(var.to_string(), true))
+                .ok_or_else(|| anyhow::anyhow!("{} requires variable name at line {}", parts[0], line_num))?;
+            Ok(ResponseCommand::ReadDouble(var.to_string(), explicit_endian(parts[0])))
         }
         "READ_STRING" => {
             if parts.len() < 3 {
                 anyhow::bail!("READ_STRING requires variable name and length at line {}", line_num);
             }
             let var = parts[1].to_string();
+            if is_variable_name(parts[2]) {
+                return Ok(ResponseCommand::ReadStringVarLen(var, parts[2].to_string()));
+            }
             let length: usize = parts[2].parse()
                 .with_context(|| format!("Invalid length at line {}", line_num))?;
             Ok(ResponseCommand::ReadString(var, Some(length)))
@@ -647,44 +1679,159 @@ fn parse_response_command(line: &str, line_num: usize) -> Result<ResponseCommand
                 .ok_or_else(|| anyhow::anyhow!("READ_STRING_NULL requires variable name at line {}", line_num))?;
             Ok(ResponseCommand::ReadStringNull(var.to_string()))
         }
+        "READ_STRING_BYTE_LEN" | "READ_STRING_SHORT_LE_LEN" | "READ_STRING_SHORT_BE_LEN" | "READ_STRING_VARINT_LEN"
+        | "READ_STRING_VARINT" => {
+            let prefix = LenPrefix::from_keyword(parts[0], "READ_STRING_").unwrap();
+            let var = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("{} requires variable name at line {}", parts[0], line_num))?;
+            Ok(ResponseCommand::ReadStringLenPrefixed(var.to_string(), prefix))
+        }
+        "READ_STRING_PREFIXED" => {
+            let prefix = parts.get(1).and_then(|name| LenPrefix::from_name(name));
+            match (prefix, parts.get(2)) {
+                (Some(prefix), Some(var)) => Ok(ResponseCommand::ReadStringLenPrefixed(var.to_string(), prefix)),
+                _ => anyhow::bail!(
+                    "READ_STRING_PREFIXED requires a prefix (BYTE, SHORT, SHORT_BE, INT, INT_BE or VARINT) and variable name at line {}",
+                    line_num
+                ),
+            }
+        }
+        "READ_STRING_UTF16" | "READ_STRING_UTF16_BE" | "READ_STRING_UTF16_LE" => {
+            if parts.len() < 3 {
+                anyhow::bail!("{} requires variable name and character count or NULL at line {}", parts[0], line_num);
+            }
+            let count = if parts[2].eq_ignore_ascii_case("NULL") {
+                None
+            } else {
+                Some(parts[2].parse::<usize>()
+                    .with_context(|| format!("Invalid character count at line {}", line_num))?)
+            };
+            Ok(ResponseCommand::ReadStringUtf16(parts[1].to_string(), count, parts[0] == "READ_STRING_UTF16_BE"))
+        }
         "READ_VARINT" => {
             let var = parts.get(1)
                 .ok_or_else(|| anyhow::anyhow!("READ_VARINT requires variable name at line {}", line_num))?;
             Ok(ResponseCommand::ReadVarInt(var.to_string()))
         }
         "SKIP_BYTES" => {
-            let count: usize = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("SKIP_BYTES requires count at line {}", line_num))?
-                .parse()
+            let token = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("SKIP_BYTES requires count at line {}", line_num))?;
+            if is_variable_name(token) {
+                return Ok(ResponseCommand::SkipBytesVar(token.to_string()));
+            }
+            let count: usize = token.parse()
                 .with_context(|| format!("Invalid count at line {}", line_num))?;
             Ok(ResponseCommand::SkipBytes(count))
         }
+        "SEEK_TO_OFFSET" | "SEEK_FROM_END" => {
+            let offset: usize = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("{} requires offset at line {}", parts[0], line_num))?
+                .parse()
+                .with_context(|| format!("Invalid offset at line {}", line_num))?;
+            Ok(if parts[0] == "SEEK_TO_OFFSET" {
+                ResponseCommand::SeekToOffset(offset)
+            } else {
+                ResponseCommand::SeekFromEnd(offset)
+            })
+        }
+        "DECOMPRESS" => {
+            let compression = parts.get(1).and_then(|name| Compression::from_name(name))
+                .ok_or_else(|| anyhow::anyhow!("DECOMPRESS requires ZLIB or GZIP at line {}", line_num))?;
+            Ok(ResponseCommand::Decompress(compression))
+        }
+        "EXPECT_VAR" => Ok(ResponseCommand::ExpectVar(parse_expect_var(line, line_num)?)),
+        "CURRENT_OFFSET" => {
+            let var = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("CURRENT_OFFSET requires variable name at line {}", line_num))?;
+            Ok(ResponseCommand::ReadCurrentOffset(var.to_string()))
+        }
+        "READ_BYTES" => {
+            if parts.len() < 3 {
+                anyhow::bail!("READ_BYTES requires variable name and count at line {}", line_num);
+            }
+            if is_variable_name(parts[2]) {
+                return Ok(ResponseCommand::ReadBytesVarLen(parts[1].to_string(), parts[2].to_string()));
+            }
+            let count: usize = parts[2]
+                .parse()
+                .with_context(|| format!("Invalid count at line {}", line_num))?;
+            Ok(ResponseCommand::ReadBytes(parts[1].to_string(), count))
+        }
+        "READ_REMAINING" => {
+            let var = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("READ_REMAINING requires variable name at line {}", line_num))?;
+            Ok(ResponseCommand::ReadRemaining(var.to_string()))
+        }
         "EXPECT_BYTE" => {
             let value = parse_byte_value(parts.get(1).copied())?;
             Ok(ResponseCommand::ExpectByte(value))
         }
         "EXPECT_MAGIC" => {
-            let hex = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("EXPECT_MAGIC requires hex string at line {}", line_num))?;
-            let bytes = hex::decode(hex.replace("0x", "").replace("0X", ""))
-                .with_context(|| format!("Invalid hex string at line {}", line_num))?;
-            Ok(ResponseCommand::ExpectMagic(bytes))
+            if parts.len() < 2 {
+                anyhow::bail!("EXPECT_MAGIC requires hex string at line {}", line_num);
+            }
+            Ok(ResponseCommand::ExpectMagic(parse_hex_literal(&parts[1..].join(" "), line_num)?))
+        }
+        "EXPECT_VARINT" => {
+            let token = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("EXPECT_VARINT requires value at line {}", line_num))?;
+            let value = parse_literal_value(token)
+                .with_context(|| format!("Invalid varint value at line {}", line_num))?;
+            Ok(ResponseCommand::ExpectVarInt(value))
+        }
+        "EXPECT_INT" | "EXPECT_INT_BE" | "EXPECT_INT_LE" => {
+            let value = parse_int_value(parts.get(1).copied())
+                .with_context(|| format!("{} requires a 32-bit value at line {}", parts[0], line_num))?;
+            Ok(ResponseCommand::ExpectInt(value, explicit_endian(parts[0])))
         }
         "EXPECT_STATUS" => {
-            let status_code: u16 = parts.get(1)
-                .ok_or_else(|| anyhow::anyhow!("EXPECT_STATUS requires status code at line {}", line_num))?
-                .parse()
-                .with_context(|| format!("Invalid status code at line {}", line_num))?;
-            Ok(ResponseCommand::ExpectStatus(status_code))
+            // 200, 2xx or 200-299
+            let spec = parts.get(1)
+                .ok_or_else(|| anyhow::anyhow!("EXPECT_STATUS requires status code at line {}", line_num))?;
+            let invalid = || anyhow::anyhow!("Invalid status code '{}' at line {} (use 200, 2xx or 200-299)", spec, line_num);
+            let (min, max) = if let Some(class) = spec.strip_suffix("xx").or_else(|| spec.strip_suffix("XX")) {
+                let class: u16 = class.parse().map_err(|_| invalid())?;
+                if !(1..=5).contains(&class) {
+                    return Err(invalid());
+                }
+                (class * 100, class * 100 + 99)
+            } else if let Some((min, max)) = spec.split_once('-') {
+                (min.parse().map_err(|_| invalid())?, max.parse().map_err(|_| invalid())?)
+            } else {
+                let code = spec.parse().map_err(|_| invalid())?;
+                (code, code)
+            };
+            if min > max {
+                return Err(invalid());
+            }
+            Ok(ResponseCommand::ExpectStatus(min, max))
+        }
+        "EXPECT_BODY_CONTAINS" => {
+            // Everything between the first and last quote, so the text may contain quotes itself
+            let rest = line.trim().strip_prefix("EXPECT_BODY_CONTAINS").unwrap_or("").trim();
+            if rest.len() < 2 || !rest.starts_with('"') || !rest.ends_with('"') {
+                anyhow::bail!("EXPECT_BODY_CONTAINS requires quoted text at line {}", line_num);
+            }
+            let text = rest[1..rest.len() - 1].replace("\\\"", "\"");
+            Ok(ResponseCommand::ExpectBodyContains(text))
         }
         "EXPECT_HEADER" => {
             if parts.len() < 3 {
                 anyhow::bail!("EXPECT_HEADER requires header key and value at line {}", line_num);
             }
             let key = parts[1].to_string();
-            let value = parts[2..].join(" "); // Handle values with spaces
-            Ok(ResponseCommand::ExpectHeader { key, value })
+            // EXPECT_HEADER <Key> CONTAINS <text> matches part of the value
+            let contains = parts[2] == "CONTAINS" && parts.len() > 3;
+            let value = parts[if contains { 3 } else { 2 }..].join(" "); // Handle values with spaces
+            Ok(ResponseCommand::ExpectHeader { key, value, contains })
+        }
+        "READ_HEADER" => {
+            if parts.len() < 3 {
+                anyhow::bail!("READ_HEADER requires header key and variable name at line {}", line_num);
+            }
+            Ok(ResponseCommand::ReadHeader { key: parts[1].to_string(), var_name: parts[2].to_string() })
         }
+        "NO_HEADER_VARS" => Ok(ResponseCommand::NoHeaderVars),
         "READ_BODY_JSON" => {
             let var = parts.get(1)
                 .ok_or_else(|| anyhow::anyhow!("READ_BODY_JSON requires variable name at line {}", line_num))?;
@@ -714,6 +1861,12 @@ fn parse_http_command(line: &str, line_num: usize) -> Result<HttpCommand> {
             let value = parts[2..].join(" "); // Handle values with spaces
             Ok(HttpCommand::Param { key, value })
         }
+        "PARAM_VAR" => {
+            if parts.len() != 3 {
+                anyhow::bail!("PARAM_VAR requires key and variable name at line {}", line_num);
+            }
+            Ok(HttpCommand::ParamVar { key: parts[1].to_string(), var_name: parts[2].to_string() })
+        }
         "HEADER" => {
             if parts.len() < 3 {
                 anyhow::bail!("HEADER requires key and value at line {}", line_num);
@@ -722,6 +1875,12 @@ fn parse_http_command(line: &str, line_num: usize) -> Result<HttpCommand> {
             let value = parts[2..].join(" "); // Handle values with spaces
             Ok(HttpCommand::Header { key, value })
         }
+        "HEADER_VAR" => {
+            if parts.len() != 3 {
+                anyhow::bail!("HEADER_VAR requires key and variable name at line {}", line_num);
+            }
+            Ok(HttpCommand::HeaderVar { key: parts[1].to_string(), var_name: parts[2].to_string() })
+        }
         "BODY_START" => {
             if parts.len() < 3 || parts[1] != "TYPE" {
                 anyhow::bail!("BODY_START requires TYPE and body type (FORM or RAW) at line {}", line_num);
@@ -732,18 +1891,24 @@ fn parse_http_command(line: &str, line_num: usize) -> Result<HttpCommand> {
                 "RAW" => HttpBodyType::Raw,
                 _ => anyhow::bail!("BODY_START TYPE must be FORM or RAW at line {}", line_num),
             };
-            Ok(HttpCommand::BodyStart { body_type })
+            let verbatim = match (&body_type, parts.get(3).copied(), parts.len()) {
+                (_, None, _) => false,
+                (HttpBodyType::Raw, Some("VERBATIM"), 4) => true,
+                _ => anyhow::bail!("Only BODY_START TYPE RAW can be followed by VERBATIM at line {}", line_num),
+            };
+            Ok(HttpCommand::BodyStart { body_type, verbatim })
         }
         "DATA" => {
-            // DATA content can span the rest of the line, and may include JSON or other content
-            // For JSON, we need to handle multiline JSON objects. For now, handle single line.
-            // The content is the rest of the line after "DATA "
+            // DATA content is the rest of the line after "DATA " (use DATA_MULTILINE_START for multi-line bodies)
             if parts.len() < 2 {
                 anyhow::bail!("DATA requires content at line {}", line_num);
             }
             let content = parts[1..].join(" "); // Join all remaining parts
             Ok(HttpCommand::Data { content })
         }
+        "DATA_MULTILINE_END" => {
+            anyhow::bail!("DATA_MULTILINE_END without DATA_MULTILINE_START at line {}", line_num);
+        }
         "BODY_END" => {
             Ok(HttpCommand::BodyEnd)
         }
@@ -751,6 +1916,12 @@ fn parse_http_command(line: &str, line_num: usize) -> Result<HttpCommand> {
     }
 }
 
+/// Index of the `BODY_END` closing a `BODY_START TYPE RAW VERBATIM` body that starts at `lines[start]`.
+/// Nothing else in the body is looked at, so it may hold `DATA`, `HTTP_END` or `#` lines.
+pub fn verbatim_body_end(lines: &[&str], start: usize) -> Option<usize> {
+    lines.iter().skip(start).position(|line| line.trim() == "BODY_END").map(|offset| start + offset)
+}
+
 fn build_http_request_from_commands(
     mut request: HttpRequest,
     commands: &[HttpCommand],
@@ -758,17 +1929,26 @@ fn build_http_request_from_commands(
     for cmd in commands {
         match cmd {
             HttpCommand::Param { key, value } => {
-                request.params.push((key.clone(), value.clone()));
+                request.params.push((key.clone(), HttpValue::Literal(value.clone())));
+            }
+            HttpCommand::ParamVar { key, var_name } => {
+                request.params.push((key.clone(), HttpValue::Var(var_name.clone())));
             }
             HttpCommand::Header { key, value } => {
-                request.headers.push((key.clone(), value.clone()));
+                request.headers.push((key.clone(), HttpValue::Literal(value.clone())));
             }
-            HttpCommand::BodyStart { body_type } => {
+            HttpCommand::HeaderVar { key, var_name } => {
+                request.headers.push((key.clone(), HttpValue::Var(var_name.clone())));
+            }
+            HttpCommand::BodyStart { body_type, .. } => {
                 request.body_type = Some(body_type.clone());
             }
             HttpCommand::Data { content } => {
                 request.body_data.push(content.clone());
             }
+            HttpCommand::DataMultiline { lines } => {
+                request.body_data.push(lines.join("\n"));
+            }
             HttpCommand::BodyEnd => {
                 // No-op, just marks the end
             }
@@ -786,23 +1966,29 @@ fn handle_output_line(
     current_output: &mut Option<OutputBlock>,
     output_blocks: &mut Vec<OutputBlock>,
 ) -> Result<()> {
-    match line {
-        "OUTPUT_SUCCESS" => {
-            if current_output.is_some() {
-                anyhow::bail!("OUTPUT_SUCCESS without closing previous block at line {}", line_num);
-            }
-            *current_output = Some(OutputBlock {
-                status: OutputStatus::Success,
-                commands: Vec::new(),
-            });
-            Ok(())
+    if let Some(rest) = line.strip_prefix("OUTPUT_SUCCESS").filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        if current_output.is_some() {
+            anyhow::bail!("OUTPUT_SUCCESS without closing previous block at line {}", line_num);
+        }
+        let name = rest.trim();
+        if name.split_whitespace().count() > 1 {
+            anyhow::bail!("OUTPUT_SUCCESS takes a single label name at line {}", line_num);
         }
+        *current_output = Some(OutputBlock {
+            status: OutputStatus::Success,
+            output_label_name: (!name.is_empty()).then(|| name.to_string()),
+            commands: Vec::new(),
+        });
+        return Ok(());
+    }
+    match line {
         "OUTPUT_ERROR" => {
             if current_output.is_some() {
                 anyhow::bail!("OUTPUT_ERROR without closing previous block at line {}", line_num);
             }
             *current_output = Some(OutputBlock {
                 status: OutputStatus::Error,
+                output_label_name: None,
                 commands: Vec::new(),
             });
             Ok(())
@@ -834,6 +2020,17 @@ fn parse_output_command(line: &str, line_num: usize) -> Result<OutputCommand> {
         }
         return Ok(OutputCommand::JsonOutput(var.to_string()));
     }
+    if let Some(rest) = trimmed.strip_prefix("RETURN IF ") {
+        let (cond_str, branches) = rest.split_once(" THEN ")
+            .ok_or_else(|| anyhow::anyhow!("RETURN IF requires THEN at line {}", line_num))?;
+        let (then_str, else_str) = branches.rsplit_once(" ELSE ")
+            .ok_or_else(|| anyhow::anyhow!("RETURN IF requires ELSE at line {}", line_num))?;
+        return Ok(OutputCommand::ConditionalReturn {
+            condition: Box::new(parse_condition(cond_str, line_num)?),
+            then_template: strip_quotes(then_str),
+            else_template: strip_quotes(else_str),
+        });
+    }
     if let Some(rest) = trimmed.strip_prefix("RETURN") {
         let argument = rest.trim();
         if argument.is_empty() {
@@ -902,6 +2099,14 @@ fn parse_function_args(args_str: &str) -> Result<Vec<String>> {
     Ok(args)
 }
 
+/// `line` up to an unquoted `#`, trailing whitespace removed
+fn strip_inline_comment(line: &str) -> &str {
+    match find_comment_position(line) {
+        Some(pos) => line[..pos].trim_end(),
+        None => line,
+    }
+}
+
 fn find_comment_position(text: &str) -> Option<usize> {
     let mut in_quotes = false;
     let mut quote_char = '\0';
@@ -923,12 +2128,39 @@ fn find_comment_position(text: &str) -> Option<usize> {
                     return Some(pos);
                 }
             }
+            // An escaped quote doesn't end the string
+            '\\' if in_quotes => {
+                chars.next();
+            }
             _ => {}
         }
     }
     None
 }
 
+/// Hex literal of `WRITE_BYTES`/`EXPECT_MAGIC`, optionally quoted: `FEFD`, `"FE FD 09 00_00_00_01"`, `0xFE 0xFD`
+fn parse_hex_literal(literal: &str, line_num: usize) -> Result<Vec<u8>> {
+    let bytes = decode_hex_groups(&strip_quotes(literal))
+        .map_err(|e| anyhow::anyhow!("Invalid hex literal {} at line {}: {}", literal, line_num, e))?;
+    if bytes.is_empty() {
+        anyhow::bail!("Empty hex literal {} at line {}", literal, line_num);
+    }
+    Ok(bytes)
+}
+
+/// Decode hex digits split into groups by whitespace or `_`; `0x` prefixes are removed anywhere
+/// in a group, so both `0xFE 0xFD` and `0xFE0xFD` work
+fn decode_hex_groups(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for group in text.split(|c: char| c.is_whitespace() || c == '_').filter(|group| !group.is_empty()) {
+        let digits = group.replace("0x", "").replace("0X", "");
+        let decoded = hex::decode(digits)
+            .map_err(|e| anyhow::anyhow!("'{}': {}", group, e))?;
+        bytes.extend_from_slice(&decoded);
+    }
+    Ok(bytes)
+}
+
 fn parse_byte_value(s: Option<&str>) -> Result<u8> {
     let s = s.ok_or_else(|| anyhow::anyhow!("Missing value"))?;
     if s.starts_with("0x") || s.starts_with("0X") {
@@ -962,6 +2194,35 @@ fn parse_int_value(s: Option<&str>) -> Result<u32> {
     }
 }
 
+/// String argument of a WRITE_STRING* command: a literal or a variable resolved at build time
+enum StringOperand {
+    Literal(String),
+    Var(String),
+}
+
+/// Parse `"quoted text"`, `unquoted_text` or a variable name
+fn parse_string_operand(rest: &str) -> Result<StringOperand> {
+    // Handle quoted strings with spaces by finding the closing quote
+    if let Some(quote_start) = rest.find('"') {
+        // Find the closing quote after the opening one
+        if let Some(quote_end) = rest[quote_start + 1..].find('"') {
+            let text = rest[quote_start + 1..quote_start + 1 + quote_end].to_string();
+            Ok(StringOperand::Literal(text))
+        } else {
+            // Fallback: treat rest as unquoted string
+            Ok(StringOperand::Literal(parse_string_value(Some(rest))?))
+        }
+    } else {
+        // No quotes, check if it's a variable name
+        let token = rest.trim();
+        if is_variable_name(token) {
+            Ok(StringOperand::Var(token.to_string()))
+        } else {
+            Ok(StringOperand::Literal(parse_string_value(Some(token))?))
+        }
+    }
+}
+
 fn parse_string_value(s: Option<&str>) -> Result<String> {
     let s = s.ok_or_else(|| anyhow::anyhow!("Missing value"))?;
     // Remove quotes if present
@@ -1001,6 +2262,15 @@ fn parse_code_command(line: &str, line_num: usize) -> Result<CodeCommand> {
     // Handle indented lines (for loops, if statements) - they're handled by the caller
     // This function handles single-line commands
     
+    // ELSE branches are consumed together with their IF
+    if trimmed == "ELSE:" || trimmed.starts_with("ELSE ") {
+        anyhow::bail!("ELSE without a matching IF at line {}", line_num);
+    }
+    
+    if trimmed.starts_with("DEF ") {
+        anyhow::bail!("DEF is only allowed at the top level of a CODE block at line {}", line_num);
+    }
+    
     // Check for control flow statements that end with ':'
     if trimmed.ends_with(':') {
         // This is a control flow statement start - will be handled by multi-line parser
@@ -1033,6 +2303,12 @@ fn parse_code_command(line: &str, line_num: usize) -> Result<CodeCommand> {
             _ => anyhow::bail!("Unknown variable type: {} at line {}", var_type_str, line_num),
         };
         
+        // TYPE VAR_NAME = CALL function(args)
+        if let Some(call) = value_str.trim().strip_prefix("CALL ") {
+            let (name, args) = parse_call(call, line_num)?;
+            return Ok(CodeCommand::Call { name, args, result: Some(var_name), declared: true, line: line_num });
+        }
+        
         // Check if value_str is a SPLIT function call
         if value_str.trim().starts_with("SPLIT(") && value_str.trim().ends_with(')') {
             let func_call = value_str.trim().strip_prefix("SPLIT").unwrap_or("").trim();
@@ -1090,6 +2366,11 @@ fn parse_code_command(line: &str, line_num: usize) -> Result<CodeCommand> {
             value_str = value_str[..comment_pos].trim().to_string();
         }
         
+        if let Some(call) = value_str.trim().strip_prefix("CALL ") {
+            let (name, args) = parse_call(call, line_num)?;
+            return Ok(CodeCommand::Call { name, args, result: Some(var_name), declared: false, line: line_num });
+        }
+        
         let value = parse_expression(&value_str, line_num)?;
         return Ok(CodeCommand::AssignVar {
             name: var_name,
@@ -1153,6 +2434,61 @@ fn parse_code_command(line: &str, line_num: usize) -> Result<CodeCommand> {
         return Ok(CodeCommand::Break);
     }
     
+    // CONTINUE command
+    if parts[0] == "CONTINUE" {
+        return Ok(CodeCommand::Continue);
+    }
+    
+    // RETURN_EARLY command
+    if parts[0] == "RETURN_EARLY" {
+        return Ok(CodeCommand::ReturnEarly);
+    }
+    
+    // CALL function(args)
+    if let Some(call) = trimmed.strip_prefix("CALL ") {
+        let call = match find_comment_position(call) {
+            Some(pos) => &call[..pos],
+            None => call,
+        };
+        let (name, args) = parse_call(call, line_num)?;
+        return Ok(CodeCommand::Call { name, args, result: None, declared: false, line: line_num });
+    }
+    
+    // RETURN [expr] inside a DEF
+    if parts[0] == "RETURN" {
+        let value = trimmed.strip_prefix("RETURN").unwrap_or("").trim();
+        let value = match find_comment_position(value) {
+            Some(pos) => value[..pos].trim(),
+            None => value,
+        };
+        return Ok(CodeCommand::Return(
+            (!value.is_empty()).then(|| parse_expression(value, line_num)).transpose()?,
+        ));
+    }
+    
+    // EXPECT_VAR var op value ["message"]
+    if parts[0] == "EXPECT_VAR" {
+        return Ok(CodeCommand::ExpectVar(parse_expect_var(trimmed, line_num)?));
+    }
+    
+    // ASSERT condition ["message"]
+    if parts[0] == "ASSERT" {
+        return Ok(CodeCommand::Assert(parse_assert(trimmed, line_num)?));
+    }
+    
+    // FAIL "message"
+    if parts[0] == "FAIL" {
+        let message = trimmed.strip_prefix("FAIL").unwrap_or("").trim();
+        let message = match find_comment_position(message) {
+            Some(pos) => message[..pos].trim(),
+            None => message,
+        };
+        if message.len() < 2 || !message.starts_with('"') || !message.ends_with('"') {
+            anyhow::bail!("FAIL requires a quoted message: FAIL \"message\" at line {}", line_num);
+        }
+        return Ok(CodeCommand::Fail { message: strip_quotes(message), line: line_num });
+    }
+    
     // Try to parse as packet/response command (for nested execution)
     if let Ok(packet_cmd) = parse_packet_command(line, line_num) {
         return Ok(CodeCommand::ExecutePacketCommand(packet_cmd));
@@ -1164,6 +2500,142 @@ fn parse_code_command(line: &str, line_num: usize) -> Result<CodeCommand> {
     anyhow::bail!("Unknown code command: {} at line {}", parts[0], line_num);
 }
 
+/// Parse `EXPECT_VAR <var> <op> <value-or-var> ["message"]`, where the value may be a quoted string
+fn parse_expect_var(line: &str, line_num: usize) -> Result<Assertion> {
+    let usage = || anyhow::anyhow!(
+        "EXPECT_VAR requires a variable, an operator and a value: EXPECT_VAR var == value [\"message\"] at line {}",
+        line_num
+    );
+    let rest = line.trim().strip_prefix("EXPECT_VAR").ok_or_else(usage)?;
+    let rest = match find_comment_position(rest) {
+        Some(pos) => &rest[..pos],
+        None => rest,
+    };
+    let mut tokens = rest.split_whitespace();
+    let var = tokens.next().ok_or_else(usage)?;
+    let op = tokens.next().ok_or_else(usage)?;
+    let after_op = rest.trim_start()[var.len()..].trim_start()[op.len()..].trim_start();
+    // The value is one token or a quoted string that may contain spaces
+    let value_len = match after_op.chars().next() {
+        Some(quote @ ('"' | '\'')) => after_op[1..].find(quote).map(|end| end + 2)
+            .ok_or_else(|| anyhow::anyhow!("Unterminated string in EXPECT_VAR at line {}", line_num))?,
+        Some(_) => after_op.find(char::is_whitespace).unwrap_or(after_op.len()),
+        None => return Err(usage()),
+    };
+    let value = &after_op[..value_len];
+    let message = after_op[value_len..].trim();
+    let message = if message.is_empty() {
+        None
+    } else if message.len() >= 2 && message.starts_with('"') && message.ends_with('"') {
+        Some(strip_quotes(message))
+    } else {
+        anyhow::bail!("EXPECT_VAR message must be a quoted string at line {}", line_num);
+    };
+
+    let left = parse_expression(var, line_num)?;
+    let right = parse_expression(value, line_num)?;
+    let condition = match op {
+        "==" => Condition::Equals(left, right),
+        "!=" => Condition::NotEquals(left, right),
+        ">" => Condition::GreaterThan(left, right),
+        "<" => Condition::LessThan(left, right),
+        ">=" => Condition::GreaterOrEqual(left, right),
+        "<=" => Condition::LessOrEqual(left, right),
+        "CONTAINS" => Condition::Contains(left, right),
+        _ => anyhow::bail!("Unknown EXPECT_VAR operator '{}' at line {} (use ==, !=, >, <, >=, <= or CONTAINS)", op, line_num),
+    };
+    Ok(Assertion {
+        kind: AssertionKind::Expectation,
+        condition,
+        text: format!("EXPECT_VAR {} {} {}", var, op, value),
+        message,
+        line: line_num,
+    })
+}
+
+/// Parse `ASSERT <condition> ["message"]`. A trailing quoted string is the message unless it's the
+/// right-hand side of the condition, as in `ASSERT motd CONTAINS "Survival"`.
+fn parse_assert(line: &str, line_num: usize) -> Result<Assertion> {
+    let rest = line.trim().strip_prefix("ASSERT").unwrap_or("").trim();
+    let rest = match find_comment_position(rest) {
+        Some(pos) => rest[..pos].trim(),
+        None => rest,
+    };
+    if rest.is_empty() {
+        anyhow::bail!("ASSERT requires a condition: ASSERT max_players > 0 [\"message\"] at line {}", line_num);
+    }
+    let mut cond_str = rest;
+    let mut message = None;
+    if rest.len() >= 2 && rest.ends_with('"') {
+        if let Some(start) = rest[..rest.len() - 1].rfind('"') {
+            let before = rest[..start].trim_end();
+            let ends_with_operator = before.rsplit(char::is_whitespace).next()
+                .is_some_and(|last| matches!(last, "==" | "!=" | ">" | "<" | ">=" | "<=" | "CONTAINS"));
+            if !before.is_empty() && !ends_with_operator {
+                cond_str = before;
+                message = Some(rest[start + 1..rest.len() - 1].to_string());
+            }
+        }
+    }
+    Ok(Assertion {
+        kind: AssertionKind::HealthCheck,
+        condition: parse_condition(cond_str, line_num)?,
+        text: format!("ASSERT {}", cond_str),
+        message,
+        line: line_num,
+    })
+}
+
+/// `DEF name(param, ...):` and its indented body; returns the name, the function and the lines consumed
+fn parse_function(lines: &[&str], start_line: usize) -> Result<(String, ScriptFunction, usize)> {
+    let line = lines[start_line].trim();
+    let signature = line.strip_prefix("DEF ")
+        .and_then(|rest| rest.trim().strip_suffix("):"))
+        .and_then(|rest| rest.split_once('('))
+        .ok_or_else(|| anyhow::anyhow!("Invalid DEF syntax: DEF name(param, ...): at line {}", start_line + 1))?;
+    let name = signature.0.trim().to_string();
+    if !is_variable_name(&name) {
+        anyhow::bail!("Invalid function name '{}' at line {}", name, start_line + 1);
+    }
+    let mut params: Vec<String> = Vec::new();
+    if !signature.1.trim().is_empty() {
+        for param in signature.1.split(',').map(str::trim) {
+            if !is_variable_name(param) {
+                anyhow::bail!("Invalid parameter '{}' of function '{}' at line {}", param, name, start_line + 1);
+            }
+            if params.iter().any(|existing| existing == param) {
+                anyhow::bail!("Duplicate parameter '{}' of function '{}' at line {}", param, name, start_line + 1);
+            }
+            params.push(param.to_string());
+        }
+    }
+    let (body, lines_consumed) = parse_indented_body(lines, start_line + 1, 2)?;
+    if body.is_empty() {
+        anyhow::bail!("Function '{}' has an empty body at line {}", name, start_line + 1);
+    }
+    Ok((name, ScriptFunction { params, body }, lines_consumed + 1))
+}
+
+/// `name(expr, ...)` after `CALL`
+fn parse_call(call: &str, line_num: usize) -> Result<(String, Vec<Expression>)> {
+    let (name, args) = call.trim()
+        .strip_suffix(')')
+        .and_then(|call| call.split_once('('))
+        .ok_or_else(|| anyhow::anyhow!("Invalid CALL syntax: CALL name(arg, ...) at line {}", line_num))?;
+    let name = name.trim();
+    if !is_variable_name(name) {
+        anyhow::bail!("Invalid function name '{}' at line {}", name, line_num);
+    }
+    let args = if args.trim().is_empty() {
+        Vec::new()
+    } else {
+        split_top_level_args(args).into_iter()
+            .map(|arg| parse_expression(arg, line_num))
+            .collect::<Result<_>>()?
+    };
+    Ok((name.to_string(), args))
+}
+
 fn parse_control_flow(
     lines: &[&str],
     start_line: usize,
@@ -1177,6 +2649,25 @@ fn parse_control_flow(
         if let Some(in_pos) = rest.find(" IN ") {
             let var_name = rest[..in_pos].trim().to_string();
             let array_part = rest[in_pos + 4..].trim();
+            // FOR var_name IN RANGE(start, end):
+            if let Some(args) = array_part.strip_prefix("RANGE(").and_then(|a| a.strip_suffix("):")) {
+                let (start_str, end_str) = split_range_args(args).ok_or_else(|| anyhow::anyhow!(
+                    "Invalid RANGE syntax: RANGE(start, end) at line {}", start_line + 1
+                ))?;
+                let range_start = parse_expression(start_str, start_line + 1)?;
+                let range_end = parse_expression(end_str, start_line + 1)?;
+                
+                let body_indent = base_indent + 2; // Assume 2-space indentation
+                let (body, lines_consumed) = parse_indented_body(lines, start_line + 1, body_indent)?;
+                
+                return Ok((CodeCommand::ForLoop {
+                    var_name,
+                    range_start,
+                    range_end,
+                    body,
+                    line: start_line + 1,
+                }, lines_consumed + 1));
+            }
             if array_part.ends_with(':') {
                 let array_name = array_part[..array_part.len() - 1].trim().to_string();
                 
@@ -1191,7 +2682,23 @@ fn parse_control_flow(
                 }, lines_consumed + 1));
             }
         }
-        anyhow::bail!("Invalid FOR syntax: FOR var_name IN array_name: at line {}", start_line + 1);
+        anyhow::bail!("Invalid FOR syntax: FOR var_name IN array_name: or FOR var_name IN RANGE(start, end): at line {}", start_line + 1);
+    } else if line.starts_with("WHILE ") {
+        // WHILE condition:
+        let rest = line.strip_prefix("WHILE ").unwrap_or("").trim();
+        if let Some(cond_str) = rest.strip_suffix(':') {
+            let condition = parse_condition(cond_str.trim(), start_line + 1)?;
+            
+            let body_indent = base_indent + 2; // Assume 2-space indentation
+            let (body, lines_consumed) = parse_indented_body(lines, start_line + 1, body_indent)?;
+            
+            return Ok((CodeCommand::WhileLoop {
+                condition,
+                body,
+                line: start_line + 1,
+            }, lines_consumed + 1));
+        }
+        anyhow::bail!("Invalid WHILE syntax: WHILE condition: at line {}", start_line + 1);
     } else if line.starts_with("IF ") {
         // IF condition:
         let rest = line.strip_prefix("IF ").unwrap_or("").trim();
@@ -1202,13 +2709,38 @@ fn parse_control_flow(
             // Parse the indented body
             let body_indent = base_indent + 2; // Assume 2-space indentation
             let (body, lines_consumed) = parse_indented_body(lines, start_line + 1, body_indent)?;
+            let mut consumed = lines_consumed + 1;
+            
+            // ELSE IF / ELSE branches sit at the same indentation as the IF
+            let mut else_if = Vec::new();
+            let mut else_body = None;
+            while let Some(branch_line) = next_code_line(lines, start_line + consumed) {
+                let raw = lines[branch_line];
+                let branch = raw.trim();
+                if raw.len() - raw.trim_start().len() != base_indent {
+                    break;
+                }
+                if let Some(cond_str) = branch.strip_prefix("ELSE IF ").and_then(|c| c.strip_suffix(':')) {
+                    let condition = parse_condition(cond_str.trim(), branch_line + 1)?;
+                    let (branch_body, branch_consumed) = parse_indented_body(lines, branch_line + 1, body_indent)?;
+                    else_if.push((condition, branch_body));
+                    consumed = branch_line + 1 + branch_consumed - start_line;
+                } else if branch == "ELSE:" {
+                    let (branch_body, branch_consumed) = parse_indented_body(lines, branch_line + 1, body_indent)?;
+                    else_body = Some(branch_body);
+                    consumed = branch_line + 1 + branch_consumed - start_line;
+                    break;
+                } else {
+                    break;
+                }
+            }
             
             return Ok((CodeCommand::IfStatement {
                 condition,
                 body,
-                else_if: Vec::new(),
-                else_body: None,
-            }, lines_consumed + 1));
+                else_if,
+                else_body,
+            }, consumed));
         }
         anyhow::bail!("Invalid IF syntax: IF condition: at line {}", start_line + 1);
     }
@@ -1216,11 +2748,50 @@ fn parse_control_flow(
     anyhow::bail!("Not a control flow statement at line {}", start_line + 1);
 }
 
+/// Split `start, end` of a RANGE on the comma between them
+fn split_range_args(args: &str) -> Option<(&str, &str)> {
+    match split_top_level_args(args)[..] {
+        [start, end] if !start.is_empty() && !end.is_empty() => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// Split comma-separated arguments, ignoring commas inside quotes, parentheses and brackets
+fn split_top_level_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut part_start = 0;
+    for (i, c) in args.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('(' | '[', None) => depth += 1,
+            (')' | ']', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                parts.push(args[part_start..i].trim());
+                part_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[part_start..].trim());
+    parts
+}
+
+/// Index of the next line that is not blank or a comment
+fn next_code_line(lines: &[&str], from: usize) -> Option<usize> {
+    (from..lines.len()).find(|&i| {
+        let trimmed = lines[i].trim();
+        !trimmed.is_empty() && !trimmed.starts_with('#')
+    })
+}
+
 fn parse_indented_body(
     lines: &[&str],
     start_line: usize,
     expected_indent: usize,
-) -> Result<(Vec<CodeCommand>, usize)> {
+) -> Result<(Vec<Sourced<CodeCommand>>, usize)> {
     let mut body = Vec::new();
     let mut line_idx = start_line;
     
@@ -1245,13 +2816,13 @@ fn parse_indented_body(
         let line_content = line[expected_indent..].trim();
         
         // Check if it's a control flow statement
-        if line_content.ends_with(':') && (line_content.starts_with("FOR ") || line_content.starts_with("IF ")) {
+        if line_content.ends_with(':') && (line_content.starts_with("FOR ") || line_content.starts_with("WHILE ") || line_content.starts_with("IF ")) {
             let (cmd, consumed) = parse_control_flow(lines, line_idx, expected_indent)?;
-            body.push(cmd);
+            body.push(Sourced::new(cmd, line_idx + 1));
             line_idx += consumed;
         } else {
             // Regular command
-            body.push(parse_code_command(line_content, line_idx + 1)?);
+            body.push(Sourced::new(parse_code_command(line_content, line_idx + 1)?, line_idx + 1));
             line_idx += 1;
         }
     }
@@ -1265,6 +2836,10 @@ fn parse_expression(expr: &str, line_num: usize) -> Result<Expression> {
     // Check if it's a quoted string
     if expr.starts_with('"') && expr.ends_with('"') {
         let value = strip_quotes(expr);
+        if has_placeholders(&value) {
+            check_placeholders(&value, line_num)?;
+            return Ok(Expression::Template(value));
+        }
         return Ok(Expression::Literal(JsonValue::String(value)));
     }
     
@@ -1349,6 +2924,11 @@ fn parse_expression(expr: &str, line_num: usize) -> Result<Expression> {
         }
     }
     
+    // Check if it's a JSON path: var_name.field[index]...
+    if let Some(path) = parse_path_expression(expr, line_num)? {
+        return Ok(path);
+    }
+    
     // Check if it's an array index: var_name[index]
     // This must come after array literal check to avoid conflicts
     if let Some(bracket_pos) = expr.find('[') {
@@ -1379,18 +2959,81 @@ fn parse_expression(expr: &str, line_num: usize) -> Result<Expression> {
             let args: Vec<Expression> = if args_str.is_empty() {
                 Vec::new()
             } else {
-                args_str.split(',').map(|a| parse_expression(a.trim(), line_num)).collect::<Result<_>>()?
+                split_top_level_args(args_str).into_iter().map(|a| parse_expression(a, line_num)).collect::<Result<_>>()?
             };
-            return Ok(Expression::FunctionCall {
-                name: func_name.trim().to_string(),
-                args,
-            });
+            let name = func_name.trim().to_string();
+            if name == "REGEX_MATCH" || name == "REGEX_EXTRACT" {
+                let regex = match args.get(1) {
+                    Some(Expression::Literal(JsonValue::String(pattern))) => Some(compile_regex(pattern, &name, line_num)?),
+                    _ => None,
+                };
+                return Ok(Expression::RegexCall { name, args, regex, line: line_num });
+            }
+            return Ok(Expression::FunctionCall { name, args });
         }
     }
     
     anyhow::bail!("Invalid expression: {} at line {}", expr, line_num);
 }
 
+/// Parses `var.field`, `var.list[0].field` and similar paths. Returns None for anything that
+/// isn't a path, including a plain `var[index]` which stays an ArrayIndex.
+fn parse_path_expression(expr: &str, line_num: usize) -> Result<Option<Expression>> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_';
+    let root_len = expr.find(|c: char| !is_name_char(c)).unwrap_or(expr.len());
+    if root_len == 0 || root_len == expr.len() {
+        return Ok(None);
+    }
+    
+    let mut segments = Vec::new();
+    let mut has_field = false;
+    let mut rest = &expr[root_len..];
+    while !rest.is_empty() {
+        if let Some(after_dot) = rest.strip_prefix('.') {
+            let field_len = after_dot.find(|c: char| !is_name_char(c)).unwrap_or(after_dot.len());
+            if field_len == 0 {
+                return Ok(None);
+            }
+            segments.push(PathSegment::Field(after_dot[..field_len].to_string()));
+            has_field = true;
+            rest = &after_dot[field_len..];
+        } else if rest.starts_with('[') {
+            // Find the matching bracket so indexes can themselves contain brackets
+            let mut depth = 0;
+            let mut close = None;
+            for (i, ch) in rest.char_indices() {
+                match ch {
+                    '[' => depth += 1,
+                    ']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            close = Some(i);
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            let Some(close) = close else {
+                return Ok(None);
+            };
+            let index_expr = parse_expression(&rest[1..close], line_num)?;
+            segments.push(PathSegment::Index(Box::new(index_expr)));
+            rest = &rest[close + 1..];
+        } else {
+            return Ok(None);
+        }
+    }
+    
+    if !has_field && segments.len() < 2 {
+        return Ok(None);
+    }
+    Ok(Some(Expression::Path {
+        var_name: expr[..root_len].to_string(),
+        segments,
+    }))
+}
+
 fn parse_condition(cond_str: &str, line_num: usize) -> Result<Condition> {
     let cond_str = cond_str.trim();
     
@@ -1461,9 +3104,35 @@ fn parse_condition(cond_str: &str, line_num: usize) -> Result<Condition> {
         }
     }
     
+    // A bare function call, e.g. REGEX_MATCH(status, "^ok")
+    if cond_str.ends_with(')') {
+        if let Ok(expr) = parse_expression(cond_str, line_num) {
+            return Ok(Condition::IsTrue(expr));
+        }
+    }
+    
     anyhow::bail!("Invalid condition: {} at line {}", cond_str, line_num);
 }
 
+/// Longest pattern accepted by REGEX_MATCH / REGEX_EXTRACT
+const MAX_REGEX_PATTERN_LEN: usize = 1024;
+/// Compiled size limit for a pattern; the regex crate matches in linear time, this bounds memory
+const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+
+fn compile_regex(pattern: &str, func_name: &str, line_num: usize) -> Result<Regex> {
+    if pattern.len() > MAX_REGEX_PATTERN_LEN {
+        anyhow::bail!(
+            "{} pattern is {} characters long, limit is {} at line {}",
+            func_name, pattern.len(), MAX_REGEX_PATTERN_LEN, line_num
+        );
+    }
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Invalid {} pattern at line {}: {}", func_name, line_num, e))
+}
+
 /// Helper function to resolve a variable value from the variables map
 fn resolve_var_value(vars: &IndexMap<String, JsonValue>, var_name: &str) -> Result<JsonValue> {
     vars.get(var_name)
@@ -1471,81 +3140,288 @@ fn resolve_var_value(vars: &IndexMap<String, JsonValue>, var_name: &str) -> Resu
         .cloned()
 }
 
-/// Helper function to get a numeric value (u32) from a JSON value
-fn get_u32_from_json(value: &JsonValue) -> Result<u32> {
-    if let Some(n) = value.as_u64() {
-        Ok(n as u32)
-    } else if let Some(n) = value.as_i64() {
-        Ok(n as u32)
-    } else if let Some(s) = value.as_str() {
-        // Try parsing as hex or decimal
-        if s.starts_with("0x") || s.starts_with("0X") {
-            u32::from_str_radix(&s[2..], 16)
-                .with_context(|| format!("Invalid hex string: {}", s))
-        } else {
-            s.parse::<u32>()
-                .with_context(|| format!("Invalid number string: {}", s))
+/// How execution continues after a code command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ControlFlow {
+    Next,
+    /// `BREAK`: leave the innermost loop
+    Break,
+    /// `CONTINUE`: go on with the next iteration of the innermost loop
+    Continue,
+    /// `RETURN_EARLY`: stop the current code block without failing it
+    ReturnEarly,
+    /// `RETURN`: leave the current function; the value is in [`CallStack::returned`]
+    Return,
+}
+
+/// Functions of the script and how deep the running `CALL`s are nested
+struct CallStack<'a> {
+    functions: &'a IndexMap<String, ScriptFunction>,
+    depth: usize,
+    /// Value of the last `RETURN`, taken by the `CALL` that ran it
+    returned: Option<JsonValue>,
+}
+
+/// Name the function an error happened in, once, keeping the line inside the function
+fn in_function(e: anyhow::Error, name: &str) -> anyhow::Error {
+    match e.downcast::<ScriptLineError>() {
+        Ok(failed) if !failed.message.starts_with("in function ") => ScriptLineError {
+            line: failed.line,
+            message: format!("in function '{}': {}", name, failed.message),
+        }.into(),
+        Ok(failed) => failed.into(),
+        Err(e) => e,
+    }
+}
+
+/// A `FOR ... IN RANGE(...)` or `WHILE` loop would run more iterations than allowed
+#[derive(Debug)]
+pub struct LoopLimitExceeded {
+    pub line: usize,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for LoopLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Loop at line {} exceeded the limit of {} iterations", self.line, self.limit)
+    }
+}
+
+impl std::error::Error for LoopLimitExceeded {}
+
+/// A command failed while building a packet, reading a response or running a code block
+#[derive(Debug)]
+pub struct ScriptLineError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScriptLineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)
+    }
+}
+
+impl std::error::Error for ScriptLineError {}
+
+/// A script that doesn't parse. `line` is the line of the server's own script the error is on;
+/// for snippet lines it is the `INCLUDE` they came through.
+#[derive(Debug)]
+pub struct ScriptParseError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScriptParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ScriptParseError {}
+
+/// Tag an execution error with the script line of the failing command, unless it already names one
+fn at_line(e: anyhow::Error, line: Option<usize>) -> anyhow::Error {
+    match line {
+        Some(line) if !e.is::<ScriptLineError>() && !e.is::<LoopLimitExceeded>() && !e.is::<AssertionFailed>() => {
+            ScriptLineError { line, message: e.to_string() }.into()
         }
-    } else {
-        anyhow::bail!("Cannot convert value to u32: {:?}", value)
+        _ => e,
     }
 }
 
-/// Helper function to get a numeric value (u16) from a JSON value
-fn get_u16_from_json(value: &JsonValue) -> Result<u16> {
-    if let Some(n) = value.as_u64() {
-        Ok(n as u16)
-    } else if let Some(n) = value.as_i64() {
-        Ok(n as u16)
-    } else if let Some(s) = value.as_str() {
-        if s.starts_with("0x") || s.starts_with("0X") {
-            u16::from_str_radix(&s[2..], 16)
-                .with_context(|| format!("Invalid hex string: {}", s))
-        } else {
-            s.parse::<u16>()
-                .with_context(|| format!("Invalid number string: {}", s))
+/// Script line a parse or execution error was tagged with, if any
+pub fn error_line(e: &anyhow::Error) -> Option<usize> {
+    if let Some(failed) = e.downcast_ref::<ScriptParseError>() {
+        return failed.line;
+    }
+    e.downcast_ref::<ScriptLineError>().map(|failed| failed.line)
+}
+
+/// An `EXPECT_VAR` or `ASSERT` that didn't hold, or a `FAIL` that was reached
+#[derive(Debug)]
+pub struct AssertionFailed {
+    pub kind: AssertionKind,
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AssertionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AssertionFailed {}
+
+impl Assertion {
+    /// Evaluate the condition; a false condition or one that can't be evaluated fails the assertion
+    pub fn check(
+        &self,
+        parsed_vars: &IndexMap<String, JsonValue>,
+        code_vars: &IndexMap<String, JsonValue>,
+    ) -> std::result::Result<(), AssertionFailed> {
+        let reason = match evaluate_condition(&self.condition, parsed_vars, code_vars) {
+            Ok(true) => return Ok(()),
+            Ok(false) => format!("{} failed", self.text),
+            Err(e) => format!("{} could not be evaluated: {}", self.text, e),
+        };
+        Err(AssertionFailed {
+            kind: self.kind,
+            line: self.line,
+            message: self.message.clone().unwrap_or(reason),
+        })
+    }
+}
+
+/// Check the `EXPECT_VAR` assertions of a response against the variables parsed so far
+pub fn check_response_assertions(
+    response_commands: &[Sourced<ResponseCommand>],
+    parsed_vars: &IndexMap<String, JsonValue>,
+) -> std::result::Result<(), AssertionFailed> {
+    for sourced in response_commands {
+        if let ResponseCommand::ExpectVar(assertion) = &sourced.command {
+            assertion.check(parsed_vars, &IndexMap::new())?;
         }
-    } else {
-        anyhow::bail!("Cannot convert value to u16: {:?}", value)
     }
+    Ok(())
 }
 
-/// Helper function to get a numeric value (u8) from a JSON value
-fn get_u8_from_json(value: &JsonValue) -> Result<u8> {
-    if let Some(n) = value.as_u64() {
-        Ok(n as u8)
-    } else if let Some(n) = value.as_i64() {
-        Ok(n as u8)
-    } else if let Some(s) = value.as_str() {
-        if s.starts_with("0x") || s.starts_with("0X") {
-            u8::from_str_radix(&s[2..], 16)
-                .with_context(|| format!("Invalid hex string: {}", s))
-        } else {
-            s.parse::<u8>()
-                .with_context(|| format!("Invalid number string: {}", s))
+/// Run commands in order until one changes the control flow
+fn execute_code_body(
+    body: &[Sourced<CodeCommand>],
+    parsed_vars: &IndexMap<String, JsonValue>,
+    code_vars: &mut IndexMap<String, JsonValue>,
+    max_loop_iterations: u64,
+    shadowed_parsed: &mut Vec<String>,
+    calls: &mut CallStack,
+) -> Result<ControlFlow> {
+    for body_cmd in body {
+        let flow = execute_code_command(&body_cmd.command, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls)
+            .map_err(|e| at_line(e, Some(body_cmd.source_line)))?;
+        if flow != ControlFlow::Next {
+            return Ok(flow);
         }
-    } else {
-        anyhow::bail!("Cannot convert value to u8: {:?}", value)
     }
+    Ok(ControlFlow::Next)
+}
+
+/// Run one iteration of a loop body, returning true when the loop should stop
+fn run_loop_body(
+    body: &[Sourced<CodeCommand>],
+    parsed_vars: &IndexMap<String, JsonValue>,
+    code_vars: &mut IndexMap<String, JsonValue>,
+    max_loop_iterations: u64,
+    shadowed_parsed: &mut Vec<String>,
+    calls: &mut CallStack,
+    flow: &mut ControlFlow,
+) -> Result<bool> {
+    let flow_out = execute_code_body(body, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls)?;
+    match flow_out {
+        ControlFlow::Next | ControlFlow::Continue => Ok(false),
+        ControlFlow::Break => Ok(true),
+        ControlFlow::ReturnEarly | ControlFlow::Return => {
+            // Leave the loop and pass RETURN_EARLY / RETURN on to the enclosing block or function
+            *flow = flow_out;
+            Ok(true)
+        }
+    }
+}
+
+/// Integer value of a number or a decimal / `0x`-prefixed hex string, as done by `TO_INT`.
+/// Floats are truncated toward zero.
+fn json_to_int(value: &JsonValue) -> Result<i128> {
+    let converted = match value {
+        JsonValue::Number(n) => n.as_i64().map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from))
+            .or_else(|| n.as_f64().filter(|f| f.is_finite()).map(|f| f.trunc() as i128)),
+        JsonValue::String(s) => {
+            let s = s.trim();
+            match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                Some(hex) => i128::from_str_radix(hex, 16).ok(),
+                None => s.parse::<i128>().ok(),
+            }
+        }
+        _ => None,
+    };
+    converted.ok_or_else(|| anyhow::anyhow!("Cannot convert {} to INT", value))
+}
+
+/// Integer from a JSON value that must fit in `bits` bits, either unsigned or as a signed
+/// (two's complement) value, so both 255 and -1 are valid bytes
+fn int_bits_from_json(value: &JsonValue, bits: u32, type_name: &str) -> Result<u64> {
+    let n = json_to_int(value)?;
+    let min = -(1i128 << (bits - 1));
+    let max = (1i128 << bits) - 1;
+    if n < min || n > max {
+        anyhow::bail!("Value {} does not fit in {}", n, type_name);
+    }
+    let mask = if bits == 64 { u64::MAX } else { (1u64 << bits) - 1 };
+    Ok((n as u64) & mask)
+}
+
+fn get_i64_from_json(value: &JsonValue) -> Result<i64> {
+    let n = json_to_int(value)?;
+    i64::try_from(n).map_err(|_| anyhow::anyhow!("Value {} does not fit in a 64-bit integer", n))
+}
+
+/// Helper function to get a numeric value (u32) from a JSON value
+fn get_u32_from_json(value: &JsonValue) -> Result<u32> {
+    Ok(int_bits_from_json(value, 32, "a 32-bit integer")? as u32)
+}
+
+/// Helper function to get a numeric value (u16) from a JSON value
+fn get_u16_from_json(value: &JsonValue) -> Result<u16> {
+    Ok(int_bits_from_json(value, 16, "a 16-bit integer")? as u16)
+}
+
+/// Helper function to get a numeric value (u8) from a JSON value
+fn get_u8_from_json(value: &JsonValue) -> Result<u8> {
+    Ok(int_bits_from_json(value, 8, "a byte")? as u8)
 }
 
 /// Helper function to get a numeric value (u64) from a JSON value
 fn get_u64_from_json(value: &JsonValue) -> Result<u64> {
-    if let Some(n) = value.as_u64() {
-        Ok(n)
-    } else if let Some(n) = value.as_i64() {
-        Ok(n as u64)
-    } else if let Some(s) = value.as_str() {
-        if s.starts_with("0x") || s.starts_with("0X") {
-            u64::from_str_radix(&s[2..], 16)
-                .with_context(|| format!("Invalid hex string: {}", s))
-        } else {
-            s.parse::<u64>()
-                .with_context(|| format!("Invalid number string: {}", s))
-        }
+    int_bits_from_json(value, 64, "a 64-bit integer")
+}
+
+/// Float from a number or a decimal string, as done by `TO_FLOAT`
+fn get_f64_from_json(value: &JsonValue) -> Result<f64> {
+    let converted = match value {
+        JsonValue::Number(n) => n.as_f64(),
+        JsonValue::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    };
+    converted.ok_or_else(|| anyhow::anyhow!("Cannot convert {} to FLOAT", value))
+}
+
+/// Two's complement integer of `width` bytes (1, 2, 4 or 8) at the cursor, sign-extended to i64
+fn read_signed(response: &[u8], cursor: &mut usize, width: usize, big_endian: bool) -> Result<i64> {
+    if *cursor + width > response.len() {
+        anyhow::bail!(
+            "Insufficient data: need {} byte{}, have {}",
+            width,
+            if width == 1 { "" } else { "s" },
+            response.len() - *cursor
+        );
+    }
+    let mut bytes = [0u8; 8];
+    let field = &response[*cursor..*cursor + width];
+    if big_endian {
+        bytes[8 - width..].copy_from_slice(field);
     } else {
-        anyhow::bail!("Cannot convert value to u64: {:?}", value)
+        bytes[..width].copy_from_slice(field);
     }
+    let raw = if big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) };
+    // Shift the sign bit of the field up to bit 63, then arithmetic-shift back down
+    let unused_bits = 64 - 8 * width as u32;
+    *cursor += width;
+    Ok(((raw << unused_bits) as i64) >> unused_bits)
+}
+
+/// JSON number for a float read from a response; NaN and infinity have no JSON form and become null
+fn float_to_json(value: f64) -> JsonValue {
+    serde_json::Number::from_f64(value).map(JsonValue::Number).unwrap_or(JsonValue::Null)
 }
 
 pub fn build_packets(script: &PacketScript) -> Result<Vec<Vec<u8>>> {
@@ -1553,129 +3429,294 @@ pub fn build_packets(script: &PacketScript) -> Result<Vec<Vec<u8>>> {
 }
 
 pub fn build_packets_with_vars(script: &PacketScript, vars: &IndexMap<String, JsonValue>) -> Result<Vec<Vec<u8>>> {
+    let mut line = None;
+    build_packets_at(script, vars, &mut line).map_err(|e| at_line(e, line))
+}
+
+/// Body of [`build_packets_with_vars`], keeping `line` at the command being built
+fn build_packets_at(script: &PacketScript, vars: &IndexMap<String, JsonValue>, line: &mut Option<usize>) -> Result<Vec<Vec<u8>>> {
     let mut built_packets = Vec::new();
+    for pair in &script.pairs {
+        for packet_commands in &pair.packets {
+            built_packets.push(build_packet_at(packet_commands, vars, script.big_endian, line)?.bytes);
+        }
+    }
+    Ok(built_packets)
+}
 
-    for (_pair_idx, pair) in script.pairs.iter().enumerate() {
-        // Build all packets for this pair
-        for (_packet_in_pair_idx, packet_commands) in pair.packets.iter().enumerate() {
-            let mut packet = Vec::new();
-            let mut varint_placeholders = Vec::new();
-        let mut int_placeholders = Vec::new(); // (position, big_endian)
-
-        for (_idx, cmd) in packet_commands.iter().enumerate() {
-            match cmd {
-                PacketCommand::WriteByte(v) => {
-                    packet.push(*v);
-                }
-                PacketCommand::WriteByteVar(var_name) => {
-                    let value = get_u8_from_json(&resolve_var_value(vars, var_name)?)?;
-                    packet.push(value);
-                }
-                PacketCommand::WriteShort(v, big_endian) => {
-                    let bytes = if *big_endian {
-                        v.to_be_bytes()
-                    } else {
-                        v.to_le_bytes()
-                    };
-                    packet.extend_from_slice(&bytes);
-                }
-                PacketCommand::WriteShortVar(var_name, big_endian) => {
-                    let value = get_u16_from_json(&resolve_var_value(vars, var_name)?)?;
-                    let bytes = if *big_endian {
-                        value.to_be_bytes()
-                    } else {
-                        value.to_le_bytes()
-                    };
-                    packet.extend_from_slice(&bytes);
-                }
-                PacketCommand::WriteInt(v, big_endian) => {
-                    let bytes = if *big_endian {
-                        v.to_be_bytes()
-                    } else {
-                        v.to_le_bytes()
-                    };
-                    packet.extend_from_slice(&bytes);
-                }
-                PacketCommand::WriteIntVar(var_name, big_endian) => {
-                    let value = get_u32_from_json(&resolve_var_value(vars, var_name)?)?;
-                    let bytes = if *big_endian {
-                        value.to_be_bytes()
-                    } else {
-                        value.to_le_bytes()
-                    };
-                    packet.extend_from_slice(&bytes);
-                }
-                PacketCommand::WriteString(text, length_opt) => {
-                    if let Some(length) = length_opt {
-                        let mut bytes = text.as_bytes().to_vec();
-                        bytes.resize(*length, 0);
-                        packet.extend_from_slice(&bytes[..*length]);
-                    } else {
-                        packet.extend_from_slice(text.as_bytes());
-                        packet.push(0); // Null terminator
-                    }
-                }
-                PacketCommand::WriteStringVar(var_name, length_opt) => {
-                    let value = resolve_var_value(vars, var_name)?;
-                    let text = value.as_str()
-                        .ok_or_else(|| anyhow::anyhow!("Variable '{}' is not a string", var_name))?;
-                    if let Some(length) = length_opt {
-                        let mut bytes = text.as_bytes().to_vec();
-                        bytes.resize(*length, 0);
-                        packet.extend_from_slice(&bytes[..*length]);
-                    } else {
-                        packet.extend_from_slice(text.as_bytes());
-                        packet.push(0); // Null terminator
-                    }
-                }
-                PacketCommand::WriteBytes(bytes) => {
-                    packet.extend_from_slice(bytes);
-                }
-                PacketCommand::WriteVarInt(value) => {
-                    let encoded = encode_varint(*value);
-                    packet.extend_from_slice(&encoded);
-                }
-                PacketCommand::WriteVarIntVar(var_name) => {
-                    let value = get_u64_from_json(&resolve_var_value(vars, var_name)?)?;
-                    let encoded = encode_varint(value);
-                    packet.extend_from_slice(&encoded);
+/// A built packet and the bytes each of its commands contributed
+#[derive(Debug, Clone)]
+pub struct BuiltPacket {
+    pub bytes: Vec<u8>,
+    /// In packet order; commands inside a `COMPRESS` section are folded into the `COMPRESS` segment
+    pub segments: Vec<PacketSegment>,
+}
+
+/// Bytes written by the command on `line`, starting at `offset`
+#[derive(Debug, Clone)]
+pub struct PacketSegment {
+    pub line: usize,
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Build the packets of one pair, keeping track of which command wrote which bytes.
+/// `big_endian` is the script's `ENDIAN` default.
+pub fn build_pair_packets(pair: &PacketResponsePair, vars: &IndexMap<String, JsonValue>, big_endian: bool) -> Result<Vec<BuiltPacket>> {
+    let mut line = None;
+    pair.packets.iter()
+        .map(|packet_commands| build_packet_at(packet_commands, vars, big_endian, &mut line).map_err(|e| at_line(e, line)))
+        .collect()
+}
+
+fn build_packet_at(
+    packet_commands: &[Sourced<PacketCommand>],
+    vars: &IndexMap<String, JsonValue>,
+    default_big_endian: bool,
+    line: &mut Option<usize>,
+) -> Result<BuiltPacket> {
+    let is_big = |big_endian: &Option<bool>| big_endian.unwrap_or(default_big_endian);
+    let mut packet = Vec::new();
+    let mut segments: Vec<PacketSegment> = Vec::new();
+    let mut varint_placeholders = Vec::new(); // (position, segment)
+    let mut int_placeholders = Vec::new(); // (position, big_endian)
+    let mut crc32_placeholders = Vec::new(); // (position, big_endian)
+    let mut compress_from: Option<(usize, Compression, usize)> = None;
+
+    for (_idx, sourced) in packet_commands.iter().enumerate() {
+        let cmd = &sourced.command;
+        *line = Some(sourced.source_line);
+        if compress_from.is_some()
+            && matches!(cmd, PacketCommand::WriteVarIntLen | PacketCommand::WriteIntLen(_) | PacketCommand::WriteCrc32(_) | PacketCommand::Compress(_))
+        {
+            anyhow::bail!("PACKET_LEN, WRITE_CRC32 and COMPRESS can't follow COMPRESS in the same packet");
+        }
+        let offset = packet.len();
+        match cmd {
+            PacketCommand::WriteByte(v) => {
+                packet.push(*v);
+            }
+            PacketCommand::WriteByteVar(var_name) => {
+                let value = get_u8_from_json(&resolve_var_value(vars, var_name)?)?;
+                packet.push(value);
+            }
+            PacketCommand::WriteShort(v, big_endian) => {
+                let bytes = if is_big(big_endian) {
+                    v.to_be_bytes()
+                } else {
+                    v.to_le_bytes()
+                };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteShortVar(var_name, big_endian) => {
+                let value = get_u16_from_json(&resolve_var_value(vars, var_name)?)?;
+                let bytes = if is_big(big_endian) {
+                    value.to_be_bytes()
+                } else {
+                    value.to_le_bytes()
+                };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteInt(v, big_endian) => {
+                let bytes = if is_big(big_endian) {
+                    v.to_be_bytes()
+                } else {
+                    v.to_le_bytes()
+                };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteIntVar(var_name, big_endian) => {
+                let value = get_u32_from_json(&resolve_var_value(vars, var_name)?)?;
+                let bytes = if is_big(big_endian) {
+                    value.to_be_bytes()
+                } else {
+                    value.to_le_bytes()
+                };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteLong(v, big_endian) => {
+                let bytes = if is_big(big_endian) { v.to_be_bytes() } else { v.to_le_bytes() };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteLongVar(var_name, big_endian) => {
+                let value = get_u64_from_json(&resolve_var_value(vars, var_name)?)?;
+                let bytes = if is_big(big_endian) { value.to_be_bytes() } else { value.to_le_bytes() };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteFloat(v, big_endian) => {
+                let bytes = if is_big(big_endian) { v.to_be_bytes() } else { v.to_le_bytes() };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteFloatVar(var_name, big_endian) => {
+                let value = get_f64_from_json(&resolve_var_value(vars, var_name)?)? as f32;
+                let bytes = if is_big(big_endian) { value.to_be_bytes() } else { value.to_le_bytes() };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteDouble(v, big_endian) => {
+                let bytes = if is_big(big_endian) { v.to_be_bytes() } else { v.to_le_bytes() };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteDoubleVar(var_name, big_endian) => {
+                let value = get_f64_from_json(&resolve_var_value(vars, var_name)?)?;
+                let bytes = if is_big(big_endian) { value.to_be_bytes() } else { value.to_le_bytes() };
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteString(text, length_opt) => {
+                let text = resolve_string_value(text, vars)?;
+                if let Some(length) = length_opt {
+                    let mut bytes = text.as_bytes().to_vec();
+                    bytes.resize(*length, 0);
+                    packet.extend_from_slice(&bytes[..*length]);
+                } else {
+                    packet.extend_from_slice(text.as_bytes());
+                    packet.push(0); // Null terminator
                 }
-                PacketCommand::WriteVarIntLen => {
-                    varint_placeholders.push(packet.len());
+            }
+            PacketCommand::WriteStringVar(var_name, length_opt) => {
+                let value = resolve_var_value(vars, var_name)?;
+                let text = value.as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Variable '{}' is not a string", var_name))?;
+                if let Some(length) = length_opt {
+                    let mut bytes = text.as_bytes().to_vec();
+                    bytes.resize(*length, 0);
+                    packet.extend_from_slice(&bytes[..*length]);
+                } else {
+                    packet.extend_from_slice(text.as_bytes());
+                    packet.push(0); // Null terminator
                 }
-                PacketCommand::WriteIntLen(big_endian) => {
-                    int_placeholders.push((packet.len(), *big_endian));
-                    // Reserve 4 bytes for the length field
-                    packet.extend_from_slice(&[0u8; 4]);
+            }
+            PacketCommand::WriteStringRaw(text) => {
+                packet.extend_from_slice(resolve_string_value(text, vars)?.as_bytes());
+            }
+            PacketCommand::WriteStringRawVar(var_name) => {
+                let value = resolve_var_value(vars, var_name)?;
+                let text = value.as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Variable '{}' is not a string", var_name))?;
+                packet.extend_from_slice(text.as_bytes());
+            }
+            PacketCommand::WriteStringLenPrefixed(text, prefix) => {
+                let text = resolve_string_value(text, vars)?;
+                packet.extend_from_slice(&prefix.encode(text.len())?);
+                packet.extend_from_slice(text.as_bytes());
+            }
+            PacketCommand::WriteStringLenPrefixedVar(var_name, prefix) => {
+                let value = resolve_var_value(vars, var_name)?;
+                let text = value.as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Variable '{}' is not a string", var_name))?;
+                packet.extend_from_slice(&prefix.encode(text.len())?);
+                packet.extend_from_slice(text.as_bytes());
+            }
+            PacketCommand::WriteStringUtf16(text, big_endian) => {
+                packet.extend_from_slice(&encode_utf16(&resolve_string_value(text, vars)?, *big_endian));
+            }
+            PacketCommand::WriteStringUtf16Var(var_name, big_endian) => {
+                let value = resolve_var_value(vars, var_name)?;
+                let text = value.as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Variable '{}' is not a string", var_name))?;
+                packet.extend_from_slice(&encode_utf16(text, *big_endian));
+            }
+            PacketCommand::WriteBytes(bytes) => {
+                packet.extend_from_slice(bytes);
+            }
+            PacketCommand::WriteBytesRepeat(value, count) => {
+                packet.resize(packet.len() + count, *value);
+            }
+            PacketCommand::WriteBytesRepeatToLen(value, length) => {
+                if packet.len() >= *length {
+                    out::debug(
+                        "packet_parser",
+                        &format!(
+                            "WRITE_BYTES_REPEAT_TO_LEN {}: packet is already {} bytes, nothing to pad",
+                            length,
+                            packet.len()
+                        ),
+                    );
+                } else {
+                    packet.resize(*length, *value);
                 }
             }
+            PacketCommand::WriteBytesVar(var_name) => {
+                let value = resolve_var_value(vars, var_name)?;
+                let hex_text = value.as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Variable '{}' is not a hex string", var_name))?;
+                let bytes = decode_hex_groups(hex_text)
+                    .map_err(|e| anyhow::anyhow!("Variable '{}' is not a valid hex string: {}", var_name, e))?;
+                packet.extend_from_slice(&bytes);
+            }
+            PacketCommand::WriteVarInt(value) => {
+                let encoded = encode_varint(*value);
+                packet.extend_from_slice(&encoded);
+            }
+            PacketCommand::WriteVarIntVar(var_name) => {
+                let value = get_u64_from_json(&resolve_var_value(vars, var_name)?)?;
+                let encoded = encode_varint(value);
+                packet.extend_from_slice(&encoded);
+            }
+            PacketCommand::WriteVarIntLen => {
+                varint_placeholders.push((packet.len(), segments.len()));
+            }
+            PacketCommand::WriteIntLen(big_endian) => {
+                int_placeholders.push((packet.len(), is_big(big_endian)));
+                // Reserve 4 bytes for the length field
+                packet.extend_from_slice(&[0u8; 4]);
+            }
+            PacketCommand::WriteCrc32(big_endian) => {
+                crc32_placeholders.push((packet.len(), *big_endian));
+                packet.extend_from_slice(&[0u8; 4]);
+            }
+            PacketCommand::Compress(compression) => {
+                compress_from = Some((packet.len(), *compression, segments.len()));
+            }
         }
+        segments.push(PacketSegment { line: sourced.source_line, offset, len: packet.len() - offset });
+    }
+    *line = None;
 
-        // Replace VarInt placeholders (in reverse order to maintain positions)
-        for &placeholder_pos in varint_placeholders.iter().rev() {
-            let suffix_len = packet.len() - placeholder_pos;
-            let encoded = encode_varint(suffix_len as u64);
-            packet.splice(placeholder_pos..placeholder_pos, encoded.iter().cloned());
-        }
-        
-        // Replace fixed Int placeholders (in reverse order to maintain positions)
-        for &(placeholder_pos, big_endian) in int_placeholders.iter().rev() {
-            // Calculate length: everything after the 4-byte length field itself
-            // If placeholder is at position 0, length = packet.len() - 4
-            // If placeholder is at position N, length = packet.len() - N - 4
-            let length = packet.len() - placeholder_pos - 4;
-            let bytes = if big_endian {
-                (length as u32).to_be_bytes()
-            } else {
-                (length as u32).to_le_bytes()
-            };
-            packet[placeholder_pos..placeholder_pos + 4].copy_from_slice(&bytes);
+    // Compress before filling the placeholders, so PACKET_LEN counts the compressed bytes
+    if let Some((start, compression, segment)) = compress_from {
+        let uncompressed = packet.split_off(start);
+        packet.extend_from_slice(&compression.compress(&uncompressed)?);
+        segments.truncate(segment + 1);
+        segments[segment].len = packet.len() - start;
+    }
+
+    // Replace VarInt placeholders (in reverse order to maintain positions)
+    for &(placeholder_pos, segment) in varint_placeholders.iter().rev() {
+        let suffix_len = packet.len() - placeholder_pos;
+        let encoded = encode_varint(suffix_len as u64);
+        packet.splice(placeholder_pos..placeholder_pos, encoded.iter().cloned());
+        segments[segment].len = encoded.len();
+        for later in &mut segments[segment + 1..] {
+            later.offset += encoded.len();
         }
-        
-        built_packets.push(packet);
+        for (crc_pos, _) in crc32_placeholders.iter_mut().filter(|(crc_pos, _)| *crc_pos >= placeholder_pos) {
+            *crc_pos += encoded.len();
         }
     }
-    Ok(built_packets)
+    
+    // Replace fixed Int placeholders (in reverse order to maintain positions)
+    for &(placeholder_pos, big_endian) in int_placeholders.iter().rev() {
+        // Calculate length: everything after the 4-byte length field itself
+        // If placeholder is at position 0, length = packet.len() - 4
+        // If placeholder is at position N, length = packet.len() - N - 4
+        let length = packet.len() - placeholder_pos - 4;
+        let bytes = if big_endian {
+            (length as u32).to_be_bytes()
+        } else {
+            (length as u32).to_le_bytes()
+        };
+        packet[placeholder_pos..placeholder_pos + 4].copy_from_slice(&bytes);
+    }
+
+    // Fill CRC32 placeholders last so they cover the final length fields (in order, so a
+    // later checksum also covers an earlier one)
+    for &(placeholder_pos, big_endian) in &crc32_placeholders {
+        let crc = crc32fast::hash(&packet[..placeholder_pos]);
+        let bytes = if big_endian { crc.to_be_bytes() } else { crc.to_le_bytes() };
+        packet[placeholder_pos..placeholder_pos + 4].copy_from_slice(&bytes);
+    }
+    
+    Ok(BuiltPacket { bytes: packet, segments })
 }
 
 fn encode_varint(mut value: u64) -> Vec<u8> {
@@ -1694,14 +3735,49 @@ fn encode_varint(mut value: u64) -> Vec<u8> {
     bytes
 }
 
+/// Length or count taken from a variable read earlier in the same response
+fn length_from_var(vars: &IndexMap<String, serde_json::Value>, name: &str) -> Result<usize> {
+    let value = vars.get(name)
+        .ok_or_else(|| anyhow::anyhow!("Length variable '{}' has not been read yet", name))?;
+    value.as_u64()
+        .and_then(|length| usize::try_from(length).ok())
+        .ok_or_else(|| anyhow::anyhow!("Length variable '{}' is not a non-negative integer: {}", name, value))
+}
+
+/// UTF-16 code units of `text` (surrogate pairs for characters outside the BMP)
+fn encode_utf16(text: &str, big_endian: bool) -> Vec<u8> {
+    text.encode_utf16()
+        .flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() })
+        .collect()
+}
+
+/// Run the response commands against `response`; `big_endian` is the script's `ENDIAN` default
 pub fn parse_response(
-    response_commands: &[ResponseCommand],
+    response_commands: &[Sourced<ResponseCommand>],
     response: &[u8],
+    big_endian: bool,
 ) -> Result<(IndexMap<String, serde_json::Value>, usize)> {
+    let mut line = None;
+    parse_response_at(response_commands, response, big_endian, &mut line).map_err(|e| at_line(e, line))
+}
+
+/// Body of [`parse_response`], keeping `line` at the command being run
+fn parse_response_at(
+    response_commands: &[Sourced<ResponseCommand>],
+    response: &[u8],
+    default_big_endian: bool,
+    line: &mut Option<usize>,
+) -> Result<(IndexMap<String, serde_json::Value>, usize)> {
+    let is_big = |big_endian: &Option<bool>| big_endian.unwrap_or(default_big_endian);
     let mut vars = IndexMap::new();
     let mut cursor = 0;
+    // Read bytes plus the inflated rest, once DECOMPRESS has run
+    let mut decompressed: Option<Vec<u8>> = None;
 
-    for (_idx, cmd) in response_commands.iter().enumerate() {
+    for sourced in response_commands {
+        let cmd = &sourced.command;
+        *line = Some(sourced.source_line);
+        let response = decompressed.as_deref().unwrap_or(response);
         match cmd {
             ResponseCommand::ReadByte(var) => {
                 if cursor >= response.len() {
@@ -1715,7 +3791,7 @@ pub fn parse_response(
                 if cursor + 2 > response.len() {
                     anyhow::bail!("Insufficient data: need 2 bytes, have {}", response.len() - cursor);
                 }
-                let value = if *big_endian {
+                let value = if is_big(big_endian) {
                     u16::from_be_bytes([response[cursor], response[cursor + 1]])
                 } else {
                     u16::from_le_bytes([response[cursor], response[cursor + 1]])
@@ -1727,7 +3803,7 @@ pub fn parse_response(
                 if cursor + 4 > response.len() {
                     anyhow::bail!("Insufficient data: need 4 bytes, have {}", response.len() - cursor);
                 }
-                let value = if *big_endian {
+                let value = if is_big(big_endian) {
                     u32::from_be_bytes([
                         response[cursor],
                         response[cursor + 1],
@@ -1745,6 +3821,63 @@ pub fn parse_response(
                 vars.insert(var.clone(), serde_json::Value::Number(value.into()));
                 cursor += 4;
             }
+            ResponseCommand::ReadLong(var, big_endian) => {
+                if cursor + 8 > response.len() {
+                    anyhow::bail!("Insufficient data: need 8 bytes, have {}", response.len() - cursor);
+                }
+                let bytes: [u8; 8] = response[cursor..cursor + 8].try_into()?;
+                let value = if is_big(big_endian) { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) };
+                // Beyond 2^53 a JSON number loses precision in most consumers, so keep the exact digits
+                let json_value = if value <= MAX_SAFE_INTEGER {
+                    serde_json::Value::Number(value.into())
+                } else {
+                    serde_json::Value::String(value.to_string())
+                };
+                vars.insert(var.clone(), json_value);
+                cursor += 8;
+            }
+            ResponseCommand::ReadSByte(var) => {
+                let value = read_signed(response, &mut cursor, 1, false)?;
+                vars.insert(var.clone(), serde_json::Value::Number(value.into()));
+            }
+            ResponseCommand::ReadSShort(var, big_endian) => {
+                let value = read_signed(response, &mut cursor, 2, is_big(big_endian))?;
+                vars.insert(var.clone(), serde_json::Value::Number(value.into()));
+            }
+            ResponseCommand::ReadSInt(var, big_endian) => {
+                let value = read_signed(response, &mut cursor, 4, is_big(big_endian))?;
+                vars.insert(var.clone(), serde_json::Value::Number(value.into()));
+            }
+            ResponseCommand::ReadSLong(var, big_endian) => {
+                let value = read_signed(response, &mut cursor, 8, is_big(big_endian))?;
+                // Same precision rule as READ_LONG: keep the exact digits beyond +/-(2^53 - 1)
+                let json_value = if value.unsigned_abs() <= MAX_SAFE_INTEGER {
+                    serde_json::Value::Number(value.into())
+                } else {
+                    serde_json::Value::String(value.to_string())
+                };
+                vars.insert(var.clone(), json_value);
+            }
+            ResponseCommand::ReadFloat(var, big_endian) => {
+                if cursor + 4 > response.len() {
+                    anyhow::bail!("Insufficient data: need 4 bytes, have {}", response.len() - cursor);
+                }
+                let bytes: [u8; 4] = response[cursor..cursor + 4].try_into()?;
+                let value = if is_big(big_endian) { f32::from_be_bytes(bytes) } else { f32::from_le_bytes(bytes) };
+                // Widen via the shortest decimal form so 0.1f32 is stored as 0.1, not 0.10000000149011612
+                let widened = value.to_string().parse::<f64>().unwrap_or(value as f64);
+                vars.insert(var.clone(), float_to_json(widened));
+                cursor += 4;
+            }
+            ResponseCommand::ReadDouble(var, big_endian) => {
+                if cursor + 8 > response.len() {
+                    anyhow::bail!("Insufficient data: need 8 bytes, have {}", response.len() - cursor);
+                }
+                let bytes: [u8; 8] = response[cursor..cursor + 8].try_into()?;
+                let value = if is_big(big_endian) { f64::from_be_bytes(bytes) } else { f64::from_le_bytes(bytes) };
+                vars.insert(var.clone(), float_to_json(value));
+                cursor += 8;
+            }
             ResponseCommand::ReadVarInt(var) => {
                 let _start = cursor;
                 let value = read_varint(response, &mut cursor)?;
@@ -1763,6 +3896,15 @@ pub fn parse_response(
                     anyhow::bail!("READ_STRING requires length");
                 }
             }
+            ResponseCommand::ReadStringVarLen(var, length_var) => {
+                let length = length_from_var(&vars, length_var)?;
+                if cursor + length > response.len() {
+                    anyhow::bail!("Insufficient data: need {} bytes ({}), have {}", length, length_var, response.len() - cursor);
+                }
+                let text = String::from_utf8_lossy(&response[cursor..cursor + length]).trim_end_matches('\0').to_string();
+                vars.insert(var.clone(), serde_json::Value::String(text));
+                cursor += length;
+            }
             ResponseCommand::ReadStringNull(var) => {
                 let start = cursor;
                 while cursor < response.len() && response[cursor] != 0 {
@@ -1775,12 +3917,107 @@ pub fn parse_response(
                     cursor += 1; // Skip null terminator
                 }
             }
+            ResponseCommand::ReadStringLenPrefixed(var, prefix) => {
+                let length = prefix.decode(response, &mut cursor)?;
+                if length > response.len() - cursor {
+                    anyhow::bail!(
+                        "Insufficient data: string length prefix is {} bytes, only {} left in the response",
+                        length,
+                        response.len() - cursor
+                    );
+                }
+                let text = String::from_utf8_lossy(&response[cursor..cursor + length]).to_string();
+                vars.insert(var.clone(), serde_json::Value::String(text));
+                cursor += length;
+            }
+            ResponseCommand::ReadStringUtf16(var, count, big_endian) => {
+                let unit_at = |offset: usize| {
+                    let bytes = [response[offset], response[offset + 1]];
+                    if *big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+                };
+                let mut units = Vec::new();
+                match count {
+                    Some(count) => {
+                        let byte_len = match count.checked_mul(2) {
+                            Some(byte_len) if byte_len <= response.len() - cursor => byte_len,
+                            _ => anyhow::bail!(
+                                "Insufficient data: need {} bytes for {} UTF-16 characters, have {}",
+                                count.saturating_mul(2),
+                                count,
+                                response.len() - cursor
+                            ),
+                        };
+                        units.extend((0..*count).map(|i| unit_at(cursor + i * 2)));
+                        cursor += byte_len;
+                    }
+                    None => loop {
+                        if response.len() - cursor < 2 {
+                            anyhow::bail!("Insufficient data: UTF-16 string has no 0x0000 terminator");
+                        }
+                        let unit = unit_at(cursor);
+                        cursor += 2;
+                        if unit == 0 {
+                            break;
+                        }
+                        units.push(unit);
+                    },
+                }
+                vars.insert(var.clone(), serde_json::Value::String(String::from_utf16_lossy(&units)));
+            }
             ResponseCommand::SkipBytes(count) => {
                 if cursor + count > response.len() {
                     anyhow::bail!("Insufficient data: need {} bytes, have {}", count, response.len() - cursor);
                 }
                 cursor += count;
             }
+            ResponseCommand::SkipBytesVar(count_var) => {
+                let count = length_from_var(&vars, count_var)?;
+                if cursor + count > response.len() {
+                    anyhow::bail!("Insufficient data: need {} bytes ({}), have {}", count, count_var, response.len() - cursor);
+                }
+                cursor += count;
+            }
+            ResponseCommand::SeekToOffset(offset) => {
+                if *offset > response.len() {
+                    anyhow::bail!("SEEK_TO_OFFSET {} is past the end of the {} byte response", offset, response.len());
+                }
+                cursor = *offset;
+            }
+            ResponseCommand::SeekFromEnd(count) => {
+                if *count > response.len() {
+                    anyhow::bail!("SEEK_FROM_END {} is before the start of the {} byte response", count, response.len());
+                }
+                cursor = response.len() - count;
+            }
+            ResponseCommand::ReadCurrentOffset(var) => {
+                vars.insert(var.clone(), serde_json::Value::Number((cursor as u64).into()));
+            }
+            // Checked by the caller once every variable is known, see check_response_assertions
+            ResponseCommand::ExpectVar(_) => {}
+            ResponseCommand::ReadBytes(var, count) => {
+                if cursor + count > response.len() {
+                    anyhow::bail!("Insufficient data: need {} bytes, have {}", count, response.len() - cursor);
+                }
+                vars.insert(var.clone(), serde_json::Value::String(hex::encode(&response[cursor..cursor + count])));
+                cursor += count;
+            }
+            ResponseCommand::ReadBytesVarLen(var, count_var) => {
+                let count = length_from_var(&vars, count_var)?;
+                if cursor + count > response.len() {
+                    anyhow::bail!("Insufficient data: need {} bytes ({}), have {}", count, count_var, response.len() - cursor);
+                }
+                vars.insert(var.clone(), serde_json::Value::String(hex::encode(&response[cursor..cursor + count])));
+                cursor += count;
+            }
+            ResponseCommand::Decompress(compression) => {
+                let mut data = response[..cursor].to_vec();
+                data.extend(compression.decompress(&response[cursor..])?);
+                decompressed = Some(data);
+            }
+            ResponseCommand::ReadRemaining(var) => {
+                vars.insert(var.clone(), serde_json::Value::String(hex::encode(&response[cursor..])));
+                cursor = response.len();
+            }
             ResponseCommand::ExpectByte(expected) => {
                 if cursor >= response.len() {
                     anyhow::bail!("Insufficient data: need 1 byte for EXPECT_BYTE, have {}", response.len() - cursor);
@@ -1799,14 +4036,40 @@ pub fn parse_response(
                 if actual != expected.as_slice() {
                     anyhow::bail!("Expected magic bytes {:?}, got {:?}", hex::encode(expected), hex::encode(actual));
                 }
-                cursor += expected.len();
+                cursor += expected.len();
+            }
+            ResponseCommand::ExpectVarInt(expected) => {
+                let actual = read_varint(response, &mut cursor)?;
+                if actual != *expected {
+                    anyhow::bail!("Expected varint {} (0x{:X}), got {} (0x{:X})", expected, expected, actual, actual);
+                }
+            }
+            ResponseCommand::ExpectInt(expected, big_endian) => {
+                if cursor + 4 > response.len() {
+                    anyhow::bail!("Insufficient data: need 4 bytes for EXPECT_INT, have {}", response.len() - cursor);
+                }
+                let bytes = [response[cursor], response[cursor + 1], response[cursor + 2], response[cursor + 3]];
+                let actual = if is_big(big_endian) { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) };
+                if actual != *expected {
+                    anyhow::bail!("Expected int 0x{:08X}, got 0x{:08X}", expected, actual);
+                }
+                cursor += 4;
             }
-            ResponseCommand::ExpectStatus(_) => {
+            ResponseCommand::ExpectStatus(..) => {
                 anyhow::bail!("EXPECT_STATUS is only valid for HTTP responses, not binary responses");
             }
+            ResponseCommand::ExpectBodyContains(_) => {
+                anyhow::bail!("EXPECT_BODY_CONTAINS is only valid for HTTP responses, not binary responses");
+            }
             ResponseCommand::ExpectHeader { .. } => {
                 anyhow::bail!("EXPECT_HEADER is only valid for HTTP responses, not binary responses");
             }
+            ResponseCommand::ReadHeader { .. } => {
+                anyhow::bail!("READ_HEADER is only valid for HTTP responses, not binary responses");
+            }
+            ResponseCommand::NoHeaderVars => {
+                anyhow::bail!("NO_HEADER_VARS is only valid for HTTP responses, not binary responses");
+            }
             ResponseCommand::ReadBodyJson(_) => {
                 anyhow::bail!("READ_BODY_JSON is only valid for HTTP responses, not binary responses");
             }
@@ -1819,15 +4082,82 @@ pub fn parse_response(
     Ok((vars, cursor))
 }
 
+/// Parse a WebSocket frame payload. Binary response commands read from the frame like any other
+/// response; READ_BODY and READ_BODY_JSON consume the rest of the frame as text or JSON.
+pub fn parse_frame_response(
+    response_commands: &[Sourced<ResponseCommand>],
+    frame: &[u8],
+    big_endian: bool,
+) -> Result<IndexMap<String, serde_json::Value>> {
+    let mut vars = IndexMap::new();
+    let mut cursor = 0;
+    let mut binary_start = 0;
+
+    for (idx, sourced) in response_commands.iter().enumerate() {
+        let cmd = &sourced.command;
+        if !matches!(cmd, ResponseCommand::ReadBody(_) | ResponseCommand::ReadBodyJson(_)) {
+            continue;
+        }
+        // The binary reads below work on the inflated data, which the frame offsets don't match
+        if response_commands[binary_start..idx].iter().any(|sourced| matches!(sourced.command, ResponseCommand::Decompress(_))) {
+            anyhow::bail!("DECOMPRESS can't be followed by READ_BODY or READ_BODY_JSON in a WebSocket frame");
+        }
+        // Run the binary commands preceding this one against the unread part of the frame
+        let (binary_vars, read) = parse_response(&response_commands[binary_start..idx], &frame[cursor..], big_endian)?;
+        vars.extend(binary_vars);
+        cursor += read;
+        binary_start = idx + 1;
+
+        let rest = &frame[cursor..];
+        match cmd {
+            ResponseCommand::ReadBodyJson(var_name) => {
+                let json_value: serde_json::Value = serde_json::from_slice(rest)
+                    .context("Failed to parse WebSocket frame as JSON")
+                    .map_err(|e| at_line(e, Some(sourced.source_line)))?;
+                vars.insert(var_name.clone(), json_value);
+            }
+            ResponseCommand::ReadBody(var_name) => {
+                let text = String::from_utf8(rest.to_vec())
+                    .context("Failed to parse WebSocket frame as UTF-8 text")
+                    .map_err(|e| at_line(e, Some(sourced.source_line)))?;
+                vars.insert(var_name.clone(), serde_json::json!(text));
+            }
+            _ => unreachable!(),
+        }
+        cursor = frame.len();
+    }
+
+    let (binary_vars, _) = parse_response(&response_commands[binary_start..], &frame[cursor..], big_endian)?;
+    vars.extend(binary_vars);
+    Ok(vars)
+}
+
+/// `max_loop_iterations` caps each `FOR ... IN RANGE(...)` and `WHILE` loop; going over fails with [`LoopLimitExceeded`].
+/// Names of declared variables that were already in `parsed_vars` are added to `shadowed_parsed`, once each.
+/// `functions` are the script's `DEF`s; each `CALL` runs with its own variables, which don't end up in the result.
 pub fn execute_code_blocks(
     code_blocks: &[CodeBlock],
+    functions: &IndexMap<String, ScriptFunction>,
     parsed_vars: &mut IndexMap<String, JsonValue>,
+    max_loop_iterations: u64,
+    shadowed_parsed: &mut Vec<String>,
 ) -> Result<IndexMap<String, JsonValue>> {
     let mut code_vars = IndexMap::new();
+    let mut calls = CallStack { functions, depth: 0, returned: None };
+    let calls = &mut calls;
     
     for (_block_idx, block) in code_blocks.iter().enumerate() {
         for (_cmd_idx, cmd) in block.commands.iter().enumerate() {
-            execute_code_command(cmd, parsed_vars, &mut code_vars)?;
+            let flow = execute_code_command(&cmd.command, parsed_vars, &mut code_vars, max_loop_iterations, shadowed_parsed, calls)
+                .map_err(|e| at_line(e, Some(cmd.source_line)))?;
+            match flow {
+                ControlFlow::Next => {}
+                // RETURN_EARLY ends this block successfully; later blocks still run
+                ControlFlow::ReturnEarly => break,
+                ControlFlow::Break => anyhow::bail!("BREAK outside of a loop at line {}", cmd.source_line),
+                ControlFlow::Continue => anyhow::bail!("CONTINUE outside of a loop at line {}", cmd.source_line),
+                ControlFlow::Return => anyhow::bail!("RETURN outside of a function at line {}", cmd.source_line),
+            }
         }
     }
     
@@ -1838,9 +4168,16 @@ fn execute_code_command(
     cmd: &CodeCommand,
     parsed_vars: &IndexMap<String, JsonValue>,
     code_vars: &mut IndexMap<String, JsonValue>,
-) -> Result<()> {
+    max_loop_iterations: u64,
+    shadowed_parsed: &mut Vec<String>,
+    calls: &mut CallStack,
+) -> Result<ControlFlow> {
+    let mut flow = ControlFlow::Next;
     match cmd {
         CodeCommand::DeclareVar { name, value, .. } => {
+            if parsed_vars.contains_key(name) && !shadowed_parsed.contains(name) {
+                shadowed_parsed.push(name.clone());
+            }
             let evaluated = evaluate_expression(value, parsed_vars, code_vars)?;
             code_vars.insert(name.clone(), evaluated);
         }
@@ -1869,51 +4206,100 @@ fn execute_code_command(
             let result = source_str.replace(search, replace);
             code_vars.insert(var_name.clone(), JsonValue::String(result));
         }
-        CodeCommand::ForLoop { .. } => {
-            // TODO: Implement FOR loop execution
+        CodeCommand::ForLoop { var_name, range_start, range_end, body, line } => {
+            let start = get_i64_from_json(&evaluate_expression(range_start, parsed_vars, code_vars)?)
+                .context("Invalid RANGE start")?;
+            let end = get_i64_from_json(&evaluate_expression(range_end, parsed_vars, code_vars)?)
+                .context("Invalid RANGE end")?;
+            
+            // Bounds can come from the response, so refuse oversized ranges instead of running them
+            let iterations = end.saturating_sub(start).max(0) as u64;
+            if iterations > max_loop_iterations {
+                return Err(LoopLimitExceeded { line: *line, limit: max_loop_iterations }.into());
+            }
+            
+            // Same scoping as FOR ... IN array: restore whatever the loop variable shadowed
+            let shadowed = code_vars.get(var_name).cloned();
+            let mut outcome = Ok(());
+            for i in start..end {
+                code_vars.insert(var_name.clone(), JsonValue::from(i));
+                match run_loop_body(body, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls, &mut flow) {
+                    Ok(false) => {}
+                    Ok(true) => break,
+                    Err(e) => {
+                        outcome = Err(e);
+                        break;
+                    }
+                }
+            }
+            match shadowed {
+                Some(value) => {
+                    code_vars.insert(var_name.clone(), value);
+                }
+                None => {
+                    code_vars.shift_remove(var_name);
+                }
+            }
+            outcome?;
+        }
+        CodeCommand::WhileLoop { condition, body, line } => {
+            // The condition is re-evaluated each time, so it sees variables assigned in the body
+            let mut iterations = 0;
+            while evaluate_condition(condition, parsed_vars, code_vars)? {
+                if iterations == max_loop_iterations {
+                    return Err(LoopLimitExceeded { line: *line, limit: max_loop_iterations }.into());
+                }
+                iterations += 1;
+                if run_loop_body(body, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls, &mut flow)? {
+                    break;
+                }
+            }
         }
         CodeCommand::ForInArray { var_name, array_name, body } => {
             let array_value = get_variable_value(array_name, parsed_vars, code_vars)?;
             let array = array_value.as_array()
                 .ok_or_else(|| anyhow::anyhow!("Variable '{}' is not an array", array_name))?;
             
+            // The loop variable is scoped to the loop: restore whatever it shadowed afterwards.
+            // Other variables set in the body stay visible after the loop.
+            let shadowed = code_vars.get(var_name).cloned();
+            let mut outcome = Ok(());
             for (_idx, item) in array.iter().enumerate() {
                 // Set the loop variable
                 code_vars.insert(var_name.clone(), item.clone());
                 
                 // Execute body
-                let mut should_break = false;
-                for body_cmd in body {
-                    match execute_code_command(body_cmd, parsed_vars, code_vars) {
-                        Ok(()) => {}
-                        Err(e) if e.to_string().contains("BREAK") => {
-                            should_break = true;
-                            break;
-                        }
-                        Err(e) => return Err(e),
+                match run_loop_body(body, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls, &mut flow) {
+                    Ok(false) => {}
+                    Ok(true) => break,
+                    Err(e) => {
+                        outcome = Err(e);
+                        break;
                     }
                 }
-                
-                if should_break {
-                    break;
+            }
+            match shadowed {
+                Some(value) => {
+                    code_vars.insert(var_name.clone(), value);
+                }
+                None => {
+                    code_vars.shift_remove(var_name);
                 }
             }
+            outcome?;
         }
         CodeCommand::IfStatement { condition, body, else_if, else_body } => {
             let condition_result = evaluate_condition(condition, parsed_vars, code_vars)?;
             
+            // BREAK/CONTINUE/RETURN_EARLY inside a branch pass through to the enclosing loop or block
             if condition_result {
-                for body_cmd in body {
-                    execute_code_command(body_cmd, parsed_vars, code_vars)?;
-                }
+                flow = execute_code_body(body, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls)?;
             } else {
                 // Check else-if conditions
                 let mut matched = false;
                 for (else_cond, else_body_cmds) in else_if {
                     if evaluate_condition(else_cond, parsed_vars, code_vars)? {
-                        for body_cmd in else_body_cmds {
-                            execute_code_command(body_cmd, parsed_vars, code_vars)?;
-                        }
+                        flow = execute_code_body(else_body_cmds, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls)?;
                         matched = true;
                         break;
                     }
@@ -1922,15 +4308,67 @@ fn execute_code_command(
                 // Execute else body if no else-if matched
                 if !matched {
                     if let Some(else_body_cmds) = else_body {
-                        for body_cmd in else_body_cmds {
-                            execute_code_command(body_cmd, parsed_vars, code_vars)?;
-                        }
+                        flow = execute_code_body(else_body_cmds, parsed_vars, code_vars, max_loop_iterations, shadowed_parsed, calls)?;
                     }
                 }
             }
         }
         CodeCommand::Break => {
-            return Err(anyhow::anyhow!("BREAK"));
+            flow = ControlFlow::Break;
+        }
+        CodeCommand::Continue => {
+            flow = ControlFlow::Continue;
+        }
+        CodeCommand::ReturnEarly => {
+            flow = ControlFlow::ReturnEarly;
+        }
+        CodeCommand::Call { name, args, result, declared, .. } => {
+            let function = calls.functions.get(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown function '{}'", name))?;
+            if calls.depth == MAX_CALL_DEPTH {
+                anyhow::bail!("Calling '{}' exceeded the call depth limit of {}", name, MAX_CALL_DEPTH);
+            }
+            // Arguments are evaluated by the caller; the function only sees them, parsed variables and DEFINEs
+            let mut locals = IndexMap::new();
+            for (param, arg) in function.params.iter().zip(args) {
+                locals.insert(param.clone(), evaluate_expression(arg, parsed_vars, code_vars)?);
+            }
+            calls.depth += 1;
+            calls.returned = None;
+            let outcome = execute_code_body(&function.body, parsed_vars, &mut locals, max_loop_iterations, &mut Vec::new(), calls)
+                .map_err(|e| in_function(e, name));
+            calls.depth -= 1;
+            let returned = calls.returned.take();
+            match outcome? {
+                ControlFlow::Break => anyhow::bail!("in function '{}': BREAK outside of a loop", name),
+                ControlFlow::Continue => anyhow::bail!("in function '{}': CONTINUE outside of a loop", name),
+                // RETURN_EARLY in a function ends the calling code block too
+                ControlFlow::ReturnEarly => flow = ControlFlow::ReturnEarly,
+                ControlFlow::Next | ControlFlow::Return => {}
+            }
+            if let Some(var_name) = result {
+                let value = returned.ok_or_else(|| anyhow::anyhow!("Function '{}' did not RETURN a value", name))?;
+                if *declared && parsed_vars.contains_key(var_name) && !shadowed_parsed.contains(var_name) {
+                    shadowed_parsed.push(var_name.clone());
+                }
+                code_vars.insert(var_name.clone(), value);
+            }
+        }
+        CodeCommand::Return(value) => {
+            calls.returned = value.as_ref()
+                .map(|value| evaluate_expression(value, parsed_vars, code_vars))
+                .transpose()?;
+            flow = ControlFlow::Return;
+        }
+        CodeCommand::ExpectVar(assertion) | CodeCommand::Assert(assertion) => {
+            assertion.check(parsed_vars, code_vars)?;
+        }
+        CodeCommand::Fail { message, line } => {
+            return Err(AssertionFailed {
+                kind: AssertionKind::Expectation,
+                line: *line,
+                message: message.clone(),
+            }.into());
         }
         CodeCommand::ExecutePacketCommand(_) => {
             // TODO: Nested packet command execution
@@ -1939,10 +4377,10 @@ fn execute_code_command(
             // TODO: Nested response command execution
         }
     }
-    Ok(())
+    Ok(flow)
 }
 
-fn evaluate_condition(
+pub fn evaluate_condition(
     condition: &Condition,
     parsed_vars: &IndexMap<String, JsonValue>,
     code_vars: &IndexMap<String, JsonValue>,
@@ -1978,6 +4416,11 @@ fn evaluate_condition(
             let right_val = evaluate_expression(right, parsed_vars, code_vars)?;
             compare_values(&left_val, &right_val, |a, b| a <= b)
         }
+        Condition::IsTrue(expr) => {
+            let value = evaluate_expression(expr, parsed_vars, code_vars)?;
+            value.as_bool()
+                .ok_or_else(|| anyhow::anyhow!("Condition must be true or false, got {}", value))
+        }
         Condition::Contains(left, right) => {
             let left_val = evaluate_expression(left, parsed_vars, code_vars)?;
             let right_val = evaluate_expression(right, parsed_vars, code_vars)?;
@@ -2012,6 +4455,13 @@ fn evaluate_expression(
 ) -> Result<JsonValue> {
     match expr {
         Expression::Literal(value) => Ok(value.clone()),
+        Expression::Template(template) => {
+            let expanded = expand_placeholders(template, |path| {
+                let expr = parse_expression(path, 0)?;
+                evaluate_expression(&expr, parsed_vars, code_vars).map(|value| value_to_text(&value))
+            })?;
+            Ok(JsonValue::String(expanded))
+        }
         Expression::Variable(name) => {
             get_variable_value(name, parsed_vars, code_vars)
         }
@@ -2045,16 +4495,247 @@ fn evaluate_expression(
             }
             
             // Evaluate function calls
-            let _evaluated_args: Result<Vec<JsonValue>> = args.iter()
+            let evaluated_args: Vec<JsonValue> = args.iter()
                 .map(|arg| evaluate_expression(arg, parsed_vars, code_vars))
-                .collect();
+                .collect::<Result<_>>()?;
+            
+            call_builtin(name, &evaluated_args)
+        }
+        Expression::RegexCall { name, args, regex, line } => {
+            let evaluated_args: Vec<JsonValue> = args.iter()
+                .map(|arg| evaluate_expression(arg, parsed_vars, code_vars))
+                .collect::<Result<_>>()?;
+            let expected = if name == "REGEX_MATCH" { 2 } else { 3 };
+            if evaluated_args.len() != expected {
+                anyhow::bail!("{} expects {} argument(s), got {}", name, expected, evaluated_args.len());
+            }
+            let text = evaluated_args[0].as_str()
+                .ok_or_else(|| anyhow::anyhow!("{} expects a string for argument 1, got {}", name, evaluated_args[0]))?;
+            // Patterns that aren't literals are only known now
+            let compiled;
+            let regex = match regex {
+                Some(regex) => regex,
+                None => {
+                    let pattern = evaluated_args[1].as_str()
+                        .ok_or_else(|| anyhow::anyhow!("{} expects a string for argument 2, got {}", name, evaluated_args[1]))?;
+                    compiled = compile_regex(pattern, name, *line)?;
+                    &compiled
+                }
+            };
             
-            // Handle built-in functions
-            match name.as_str() {
-                // Add more functions as needed
-                _ => anyhow::bail!("Unknown function: {}", name),
+            if name == "REGEX_MATCH" {
+                return Ok(JsonValue::Bool(regex.is_match(text)));
+            }
+            // REGEX_EXTRACT: group by number or name; an empty string when the text doesn't match
+            let captures = regex.captures(text);
+            let group = match &evaluated_args[2] {
+                JsonValue::String(group_name) => {
+                    if !regex.capture_names().any(|n| n == Some(group_name.as_str())) {
+                        anyhow::bail!("REGEX_EXTRACT pattern has no group named '{}' at line {}", group_name, line);
+                    }
+                    captures.and_then(|c| c.name(group_name))
+                }
+                other => {
+                    let idx = other.as_u64()
+                        .ok_or_else(|| anyhow::anyhow!("REGEX_EXTRACT expects a group number or name for argument 3, got {}", other))? as usize;
+                    if idx >= regex.captures_len() {
+                        anyhow::bail!(
+                            "REGEX_EXTRACT group {} does not exist, the pattern has {} group(s) at line {}",
+                            idx, regex.captures_len() - 1, line
+                        );
+                    }
+                    captures.and_then(|c| c.get(idx))
+                }
+            };
+            Ok(JsonValue::String(group.map(|m| m.as_str().to_string()).unwrap_or_default()))
+        }
+        Expression::Path { var_name, segments } => {
+            let mut value = get_variable_value(var_name, parsed_vars, code_vars)?;
+            let mut walked = var_name.clone();
+            for segment in segments {
+                value = match segment {
+                    PathSegment::Field(field) => {
+                        let object = value.as_object()
+                            .ok_or_else(|| anyhow::anyhow!("'{}' is not an object, cannot read field '{}'", walked, field))?;
+                        let next = object.get(field)
+                            .ok_or_else(|| anyhow::anyhow!("Field '{}' not found in '{}'", field, walked))?
+                            .clone();
+                        walked = format!("{}.{}", walked, field);
+                        next
+                    }
+                    PathSegment::Index(index) => {
+                        let index_value = evaluate_expression(index, parsed_vars, code_vars)?;
+                        let idx = index_value.as_u64()
+                            .ok_or_else(|| anyhow::anyhow!("Array index must be a number, got: {:?}", index_value))? as usize;
+                        let array = value.as_array()
+                            .ok_or_else(|| anyhow::anyhow!("'{}' is not an array, cannot read index {}", walked, idx))?;
+                        let next = array.get(idx)
+                            .ok_or_else(|| anyhow::anyhow!("Index {} out of bounds for '{}' of length {}", idx, walked, array.len()))?
+                            .clone();
+                        walked = format!("{}[{}]", walked, idx);
+                        next
+                    }
+                };
+            }
+            Ok(value)
+        }
+    }
+}
+
+/// Built-in functions usable in expressions
+fn call_builtin(name: &str, args: &[JsonValue]) -> Result<JsonValue> {
+    let expect_args = |count: usize| -> Result<()> {
+        if args.len() != count {
+            anyhow::bail!("{} expects {} argument(s), got {}", name, count, args.len());
+        }
+        Ok(())
+    };
+    let string_arg = |idx: usize| -> Result<&str> {
+        args[idx].as_str()
+            .ok_or_else(|| anyhow::anyhow!("{} expects a string for argument {}, got {}", name, idx + 1, args[idx]))
+    };
+    let index_arg = |idx: usize| -> Result<usize> {
+        args[idx].as_u64()
+            .map(|n| n as usize)
+            .ok_or_else(|| anyhow::anyhow!("{} expects a non-negative integer for argument {}, got {}", name, idx + 1, args[idx]))
+    };
+    
+    match name {
+        "LEN" => {
+            expect_args(1)?;
+            match &args[0] {
+                JsonValue::String(s) => Ok(JsonValue::from(s.chars().count())),
+                JsonValue::Array(items) => Ok(JsonValue::from(items.len())),
+                other => anyhow::bail!("LEN expects a string or array, got {}", other),
+            }
+        }
+        "SUBSTRING" => {
+            // Character based; a range past the end of the string is cut short
+            expect_args(3)?;
+            let source = string_arg(0)?;
+            let start = index_arg(1)?;
+            let len = index_arg(2)?;
+            Ok(JsonValue::String(source.chars().skip(start).take(len).collect()))
+        }
+        "UPPER" => {
+            expect_args(1)?;
+            Ok(JsonValue::String(string_arg(0)?.to_uppercase()))
+        }
+        "LOWER" => {
+            expect_args(1)?;
+            Ok(JsonValue::String(string_arg(0)?.to_lowercase()))
+        }
+        "TRIM" => {
+            expect_args(1)?;
+            Ok(JsonValue::String(string_arg(0)?.trim().to_string()))
+        }
+        "CONCAT" => {
+            if args.is_empty() {
+                anyhow::bail!("CONCAT expects at least 1 argument, got 0");
+            }
+            let mut result = String::new();
+            for (idx, arg) in args.iter().enumerate() {
+                match arg {
+                    JsonValue::String(s) => result.push_str(s),
+                    JsonValue::Number(_) | JsonValue::Bool(_) => result.push_str(&arg.to_string()),
+                    other => anyhow::bail!("CONCAT cannot join argument {} ({})", idx + 1, other),
+                }
+            }
+            Ok(JsonValue::String(result))
+        }
+        "TO_INT" => {
+            expect_args(1)?;
+            let n = json_to_int(&args[0])?;
+            i64::try_from(n).map(JsonValue::from)
+                .or_else(|_| u64::try_from(n).map(JsonValue::from))
+                .map_err(|_| anyhow::anyhow!("Cannot convert {} to INT", args[0]))
+        }
+        "TO_FLOAT" => {
+            expect_args(1)?;
+            let converted = match &args[0] {
+                JsonValue::Number(n) => n.as_f64(),
+                JsonValue::String(s) => s.trim().parse::<f64>().ok(),
+                _ => None,
+            };
+            converted
+                .and_then(serde_json::Number::from_f64)
+                .map(JsonValue::Number)
+                .ok_or_else(|| anyhow::anyhow!("Cannot convert {} to FLOAT", args[0]))
+        }
+        "TO_STRING" => {
+            expect_args(1)?;
+            match &args[0] {
+                JsonValue::String(s) => Ok(JsonValue::String(s.clone())),
+                other => Ok(JsonValue::String(other.to_string())),
+            }
+        }
+        "HEX" => {
+            // Upper-case hex digits without a 0x prefix
+            expect_args(1)?;
+            let n = json_to_int(&args[0])
+                .ok()
+                .filter(|n| *n >= 0)
+                .ok_or_else(|| anyhow::anyhow!("Cannot convert {} to HEX", args[0]))?;
+            Ok(JsonValue::String(format!("{:X}", n)))
+        }
+        "RANDOM_INT" => {
+            // Inclusive on both ends
+            expect_args(2)?;
+            let min = json_to_int(&args[0])?;
+            let max = json_to_int(&args[1])?;
+            let (Ok(min), Ok(max)) = (i64::try_from(min), i64::try_from(max)) else {
+                anyhow::bail!("RANDOM_INT bounds must fit in a 64-bit integer");
+            };
+            if min > max {
+                anyhow::bail!("RANDOM_INT minimum {} is greater than maximum {}", min, max);
+            }
+            Ok(JsonValue::from(rand::Rng::gen_range(&mut rand::thread_rng(), min..=max)))
+        }
+        "RANDOM_HEX" => {
+            // Lower-case hex, usable with WRITE_BYTES_VAR
+            expect_args(1)?;
+            let count = index_arg(0)?;
+            if count == 0 || count > MAX_RANDOM_BYTES {
+                anyhow::bail!("RANDOM_HEX takes 1 to {} bytes, got {}", MAX_RANDOM_BYTES, count);
             }
+            let mut bytes = vec![0u8; count];
+            rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut bytes);
+            Ok(JsonValue::String(hex::encode(bytes)))
         }
+        "TIMESTAMP" | "TIMESTAMP_MS" => {
+            expect_args(0)?;
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+            Ok(JsonValue::from(if name == "TIMESTAMP" { now.as_secs() } else { now.as_millis() as u64 }))
+        }
+        "CRC32" | "MD5" | "SHA1" => {
+            // Lower-case hex digest, as stored by READ_BYTES
+            if args.is_empty() || args.len() > 2 {
+                anyhow::bail!("{} expects 1 or 2 argument(s), got {}", name, args.len());
+            }
+            let encoding = if args.len() == 2 { string_arg(1)? } else { "TEXT" };
+            let data = checksum_input(name, string_arg(0)?, encoding)?;
+            Ok(JsonValue::String(match name {
+                "CRC32" => format!("{:08x}", crc32fast::hash(&data)),
+                "MD5" => hex::encode(md5::Md5::digest(&data)),
+                _ => hex::encode(sha1::Sha1::digest(&data)),
+            }))
+        }
+        _ => anyhow::bail!("Unknown function: {}", name),
+    }
+}
+
+/// Largest `RANDOM_HEX` argument
+const MAX_RANDOM_BYTES: usize = 1024;
+
+/// Bytes hashed by the checksum builtins: the UTF-8 text by default, or the bytes a hex string
+/// (such as a `READ_BYTES` value) encodes when the second argument is `"HEX"`
+fn checksum_input(name: &str, value: &str, encoding: &str) -> Result<Vec<u8>> {
+    if encoding.eq_ignore_ascii_case("TEXT") {
+        Ok(value.as_bytes().to_vec())
+    } else if encoding.eq_ignore_ascii_case("HEX") {
+        decode_hex_groups(value).map_err(|e| anyhow::anyhow!("{} input is not a valid hex string: {}", name, e))
+    } else {
+        anyhow::bail!("{} encoding must be \"TEXT\" or \"HEX\", got \"{}\"", name, encoding)
     }
 }
 
@@ -2073,7 +4754,31 @@ fn get_variable_value(
     }
 }
 
-fn read_varint(response: &[u8], cursor: &mut usize) -> Result<u64> {
+/// Largest integer a JSON number (an IEEE double) holds exactly
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Classic 16-bytes-per-line dump: offset, hex bytes (split after eight) and printable ASCII
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut result = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        result.push_str(&format!("{:08X}: ", i * 16));
+        for j in 0..16 {
+            if j == 8 {
+                result.push(' ');
+            }
+            match chunk.get(j) {
+                Some(byte) => result.push_str(&format!("{:02X} ", byte)),
+                None => result.push_str("   "),
+            }
+        }
+        result.push(' ');
+        result.extend(chunk.iter().map(|&byte| if (32..127).contains(&byte) { byte as char } else { '.' }));
+        result.push('\n');
+    }
+    result
+}
+
+pub fn read_varint(response: &[u8], cursor: &mut usize) -> Result<u64> {
     let mut value = 0u64;
     let mut shift = 0;
     loop {
@@ -2115,7 +4820,7 @@ pub fn prepare_http_request_with_vars(
     let mut resolved_params = Vec::new();
     for (key, value) in &http_req.params {
         let resolved_key = resolve_string_value(key, vars)?;
-        let resolved_value = resolve_string_value(value, vars)?;
+        let resolved_value = resolve_http_value(value, vars)?;
         resolved_params.push((resolved_key, resolved_value));
     }
     
@@ -2127,7 +4832,7 @@ pub fn prepare_http_request_with_vars(
         if resolved_key.eq_ignore_ascii_case("Host") {
             continue;
         }
-        let resolved_value = resolve_string_value(value, vars)?;
+        let resolved_value = resolve_http_value(value, vars)?;
         resolved_headers.push((resolved_key, resolved_value));
     }
     
@@ -2157,14 +4862,11 @@ pub fn prepare_http_request_with_vars(
         let body_bytes = match body_type {
             HttpBodyType::Form => {
                 // Build form data from body_data (key=value pairs)
+                // Each DATA entry is expected to be "key=value"
                 let form_parts: Vec<String> = http_req.body_data
                     .iter()
-                    .map(|data| {
-                        // Each DATA entry might be "key=value" or just "value"
-                        // For form, we expect "key=value" format
-                        resolve_string_value(data, vars).unwrap_or_else(|_| data.clone())
-                    })
-                    .collect();
+                    .map(|data| resolve_string_value(data, vars))
+                    .collect::<Result<_>>()?;
                 form_parts.join("&").into_bytes()
             }
             HttpBodyType::Raw => {
@@ -2199,59 +4901,221 @@ pub fn prepare_http_request_with_vars(
     })
 }
 
-/// Helper to resolve string values, substituting variables
+/// Whether `{` at the start of `rest` opens a placeholder: `{name}`, `{name.field}` or `{name[0]}`.
+/// Returns the path between the braces.
+fn placeholder_at(rest: &str) -> Option<&str> {
+    let inner = &rest[1..rest.find('}')?];
+    let starts_like_name = inner.starts_with(|c: char| c.is_alphabetic() || c == '_');
+    let path_chars = inner.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '[' | ']'));
+    (starts_like_name && path_chars).then_some(inner)
+}
+
+/// Whether a string uses `{var}` placeholders or `{{` escapes
+pub fn has_placeholders(s: &str) -> bool {
+    s.match_indices('{').any(|(i, _)| s[i..].starts_with("{{") || placeholder_at(&s[i..]).is_some())
+}
+
+/// Expand `{var}` / `{var.path[0]}` placeholders with `resolve`; `{{` is a literal `{` and the
+/// `}}` closing it a literal `}`. Braces around anything else (such as JSON) are kept as written.
+pub fn expand_placeholders(template: &str, mut resolve: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    // `{{` escapes whose `}}` hasn't been seen yet
+    let mut open_escapes = 0;
+    while let Some(pos) = rest.find(['{', '}']) {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if rest.starts_with("{{") {
+            result.push('{');
+            open_escapes += 1;
+            rest = &rest[2..];
+        } else if rest.starts_with("}}") && open_escapes > 0 {
+            result.push('}');
+            open_escapes -= 1;
+            rest = &rest[2..];
+        } else if let Some(path) = rest.starts_with('{').then(|| placeholder_at(rest)).flatten() {
+            result.push_str(&resolve(path)?);
+            rest = &rest[path.len() + 2..];
+        } else {
+            result.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Check the paths of the placeholders in `text` so a malformed one is a syntax error naming its line
+fn check_placeholders(text: &str, line_num: usize) -> Result<()> {
+    expand_placeholders(text, |path| parse_expression(path, line_num).map(|_| String::new())).map(|_| ())
+}
+
+/// Value of a placeholder path such as `status.players[0]`, as text
+pub fn resolve_placeholder(path: &str, vars: &IndexMap<String, JsonValue>) -> Result<String> {
+    // Script placeholders were checked by `check_placeholders` with their line when parsed
+    let expr = parse_expression(path, 0)?;
+    evaluate_expression(&expr, vars, &IndexMap::new()).map(|value| value_to_text(&value))
+}
+
+/// Strings as-is, everything else as JSON
+fn value_to_text(value: &JsonValue) -> String {
+    value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string())
+}
+
+/// Expand `{var}` placeholders in a string, failing on unknown variables
 fn resolve_string_value(s: &str, vars: &IndexMap<String, JsonValue>) -> Result<String> {
-    // Simple variable substitution: if the string matches a variable name exactly, use it
-    // Otherwise, return as-is (future: could support embedded variables like "Bearer {token}")
-    if let Some(value) = vars.get(s) {
-        Ok(value.as_str().unwrap_or(&value.to_string()).to_string())
+    expand_placeholders(s, |path| resolve_placeholder(path, vars))
+}
+
+/// Resolve a PARAM/HEADER value; `*_VAR` values must name an existing variable
+fn resolve_http_value(value: &HttpValue, vars: &IndexMap<String, JsonValue>) -> Result<String> {
+    match value {
+        HttpValue::Literal(s) => resolve_string_value(s, vars),
+        HttpValue::Var(name) => {
+            let value = resolve_var_value(vars, name)?;
+            Ok(value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string()))
+        }
+    }
+}
+
+/// Start of an HTTP body for error messages, cut after `BODY_EXCERPT_CHARS` characters
+fn body_excerpt(body: &[u8]) -> String {
+    const BODY_EXCERPT_CHARS: usize = 200;
+    let text = String::from_utf8_lossy(body);
+    let text = text.trim();
+    if text.is_empty() {
+        return "(empty)".to_string();
+    }
+    match text.char_indices().nth(BODY_EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Name and value of each `Set-Cookie` header, as sent; attributes such as `Path` and `Max-Age` are dropped
+fn response_cookies(headers: &reqwest::header::HeaderMap) -> Vec<(String, String)> {
+    headers.get_all(reqwest::header::SET_COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|cookie| {
+            let (name, value) = cookie.split(';').next()?.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            Some((name.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Value of a response header looked up case-insensitively; repeated headers are joined with `, `
+fn header_value(headers: &reqwest::header::HeaderMap, key: &str) -> Option<String> {
+    let values = headers.get_all(key).iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if values.is_empty() {
+        None
     } else {
-        Ok(s.to_string())
+        Some(values.join(", "))
     }
 }
 
 /// Parse HTTP response using response commands
 pub fn parse_http_response(
-    response_commands: &[ResponseCommand],
+    response_commands: &[Sourced<ResponseCommand>],
     status_code: u16,
     headers: &reqwest::header::HeaderMap,
     body: &[u8],
 ) -> Result<IndexMap<String, serde_json::Value>> {
     let mut vars = IndexMap::new();
+
+    // reqwest is built without its decompression features, so compressed bodies arrive as sent
+    let mut body = std::borrow::Cow::Borrowed(body);
+    let content_encoding = headers.get(reqwest::header::CONTENT_ENCODING).and_then(|v| v.to_str().ok());
+    match content_encoding.map(|encoding| encoding.trim().to_ascii_lowercase()).as_deref() {
+        Some("gzip") | Some("x-gzip") => body = Compression::Gzip.decompress(&body)?.into(),
+        Some("deflate") => body = Compression::Zlib.decompress(&body)?.into(),
+        _ => {}
+    }
     
     // Store status code as a variable
     vars.insert("STATUS_CODE".to_string(), serde_json::json!(status_code));
     
-    // Store headers as variables (HEADER_<Key>)
-    for (key, value) in headers.iter() {
-        let header_name = format!("HEADER_{}", key.as_str().replace("-", "_"));
-        if let Ok(value_str) = value.to_str() {
-            vars.insert(header_name, serde_json::json!(value_str));
+    // Store cookies set by the response as variables (COOKIE_<name>)
+    for (name, value) in response_cookies(headers) {
+        vars.insert(format!("COOKIE_{}", name), serde_json::json!(value));
+    }
+
+    // Store headers as variables (HEADER_<Key>) unless the response asks for NO_HEADER_VARS
+    if !response_commands.iter().any(|sourced| matches!(sourced.command, ResponseCommand::NoHeaderVars)) {
+        for (key, value) in headers.iter() {
+            let header_name = format!("HEADER_{}", key.as_str().replace("-", "_"));
+            if let Ok(value_str) = value.to_str() {
+                vars.insert(header_name, serde_json::json!(value_str));
+            }
         }
     }
     
-    for cmd in response_commands {
+    let mut line = None;
+    read_http_body(response_commands, status_code, headers, body, &mut vars, &mut line).map_err(|e| at_line(e, line))?;
+    Ok(vars)
+}
+
+/// Run the response commands of [`parse_http_response`], keeping `line` at the command being run
+fn read_http_body(
+    response_commands: &[Sourced<ResponseCommand>],
+    status_code: u16,
+    headers: &reqwest::header::HeaderMap,
+    mut body: std::borrow::Cow<'_, [u8]>,
+    vars: &mut IndexMap<String, serde_json::Value>,
+    line: &mut Option<usize>,
+) -> Result<()> {
+    for sourced in response_commands {
+        let cmd = &sourced.command;
+        *line = Some(sourced.source_line);
         match cmd {
-            ResponseCommand::ExpectStatus(expected) => {
-                if status_code != *expected {
-                    anyhow::bail!("Expected status code {}, got {}", expected, status_code);
+            ResponseCommand::ExpectStatus(min, max) => {
+                if status_code < *min || status_code > *max {
+                    let expected = if min == max {
+                        min.to_string()
+                    } else if min.is_multiple_of(100) && *max == min + 99 {
+                        format!("{}xx", min / 100)
+                    } else {
+                        format!("{}-{}", min, max)
+                    };
+                    anyhow::bail!("Expected status code {}, got {}; body: {}", expected, status_code, body_excerpt(&body));
+                }
+            }
+            ResponseCommand::ExpectBodyContains(text) => {
+                if !String::from_utf8_lossy(&body).contains(text.as_str()) {
+                    anyhow::bail!("Response body does not contain \"{}\" (status {}); body: {}", text, status_code, body_excerpt(&body));
                 }
             }
-            ResponseCommand::ExpectHeader { key, value } => {
-                let header_value = headers
-                    .get(key)
-                    .and_then(|v| v.to_str().ok())
+            ResponseCommand::ExpectHeader { key, value, contains } => {
+                let header_value = header_value(headers, key)
                     .ok_or_else(|| anyhow::anyhow!("Header '{}' not found or invalid", key))?;
                 
-                if header_value != value.as_str() {
+                if *contains && !header_value.contains(value.as_str()) {
+                    anyhow::bail!("Expected header '{}' to contain '{}', got '{}'", key, value, header_value);
+                }
+                if !*contains && header_value != *value {
                     anyhow::bail!("Expected header '{}' to be '{}', got '{}'", key, value, header_value);
                 }
             }
+            ResponseCommand::ReadHeader { key, var_name } => {
+                let header_value = header_value(headers, key)
+                    .ok_or_else(|| anyhow::anyhow!("Header '{}' not found or invalid", key))?;
+                vars.insert(var_name.clone(), serde_json::json!(header_value));
+            }
+            ResponseCommand::NoHeaderVars => {}
             ResponseCommand::ReadBodyJson(var_name) => {
-                let json_value: serde_json::Value = serde_json::from_slice(body)
+                let json_value: serde_json::Value = serde_json::from_slice(&body)
                     .context("Failed to parse response body as JSON")?;
                 vars.insert(var_name.clone(), json_value);
             }
+            ResponseCommand::Decompress(compression) => {
+                body = compression.decompress(&body)?.into();
+            }
+            ResponseCommand::ExpectVar(_) => {}
             ResponseCommand::ReadBody(var_name) => {
                 let body_text = String::from_utf8(body.to_vec())
                     .context("Failed to parse response body as UTF-8 text")?;
@@ -2264,6 +5128,366 @@ pub fn parse_http_response(
         }
     }
     
-    Ok(vars)
+    Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Run the script's CODE blocks against `parsed_vars`, returning the code variables
+    fn run_code(script: &PacketScript, parsed_vars: &mut IndexMap<String, JsonValue>) -> Result<IndexMap<String, JsonValue>> {
+        execute_code_blocks(&script.code_blocks, &script.functions, parsed_vars, 1000, &mut Vec::new())
+    }
+
+    #[test]
+    fn for_in_array_scopes_the_loop_variable() {
+        let script = parse_script(
+            "CODE_START\nARRAY items = [\"a\", \"b\", \"c\"]\nSTRING seen = \"\"\nSTRING last = \"\"\nFOR item IN items:\n  seen = CONCAT(seen, item)\n  last = item\nCODE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let vars = run_code(&script, &mut IndexMap::new()).unwrap();
+        assert_eq!(vars.get("item"), None);
+        assert_eq!(vars["seen"], json!("abc"));
+        assert_eq!(vars["last"], json!("c"));
+    }
+
+    #[test]
+    fn for_in_array_restores_a_shadowed_variable() {
+        let script = parse_script(
+            "CODE_START\nSTRING item = \"before\"\nARRAY items = [1, 2]\nFOR item IN items:\n  INT inner = item\nCODE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let vars = run_code(&script, &mut IndexMap::new()).unwrap();
+        assert_eq!(vars["item"], json!("before"));
+        assert_eq!(vars["inner"], json!(2));
+    }
+
+    const RANGE_SCRIPT: &str = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE count\nRESPONSE_END\n\
+        CODE_START\nSTRING marks = \"\"\nFOR i IN RANGE(0, count):\n  marks = CONCAT(marks, \"x\")\nCODE_END\n";
+
+    #[test]
+    fn range_bound_from_response_loops_that_many_times() {
+        let script = parse_script(RANGE_SCRIPT, &Snippets::new()).unwrap();
+        for count in [0u8, 1, 5, 42] {
+            let (mut parsed, _) = parse_response(&script.pairs[0].response, &[count], script.big_endian).unwrap();
+            let vars = run_code(&script, &mut parsed).unwrap();
+            assert_eq!(vars["marks"], json!("x".repeat(count as usize)), "count {count}");
+            assert_eq!(vars.get("i"), None);
+        }
+    }
+
+    #[test]
+    fn range_over_the_iteration_cap_fails_with_loop_line() {
+        let script = parse_script(RANGE_SCRIPT, &Snippets::new()).unwrap();
+        let (mut parsed, _) = parse_response(&script.pairs[0].response, &[200], script.big_endian).unwrap();
+        let err = execute_code_blocks(&script.code_blocks, &script.functions, &mut parsed, 100, &mut Vec::new()).unwrap_err();
+        let limit = err.downcast_ref::<LoopLimitExceeded>().expect("loop limit error");
+        assert_eq!(limit.line, 9);
+    }
+
+    #[test]
+    fn range_honours_break() {
+        let script = parse_script(
+            "CODE_START\nINT last = 0\nFOR i IN RANGE(2, 100):\n  last = i\n  IF i == 10:\n    BREAK\nCODE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let vars = run_code(&script, &mut IndexMap::new()).unwrap();
+        assert_eq!(vars["last"], json!(10));
+    }
+
+    #[test]
+    fn builtins_combine_in_declarations() {
+        let script = parse_script(
+            "CODE_START\nSTRING motd_short = SUBSTRING(TRIM(motd), 0, 32)\nINT motd_len = LEN(TRIM(motd))\nSTRING shout = UPPER(CONCAT(LOWER(\"Hi \"), name, \"!\"))\nCODE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let mut parsed = IndexMap::new();
+        parsed.insert("motd".to_string(), json!("   A Minecraft server with a rather long message of the day  "));
+        parsed.insert("name".to_string(), json!("Steve"));
+        let vars = run_code(&script, &mut parsed).unwrap();
+        assert_eq!(vars["motd_short"], json!("A Minecraft server with a rather"));
+        assert_eq!(vars["motd_len"], json!(56));
+        assert_eq!(vars["shout"], json!("HI STEVE!"));
+    }
+
+    #[test]
+    fn builtins_work_in_conditions_and_indexes() {
+        let script = parse_script(
+            "CODE_START\nARRAY parts = SPLIT(\"a,b,c\", \",\")\nSTRING third = parts[LEN(\"ab\")]\nSTRING size = \"small\"\nIF LEN(parts) >= 3:\n  size = UPPER(\"large\")\nCODE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let vars = run_code(&script, &mut IndexMap::new()).unwrap();
+        assert_eq!(vars["third"], json!("c"));
+        assert_eq!(vars["size"], json!("LARGE"));
+    }
+
+    #[test]
+    fn conversion_builtins() {
+        let script = parse_script(
+            "CODE_START\nINT port = TO_INT(\" 25565 \")\nFLOAT ratio = TO_FLOAT(\"0.75\")\nSTRING label = CONCAT(\"port \", TO_STRING(port))\nSTRING hex = HEX(255)\nINT from_float = TO_INT(3.9)\nCODE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let vars = run_code(&script, &mut IndexMap::new()).unwrap();
+        assert_eq!(vars["port"], json!(25565));
+        assert_eq!(vars["ratio"], json!(0.75));
+        assert_eq!(vars["label"], json!("port 25565"));
+        assert_eq!(vars["hex"], json!("FF"));
+        assert_eq!(vars["from_float"], json!(3));
+    }
+
+    #[test]
+    fn builtin_errors_name_the_function() {
+        for (code, expected) in [
+            ("STRING s = SUBSTRING(\"abc\", 0)", "SUBSTRING expects 3 argument(s), got 2"),
+            ("INT n = LEN(5)", "LEN expects a string or array"),
+            ("STRING s = TRIM(5)", "TRIM expects a string for argument 1"),
+            ("STRING s = HEX(\"zz\")", "Cannot convert \"zz\" to HEX"),
+            ("STRING s = NOPE(1)", "NOPE"),
+        ] {
+            let script = parse_script(&format!("CODE_START\n{code}\nCODE_END\n"), &Snippets::new()).unwrap();
+            let err = run_code(&script, &mut IndexMap::new()).unwrap_err();
+            assert!(format!("{err:#}").contains(expected), "{code}: {err:#}");
+        }
+    }
+
+    const PREFIXED_CASES: [(&str, &[u8]); 6] = [
+        ("BYTE", &[3]),
+        ("SHORT", &[3, 0]),
+        ("SHORT_BE", &[0, 3]),
+        ("INT", &[3, 0, 0, 0]),
+        ("INT_BE", &[0, 0, 0, 3]),
+        ("VARINT", &[3]),
+    ];
+
+    /// A pair writing `hey` and reading it back, both with `prefix`
+    fn prefixed_script(prefix: &str, header: &str) -> PacketScript {
+        let script = format!(
+            "{header}PACKET_START\nWRITE_STRING_PREFIXED {prefix} \"hey\"\nPACKET_END\nRESPONSE_START\nREAD_STRING_PREFIXED {prefix} text\nREAD_BYTE after\nRESPONSE_END\n"
+        );
+        parse_script(&script, &Snippets::new()).unwrap()
+    }
+
+    #[test]
+    fn string_prefixed_widths_and_byte_orders() {
+        for (prefix, len_bytes) in PREFIXED_CASES {
+            let expected: Vec<u8> = [len_bytes, b"hey"].concat();
+            // Prefixes name their byte order, so ENDIAN BIG changes nothing
+            for header in ["", "ENDIAN BIG\n"] {
+                let script = prefixed_script(prefix, header);
+                let packets = build_pair_packets(&script.pairs[0], &IndexMap::new(), script.big_endian).unwrap();
+                assert_eq!(packets[0].bytes, expected, "WRITE_STRING_PREFIXED {prefix} with {header:?}");
+
+                let response = [expected.as_slice(), &[9]].concat();
+                let (vars, read) = parse_response(&script.pairs[0].response, &response, script.big_endian).unwrap();
+                assert_eq!(vars["text"], json!("hey"), "READ_STRING_PREFIXED {prefix} with {header:?}");
+                assert_eq!(vars["after"], json!(9));
+                assert_eq!(read, response.len());
+            }
+        }
+    }
+
+    #[test]
+    fn string_prefixed_accepts_variables_and_lowercase_prefixes() {
+        let script = parse_script(
+            "PACKET_START\nWRITE_STRING_PREFIXED short_be name\nPACKET_END\nRESPONSE_START\nREAD_STRING_PREFIXED varint text\nRESPONSE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let mut vars = IndexMap::new();
+        vars.insert("name".to_string(), json!("lobby"));
+        let packets = build_pair_packets(&script.pairs[0], &vars, script.big_endian).unwrap();
+        assert_eq!(packets[0].bytes, b"\x00\x05lobby");
+        let long_text = "x".repeat(200);
+        let response = [&[0xC8, 0x01][..], long_text.as_bytes()].concat();
+        let (vars, _) = parse_response(&script.pairs[0].response, &response, false).unwrap();
+        assert_eq!(vars["text"], json!(long_text));
+    }
+
+    #[test]
+    fn string_prefixed_errors() {
+        for line in ["WRITE_STRING_PREFIXED LONG \"hey\"", "WRITE_STRING_PREFIXED \"hey\""] {
+            let err = parse_script(&format!("PACKET_START\n{line}\nPACKET_END\n"), &Snippets::new()).unwrap_err();
+            assert!(err.to_string().contains("requires a prefix"), "{line}: {err}");
+        }
+        let err = parse_script("PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_STRING_PREFIXED WORD text\nRESPONSE_END\n", &Snippets::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("requires a prefix"), "{err}");
+
+        let too_long = format!("PACKET_START\nWRITE_STRING_PREFIXED BYTE \"{}\"\nPACKET_END\n", "x".repeat(256));
+        let script = parse_script(&too_long, &Snippets::new()).unwrap();
+        let err = build_pair_packets(&script.pairs[0], &IndexMap::new(), false).unwrap_err();
+        assert!(format!("{err:#}").contains("too long for a byte length prefix"), "{err:#}");
+
+        let script = prefixed_script("INT_BE", "");
+        let err = parse_response(&script.pairs[0].response, &[0, 0], false).unwrap_err();
+        assert!(format!("{err:#}").contains("need 4 bytes for string length"), "{err:#}");
+    }
+
+    /// Script with one POST whose body section is `body`, between `BODY_START TYPE RAW VERBATIM` and `BODY_END`
+    fn verbatim_request(body: &[&str]) -> HttpRequest {
+        let script = [&["HTTP_START REQUEST POST /chat", "BODY_START TYPE RAW VERBATIM"][..], body, &["BODY_END", "HTTP_END"]].concat().join("\n");
+        let parsed = parse_script(&script, &Snippets::new()).unwrap();
+        parsed.pairs[0].http_request.clone().expect("HTTP request")
+    }
+
+    #[test]
+    fn verbatim_body_keeps_nested_json_with_hashes() {
+        let body = [
+            "{",
+            "  \"channel\": \"#general\",  # not a comment",
+            "  \"user\": \"{username}\",",
+            "  \"meta\": {",
+            "    \"tags\": [\"#ops\", \"#1\"],",
+            "    \"color\": \"#ff00aa\"",
+            "  }",
+            "}",
+        ];
+        let request = verbatim_request(&body);
+        assert_eq!(request.body_data, vec![body.join("\n")]);
+
+        // Placeholders are still filled in
+        let body = [
+            "{",
+            "  \"channel\": \"#general\",",
+            "  \"user\": \"{username}\",",
+            "  \"meta\": {\"tags\": [\"#ops\", \"#1\"], \"color\": \"#ff00aa\"}",
+            "}",
+        ];
+        let mut vars = IndexMap::new();
+        vars.insert("username".to_string(), json!("steve"));
+        let prepared = prepare_http_request_with_vars(&verbatim_request(&body), &vars).unwrap();
+        let (_, bytes) = prepared.body.expect("request body");
+        assert_eq!(
+            serde_json::from_slice::<JsonValue>(&bytes).unwrap(),
+            json!({"channel": "#general", "user": "steve", "meta": {"tags": ["#ops", "#1"], "color": "#ff00aa"}})
+        );
+    }
+
+    #[test]
+    fn verbatim_body_is_opaque_to_commands() {
+        // Lines that would otherwise be HTTP commands or comments are part of the body
+        let body = ["DATA is just text here", "DATA_MULTILINE_START", "  HTTP_END", "# heading", "HEADER X-Not-A-Header 1"];
+        let request = verbatim_request(&body);
+        assert_eq!(request.body_data, vec![body.join("\n")]);
+        assert!(request.headers.is_empty());
+
+        let comments_only = ["# first", "  # second"];
+        assert_eq!(verbatim_request(&comments_only).body_data, vec![comments_only.join("\n")]);
+        assert_eq!(verbatim_request(&[]).body_data, vec![String::new()]);
+    }
+
+    #[test]
+    fn raw_body_without_verbatim_still_uses_data_commands() {
+        let script = "HTTP_START REQUEST POST /chat\nBODY_START TYPE RAW\n# a comment\nDATA {\"a\": 1}\nBODY_END\nHTTP_END\n";
+        let parsed = parse_script(script, &Snippets::new()).unwrap();
+        assert_eq!(parsed.pairs[0].http_request.as_ref().unwrap().body_data, vec!["{\"a\": 1}".to_string()]);
+    }
+
+    #[test]
+    fn verbatim_body_errors() {
+        let err = parse_script("HTTP_START REQUEST POST /\nBODY_START TYPE RAW VERBATIM\n{}\nHTTP_END\n", &Snippets::new()).unwrap_err();
+        assert!(err.to_string().contains("has no matching BODY_END"), "{err}");
+        let err = parse_script("HTTP_START REQUEST POST /\nBODY_START TYPE FORM VERBATIM\nBODY_END\nHTTP_END\n", &Snippets::new()).unwrap_err();
+        assert!(err.to_string().contains("Only BODY_START TYPE RAW can be followed by VERBATIM"), "{err}");
+    }
+
+    #[test]
+    fn utf16_counts_too_large_for_the_response_are_errors() {
+        let script = parse_script(
+            &format!("PACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_STRING_UTF16 name {}\nRESPONSE_END\n", usize::MAX),
+            &Snippets::new(),
+        )
+        .unwrap();
+        let err = parse_response(&script.pairs[0].response, &[0x41, 0x00], false).unwrap_err();
+        assert!(err.to_string().contains("Insufficient data"), "{err}");
+    }
+
+    #[test]
+    fn repeat_counts_above_the_maximum_are_rejected() {
+        for command in ["WRITE_BYTES_REPEAT", "WRITE_BYTES_REPEAT_TO_LEN"] {
+            let script = |count: usize| format!("PACKET_START\n{command} 0x00 {count}\nPACKET_END\n");
+            assert!(parse_script(&script(MAX_REPEAT_BYTES), &Snippets::new()).is_ok(), "{command}");
+            let err = parse_script(&script(999_999_999_999), &Snippets::new()).unwrap_err();
+            assert!(err.to_string().contains("larger than the maximum"), "{command}: {err}");
+        }
+    }
+
+    #[test]
+    fn hex_literals_strip_every_0x_prefix() {
+        for literal in ["FEFD", "0xFE0xFD", "0xFE 0xFD", "\"0XFE_0xFD\""] {
+            let script = parse_script(&format!("PACKET_START\nWRITE_BYTES {literal}\nPACKET_END\n"), &Snippets::new()).unwrap();
+            let packets = build_pair_packets(&script.pairs[0], &IndexMap::new(), false).unwrap();
+            assert_eq!(packets[0].bytes, [0xFE, 0xFD], "{literal}");
+        }
+    }
+
+    #[test]
+    fn checksums_hash_text_unless_hex_is_requested() {
+        let script = parse_script(
+            "CODE_START\nSTRING text = CRC32(\"cafe\")\nSTRING bytes = CRC32(\"cafe\", \"HEX\")\nSTRING digest = MD5(\"CAFE\", \"hex\")\nCODE_END\n",
+            &Snippets::new(),
+        )
+        .unwrap();
+        let vars = run_code(&script, &mut IndexMap::new()).unwrap();
+        assert_eq!(vars["text"], json!(format!("{:08x}", crc32fast::hash(b"cafe"))));
+        assert_eq!(vars["bytes"], json!(format!("{:08x}", crc32fast::hash(&[0xca, 0xfe]))));
+        assert_eq!(vars["digest"], json!(hex::encode(md5::Md5::digest([0xca, 0xfe]))));
+
+        for call in ["CRC32(\"xyz\", \"HEX\")", "SHA1(\"cafe\", \"BASE64\")"] {
+            let script = parse_script(&format!("CODE_START\nSTRING sum = {call}\nCODE_END\n"), &Snippets::new()).unwrap();
+            assert!(run_code(&script, &mut IndexMap::new()).is_err(), "{call}");
+        }
+    }
+
+    #[test]
+    fn placeholder_escapes_close_with_double_braces() {
+        let vars: IndexMap<String, JsonValue> = [("name".to_string(), json!("lobby"))].into_iter().collect();
+        let expand = |template: &str| expand_placeholders(template, |path| resolve_placeholder(path, &vars)).unwrap();
+        assert_eq!(expand("{{name}} is {name}"), "{name} is lobby");
+        assert_eq!(expand("{{{name}}}"), "{lobby}");
+        // Closing braces of JSON objects are not escapes
+        assert_eq!(expand("{\"a\":{\"b\":\"{name}\"}}"), "{\"a\":{\"b\":\"lobby\"}}");
+    }
+
+    #[test]
+    fn malformed_placeholders_name_their_line() {
+        let scripts = [
+            ("PACKET_START\nWRITE_BYTE 1\nWRITE_STRING \"{a[}\"\nPACKET_END\n", 3),
+            ("CODE_START\nSTRING a = \"x\"\nSTRING b = \"{a[}\"\nCODE_END\n", 3),
+            ("HTTP_START REQUEST GET /\nHEADER X-Id {a[}\nHTTP_END\n", 2),
+        ];
+        for (script, line) in scripts {
+            let err = parse_script(script, &Snippets::new()).unwrap_err();
+            assert!(err.to_string().contains(&format!("at line {line}")), "{script:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn delays_are_capped_per_script() {
+        let pair = "PACKET_START\nWRITE_BYTE 1\nPACKET_END\nNO_RESPONSE\n";
+        let script = |delays: usize| format!("{pair}{}{pair}", "DELAY 5000\n".repeat(delays));
+        assert!(parse_script(&script(2), &Snippets::new()).is_ok());
+        let err = parse_script(&script(3), &Snippets::new()).unwrap_err();
+        assert!(err.to_string().contains("more than the maximum of 10000ms per script"), "{err}");
+    }
+
+    #[test]
+    fn defines_cannot_be_read_into_or_called_into() {
+        let read = "DEFINE VERSION 3\nPACKET_START\nWRITE_BYTE 1\nPACKET_END\nRESPONSE_START\nREAD_BYTE VERSION\nRESPONSE_END\n";
+        let err = parse_script(read, &Snippets::new()).unwrap_err();
+        assert!(err.to_string().contains("VERSION is a DEFINE constant and can't be read into at line 6"), "{err}");
+
+        let call = "DEFINE VERSION 3\nCODE_START\nDEF four():\n  RETURN 4\nVERSION = CALL four()\nCODE_END\n";
+        let err = parse_script(call, &Snippets::new()).unwrap_err();
+        assert!(err.to_string().contains("VERSION is a DEFINE constant and can't be assigned at line 5"), "{err}");
+    }
+}
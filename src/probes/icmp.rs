@@ -0,0 +1,108 @@
+use anyhow::Result;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// Sent after the 8-byte ICMP header
+const PAYLOAD: &[u8] = b"net_sentinel ping";
+
+/// Sequence numbers of echo requests, so a late reply to an earlier ping isn't taken for this one
+static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// Send one ICMP echo request to `ip` and wait up to `timeout` for the reply, returning the round-trip time.
+/// Needs a raw socket, so the process must run as root or have `CAP_NET_RAW`.
+pub async fn icmp_ping(ip: IpAddr, timeout: Duration) -> Result<Duration> {
+    tokio::task::spawn_blocking(move || ping_blocking(ip, timeout)).await?
+}
+
+fn ping_blocking(ip: IpAddr, timeout: Duration) -> Result<Duration> {
+    let (domain, protocol, request_type, reply_type) = match ip {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4, ECHO_REQUEST_V4, ECHO_REPLY_V4),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6, ECHO_REQUEST_V6, ECHO_REPLY_V6),
+    };
+    let socket = Socket::new(domain, Type::RAW, Some(protocol)).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            anyhow::anyhow!(
+                "Opening a raw ICMP socket was denied ({}); run as root or grant CAP_NET_RAW, e.g. `setcap cap_net_raw+ep net_sentinel`",
+                e
+            )
+        } else {
+            anyhow::anyhow!("Failed to open a raw ICMP socket: {}", e)
+        }
+    })?;
+    // Only receive packets from the target
+    socket.connect(&SockAddr::from(SocketAddr::new(ip, 0)))?;
+
+    let identifier = std::process::id() as u16;
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let mut packet = echo_request(request_type, identifier, sequence);
+    // The kernel fills in the ICMPv6 checksum, which covers a pseudo-header we don't see
+    if ip.is_ipv4() {
+        let checksum = internet_checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    let start = Instant::now();
+    socket.send(&packet)?;
+    let mut buf = [0u8; 1500];
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            anyhow::bail!("No ICMP echo reply from {} within {} ms", ip, timeout.as_millis());
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match (&socket).read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                anyhow::bail!("No ICMP echo reply from {} within {} ms", ip, timeout.as_millis());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Raw IPv4 sockets deliver the IP header too, IPv6 ones only the ICMP message
+        let message = if ip.is_ipv4() { strip_ipv4_header(&buf[..len]) } else { Some(&buf[..len]) };
+        if message.is_some_and(|message| is_echo_reply(message, reply_type, identifier, sequence)) {
+            return Ok(start.elapsed());
+        }
+    }
+}
+
+/// Type, code 0, checksum (filled in later), identifier, sequence number and payload
+fn echo_request(request_type: u8, identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![request_type, 0, 0, 0];
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(PAYLOAD);
+    packet
+}
+
+fn strip_ipv4_header(packet: &[u8]) -> Option<&[u8]> {
+    let header_len = usize::from(packet.first()? & 0x0F) * 4;
+    packet.get(header_len..)
+}
+
+fn is_echo_reply(message: &[u8], reply_type: u8, identifier: u16, sequence: u16) -> bool {
+    message.len() >= 8
+        && message[0] == reply_type
+        && message[1] == 0
+        && message[4..6] == identifier.to_be_bytes()
+        && message[6..8] == sequence.to_be_bytes()
+}
+
+/// RFC 1071 ones' complement sum of 16-bit words
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,3 @@
+//! Low-level network probes used by the ISP checks
+
+pub mod icmp;
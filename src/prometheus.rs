@@ -0,0 +1,147 @@
+use crate::db::Database;
+use crate::{escape_prometheus_label, game_server_metric_prefix, website_site_label};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How long an `_up` metric has to stay at 0 before the alert fires, unless `?alert_for=` says otherwise
+pub const DEFAULT_ALERT_FOR: &str = "5m";
+
+#[derive(Debug, Serialize)]
+struct RuleFile {
+    groups: Vec<RuleGroup>,
+}
+
+#[derive(Debug, Serialize)]
+struct RuleGroup {
+    name: String,
+    rules: Vec<Rule>,
+}
+
+/// A recording rule (`record`) or an alerting rule (`alert`, `for`, `annotations`)
+#[derive(Debug, Serialize)]
+struct Rule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alert: Option<String>,
+    expr: String,
+    #[serde(rename = "for", skip_serializing_if = "Option::is_none")]
+    for_duration: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+}
+
+impl Rule {
+    fn record(name: &str, expr: String) -> Self {
+        Self {
+            record: Some(name.to_string()),
+            alert: None,
+            expr,
+            for_duration: None,
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    fn alert(name: &str, expr: String, for_duration: &str, summary: String) -> Self {
+        Self {
+            record: None,
+            alert: Some(name.to_string()),
+            expr,
+            for_duration: Some(for_duration.to_string()),
+            labels: BTreeMap::from([("severity".to_string(), "critical".to_string())]),
+            annotations: BTreeMap::from([("summary".to_string(), summary)]),
+        }
+    }
+}
+
+/// Whether `value` is a Prometheus duration such as `90s`, `5m` or `1h30m`
+pub fn is_prometheus_duration(value: &str) -> bool {
+    let mut rest = value;
+    if rest.is_empty() {
+        return false;
+    }
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return false;
+        }
+        rest = &rest[digits..];
+        let Some(unit) = ["ms", "s", "m", "h", "d", "w", "y"].into_iter().find(|unit| rest.starts_with(unit)) else {
+            return false;
+        };
+        rest = &rest[unit.len()..];
+    }
+    true
+}
+
+/// Recording and alerting rules for every configured entity, selecting the series `/metrics` exports:
+/// 5-minute ISP availability, 1-hour website availability, 5-minute average game server response
+/// time, and an alert for each `_up` metric that stays at 0 for `alert_for`
+pub fn rules_yaml(db: &Database, alert_for: &str) -> anyhow::Result<String> {
+    let mut recording = Vec::new();
+    let mut alerts = Vec::new();
+
+    for isp in &db.isps {
+        let selector = format!(
+            "{{name=\"{}\",ip=\"{}\"}}",
+            escape_prometheus_label(&isp.name),
+            escape_prometheus_label(&isp.ip)
+        );
+        recording.push(Rule::record(
+            "net_sentinel:isp_availability:avg5m",
+            format!("avg_over_time(net_sentinel_isp_up{}[5m])", selector),
+        ));
+        alerts.push(Rule::alert(
+            "NetSentinelIspDown",
+            format!("net_sentinel_isp_up{} == 0", selector),
+            alert_for,
+            format!("ISP {} ({}) is unreachable", isp.name, isp.ip),
+        ));
+    }
+
+    for website in &db.websites {
+        let site = website_site_label(website);
+        let selector = format!("{{site=\"{}\"}}", escape_prometheus_label(&site));
+        recording.push(Rule::record(
+            "net_sentinel:website_availability:avg1h",
+            format!("avg_over_time(net_sentinel_website_external_up{}[1h])", selector),
+        ));
+        alerts.push(Rule::alert(
+            "NetSentinelWebsiteDown",
+            format!("net_sentinel_website_external_up{} == 0", selector),
+            alert_for,
+            format!("Website {} is down", site),
+        ));
+    }
+
+    for server in &db.game_servers {
+        let prefix = game_server_metric_prefix(server);
+        let selector = format!(
+            "{{name=\"{}\",address=\"{}\",port=\"{}\"}}",
+            escape_prometheus_label(&server.name),
+            escape_prometheus_label(&server.address),
+            server.port
+        );
+        recording.push(Rule::record(
+            &format!("{}:response_time:avg5m", prefix),
+            format!("avg_over_time({}_response_time{}[5m])", prefix, selector),
+        ));
+        alerts.push(Rule::alert(
+            "NetSentinelGameServerDown",
+            format!("{}_up{} == 0", prefix, selector),
+            alert_for,
+            format!("Game server {} ({}:{}) is down", server.name, server.address, server.port),
+        ));
+    }
+
+    let file = RuleFile {
+        groups: vec![
+            RuleGroup { name: "net_sentinel_recording".to_string(), rules: recording },
+            RuleGroup { name: "net_sentinel_alerts".to_string(), rules: alerts },
+        ],
+    };
+    Ok(serde_yaml::to_string(&file)?)
+}
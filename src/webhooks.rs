@@ -0,0 +1,167 @@
+use crate::history::EntityKind;
+use crate::models::{GameServer, GameServerTestResult, Isp, ResultWebhook, Website};
+use crate::out;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Header carrying `sha256=<hex HMAC of the body>` when the webhook has a secret
+pub const SIGNATURE_HEADER: &str = "X-Net-Sentinel-Signature";
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One completed check, as POSTed to result webhooks
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckEvent {
+    pub entity_type: EntityKind,
+    pub entity_id: i64,
+    pub name: String,
+    /// UTC time the checks of the scrape finished, RFC 3339
+    pub timestamp: String,
+    pub success: bool,
+    pub response_time_ms: u64,
+    /// Full check result: `GameServerTestResult` for game servers, success and timing for ISPs and websites
+    pub result: serde_json::Value,
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+/// Events for every check that ran during a `/metrics` scrape
+pub fn events_from_results(
+    isps: &[Isp],
+    isp_results: &HashMap<crate::models::IspCheckKey, (bool, u64)>,
+    websites: &[Website],
+    website_results: &HashMap<(String, String), (bool, u64)>,
+    game_servers: &[GameServer],
+    game_server_results: &HashMap<i64, (String, String, u16, GameServerTestResult)>,
+) -> Vec<CheckEvent> {
+    let timestamp = crate::models::utc_timestamp(std::time::SystemTime::now());
+    let mut events = Vec::new();
+
+    for isp in isps {
+        if let Some(&(success, response_time_ms)) = isp_results.get(&isp.check_key()) {
+            events.push(CheckEvent {
+                entity_type: EntityKind::Isp,
+                entity_id: isp.id,
+                name: isp.name.clone(),
+                timestamp: timestamp.clone(),
+                success,
+                response_time_ms,
+                result: serde_json::json!({"ip": isp.ip, "success": success, "response_time_ms": response_time_ms}),
+            });
+        }
+    }
+
+    for website in websites {
+        let check = |kind: &str| website_results.get(&(website.url.clone(), kind.to_string())).copied();
+        // Websites count as up when their external check succeeds, as in the uptime history
+        let Some((success, response_time_ms)) = check("external") else {
+            continue;
+        };
+        let direct = check("direct").map(|(success, response_time_ms)| {
+            serde_json::json!({"success": success, "response_time_ms": response_time_ms})
+        });
+        events.push(CheckEvent {
+            entity_type: EntityKind::Website,
+            entity_id: website.id,
+            name: website.url.clone(),
+            timestamp: timestamp.clone(),
+            success,
+            response_time_ms,
+            result: serde_json::json!({
+                "url": website.url,
+                "external": {"success": success, "response_time_ms": response_time_ms},
+                "direct": direct,
+            }),
+        });
+    }
+
+    for server in game_servers {
+        if let Some((_, _, _, result)) = game_server_results.get(&server.id) {
+            events.push(CheckEvent {
+                entity_type: EntityKind::GameServer,
+                entity_id: server.id,
+                name: server.name.clone(),
+                timestamp: timestamp.clone(),
+                success: result.success,
+                response_time_ms: result.response_time_ms,
+                result: serde_json::to_value(result).unwrap_or_default(),
+            });
+        }
+    }
+
+    events
+}
+
+/// Remove the response bytes from a game server result, for webhooks without `include_raw_response`
+fn strip_raw_response(result: &mut serde_json::Value) {
+    let Some(object) = result.as_object_mut() else {
+        return;
+    };
+    object.remove("raw_response");
+    object.remove("raw_response_truncated");
+    if let Some(pairs) = object.get_mut("pairs").and_then(|pairs| pairs.as_array_mut()) {
+        for pair in pairs.iter_mut().filter_map(|pair| pair.as_object_mut()) {
+            pair.remove("sent_hex");
+            pair.remove("received_hex");
+        }
+    }
+}
+
+/// `sha256=<hex>` HMAC-SHA256 of the body with the webhook secret
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POST every event to each webhook subscribed to its entity type.
+/// Each request runs in its own task, so slow or failing endpoints never delay the checks.
+pub fn dispatch(webhooks: &[ResultWebhook], events: &[CheckEvent]) {
+    for webhook in webhooks {
+        for event in events.iter().filter(|event| webhook.accepts(event.entity_type)) {
+            let mut event = event.clone();
+            if event.entity_type == EntityKind::GameServer && !webhook.include_raw_response {
+                strip_raw_response(&mut event.result);
+            }
+            let body = match serde_json::to_vec(&event) {
+                Ok(body) => body,
+                Err(e) => {
+                    out::error("webhooks", &format!("Failed to serialize check result: {}", e));
+                    continue;
+                }
+            };
+            let url = webhook.url.clone();
+            let signature = webhook.secret.as_deref().filter(|secret| !secret.is_empty()).map(|secret| sign(secret, &body));
+
+            tokio::spawn(async move {
+                let mut request = client()
+                    .post(&url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body);
+                if let Some(signature) = signature {
+                    request = request.header(SIGNATURE_HEADER, signature);
+                }
+                match request.send().await {
+                    Ok(response) if !response.status().is_success() => out::warning(
+                        "webhooks",
+                        &format!("Webhook {} answered {}", url, response.status()),
+                    ),
+                    Ok(_) => {}
+                    Err(e) => out::warning("webhooks", &format!("Webhook {} failed: {}", url, e)),
+                }
+            });
+        }
+    }
+}